@@ -1,7 +1,7 @@
 use serde::Serialize;
 
 /// Represents a range of offsets in a text file
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize)]
 pub struct Span {
     pub start: usize,
     /// Exclusive; e.g. in the string 'foo bar' the span of 'foo' is { start: 0, end: 3 }
@@ -25,6 +25,10 @@ impl Span {
             end: end.span().end,
         }
     }
+
+    pub fn contains(&self, other: &Span) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
 }
 impl HasSpan for Span {
     fn span(&self) -> &Span {
@@ -32,15 +36,61 @@ impl HasSpan for Span {
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+/// Uniquely identifies a syntax node within a compilation.
+/// Analysis passes use it as the key for results they attach to the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct NodeId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub struct Meta {
     pub span: Span,
+    pub id: NodeId,
 }
 pub trait HasMeta {
     fn meta(&self) -> &Meta;
+
+    fn id(&self) -> NodeId {
+        self.meta().id
+    }
 }
 impl<T: HasMeta> HasSpan for T {
     fn span(&self) -> &Span {
         &self.meta().span
     }
 }
+
+/// Walks the metas of a node and of the nodes inside it, e.g. to move a
+/// subtree that's reused after an edit to where it is in the new text
+pub trait WalkMeta {
+    fn walk_meta(&mut self, f: &mut impl FnMut(&mut Meta));
+}
+impl WalkMeta for Meta {
+    fn walk_meta(&mut self, f: &mut impl FnMut(&mut Meta)) {
+        f(self)
+    }
+}
+impl<T: WalkMeta> WalkMeta for Box<T> {
+    fn walk_meta(&mut self, f: &mut impl FnMut(&mut Meta)) {
+        (**self).walk_meta(f)
+    }
+}
+impl<T: WalkMeta> WalkMeta for Option<T> {
+    fn walk_meta(&mut self, f: &mut impl FnMut(&mut Meta)) {
+        if let Some(it) = self {
+            it.walk_meta(f)
+        }
+    }
+}
+impl<T: WalkMeta> WalkMeta for Vec<T> {
+    fn walk_meta(&mut self, f: &mut impl FnMut(&mut Meta)) {
+        for it in self {
+            it.walk_meta(f)
+        }
+    }
+}
+impl WalkMeta for String {
+    fn walk_meta(&mut self, _: &mut impl FnMut(&mut Meta)) {}
+}
+impl WalkMeta for bool {
+    fn walk_meta(&mut self, _: &mut impl FnMut(&mut Meta)) {}
+}
@@ -1,8 +1,7 @@
-#![feature(proc_macro_quote)]
-
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{parse_macro_input, DeriveInput};
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Fields};
 
 #[proc_macro_derive(HasSpan)]
 pub fn derive_has_span(item: TokenStream) -> TokenStream {
@@ -33,3 +32,58 @@ pub fn derive_has_meta(item: TokenStream) -> TokenStream {
     }
     .into()
 }
+
+/// Walks the fields of a struct, or of the variant of an enum, in order
+#[proc_macro_derive(WalkMeta)]
+pub fn derive_walk_meta(item: TokenStream) -> TokenStream {
+    let DeriveInput { ident, data, .. } = parse_macro_input!(item as DeriveInput);
+    let body = match data {
+        Data::Struct(s) => {
+            let (pattern, walks) = walk_fields(quote!(Self), &s.fields);
+            quote! {
+                let #pattern = self;
+                #walks
+            }
+        }
+        Data::Enum(e) => {
+            let arms = e.variants.iter().map(|variant| {
+                let name = &variant.ident;
+                let (pattern, walks) = walk_fields(quote!(Self::#name), &variant.fields);
+                quote!(#pattern => { #walks })
+            });
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => panic!("WalkMeta can't be derived for unions"),
+    };
+    quote! {
+        #[automatically_derived]
+        impl libsyntax::WalkMeta for #ident {
+            fn walk_meta(&mut self, f: &mut impl FnMut(&mut libsyntax::Meta)) {
+                #body
+            }
+        }
+    }
+    .into()
+}
+
+/// A pattern binding the fields to `field0`, `field1`, ... and the calls
+/// walking them
+fn walk_fields(path: TokenStream2, fields: &Fields) -> (TokenStream2, TokenStream2) {
+    let bindings = (0..fields.len())
+        .map(|i| format_ident!("field{}", i))
+        .collect::<Vec<_>>();
+    let pattern = match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|it| &it.ident);
+            quote!(#path { #(#names: #bindings),* })
+        }
+        Fields::Unnamed(_) => quote!(#path(#(#bindings),*)),
+        Fields::Unit => path,
+    };
+    let walks = quote!(#(libsyntax::WalkMeta::walk_meta(#bindings, f);)*);
+    (pattern, walks)
+}
@@ -0,0 +1,532 @@
+//! Checks attributes against the ones the compiler knows, and collects what
+//! they mean for type checking and code generation.
+
+use std::collections::{HashMap, HashSet};
+
+use libsyntax::{HasMeta, HasSpan, NodeId, Span};
+
+use crate::{
+    ast::{
+        self,
+        visit::{self, Visitor},
+        AttrArgs, Attribute, ItemKind, SourceFile,
+    },
+    diagnostics::{Diagnostic, LINTS},
+};
+
+pub struct AttrsResult {
+    /// Symbols given by `#[export_name]` and `#[link_name]`, keyed by the id of
+    /// the function or foreign function
+    pub symbols: HashMap<NodeId, String>,
+    /// Libraries named by `#[link(name = "...")]` on `extern` blocks
+    pub link_libs: Vec<String>,
+    pub inline: HashSet<NodeId>,
+    pub cold: HashSet<NodeId>,
+    /// Items marked `#[deprecated]`, along with the note if there is one
+    pub deprecated: HashMap<NodeId, Option<String>>,
+    /// Spans of the items, params and statements in which `#[allow(...)]`
+    /// turns off a lint
+    pub allowed: Vec<(Span, String)>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+impl AttrsResult {
+    /// Whether a warning comes from a lint that is allowed where it is reported
+    pub fn is_allowed(&self, diagnostic: &Diagnostic) -> bool {
+        let Some(lint) = diagnostic.lint else {
+            return false;
+        };
+        self.allowed
+            .iter()
+            .any(|(span, it)| it == lint && span.contains(&diagnostic.span))
+    }
+}
+
+pub fn check_attrs(source_file: &SourceFile) -> AttrsResult {
+    let mut checker = AttrChecker::new();
+    for item in source_file.items.iter() {
+        checker.visit_item(item);
+    }
+    checker.result
+}
+
+/// Like [check_attrs], for an item and the items inside it
+pub fn check_item_attrs(item: &ast::Item) -> AttrsResult {
+    let mut checker = AttrChecker::new();
+    checker.visit_item(item);
+    checker.result
+}
+
+/// What an attribute is attached to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    /// A function outside of traits and impls
+    Fn,
+    /// A function in an impl
+    Method,
+    /// A function declared in a trait
+    TraitFn,
+    ForeignMod,
+    ForeignFn,
+    /// A struct, an enum, a type alias or a newtype
+    Adt,
+    Trait,
+    /// A const or a static
+    Global,
+    /// Modules, imports and impls
+    OtherItem,
+    Param,
+    Stmt,
+}
+
+/// The arguments an attribute takes
+#[derive(Debug, Clone, Copy)]
+enum ArgsKind {
+    /// `#[name]`
+    Empty,
+    /// `#[name = "..."]`
+    Str,
+    /// `#[name]` or `#[name = "..."]`
+    OptionalStr,
+    /// `#[name(a, b)]`
+    Names,
+    /// `#[name(key = "...")]`
+    KeyValue(&'static str),
+}
+
+struct AttrSpec {
+    name: &'static str,
+    args: ArgsKind,
+    targets: &'static [Target],
+    /// Where the attribute can be used, for errors about its target
+    targets_desc: &'static str,
+}
+impl AttrSpec {
+    /// How the attribute is written, for errors about its arguments
+    fn usage(&self) -> String {
+        match self.args {
+            ArgsKind::Empty => format!("#[{}]", self.name),
+            ArgsKind::Str => format!("#[{} = \"...\"]", self.name),
+            ArgsKind::OptionalStr => format!("#[{}]` or `#[{} = \"...\"]", self.name, self.name),
+            ArgsKind::Names => format!("#[{}(...)]", self.name),
+            ArgsKind::KeyValue(key) => format!("#[{}({} = \"...\")]", self.name, key),
+        }
+    }
+
+    fn accepts(&self, args: &AttrArgs) -> bool {
+        match (self.args, args) {
+            (ArgsKind::Empty | ArgsKind::OptionalStr, AttrArgs::Empty) => true,
+            (ArgsKind::Str | ArgsKind::OptionalStr, AttrArgs::Eq(_)) => true,
+            (ArgsKind::Names, AttrArgs::List(args)) => {
+                !args.is_empty() && args.iter().all(|it| matches!(it.args, AttrArgs::Empty))
+            }
+            (ArgsKind::KeyValue(key), AttrArgs::List(args)) => {
+                matches!(&args[..], [arg] if arg.name == key && matches!(arg.args, AttrArgs::Eq(_)))
+            }
+            _ => false,
+        }
+    }
+}
+
+const FNS: &[Target] = &[Target::Fn, Target::Method];
+const ANYWHERE: &[Target] = &[
+    Target::Fn,
+    Target::Method,
+    Target::TraitFn,
+    Target::ForeignMod,
+    Target::ForeignFn,
+    Target::Adt,
+    Target::Trait,
+    Target::Global,
+    Target::OtherItem,
+    Target::Param,
+    Target::Stmt,
+];
+
+const KNOWN_ATTRS: &[AttrSpec] = &[
+    AttrSpec {
+        name: "inline",
+        args: ArgsKind::Empty,
+        targets: FNS,
+        targets_desc: "functions with a body",
+    },
+    AttrSpec {
+        name: "cold",
+        args: ArgsKind::Empty,
+        targets: FNS,
+        targets_desc: "functions with a body",
+    },
+    AttrSpec {
+        name: "export_name",
+        args: ArgsKind::Str,
+        targets: &[Target::Fn],
+        targets_desc: "functions outside of traits and impls",
+    },
+    AttrSpec {
+        name: "test",
+        args: ArgsKind::Empty,
+        targets: &[Target::Fn],
+        targets_desc: "functions outside of traits and impls",
+    },
+    AttrSpec {
+        name: "deprecated",
+        args: ArgsKind::OptionalStr,
+        targets: &[
+            Target::Fn,
+            Target::Method,
+            Target::TraitFn,
+            Target::ForeignFn,
+            Target::Adt,
+            Target::Trait,
+            Target::Global,
+        ],
+        targets_desc: "functions, types, traits, consts and statics",
+    },
+    AttrSpec {
+        name: "allow",
+        args: ArgsKind::Names,
+        targets: ANYWHERE,
+        targets_desc: "items, params and statements",
+    },
+    AttrSpec {
+        name: "link",
+        args: ArgsKind::KeyValue("name"),
+        targets: &[Target::ForeignMod],
+        targets_desc: "`extern` blocks",
+    },
+    AttrSpec {
+        name: "link_name",
+        args: ArgsKind::Str,
+        targets: &[Target::ForeignFn],
+        targets_desc: "foreign functions",
+    },
+];
+
+/// Names that symbols can't have, since the generated C uses them: `main` is
+/// the entry point, and the rest are keywords and names from the headers it
+/// includes
+const RESERVED_SYMBOLS: &[&str] = &[
+    "main", "auto", "break", "case", "char", "const", "continue", "default", "do", "double",
+    "else", "enum", "extern", "float", "for", "goto", "if", "inline", "int", "long", "register",
+    "restrict", "return", "short", "signed", "sizeof", "static", "struct", "switch", "typedef",
+    "union", "unsigned", "void", "volatile", "while", "bool", "true", "false", "NULL",
+];
+
+struct AttrChecker {
+    result: AttrsResult,
+    /// Whether the items being visited are in an impl
+    in_impl: bool,
+    /// The symbols given so far, and whether they were given by `#[link_name]`
+    given: HashMap<String, bool>,
+}
+impl AttrChecker {
+    fn new() -> Self {
+        AttrChecker {
+            result: AttrsResult {
+                symbols: HashMap::new(),
+                link_libs: vec![],
+                inline: HashSet::new(),
+                cold: HashSet::new(),
+                deprecated: HashMap::new(),
+                allowed: vec![],
+                diagnostics: vec![],
+            },
+            in_impl: false,
+            given: HashMap::new(),
+        }
+    }
+
+    /// Checks the attributes of the node with id `id` and span `span`
+    fn check(&mut self, attrs: &[Attribute], target: Target, id: NodeId, span: &Span) {
+        for attr in attrs {
+            let Some(spec) = KNOWN_ATTRS.iter().find(|it| it.name == attr.name) else {
+                self.error(attr.span(), format!("Unknown attribute `{}`", attr.name));
+                continue;
+            };
+            if !spec.targets.contains(&target) {
+                let message = format!(
+                    "`#[{}]` can only be used on {}",
+                    spec.name, spec.targets_desc
+                );
+                self.error(attr.span(), message);
+                continue;
+            }
+            if !spec.accepts(&attr.args) {
+                self.error(attr.span(), format!("Expected `{}`", spec.usage()));
+                continue;
+            }
+            self.apply(attr, id, span);
+        }
+    }
+
+    /// Records the meaning of a valid attribute
+    fn apply(&mut self, attr: &Attribute, id: NodeId, span: &Span) {
+        match (attr.name.as_str(), &attr.args) {
+            ("inline", _) => {
+                self.result.inline.insert(id);
+            }
+            ("cold", _) => {
+                self.result.cold.insert(id);
+            }
+            ("export_name" | "link_name", AttrArgs::Eq(symbol)) => {
+                let is_link = attr.name == "link_name";
+                if !is_c_ident(symbol) {
+                    let message = format!("`{}` is not a valid C identifier", symbol);
+                    self.error(attr.span(), message);
+                } else if RESERVED_SYMBOLS.contains(&symbol.as_str()) {
+                    let message = format!("`{}` is reserved in the generated C", symbol);
+                    self.error(attr.span(), message);
+                } else if self.given.get(symbol).is_some_and(|it| !(*it && is_link)) {
+                    // Foreign functions may be declared more than once
+                    let message = format!("The symbol `{}` is already used", symbol);
+                    self.error(attr.span(), message);
+                } else {
+                    self.given.insert(symbol.clone(), is_link);
+                    self.result.symbols.insert(id, symbol.clone());
+                }
+            }
+            ("deprecated", AttrArgs::Eq(note)) => {
+                self.result.deprecated.insert(id, Some(note.clone()));
+            }
+            ("deprecated", _) => {
+                self.result.deprecated.insert(id, None);
+            }
+            ("allow", AttrArgs::List(lints)) => {
+                for lint in lints {
+                    if LINTS.contains(&lint.name.as_str()) {
+                        self.result.allowed.push((*span, lint.name.clone()));
+                    } else {
+                        self.error(lint.span(), format!("Unknown lint `{}`", lint.name));
+                    }
+                }
+            }
+            ("link", AttrArgs::List(args)) => {
+                let AttrArgs::Eq(lib) = &args[0].args else {
+                    unreachable!("Checked by `AttrSpec::accepts`")
+                };
+                if !self.result.link_libs.contains(lib) {
+                    self.result.link_libs.push(lib.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_fn_item(&mut self, item: &ast::Item, f: &ast::Fn) {
+        let target = match (self.in_impl, &f.body) {
+            (true, _) => Target::Method,
+            (false, Some(_)) => Target::Fn,
+            (false, None) => Target::TraitFn,
+        };
+        self.check(&item.attrs, target, item.id(), item.span());
+        let is_test = item.attrs.iter().any(|it| it.name == "test");
+        if is_test && (!f.generics.is_empty() || !f.params.is_empty() || f.return_ty.is_some()) {
+            let message =
+                "`#[test]` functions cannot be generic, take parameters or return a value";
+            self.error(item.span(), message.to_string());
+        }
+        let exports = self.result.symbols.contains_key(&item.id());
+        if exports && !f.generics.is_empty() {
+            let message = "`#[export_name]` cannot be used on generic functions";
+            self.error(item.span(), message.to_string());
+        }
+    }
+
+    fn error(&mut self, span: &Span, message: String) {
+        self.result
+            .diagnostics
+            .push(Diagnostic::new(*span, message));
+    }
+}
+
+impl Visitor for AttrChecker {
+    fn visit_item(&mut self, item: &ast::Item) {
+        match &item.kind {
+            ItemKind::Fn(f) => self.check_fn_item(item, f),
+            ItemKind::ForeignMod(_) => {
+                self.check(&item.attrs, Target::ForeignMod, item.id(), item.span())
+            }
+            ItemKind::Struct(_)
+            | ItemKind::Enum(_)
+            | ItemKind::TyAlias(_)
+            | ItemKind::Newtype(_) => self.check(&item.attrs, Target::Adt, item.id(), item.span()),
+            ItemKind::Trait(_) => self.check(&item.attrs, Target::Trait, item.id(), item.span()),
+            ItemKind::Const(_) | ItemKind::Static(_) => {
+                self.check(&item.attrs, Target::Global, item.id(), item.span())
+            }
+            ItemKind::Impl(_) | ItemKind::Mod(_) | ItemKind::Use(_) => {
+                self.check(&item.attrs, Target::OtherItem, item.id(), item.span())
+            }
+        }
+        let in_impl = std::mem::replace(&mut self.in_impl, matches!(item.kind, ItemKind::Impl(_)));
+        visit::walk_item(self, item);
+        self.in_impl = in_impl;
+    }
+
+    fn visit_foreign_item(&mut self, item: &ast::ForeignItem) {
+        self.check(&item.attrs, Target::ForeignFn, item.id(), item.span());
+        visit::walk_foreign_item(self, item);
+    }
+
+    fn visit_param(&mut self, param: &ast::Param) {
+        self.check(&param.attrs, Target::Param, param.id(), param.span());
+        visit::walk_param(self, param);
+    }
+
+    fn visit_stmt(&mut self, stmt: &ast::Stmt) {
+        self.check(&stmt.attrs, Target::Stmt, stmt.id(), stmt.span());
+        visit::walk_stmt(self, stmt);
+    }
+}
+
+/// Whether `name` can be used as a symbol in the generated C
+fn is_c_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::parser::Parser;
+
+    use super::*;
+
+    fn check_text(text: &str) -> (SourceFile, AttrsResult) {
+        let source_file = Parser::new(text, PathBuf::from("test.hds")).parse_source_file();
+        let result = check_attrs(&source_file);
+        (source_file, result)
+    }
+
+    #[test]
+    fn validates_known_attributes() {
+        let (_, result) = check_text(
+            r#"
+            #[inline]
+            #[cold]
+            #[export_name = "exported"]
+            fn f(#[allow(deprecated)] x: i32) {
+                #[allow(unreachable_patterns, deprecated)]
+                let y = x;
+            }
+            #[test]
+            fn t() {}
+            #[test]
+            fn bad_test(x: i32) {}
+            #[deprecated = "Use `Point`"]
+            struct P {}
+            #[inline]
+            struct S {}
+            #[deprecated(note = "x")]
+            trait T {}
+            #[allow(warnings)]
+            #[unknown]
+            mod m {}
+            impl S {
+                #[export_name = "g"]
+                fn g() {}
+            }
+            "#,
+        );
+        let messages = result
+            .diagnostics
+            .iter()
+            .map(|it| it.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "`#[test]` functions cannot be generic, take parameters or return a value",
+                "`#[inline]` can only be used on functions with a body",
+                r#"Expected `#[deprecated]` or `#[deprecated = "..."]`"#,
+                "Unknown lint `warnings`",
+                "Unknown attribute `unknown`",
+                "`#[export_name]` can only be used on functions outside of traits and impls",
+            ]
+        );
+        assert_eq!(
+            result.symbols.values().collect::<Vec<_>>(),
+            vec!["exported"]
+        );
+        assert_eq!((result.inline.len(), result.cold.len()), (1, 1));
+        assert_eq!(
+            result.deprecated.values().collect::<Vec<_>>(),
+            vec![&Some("Use `Point`".to_string())]
+        );
+        let lints = result
+            .allowed
+            .iter()
+            .map(|(_, it)| it.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lints,
+            vec!["deprecated", "unreachable_patterns", "deprecated"]
+        );
+    }
+
+    #[test]
+    fn reports_symbols_that_clash() {
+        let (_, result) = check_text(
+            r#"
+            #[export_name = "main"]
+            fn f() {}
+            #[export_name = "int"]
+            fn g() {}
+            #[export_name = "run"]
+            fn h() {}
+            mod m {
+                #[export_name = "run"]
+                fn h() {}
+            }
+            extern {
+                #[link_name = "write"]
+                fn write1();
+                #[link_name = "run"]
+                fn run();
+            }
+            extern {
+                #[link_name = "write"]
+                fn write2();
+            }
+            "#,
+        );
+        let messages = result
+            .diagnostics
+            .iter()
+            .map(|it| it.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "`main` is reserved in the generated C",
+                "`int` is reserved in the generated C",
+                "The symbol `run` is already used",
+                "The symbol `run` is already used",
+            ]
+        );
+        let mut symbols = result.symbols.values().collect::<Vec<_>>();
+        symbols.sort();
+        assert_eq!(symbols, vec!["run", "write", "write"]);
+    }
+
+    #[test]
+    fn allows_lints_inside_the_annotated_node() {
+        let (source_file, result) = check_text(
+            "
+            #[allow(deprecated)]
+            fn f() {}
+            fn g() {}
+            ",
+        );
+        let [f, g] = &source_file.items[..] else {
+            panic!()
+        };
+        let warning = |span: Span| Diagnostic::lint(span, "deprecated", "");
+        assert!(result.is_allowed(&warning(*f.span())));
+        assert!(!result.is_allowed(&warning(*g.span())));
+        assert!(!result.is_allowed(&Diagnostic::new(*f.span(), "")));
+    }
+}
@@ -0,0 +1,292 @@
+//! Evaluates the initializers of consts and statics and the lengths of arrays
+//! at compile time. Expressions are type checked before they're evaluated.
+
+use std::collections::HashMap;
+
+use libsyntax::{HasMeta, HasSpan, NodeId};
+
+use crate::{
+    ast::{self, BinOp, ExprKind, UnOp},
+    diagnostics::Diagnostic,
+};
+
+use super::{
+    resolve::{DefKind, Res, ResolveResult},
+    ty::{PrimTy, Type},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstValue {
+    /// The value of an integer of any type, which always fits in its type
+    Int(i128),
+    Bool(bool),
+}
+
+/// The consts that a constant expression refers to, which must be evaluated
+/// before it. Reports expressions that can't be evaluated at compile time.
+pub fn const_deps(expr: &ast::Expr, resolve: &ResolveResult) -> Result<Vec<NodeId>, Diagnostic> {
+    let mut deps = vec![];
+    collect_deps(expr, resolve, &mut deps)?;
+    Ok(deps)
+}
+
+fn collect_deps(
+    expr: &ast::Expr,
+    resolve: &ResolveResult,
+    deps: &mut Vec<NodeId>,
+) -> Result<(), Diagnostic> {
+    match &expr.kind {
+        ExprKind::Lit(ast::Lit {
+            kind: ast::LitKind::Integer | ast::LitKind::Bool,
+            ..
+        }) => Ok(()),
+        ExprKind::Var(_) | ExprKind::Path(_) => match resolve.get(expr.id()) {
+            Res::Def(DefKind::Const, id) => {
+                deps.push(id);
+                Ok(())
+            }
+            // Already reported
+            Res::Err => Ok(()),
+            _ => {
+                let name = match &expr.kind {
+                    ExprKind::Var(var) => var.name.clone(),
+                    ExprKind::Path(path) => path.to_string(),
+                    _ => unreachable!(),
+                };
+                Err(Diagnostic::new(
+                    *expr.span(),
+                    format!("`{}` is not a constant", name),
+                ))
+            }
+        },
+        ExprKind::Paren(inner)
+        | ExprKind::Unary(UnOp::Neg | UnOp::Not, inner)
+        | ExprKind::Cast(inner, _) => collect_deps(inner, resolve, deps),
+        ExprKind::Binary(_, lhs, rhs) => {
+            collect_deps(lhs, resolve, deps)?;
+            collect_deps(rhs, resolve, deps)
+        }
+        _ => Err(Diagnostic::new(
+            *expr.span(),
+            "Only literals, constants and operators can be used in constant expressions",
+        )),
+    }
+}
+
+/// Whether a constant expression has errors, reported while resolving or
+/// type checking it, that keep it from being evaluated
+pub fn has_errors(
+    expr: &ast::Expr,
+    resolve: &ResolveResult,
+    expr_types: &HashMap<NodeId, Type>,
+) -> bool {
+    if expr_types
+        .get(&expr.id())
+        .is_none_or(|it| *it == Type::Error)
+    {
+        return true;
+    }
+    match &expr.kind {
+        ExprKind::Var(_) | ExprKind::Path(_) => resolve.get(expr.id()) == Res::Err,
+        ExprKind::Paren(inner) | ExprKind::Unary(_, inner) | ExprKind::Cast(inner, _) => {
+            has_errors(inner, resolve, expr_types)
+        }
+        ExprKind::Binary(_, lhs, rhs) => {
+            has_errors(lhs, resolve, expr_types) || has_errors(rhs, resolve, expr_types)
+        }
+        _ => false,
+    }
+}
+
+/// Evaluates a type checked constant expression, given the values of the
+/// consts it refers to
+pub fn eval(
+    expr: &ast::Expr,
+    resolve: &ResolveResult,
+    expr_types: &HashMap<NodeId, Type>,
+    values: &HashMap<NodeId, ConstValue>,
+) -> Result<ConstValue, Diagnostic> {
+    let cx = EvalCtx {
+        resolve,
+        expr_types,
+        values,
+    };
+    cx.eval(expr)
+}
+
+struct EvalCtx<'a> {
+    resolve: &'a ResolveResult,
+    expr_types: &'a HashMap<NodeId, Type>,
+    values: &'a HashMap<NodeId, ConstValue>,
+}
+impl EvalCtx<'_> {
+    fn eval(&self, expr: &ast::Expr) -> Result<ConstValue, Diagnostic> {
+        match &expr.kind {
+            ExprKind::Lit(lit) => match lit.kind {
+                ast::LitKind::Bool => Ok(ConstValue::Bool(lit.text == "true")),
+                ast::LitKind::Integer => Ok(ConstValue::Int(
+                    lit.text
+                        .parse()
+                        .expect("Integer literals fit in their type"),
+                )),
+                ast::LitKind::Null => unreachable!("`null` is not a constant"),
+            },
+            ExprKind::Var(_) | ExprKind::Path(_) => match self.resolve.get(expr.id()) {
+                Res::Def(DefKind::Const, id) => Ok(self.values[&id]),
+                _ => unreachable!("Only consts are evaluated"),
+            },
+            ExprKind::Paren(inner) => self.eval(inner),
+            ExprKind::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+                let prim = self.prim_ty(expr);
+                let result = match (op, value) {
+                    (UnOp::Not, ConstValue::Bool(value)) => return Ok(ConstValue::Bool(!value)),
+                    (UnOp::Neg, ConstValue::Int(value)) => -value,
+                    // Flips the bits of the value as an integer of its width
+                    (UnOp::Not, ConstValue::Int(value)) => wrap(!value, prim),
+                    _ => unreachable!("Operands are type checked"),
+                };
+                self.check_range(expr, result, prim)
+            }
+            ExprKind::Binary(op, lhs, rhs) => self.eval_binary(expr, *op, lhs, rhs),
+            ExprKind::Cast(inner, _) => {
+                let value = match self.eval(inner)? {
+                    ConstValue::Int(value) => value,
+                    ConstValue::Bool(value) => value as i128,
+                };
+                match self.prim_ty(expr) {
+                    PrimTy::Bool => Ok(ConstValue::Bool(value != 0)),
+                    prim => Ok(ConstValue::Int(wrap(value, prim))),
+                }
+            }
+            _ => unreachable!("Non constant expressions are reported before evaluation"),
+        }
+    }
+
+    fn eval_binary(
+        &self,
+        expr: &ast::Expr,
+        op: BinOp,
+        lhs: &ast::Expr,
+        rhs: &ast::Expr,
+    ) -> Result<ConstValue, Diagnostic> {
+        let lhs = self.eval(lhs)?;
+        // The right operand of `&&` and `||` is only evaluated when needed
+        match (op, lhs) {
+            (BinOp::And, ConstValue::Bool(false)) | (BinOp::Or, ConstValue::Bool(true)) => {
+                return Ok(lhs)
+            }
+            (BinOp::And | BinOp::Or, _) => return self.eval(rhs),
+            _ => {}
+        }
+        let rhs = self.eval(rhs)?;
+        let (ConstValue::Int(a), ConstValue::Int(b)) = (lhs, rhs) else {
+            return Ok(ConstValue::Bool(match op {
+                BinOp::Eq => lhs == rhs,
+                BinOp::Ne => lhs != rhs,
+                _ => unreachable!("Only `==` and `!=` apply to bools"),
+            }));
+        };
+        let result = match op {
+            BinOp::Add => a.checked_add(b),
+            BinOp::Sub => a.checked_sub(b),
+            BinOp::Mul => a.checked_mul(b),
+            BinOp::Div | BinOp::Rem if b == 0 => {
+                return Err(Diagnostic::new(*expr.span(), "Division by zero"));
+            }
+            BinOp::Div => a.checked_div(b),
+            BinOp::Rem => a.checked_rem(b),
+            BinOp::Eq => return Ok(ConstValue::Bool(a == b)),
+            BinOp::Ne => return Ok(ConstValue::Bool(a != b)),
+            BinOp::Lt => return Ok(ConstValue::Bool(a < b)),
+            BinOp::Le => return Ok(ConstValue::Bool(a <= b)),
+            BinOp::Gt => return Ok(ConstValue::Bool(a > b)),
+            BinOp::Ge => return Ok(ConstValue::Bool(a >= b)),
+            BinOp::And | BinOp::Or => unreachable!(),
+        };
+        let prim = self.prim_ty(expr);
+        match result {
+            Some(result) => self.check_range(expr, result, prim),
+            None => Err(overflow(expr, prim)),
+        }
+    }
+
+    fn check_range(
+        &self,
+        expr: &ast::Expr,
+        value: i128,
+        prim: PrimTy,
+    ) -> Result<ConstValue, Diagnostic> {
+        let (min, max) = range(prim);
+        if value < min || value > max {
+            return Err(overflow(expr, prim));
+        }
+        Ok(ConstValue::Int(value))
+    }
+
+    fn prim_ty(&self, expr: &ast::Expr) -> PrimTy {
+        match self.expr_types[&expr.id()] {
+            Type::Prim(prim) => prim,
+            _ => unreachable!("Constant expressions have primitive types"),
+        }
+    }
+}
+
+fn overflow(expr: &ast::Expr, prim: PrimTy) -> Diagnostic {
+    Diagnostic::new(
+        *expr.span(),
+        format!(
+            "Arithmetic overflow: the result doesn't fit in `{}`",
+            prim.name()
+        ),
+    )
+}
+
+/// The number of bits of an integer type; `isize` and `usize` are assumed to
+/// be 64 bits wide
+fn bits(prim: PrimTy) -> u32 {
+    use PrimTy::*;
+    match prim {
+        I8 | U8 => 8,
+        I16 | U16 => 16,
+        I32 | U32 => 32,
+        I64 | U64 | ISize | USize => 64,
+        Bool => unreachable!("`bool` is not an integer"),
+    }
+}
+
+/// The smallest and largest values of an integer type
+fn range(prim: PrimTy) -> (i128, i128) {
+    let bits = bits(prim);
+    if prim.is_signed() {
+        (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+    } else {
+        (0, (1 << bits) - 1)
+    }
+}
+
+/// Truncates a value to the width of an integer type, as `as` does
+fn wrap(value: i128, prim: PrimTy) -> i128 {
+    let bits = bits(prim);
+    let truncated = value & ((1 << bits) - 1);
+    if prim.is_signed() && truncated >= 1 << (bits - 1) {
+        truncated - (1 << bits)
+    } else {
+        truncated
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wraps_values_to_the_width_of_their_type() {
+        assert_eq!(wrap(300, PrimTy::U8), 44);
+        assert_eq!(wrap(200, PrimTy::I8), -56);
+        assert_eq!(wrap(-1, PrimTy::U64), u64::MAX as i128);
+        assert_eq!(wrap(!5, PrimTy::U16), 0xfffa);
+        assert_eq!(range(PrimTy::I64), (i64::MIN as i128, i64::MAX as i128));
+        assert_eq!(range(PrimTy::USize), (0, u64::MAX as i128));
+    }
+}
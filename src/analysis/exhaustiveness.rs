@@ -0,0 +1,476 @@
+//! Exhaustiveness and reachability checking for `match` expressions, based on
+//! the usefulness algorithm from "Warnings for pattern matching" (Maranget, 2007).
+//!
+//! A pattern is useful with respect to a list of patterns (the rows of a matrix) if
+//! there is a value that it matches which none of the rows match. An arm is
+//! unreachable if its pattern is not useful with respect to the arms above it, and
+//! a match is exhaustive if the wildcard pattern is not useful with respect to all
+//! of its arms. When a pattern is useful, the algorithm also produces a witness;
+//! a value that only it matches, which is used to report missing patterns.
+use std::collections::HashMap;
+
+use libsyntax::{HasMeta, HasSpan, NodeId};
+
+use crate::{
+    ast::{self, LitKind, PatKind},
+    diagnostics::Diagnostic,
+};
+
+use super::{
+    resolve::ResolveResult,
+    ty::{AdtRef, PrimTy, Type},
+    typeck::{ctor, AdtDef, VariantKind},
+};
+
+pub struct MatchCheckCtx<'a> {
+    pub resolve: &'a ResolveResult,
+    pub adts: &'a HashMap<NodeId, AdtDef>,
+    pub variant_adts: &'a HashMap<NodeId, (NodeId, usize)>,
+}
+
+/// A pattern with names resolved and bindings erased
+#[derive(Debug, Clone)]
+enum Pat {
+    Wild,
+    Ctor(Ctor, Vec<Pat>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ctor {
+    /// The only constructor of a struct or tuple
+    Single,
+    /// The n'th variant of an enum
+    Variant(usize),
+    Bool(bool),
+    Int(u128),
+}
+
+impl<'a> MatchCheckCtx<'a> {
+    pub fn check_match(
+        &self,
+        expr: &ast::Expr,
+        scrutinee_ty: &Type,
+        arms: &[ast::Arm],
+    ) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+        let tys = [scrutinee_ty.clone()];
+        let mut matrix: Vec<Vec<Pat>> = vec![];
+        for arm in arms {
+            let row = vec![self.lower_pat(&arm.pat, scrutinee_ty)];
+            if self.is_useful(&matrix, &row, &tys).is_none() {
+                diagnostics.push(Diagnostic::lint(
+                    *arm.pat.span(),
+                    "unreachable_patterns",
+                    "Unreachable pattern",
+                ));
+            }
+            // A guarded arm may not match, so it doesn't cover anything for the arms below it
+            if arm.guard.is_none() {
+                matrix.push(row);
+            }
+        }
+        if let Some(witness) = self.is_useful(&matrix, &[Pat::Wild], &tys) {
+            diagnostics.push(Diagnostic::new(
+                *expr.span(),
+                format!(
+                    "Non-exhaustive patterns: `{}` not covered",
+                    self.display(&witness[0], scrutinee_ty)
+                ),
+            ));
+        }
+        diagnostics
+    }
+
+    /// Checks that the pattern of a `let` matches every value of its type
+    pub fn check_irrefutable(&self, pat: &ast::Pat, ty: &Type) -> Vec<Diagnostic> {
+        let tys = [ty.clone()];
+        let matrix = vec![vec![self.lower_pat(pat, ty)]];
+        match self.is_useful(&matrix, &[Pat::Wild], &tys) {
+            Some(witness) => vec![Diagnostic::new(
+                *pat.span(),
+                format!(
+                    "Refutable pattern in `let`: `{}` not covered",
+                    self.display(&witness[0], ty)
+                ),
+            )],
+            None => vec![],
+        }
+    }
+
+    fn lower_pat(&self, pat: &ast::Pat, ty: &Type) -> Pat {
+        match &pat.kind {
+            PatKind::Wild | PatKind::Binding(_) => Pat::Wild,
+            PatKind::Tuple(pats) => match ty {
+                Type::Tuple(items) if items.len() == pats.len() => Pat::Ctor(
+                    Ctor::Single,
+                    pats.iter()
+                        .zip(items.iter())
+                        .map(|(pat, ty)| self.lower_pat(pat, ty))
+                        .collect(),
+                ),
+                _ => Pat::Wild,
+            },
+            PatKind::Lit(lit) => match (&lit.kind, ty) {
+                (LitKind::Bool, Type::Prim(PrimTy::Bool)) => {
+                    Pat::Ctor(Ctor::Bool(lit.text == "true"), vec![])
+                }
+                (LitKind::Integer, Type::Prim(p)) if p.is_integer() => {
+                    match lit.text.parse::<u128>() {
+                        Ok(value) => Pat::Ctor(Ctor::Int(value), vec![]),
+                        Err(_) => Pat::Wild,
+                    }
+                }
+                // Type errors have already been reported
+                _ => Pat::Wild,
+            },
+            PatKind::Path(_) | PatKind::TupleStruct(..) | PatKind::Struct(..) => {
+                let res = self.resolve.get(pat.id());
+                let Some((adt_id, index)) = ctor(self.variant_adts, res) else {
+                    return Pat::Wild;
+                };
+                let Type::Adt(adt_ref) = ty else {
+                    return Pat::Wild;
+                };
+                if adt_ref.def != adt_id {
+                    return Pat::Wild;
+                }
+                let adt = &self.adts[&adt_id];
+                let variant = &adt.variants[index];
+                let fields = variant
+                    .fields
+                    .iter()
+                    .enumerate()
+                    .map(|(i, field)| {
+                        let sub = match &pat.kind {
+                            PatKind::TupleStruct(_, pats) => pats.get(i),
+                            PatKind::Struct(_, fields, _) => fields
+                                .iter()
+                                .find(|it| it.name == field.name)
+                                .map(|it| &it.pat),
+                            _ => None,
+                        };
+                        sub.map(|it| self.lower_pat(it, &field.ty.subst(&adt_ref.args)))
+                            .unwrap_or(Pat::Wild)
+                    })
+                    .collect();
+                let ctor = if adt.is_enum {
+                    Ctor::Variant(index)
+                } else {
+                    Ctor::Single
+                };
+                Pat::Ctor(ctor, fields)
+            }
+        }
+    }
+
+    /// Returns a witness for each column of `v` if `v` is useful with respect to `matrix`
+    fn is_useful(&self, matrix: &[Vec<Pat>], v: &[Pat], tys: &[Type]) -> Option<Vec<Pat>> {
+        let Some(head) = v.first() else {
+            return if matrix.is_empty() {
+                Some(vec![])
+            } else {
+                None
+            };
+        };
+        let ty = &tys[0];
+        match head {
+            Pat::Ctor(ctor, args) => {
+                let witness = self.is_useful_specialized(matrix, v, tys, ctor, args.clone())?;
+                Some(witness)
+            }
+            Pat::Wild => {
+                let used = matrix
+                    .iter()
+                    .filter_map(|row| match &row[0] {
+                        Pat::Ctor(ctor, _) => Some(ctor.clone()),
+                        Pat::Wild => None,
+                    })
+                    .collect::<Vec<_>>();
+                let all = self.all_ctors(ty);
+                match all {
+                    Some(all) if all.iter().all(|it| used.contains(it)) => {
+                        all.into_iter().find_map(|ctor| {
+                            let wilds = vec![Pat::Wild; self.field_tys(&ctor, ty).len()];
+                            self.is_useful_specialized(matrix, v, tys, &ctor, wilds)
+                        })
+                    }
+                    all => {
+                        // Some constructor isn't mentioned by any row, so only the rows
+                        // starting with a wildcard can match the values it constructs.
+                        let default = matrix
+                            .iter()
+                            .filter(|row| matches!(row[0], Pat::Wild))
+                            .map(|row| row[1..].to_vec())
+                            .collect::<Vec<_>>();
+                        let mut witness = self.is_useful(&default, &v[1..], &tys[1..])?;
+                        let missing = all
+                            .and_then(|all| all.into_iter().find(|it| !used.contains(it)))
+                            .map(|ctor| {
+                                let wilds = vec![Pat::Wild; self.field_tys(&ctor, ty).len()];
+                                Pat::Ctor(ctor, wilds)
+                            })
+                            .unwrap_or(Pat::Wild);
+                        witness.insert(0, missing);
+                        Some(witness)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Usefulness of `v` after replacing its head with `args`, restricted to the rows
+    /// of `matrix` that can match values built with `ctor`.
+    fn is_useful_specialized(
+        &self,
+        matrix: &[Vec<Pat>],
+        v: &[Pat],
+        tys: &[Type],
+        ctor: &Ctor,
+        args: Vec<Pat>,
+    ) -> Option<Vec<Pat>> {
+        let field_tys = self.field_tys(ctor, &tys[0]);
+        let arity = field_tys.len();
+        let specialized = matrix
+            .iter()
+            .filter_map(|row| {
+                let mut new_row = match &row[0] {
+                    Pat::Ctor(c, args) if c == ctor => args.clone(),
+                    Pat::Ctor(..) => return None,
+                    Pat::Wild => vec![Pat::Wild; arity],
+                };
+                new_row.extend_from_slice(&row[1..]);
+                Some(new_row)
+            })
+            .collect::<Vec<_>>();
+        let mut new_v = args;
+        new_v.extend_from_slice(&v[1..]);
+        let mut new_tys = field_tys;
+        new_tys.extend_from_slice(&tys[1..]);
+        let mut witness = self.is_useful(&specialized, &new_v, &new_tys)?;
+        let rest = witness.split_off(arity);
+        let mut result = vec![Pat::Ctor(ctor.clone(), witness)];
+        result.extend(rest);
+        Some(result)
+    }
+
+    /// All the constructors of a type, or `None` if it has too many to list
+    fn all_ctors(&self, ty: &Type) -> Option<Vec<Ctor>> {
+        match ty {
+            Type::Prim(PrimTy::Bool) => Some(vec![Ctor::Bool(false), Ctor::Bool(true)]),
+            Type::Adt(adt) => {
+                let adt = &self.adts[&adt.def];
+                if adt.is_enum {
+                    Some((0..adt.variants.len()).map(Ctor::Variant).collect())
+                } else {
+                    Some(vec![Ctor::Single])
+                }
+            }
+            Type::Tuple(_) => Some(vec![Ctor::Single]),
+            Type::Prim(_)
+            | Type::Fn(..)
+            | Type::Ptr(..)
+            | Type::Array(..)
+            | Type::Slice(_)
+            | Type::Param(_)
+            | Type::Dyn(_)
+            | Type::Newtype(_)
+            | Type::Error => None,
+        }
+    }
+
+    fn field_tys(&self, ctor: &Ctor, ty: &Type) -> Vec<Type> {
+        match (ctor, ty) {
+            (Ctor::Single, Type::Adt(adt)) => self.variant_field_tys(adt, 0),
+            (Ctor::Single, Type::Tuple(items)) => items.clone(),
+            (Ctor::Variant(index), Type::Adt(adt)) => self.variant_field_tys(adt, *index),
+            _ => vec![],
+        }
+    }
+
+    fn variant_field_tys(&self, adt: &AdtRef, index: usize) -> Vec<Type> {
+        self.adts[&adt.def].variants[index]
+            .fields
+            .iter()
+            .map(|it| it.ty.subst(&adt.args))
+            .collect()
+    }
+
+    fn display(&self, pat: &Pat, ty: &Type) -> String {
+        match pat {
+            Pat::Wild => "_".to_string(),
+            Pat::Ctor(Ctor::Bool(b), _) => b.to_string(),
+            Pat::Ctor(Ctor::Int(i), _) => i.to_string(),
+            Pat::Ctor(Ctor::Single, args) if matches!(ty, Type::Tuple(_)) => {
+                let Type::Tuple(items) = ty else {
+                    unreachable!()
+                };
+                let args = args
+                    .iter()
+                    .zip(items.iter())
+                    .map(|(arg, ty)| self.display(arg, ty))
+                    .collect::<Vec<_>>();
+                if args.len() == 1 {
+                    format!("({},)", args[0])
+                } else {
+                    format!("({})", args.join(", "))
+                }
+            }
+            Pat::Ctor(ctor @ (Ctor::Single | Ctor::Variant(_)), args) => {
+                let Type::Adt(adt_ref) = ty else {
+                    return "_".to_string();
+                };
+                let adt = &self.adts[&adt_ref.def];
+                let index = match ctor {
+                    Ctor::Variant(index) => *index,
+                    _ => 0,
+                };
+                let variant = &adt.variants[index];
+                let path = adt.variant_path(index);
+                let args = args
+                    .iter()
+                    .zip(variant.fields.iter())
+                    .map(|(arg, field)| (field, self.display(arg, &field.ty.subst(&adt_ref.args))));
+                match variant.kind {
+                    VariantKind::Unit => path,
+                    VariantKind::Tuple => format!(
+                        "{}({})",
+                        path,
+                        args.map(|(_, arg)| arg).collect::<Vec<_>>().join(", ")
+                    ),
+                    VariantKind::Struct => format!(
+                        "{} {{ {} }}",
+                        path,
+                        args.map(|(field, arg)| format!("{}: {}", field.name, arg))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::{
+        analysis::{resolve::resolve, typeck::typeck},
+        parser::Parser,
+    };
+
+    fn match_messages(text: &str) -> Vec<String> {
+        let source_file = Parser::new(text, PathBuf::from("test.hds")).parse_source_file();
+        let resolve_result = resolve(&source_file);
+        assert_eq!(resolve_result.diagnostics, vec![]);
+        typeck(&source_file, &resolve_result)
+            .diagnostics
+            .into_iter()
+            .map(|it| it.message)
+            .collect()
+    }
+
+    #[test]
+    fn reports_missing_variants() {
+        let messages = match_messages(
+            "
+            enum Option { Some(i32), None }
+            fn f(o: Option) -> i32 { match o { Option::Some(x) => x } }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec!["Non-exhaustive patterns: `Option::None` not covered"]
+        );
+    }
+
+    #[test]
+    fn reports_missing_nested_patterns() {
+        let messages = match_messages(
+            "
+            enum Option { Some(bool), None }
+            struct P { a: Option, b: bool }
+            fn f(p: P) -> i32 {
+                match p {
+                    P { a: Option::Some(true), .. } => 1,
+                    P { a: Option::None, b } => 2,
+                    P { a: Option::Some(false), b: true } => 3,
+                }
+            }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec!["Non-exhaustive patterns: `P { a: Option::Some(false), b: false }` not covered"]
+        );
+    }
+
+    #[test]
+    fn guarded_arms_do_not_count_towards_exhaustiveness() {
+        let messages = match_messages(
+            "
+            fn f(b: bool, c: bool) -> i32 {
+                match b {
+                    true if c => 1,
+                    false => 2,
+                }
+            }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec!["Non-exhaustive patterns: `true` not covered"]
+        );
+    }
+
+    #[test]
+    fn reports_unreachable_arms() {
+        let messages = match_messages(
+            "
+            enum E { A, B }
+            fn f(e: E, i: i32) -> i32 {
+                let x = match e { E::A => 1, _ => 2, E::B => 3 };
+                match i { 1 => x, 1 => 2, _ => 3 }
+            }
+            ",
+        );
+        assert_eq!(messages, vec!["Unreachable pattern", "Unreachable pattern"]);
+    }
+
+    #[test]
+    fn reports_missing_tuple_patterns() {
+        let messages = match_messages(
+            "
+            fn f(t: (bool, bool)) -> i32 {
+                match t { (true, _) => 1, (false, true) => 2 }
+            }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec!["Non-exhaustive patterns: `(false, false)` not covered"]
+        );
+    }
+
+    #[test]
+    fn reports_missing_generic_variants() {
+        let messages = match_messages(
+            "
+            enum Option<T> { Some(T), None }
+            fn f<T>(o: Option<Option<T>>) -> i32 {
+                match o { Option::Some(Option::Some(_)) => 1, Option::None => 2 }
+            }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec!["Non-exhaustive patterns: `Option::Some(Option::None)` not covered"]
+        );
+    }
+
+    #[test]
+    fn integers_need_a_wildcard() {
+        let messages = match_messages("fn f(i: u8) -> i32 { match i { 0 => 1, 1 => 2 } }");
+        assert_eq!(messages, vec!["Non-exhaustive patterns: `_` not covered"]);
+    }
+}
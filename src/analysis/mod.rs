@@ -1 +1,6 @@
+pub mod attrs;
+pub mod const_eval;
+pub mod exhaustiveness;
 pub mod resolve;
+pub mod ty;
+pub mod typeck;
@@ -1,22 +1,1476 @@
-use crate::ast::{self, visit::Visitor, SourceFile};
+use std::{collections::HashMap, rc::Rc};
+
+use libsyntax::{HasMeta, HasSpan, NodeId, Span};
+
+use crate::{
+    ast::{
+        self,
+        visit::{self, Visitor},
+        Ident, ItemKind, SourceFile, UseKind, VariantData, Visibility,
+    },
+    diagnostics::Diagnostic,
+    walk_list,
+};
+
+use super::ty::PrimTy;
+
+#[derive(PartialEq)]
+pub struct ResolveResult {
+    /// Maps the id of every node that refers to a name (`Var` and struct expressions,
+    /// `TyKind::Var` types) to what that name refers to.
+    pub res: HashMap<NodeId, Res>,
+    /// What the path of each single import refers to, keyed by the id of the
+    /// `use` item. Values are preferred when a name is in both namespaces.
+    pub imports: Rc<HashMap<NodeId, Res>>,
+    /// Functions in the inherent impls of each struct and enum, keyed by the id
+    /// of the struct or enum item
+    pub assoc_fns: Rc<HashMap<NodeId, HashMap<Ident, NodeId>>>,
+    /// Paths of items from the crate root, e.g. `shapes::Point`. Foreign functions
+    /// and the functions of traits and impls are named by their name alone.
+    pub def_paths: Rc<HashMap<NodeId, String>>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The names declared in the crate, for [resolve_fn]
+    declared: Rc<Declared>,
+}
+impl ResolveResult {
+    pub fn get(&self, id: NodeId) -> Res {
+        self.res.get(&id).copied().unwrap_or(Res::Err)
+    }
+
+    pub fn def_path(&self, id: NodeId) -> &str {
+        &self.def_paths[&id]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Res {
+    /// The id of an [ast::Item], [ast::ForeignItem], [ast::Variant] or [ast::GenericParam].
+    /// `Self` refers to the trait or impl item it's used in.
+    Def(DefKind, NodeId),
+    /// The id of an [ast::Param], [ast::Local] or binding [ast::Pat]
+    Local(NodeId),
+    PrimTy(PrimTy),
+    /// Name could not be resolved; An error has already been reported.
+    Err,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefKind {
+    Fn,
+    ForeignFn,
+    Struct,
+    Enum,
+    Variant,
+    /// A type parameter, or `Self` inside a trait
+    TyParam,
+    Trait,
+    /// A function declared in a trait
+    TraitFn,
+    /// `Self` inside an impl; The type that the impl is for
+    Impl,
+    Mod,
+    Const,
+    Static,
+    TyAlias,
+    Newtype,
+}
 
-pub struct ResolveResult {}
 pub fn resolve(source_file: &SourceFile) -> ResolveResult {
-    let mut resolve = Resolve {};
+    let declared = Declared {
+        modules: vec![Module::new(None, ROOT, vec![])],
+        module_ids: HashMap::new(),
+        crates: HashMap::new(),
+        variants: HashMap::new(),
+        trait_fns: HashMap::new(),
+        impl_adts: HashMap::new(),
+    };
+    let resolve = Resolve {
+        declared: Rc::new(declared),
+        current_module: ROOT,
+        assoc_fns: Rc::default(),
+        scopes: vec![],
+        ty_scopes: vec![],
+        res: HashMap::new(),
+        imports: Rc::default(),
+        def_paths: Rc::default(),
+        diagnostics: vec![],
+    };
     resolve.resolve_source_file(source_file)
 }
 
-struct Resolve;
+/// Resolves a function item against the names declared in the crate that
+/// `crate_result` is of, with the items it's in given outermost first. Only
+/// the diagnostics in its body are kept, since the crate has the others.
+pub fn resolve_fn(
+    crate_result: &ResolveResult,
+    ancestors: &[&ast::Item],
+    item: &ast::Item,
+) -> ResolveResult {
+    let mut resolve = Resolve {
+        declared: crate_result.declared.clone(),
+        current_module: ROOT,
+        assoc_fns: crate_result.assoc_fns.clone(),
+        scopes: vec![],
+        ty_scopes: vec![],
+        res: HashMap::new(),
+        imports: crate_result.imports.clone(),
+        def_paths: crate_result.def_paths.clone(),
+        diagnostics: vec![],
+    };
+    resolve.visit_within(ancestors, item);
+    if let ItemKind::Fn(f) = &item.kind {
+        if let Some(body) = &f.body {
+            let body = body.span();
+            resolve.diagnostics.retain(|it| body.contains(&it.span));
+        }
+    }
+    resolve.into_result()
+}
+
+type ModuleId = usize;
+const ROOT: ModuleId = 0;
+
+/// The crate root, the root of a library or a `mod` item. Each module has its
+/// own namespaces; names from parent modules aren't in scope unless they're
+/// imported.
+#[derive(PartialEq, Eq)]
+struct Module {
+    parent: Option<ModuleId>,
+    /// The root of the crate or library the module is in
+    krate: ModuleId,
+    /// Names of the enclosing modules and this module, empty for the crate root
+    /// and the name of the library for the root of a library
+    path: Vec<Ident>,
+    /// For roots, the libraries that can be referred to by name in the crate
+    deps: Vec<Ident>,
+    /// Functions, tuple/unit struct constructors and imports of them
+    values: HashMap<Ident, Binding>,
+    /// Structs, enums, traits, modules and imports of them
+    types: HashMap<Ident, Binding>,
+    /// Modules imported with `use m::*;`, and whether the import is `pub`
+    globs: Vec<(ModuleId, bool)>,
+}
+impl Module {
+    fn new(parent: Option<ModuleId>, krate: ModuleId, path: Vec<Ident>) -> Self {
+        Module {
+            parent,
+            krate,
+            path,
+            deps: vec![],
+            values: HashMap::new(),
+            types: HashMap::new(),
+            globs: vec![],
+        }
+    }
+
+    fn bindings(&self, ns: Ns) -> &HashMap<Ident, Binding> {
+        match ns {
+            Ns::Values => &self.values,
+            Ns::Types => &self.types,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Binding {
+    res: Res,
+    /// Private bindings are only visible in their module and its descendants
+    is_pub: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ns {
+    Values,
+    Types,
+}
+
+/// The modules of a crate and the names in them, which don't change once all
+/// items are declared
+#[derive(PartialEq, Eq)]
+struct Declared {
+    modules: Vec<Module>,
+    /// The module declared by each `mod` item
+    module_ids: HashMap<NodeId, ModuleId>,
+    /// The `mod` items that libraries are loaded as, by library name
+    crates: HashMap<Ident, NodeId>,
+    /// Variants of each enum, keyed by the id of the enum item
+    variants: HashMap<NodeId, HashMap<Ident, NodeId>>,
+    /// Functions of each trait, keyed by the id of the trait item
+    trait_fns: HashMap<NodeId, HashMap<Ident, NodeId>>,
+    /// The struct or enum that each impl is for, so that `Self::f` can be resolved
+    impl_adts: HashMap<NodeId, NodeId>,
+}
+
+struct Resolve {
+    declared: Rc<Declared>,
+    /// The module whose items are being declared or resolved
+    current_module: ModuleId,
+    assoc_fns: Rc<HashMap<NodeId, HashMap<Ident, NodeId>>>,
+    /// Local scopes, innermost last
+    scopes: Vec<HashMap<Ident, NodeId>>,
+    /// Type parameters and `Self` in the items being resolved, innermost last
+    ty_scopes: Vec<HashMap<Ident, Res>>,
+    res: HashMap<NodeId, Res>,
+    imports: Rc<HashMap<NodeId, Res>>,
+    def_paths: Rc<HashMap<NodeId, String>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// A `use` item waiting for the names it refers to to be declared
+struct Import<'a> {
+    module: ModuleId,
+    item: &'a ast::Item,
+    use_item: &'a ast::Use,
+}
 
 impl Resolve {
-    fn resolve_source_file(&mut self, source_file: &SourceFile) -> ResolveResult {
+    fn resolve_source_file(mut self, source_file: &SourceFile) -> ResolveResult {
+        let mut impls = vec![];
+        let mut imports = vec![];
+        self.declared_mut().modules[ROOT].deps = source_file.deps.clone();
+        self.declare_items(ROOT, &source_file.items, &mut impls, &mut imports);
+        self.resolve_imports(imports);
+        // Impls refer to the types declared and imported above
+        for (module, item, imp) in impls {
+            self.current_module = module;
+            self.declare_impl(item, imp);
+        }
+        self.current_module = ROOT;
         for item in source_file.items.iter() {
-            self.visit_item(&item);
+            self.visit_item(item);
+        }
+        self.into_result()
+    }
+
+    fn into_result(self) -> ResolveResult {
+        ResolveResult {
+            res: self.res,
+            imports: self.imports,
+            assoc_fns: self.assoc_fns,
+            def_paths: self.def_paths,
+            diagnostics: self.diagnostics,
+            declared: self.declared,
+        }
+    }
+
+    fn declared_mut(&mut self) -> &mut Declared {
+        Rc::get_mut(&mut self.declared).expect("Names are declared before they're shared")
+    }
+
+    /// Visits an item in the scopes of the modules, traits and impls it's in
+    fn visit_within(&mut self, ancestors: &[&ast::Item], item: &ast::Item) {
+        let Some((parent, rest)) = ancestors.split_first() else {
+            return self.visit_item(item);
+        };
+        match &parent.kind {
+            ItemKind::Mod(_) => {
+                self.current_module = self.declared.module_ids[&parent.id()];
+                self.visit_within(rest, item);
+            }
+            ItemKind::Trait(_) => {
+                let self_res = Res::Def(DefKind::TyParam, parent.id());
+                let scope = HashMap::from([("Self".to_string(), self_res)]);
+                self.with_ty_scope(scope, |this| this.visit_within(rest, item));
+            }
+            ItemKind::Impl(imp) => self.with_generics(&imp.generics, |this| {
+                let self_res = Res::Def(DefKind::Impl, parent.id());
+                let scope = HashMap::from([("Self".to_string(), self_res)]);
+                this.with_ty_scope(scope, |this| this.visit_within(rest, item));
+            }),
+            _ => unreachable!("Items are only inside modules, traits and impls"),
+        }
+    }
+
+    /// Declares the items of a module and of the modules nested in it, collecting
+    /// the impls and imports, which are resolved once all items are declared
+    fn declare_items<'a>(
+        &mut self,
+        module: ModuleId,
+        items: &'a [ast::Item],
+        impls: &mut Vec<(ModuleId, &'a ast::Item, &'a ast::Impl)>,
+        imports: &mut Vec<Import<'a>>,
+    ) {
+        self.current_module = module;
+        for item in items {
+            match &item.kind {
+                ItemKind::Impl(imp) => impls.push((module, item, imp)),
+                ItemKind::Use(use_item) => imports.push(Import {
+                    module,
+                    item,
+                    use_item,
+                }),
+                ItemKind::Mod(m) => {
+                    let id = self.declared.modules.len();
+                    if let Some(deps) = &m.crate_deps {
+                        // Libraries are only in scope through the extern prelude
+                        let mut root = Module::new(None, id, vec![item.name.clone()]);
+                        root.deps = deps.clone();
+                        let declared = self.declared_mut();
+                        declared.modules.push(root);
+                        declared.crates.insert(item.name.clone(), item.id());
+                    } else {
+                        let mut path = self.declared.modules[module].path.clone();
+                        path.push(item.name.clone());
+                        let krate = self.declared.modules[module].krate;
+                        let child = Module::new(Some(module), krate, path);
+                        self.declared_mut().modules.push(child);
+                        self.declare_type(item, Res::Def(DefKind::Mod, item.id()));
+                    }
+                    self.declared_mut().module_ids.insert(item.id(), id);
+                    self.declare_items(id, &m.items, impls, imports);
+                    self.current_module = module;
+                }
+                _ => self.declare_item(item),
+            }
+        }
+    }
+
+    /// Resolves imports until no more can be resolved, since an import may refer
+    /// to names brought into scope by other imports. The remaining imports refer
+    /// to names that don't exist, or to each other in a cycle.
+    fn resolve_imports(&mut self, mut pending: Vec<Import>) {
+        loop {
+            let count = pending.len();
+            let mut unresolved = vec![];
+            for import in pending {
+                if !self.try_import(&import) {
+                    unresolved.push(import);
+                }
+            }
+            pending = unresolved;
+            if pending.len() == count {
+                break;
+            }
+        }
+        for (index, import) in pending.iter().enumerate() {
+            let message = if self.is_import_cycle(&pending, index) {
+                format!("Import `{}` refers to itself", import.use_item.path)
+            } else {
+                format!("Unresolved import `{}`", import.use_item.path)
+            };
+            self.error(import.item.span(), message);
+        }
+    }
+
+    /// Binds the names of an import, returning false if the path can't be
+    /// resolved yet. Imports of private items are reported and bound to
+    /// [Res::Err] so that their uses aren't reported again.
+    fn try_import(&mut self, import: &Import) -> bool {
+        let Import {
+            module,
+            item,
+            use_item,
+        } = *import;
+        let is_pub = matches!(item.vis, Visibility::Public);
+        let segments = &use_item.path.segments;
+        match &use_item.kind {
+            UseKind::Glob => match self.module_path(module, segments) {
+                Ok(target) => {
+                    let globs = &mut self.declared_mut().modules[module].globs;
+                    globs.push((target, is_pub));
+                    true
+                }
+                Err(PathError::NotFound(..)) => false,
+                Err(err) => {
+                    self.error(item.span(), err.message(&use_item.path));
+                    true
+                }
+            },
+            UseKind::Single(_) => {
+                let (name, prefix) = segments.split_last().unwrap();
+                let target = match self.module_path(module, prefix) {
+                    Ok(target) => target,
+                    Err(PathError::NotFound(..)) => return false,
+                    Err(err) => {
+                        self.error(item.span(), err.message(&use_item.path));
+                        return true;
+                    }
+                };
+                let binding_name = use_item.binding().unwrap();
+                let found = [Ns::Values, Ns::Types]
+                    .into_iter()
+                    .filter_map(|ns| Some((ns, self.lookup(target, name, ns, module)?)))
+                    .collect::<Vec<_>>();
+                if found.is_empty() {
+                    return false;
+                }
+                for (ns, (res, visible)) in found {
+                    let res = if visible {
+                        res
+                    } else {
+                        self.error(item.span(), format!("`{}` is private", name));
+                        Res::Err
+                    };
+                    Rc::make_mut(&mut self.imports)
+                        .entry(item.id())
+                        .or_insert(res);
+                    self.bind(
+                        module,
+                        ns,
+                        binding_name,
+                        item.span(),
+                        Binding { res, is_pub },
+                    );
+                }
+                true
+            }
+        }
+    }
+
+    /// Whether the name that an unresolved import is waiting for is bound by
+    /// another unresolved import that in turn waits for the first one
+    fn is_import_cycle(&self, pending: &[Import], start: usize) -> bool {
+        let mut index = start;
+        for _ in 0..pending.len() {
+            let import = &pending[index];
+            let segments = &import.use_item.path.segments;
+            let (module, name) = match self.module_path(import.module, segments) {
+                Err(PathError::NotFound(module, name)) => (module, name),
+                _ => (import.module, segments.last().unwrap().clone()),
+            };
+            let next = pending
+                .iter()
+                .position(|it| it.module == module && it.use_item.binding() == Some(&name));
+            match next {
+                Some(next) if next == start => return true,
+                Some(next) => index = next,
+                None => return false,
+            }
+        }
+        false
+    }
+
+    /// Records the struct or enum that an impl is for, and the functions of
+    /// inherent impls, which are called as `Point::new` or as methods.
+    fn declare_impl(&mut self, item: &ast::Item, imp: &ast::Impl) {
+        for impl_fn in imp.items.iter() {
+            Rc::make_mut(&mut self.def_paths).insert(impl_fn.id(), impl_fn.name.clone());
+        }
+        let is_param = matches!(&imp.self_ty.kind, ast::TyKind::Var(name, _)
+            if imp.generics.iter().any(|it| it.name == *name));
+        let res = match &imp.self_ty.kind {
+            _ if is_param => None,
+            ast::TyKind::Var(name, _) => self.resolve_type(name),
+            ast::TyKind::Path(path, _) => self.try_resolve_path(path, Ns::Types).ok(),
+            _ => {
+                if imp.trait_ref.is_none() {
+                    self.error_inherent_impl(&imp.self_ty);
+                }
+                return;
+            }
+        };
+        // Unknown types are reported when the impl is resolved
+        let adt = match res {
+            Some(Res::Def(DefKind::Struct | DefKind::Enum, adt)) => adt,
+            None if !is_param => return,
+            _ if imp.trait_ref.is_none() => return self.error_inherent_impl(&imp.self_ty),
+            _ => return,
+        };
+        self.declared_mut().impl_adts.insert(item.id(), adt);
+        if imp.trait_ref.is_some() {
+            return;
+        }
+        let variants = self
+            .declared
+            .variants
+            .get(&adt)
+            .cloned()
+            .unwrap_or_default();
+        for impl_fn in imp.items.iter() {
+            let fns = Rc::make_mut(&mut self.assoc_fns).entry(adt).or_default();
+            let is_duplicate = fns.insert(impl_fn.name.clone(), impl_fn.id()).is_some();
+            if is_duplicate || variants.contains_key(&impl_fn.name) {
+                self.error(
+                    impl_fn.span(),
+                    format!("`{}` is defined multiple times", impl_fn.name),
+                );
+            }
+        }
+    }
+
+    fn error_inherent_impl(&mut self, self_ty: &ast::Ty) {
+        self.error(
+            self_ty.span(),
+            "Impls without a trait are only allowed for structs and enums".to_string(),
+        );
+    }
+
+    fn declare_item(&mut self, item: &ast::Item) {
+        match &item.kind {
+            ItemKind::Fn(_) => {
+                self.declare_value(item, Res::Def(DefKind::Fn, item.id()));
+            }
+            ItemKind::ForeignMod(foreign_mod) => {
+                for foreign_item in foreign_mod.items.iter() {
+                    let binding = Binding {
+                        res: Res::Def(DefKind::ForeignFn, foreign_item.id()),
+                        is_pub: matches!(foreign_item.vis, Visibility::Public),
+                    };
+                    let module = self.current_module;
+                    let span = foreign_item.span();
+                    self.bind(module, Ns::Values, &foreign_item.name, span, binding);
+                    Rc::make_mut(&mut self.def_paths)
+                        .insert(foreign_item.id(), foreign_item.name.clone());
+                }
+            }
+            ItemKind::Enum(e) => {
+                self.declare_type(item, Res::Def(DefKind::Enum, item.id()));
+                let mut variants = HashMap::new();
+                for variant in e.variants.iter() {
+                    if variants
+                        .insert(variant.name.clone(), variant.id())
+                        .is_some()
+                    {
+                        self.error(
+                            variant.span(),
+                            format!("Variant `{}` is defined multiple times", variant.name),
+                        );
+                    }
+                }
+                self.declared_mut().variants.insert(item.id(), variants);
+            }
+            ItemKind::Struct(s) => {
+                let res = Res::Def(DefKind::Struct, item.id());
+                self.declare_type(item, res);
+                match s.data {
+                    VariantData::Tuple(_) | VariantData::Unit => self.declare_value(item, res),
+                    VariantData::Struct(_) => {}
+                }
+            }
+            ItemKind::Trait(t) => {
+                self.declare_type(item, Res::Def(DefKind::Trait, item.id()));
+                let mut fns = HashMap::new();
+                for trait_item in t.items.iter() {
+                    Rc::make_mut(&mut self.def_paths)
+                        .insert(trait_item.id(), trait_item.name.clone());
+                    if fns
+                        .insert(trait_item.name.clone(), trait_item.id())
+                        .is_some()
+                    {
+                        self.error(
+                            trait_item.span(),
+                            format!("`{}` is defined multiple times", trait_item.name),
+                        );
+                    }
+                }
+                self.declared_mut().trait_fns.insert(item.id(), fns);
+            }
+            ItemKind::Const(_) => self.declare_value(item, Res::Def(DefKind::Const, item.id())),
+            ItemKind::Static(_) => self.declare_value(item, Res::Def(DefKind::Static, item.id())),
+            ItemKind::TyAlias(_) => self.declare_type(item, Res::Def(DefKind::TyAlias, item.id())),
+            ItemKind::Newtype(_) => self.declare_type(item, Res::Def(DefKind::Newtype, item.id())),
+            ItemKind::Impl(_) | ItemKind::Mod(_) | ItemKind::Use(_) => {}
+        }
+    }
+
+    fn declare_type(&mut self, item: &ast::Item, res: Res) {
+        self.record_def_path(item);
+        let module = self.current_module;
+        let binding = Binding {
+            res,
+            is_pub: matches!(item.vis, Visibility::Public),
+        };
+        if self.declared_mut().modules[module]
+            .types
+            .insert(item.name.clone(), binding)
+            .is_some()
+        {
+            self.error(
+                item.span(),
+                format!("Type `{}` is defined multiple times", item.name),
+            );
+        }
+    }
+
+    fn declare_value(&mut self, item: &ast::Item, res: Res) {
+        self.record_def_path(item);
+        let binding = Binding {
+            res,
+            is_pub: matches!(item.vis, Visibility::Public),
+        };
+        self.bind(
+            self.current_module,
+            Ns::Values,
+            &item.name,
+            item.span(),
+            binding,
+        );
+    }
+
+    fn record_def_path(&mut self, item: &ast::Item) {
+        let mut path = self.declared.modules[self.current_module].path.clone();
+        path.push(item.name.clone());
+        Rc::make_mut(&mut self.def_paths).insert(item.id(), path.join("::"));
+    }
+
+    fn bind(&mut self, module: ModuleId, ns: Ns, name: &Ident, span: &Span, binding: Binding) {
+        let bindings = match ns {
+            Ns::Values => &mut self.declared_mut().modules[module].values,
+            Ns::Types => &mut self.declared_mut().modules[module].types,
+        };
+        if bindings.insert(name.clone(), binding).is_some() {
+            let message = match ns {
+                Ns::Values => format!("`{}` is defined multiple times", name),
+                Ns::Types => format!("Type `{}` is defined multiple times", name),
+            };
+            self.error(span, message);
+        }
+    }
+
+    /// Looks up a name declared or imported in `module`, along with whether it's
+    /// visible from the module `from`
+    fn lookup(&self, module: ModuleId, name: &str, ns: Ns, from: ModuleId) -> Option<(Res, bool)> {
+        self.lookup_in(module, name, ns, from, &mut vec![])
+    }
+
+    /// Glob imports may import each other, so the modules already searched are skipped
+    fn lookup_in(
+        &self,
+        module: ModuleId,
+        name: &str,
+        ns: Ns,
+        from: ModuleId,
+        searched: &mut Vec<ModuleId>,
+    ) -> Option<(Res, bool)> {
+        if searched.contains(&module) {
+            return None;
+        }
+        searched.push(module);
+        let is_visible = |is_pub| is_pub || self.is_within(from, module);
+        if let Some(binding) = self.declared.modules[module].bindings(ns).get(name) {
+            return Some((binding.res, is_visible(binding.is_pub)));
+        }
+        for &(glob, is_pub) in self.declared.modules[module].globs.iter() {
+            // Only the items that the importing module can see are imported
+            if let Some((res, true)) = self.lookup_in(glob, name, ns, module, searched) {
+                return Some((res, is_visible(is_pub)));
+            }
+        }
+        None
+    }
+
+    /// Whether `module` is `ancestor` or one of its descendants
+    fn is_within(&self, module: ModuleId, ancestor: ModuleId) -> bool {
+        let mut current = Some(module);
+        while let Some(id) = current {
+            if id == ancestor {
+                return true;
+            }
+            current = self.declared.modules[id].parent;
+        }
+        false
+    }
+
+    fn resolve_value(&self, name: &str) -> Option<Res> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .map(|id| Res::Local(*id))
+            .or_else(|| self.resolve_item(name, Ns::Values))
+    }
+
+    fn resolve_type(&self, name: &str) -> Option<Res> {
+        self.ty_scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .copied()
+            .or_else(|| self.resolve_item(name, Ns::Types))
+            .or_else(|| PrimTy::from_name(name).map(Res::PrimTy))
+    }
+
+    /// An item declared in or imported into the current module, or a library
+    fn resolve_item(&self, name: &str, ns: Ns) -> Option<Res> {
+        let module = self.current_module;
+        self.lookup(module, name, ns, module)
+            .map(|(res, _)| res)
+            .or_else(|| match ns {
+                Ns::Types => self.extern_crate(module, name),
+                Ns::Values => None,
+            })
+    }
+
+    /// A library that the crate containing `module` depends on
+    fn extern_crate(&self, module: ModuleId, name: &str) -> Option<Res> {
+        let krate = self.declared.modules[module].krate;
+        if !self.declared.modules[krate]
+            .deps
+            .iter()
+            .any(|it| it == name)
+        {
+            return None;
+        }
+        self.declared
+            .crates
+            .get(name)
+            .map(|id| Res::Def(DefKind::Mod, *id))
+    }
+
+    /// Resolves the module that a path of module names refers to, starting
+    /// from `crate`, `super`, `self` or a module in scope in `module`
+    fn module_path(&self, module: ModuleId, segments: &[Ident]) -> Result<ModuleId, PathError> {
+        let mut current = module;
+        for (index, segment) in segments.iter().enumerate() {
+            current = match segment.as_str() {
+                "crate" if index == 0 => self.declared.modules[module].krate,
+                "self" if index == 0 => module,
+                "super" if index == 0 || segments[..index].iter().all(|it| it == "super") => {
+                    match self.declared.modules[current].parent {
+                        Some(parent) => parent,
+                        None => return Err(PathError::NoParent),
+                    }
+                }
+                name => match self.lookup(current, name, Ns::Types, module) {
+                    Some((Res::Def(DefKind::Mod, id), true)) => self.declared.module_ids[&id],
+                    Some((Res::Err, _)) => return Err(PathError::Err),
+                    Some((_, false)) => return Err(PathError::Private(name.to_string())),
+                    Some(_) => return Err(PathError::NotAModule(name.to_string())),
+                    None => match self.extern_crate(module, name) {
+                        Some(Res::Def(_, id)) if index == 0 => self.declared.module_ids[&id],
+                        _ => return Err(PathError::NotFound(current, name.to_string())),
+                    },
+                },
+            };
+        }
+        Ok(current)
+    }
+
+    /// Splits a path into the module its leading segments refer to, if they
+    /// refer to one, and the remaining segments
+    fn split_module_prefix<'p>(
+        &self,
+        segments: &'p [Ident],
+    ) -> Result<(Option<ModuleId>, &'p [Ident]), PathError> {
+        let is_module = |name: &Ident| {
+            matches!(name.as_str(), "crate" | "self" | "super")
+                || matches!(self.resolve_type(name), Some(Res::Def(DefKind::Mod, _)))
+        };
+        if !is_module(&segments[0]) {
+            return Ok((None, segments));
+        }
+        // The longest prefix of modules, leaving the last segment for the item
+        let mut len = 1;
+        while len < segments.len() - 1 {
+            let prefix = &segments[..len + 1];
+            match self.module_path(self.current_module, prefix) {
+                Ok(_) => len += 1,
+                Err(PathError::NotAModule(_) | PathError::NotFound(..)) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        let module = self.module_path(self.current_module, &segments[..len])?;
+        Ok((Some(module), &segments[len..]))
+    }
+
+    /// Resolves a path in the value namespace, or in the type namespace if `in_types`
+    /// is set. Multi segment paths refer to items in modules, enum variants, trait
+    /// functions or the functions of inherent impls.
+    fn resolve_path(&mut self, path: &ast::Path, span: &Span, in_types: bool) -> Res {
+        let ns = if in_types { Ns::Types } else { Ns::Values };
+        self.try_resolve_path(path, ns).unwrap_or_else(|message| {
+            self.error(span, message);
+            Res::Err
+        })
+    }
+
+    fn try_resolve_path(&self, path: &ast::Path, ns: Ns) -> Result<Res, String> {
+        let (module, segments) = self
+            .split_module_prefix(&path.segments)
+            .map_err(|err| err.message(path))?;
+        let in_types = ns == Ns::Types;
+        match (module, segments) {
+            (None, [name]) => {
+                let res = if in_types {
+                    self.resolve_type(name)
+                } else {
+                    self.resolve_value(name)
+                };
+                res.ok_or_else(|| {
+                    if in_types {
+                        format!("Unknown struct `{}`", name)
+                    } else {
+                        format!("Unbound variable `{}`", name)
+                    }
+                })
+            }
+            (None, [ty_name, name]) => {
+                let res = self.resolve_type(ty_name);
+                self.resolve_in_type(res, ty_name, name, in_types)
+            }
+            (Some(module), [name]) => match self.lookup(module, name, ns, self.current_module) {
+                Some((res, true)) => Ok(res),
+                Some((_, false)) => Err(format!("`{}` is private", name)),
+                None => Err(format!("Unresolved path `{}`", path)),
+            },
+            (Some(module), [ty_name, name]) => {
+                match self.lookup(module, ty_name, Ns::Types, self.current_module) {
+                    Some((res, true)) => self.resolve_in_type(Some(res), ty_name, name, in_types),
+                    Some((_, false)) => Err(format!("`{}` is private", ty_name)),
+                    None => Err(format!("Unresolved path `{}`", path)),
+                }
+            }
+            (Some(_), []) => Err(format!("`{}` is a module", path)),
+            _ => Err(format!("Unresolved path `{}`", path)),
+        }
+    }
+
+    /// Resolves `ty_name::name`, where `res` is what `ty_name` resolved to
+    fn resolve_in_type(
+        &self,
+        res: Option<Res>,
+        ty_name: &Ident,
+        name: &Ident,
+        in_types: bool,
+    ) -> Result<Res, String> {
+        match res {
+            Some(Res::Def(DefKind::Impl, id)) if self.declared.impl_adts.contains_key(&id) => {
+                let adt = self.declared.impl_adts[&id];
+                self.resolve_assoc(adt, ty_name, name, in_types)
+            }
+            Some(Res::Def(DefKind::Enum | DefKind::Struct, id)) => {
+                self.resolve_assoc(id, ty_name, name, in_types)
+            }
+            Some(Res::Def(DefKind::Trait, id)) if !in_types => {
+                match self.declared.trait_fns[&id].get(name) {
+                    Some(f) => Ok(Res::Def(DefKind::TraitFn, *f)),
+                    None => Err(format!("No function `{}` in trait `{}`", name, ty_name)),
+                }
+            }
+            Some(Res::Err) => Ok(Res::Err),
+            Some(_) => Err(format!("`{}` is not an enum", ty_name)),
+            None => Err(format!("Unknown type `{}`", ty_name)),
+        }
+    }
+
+    /// Resolves `Type::name` to a variant of an enum, or to a function of an
+    /// inherent impl when it's a value.
+    fn resolve_assoc(
+        &self,
+        adt: NodeId,
+        ty_name: &Ident,
+        name: &Ident,
+        in_types: bool,
+    ) -> Result<Res, String> {
+        if let Some(variant) = self.declared.variants.get(&adt).and_then(|it| it.get(name)) {
+            return Ok(Res::Def(DefKind::Variant, *variant));
+        }
+        let assoc_fn = self.assoc_fns.get(&adt).and_then(|it| it.get(name));
+        match assoc_fn {
+            Some(f) if !in_types => Ok(Res::Def(DefKind::Fn, *f)),
+            _ => Err(if self.declared.variants.contains_key(&adt) {
+                format!("No variant `{}` in enum `{}`", name, ty_name)
+            } else if in_types {
+                format!("`{}` is not an enum", ty_name)
+            } else {
+                format!("No function `{}` in `{}`", name, ty_name)
+            }),
+        }
+    }
+
+    fn bind_local(&mut self, name: &Ident, id: NodeId) {
+        self.scopes
+            .last_mut()
+            .expect("Locals are always bound inside a scope")
+            .insert(name.clone(), id);
+    }
+
+    fn with_scope(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(HashMap::new());
+        f(self);
+        self.scopes.pop();
+    }
+
+    fn with_generics(&mut self, generics: &[ast::GenericParam], f: impl FnOnce(&mut Self)) {
+        let mut scope = HashMap::new();
+        for param in generics {
+            let res = Res::Def(DefKind::TyParam, param.id());
+            if scope.insert(param.name.clone(), res).is_some() {
+                self.error(
+                    param.span(),
+                    format!("Type parameter `{}` is declared multiple times", param.name),
+                );
+            }
+            for bound in param.bounds.iter() {
+                self.resolve_trait_ref(bound);
+            }
+        }
+        self.with_ty_scope(scope, f);
+    }
+
+    fn with_ty_scope(&mut self, scope: HashMap<Ident, Res>, f: impl FnOnce(&mut Self)) {
+        self.ty_scopes.push(scope);
+        f(self);
+        self.ty_scopes.pop();
+    }
+
+    fn resolve_trait_ref(&mut self, trait_ref: &ast::TraitRef) {
+        let res = match trait_ref.path.segments.as_slice() {
+            [name] => self.resolve_type(name),
+            _ => Some(self.resolve_path(&trait_ref.path, trait_ref.span(), true)),
+        };
+        let res = match res {
+            Some(res @ (Res::Def(DefKind::Trait, _) | Res::Err)) => res,
+            Some(_) => {
+                self.error(
+                    trait_ref.span(),
+                    format!("`{}` is not a trait", trait_ref.path),
+                );
+                Res::Err
+            }
+            None => {
+                self.error(
+                    trait_ref.span(),
+                    format!("Unknown trait `{}`", trait_ref.path),
+                );
+                Res::Err
+            }
+        };
+        self.res.insert(trait_ref.id(), res);
+    }
+
+    fn error(&mut self, span: &Span, message: String) {
+        self.diagnostics.push(Diagnostic::new(*span, message));
+    }
+}
+
+/// Why a path of module names couldn't be resolved
+enum PathError {
+    /// No item called `name` in the module; An import may still declare it
+    NotFound(ModuleId, Ident),
+    NotAModule(Ident),
+    Private(Ident),
+    /// `super` in the crate root
+    NoParent,
+    /// The path goes through an import that has already been reported
+    Err,
+}
+impl PathError {
+    fn message(&self, path: &ast::Path) -> String {
+        match self {
+            PathError::NotFound(..) | PathError::Err => format!("Unresolved path `{}`", path),
+            PathError::NotAModule(name) => format!("`{}` is not a module", name),
+            PathError::Private(name) => format!("Module `{}` is private", name),
+            PathError::NoParent => "`super` used in the crate root".to_string(),
         }
-        ResolveResult {}
     }
 }
 
 impl Visitor for Resolve {
-    fn visit_item(&mut self, item: &ast::Item) {}
+    fn visit_item(&mut self, item: &ast::Item) {
+        let generics = match &item.kind {
+            ItemKind::Struct(s) => &s.generics[..],
+            ItemKind::Enum(e) => &e.generics[..],
+            ItemKind::TyAlias(a) => &a.generics[..],
+            ItemKind::Trait(_) => {
+                let self_res = Res::Def(DefKind::TyParam, item.id());
+                let scope = HashMap::from([("Self".to_string(), self_res)]);
+                self.with_ty_scope(scope, |this| visit::walk_item(this, item));
+                return;
+            }
+            ItemKind::Impl(imp) => {
+                self.with_generics(&imp.generics, |this| {
+                    if let Some(trait_ref) = &imp.trait_ref {
+                        this.resolve_trait_ref(trait_ref);
+                    }
+                    this.visit_ty(&imp.self_ty);
+                    let self_res = Res::Def(DefKind::Impl, item.id());
+                    let scope = HashMap::from([("Self".to_string(), self_res)]);
+                    this.with_ty_scope(scope, |this| {
+                        walk_list!(this, visit_item, &imp.items);
+                    });
+                });
+                return;
+            }
+            ItemKind::Mod(_) => {
+                let parent = self.current_module;
+                self.current_module = self.declared.module_ids[&item.id()];
+                visit::walk_item(self, item);
+                self.current_module = parent;
+                return;
+            }
+            ItemKind::Fn(_)
+            | ItemKind::ForeignMod(_)
+            | ItemKind::Use(_)
+            | ItemKind::Const(_)
+            | ItemKind::Static(_)
+            | ItemKind::Newtype(_) => &[],
+        };
+        self.with_generics(generics, |this| visit::walk_item(this, item));
+    }
+
+    fn visit_fn(&mut self, f: &ast::Fn) {
+        self.with_generics(&f.generics, |this| {
+            this.with_scope(|this| visit::walk_fn(this, f))
+        });
+    }
+
+    fn visit_param(&mut self, param: &ast::Param) {
+        visit::walk_param(self, param);
+        self.bind_local(&param.name, param.id());
+    }
+
+    fn visit_block(&mut self, block: &ast::Block) {
+        self.with_scope(|this| visit::walk_block(this, block));
+    }
+
+    fn visit_expr(&mut self, expr: &ast::Expr) {
+        match &expr.kind {
+            ast::ExprKind::Var(var) => {
+                let res = self.resolve_value(&var.name).unwrap_or_else(|| {
+                    self.error(expr.span(), format!("Unbound variable `{}`", var.name));
+                    Res::Err
+                });
+                self.res.insert(expr.id(), res);
+            }
+            ast::ExprKind::Path(path) => {
+                let res = self.resolve_path(path, expr.span(), false);
+                self.res.insert(expr.id(), res);
+            }
+            ast::ExprKind::Struct(s) => {
+                let res = self.resolve_path(&s.path, expr.span(), true);
+                self.res.insert(expr.id(), res);
+            }
+            _ => {}
+        }
+        visit::walk_expr(self, expr);
+    }
+
+    fn visit_arm(&mut self, arm: &ast::Arm) {
+        self.with_scope(|this| visit::walk_arm(this, arm));
+    }
+
+    fn visit_pat(&mut self, pat: &ast::Pat) {
+        use ast::PatKind as P;
+        match &pat.kind {
+            P::Binding(name) => self.bind_local(name, pat.id()),
+            P::Path(path) | P::TupleStruct(path, _) => {
+                let res = self.resolve_path(path, pat.span(), false);
+                self.res.insert(pat.id(), res);
+            }
+            P::Struct(path, _, _) => {
+                let res = self.resolve_path(path, pat.span(), true);
+                self.res.insert(pat.id(), res);
+            }
+            P::Wild | P::Lit(_) | P::Tuple(_) => {}
+        }
+        visit::walk_pat(self, pat);
+    }
+
+    fn visit_ty(&mut self, ty: &ast::Ty) {
+        match &ty.kind {
+            ast::TyKind::Var(name, _) => {
+                let res = self.resolve_type(name).unwrap_or_else(|| {
+                    self.error(ty.span(), format!("Unknown type `{}`", name));
+                    Res::Err
+                });
+                self.res.insert(ty.id(), res);
+            }
+            ast::TyKind::Path(path, _) => {
+                let res = self.resolve_path(path, ty.span(), true);
+                self.res.insert(ty.id(), res);
+            }
+            ast::TyKind::Dyn(trait_ref) => self.resolve_trait_ref(trait_ref),
+            _ => {}
+        }
+        visit::walk_ty(self, ty);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::parser::Parser;
+
+    use super::*;
+
+    fn resolve_text(text: &str) -> (SourceFile, ResolveResult) {
+        let source_file = Parser::new(text, PathBuf::from("test.hds")).parse_source_file();
+        let result = resolve(&source_file);
+        (source_file, result)
+    }
+
+    #[test]
+    fn resolves_struct_names_in_types() {
+        let (source_file, result) =
+            resolve_text("struct Point { x: i32 } fn f(p: Point) -> i32 { p.x }");
+        assert_eq!(result.diagnostics, vec![]);
+        let ItemKind::Fn(f) = &source_file.items[1].kind else {
+            panic!()
+        };
+        assert_eq!(
+            result.get(f.params[0].ty.id()),
+            Res::Def(DefKind::Struct, source_file.items[0].id())
+        );
+        assert_eq!(
+            result.get(f.return_ty.as_ref().unwrap().id()),
+            Res::PrimTy(PrimTy::I32)
+        );
+    }
+
+    #[test]
+    fn reports_unbound_names() {
+        let (_, result) = resolve_text("fn f(p: Foo) { let x = y; }");
+        let messages = result
+            .diagnostics
+            .iter()
+            .map(|it| it.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, vec!["Unknown type `Foo`", "Unbound variable `y`"]);
+    }
+
+    #[test]
+    fn resolves_variants_and_pattern_bindings() {
+        let (source_file, result) = resolve_text(
+            "
+            enum E { A(i32), B }
+            fn f(e: E) -> i32 { match e { E::A(x) => x, E::B => 0, E::C => 1 } }
+            ",
+        );
+        let messages = result
+            .diagnostics
+            .iter()
+            .map(|it| it.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, vec!["No variant `C` in enum `E`"]);
+        let ItemKind::Enum(e) = &source_file.items[0].kind else {
+            panic!()
+        };
+        let variant_res = result
+            .res
+            .values()
+            .filter(|it| matches!(it, Res::Def(DefKind::Variant, _)))
+            .count();
+        assert_eq!(variant_res, 2);
+        assert!(result
+            .res
+            .values()
+            .any(|it| *it == Res::Def(DefKind::Variant, e.variants[0].id())));
+    }
+
+    #[test]
+    fn resolves_type_params_within_their_item() {
+        let (source_file, result) = resolve_text(
+            "
+            struct Pair<A, B> { a: A, b: B }
+            fn first<T>(p: Pair<T, T>) -> T { p.a }
+            fn g(x: T) {}
+            ",
+        );
+        let messages = result
+            .diagnostics
+            .iter()
+            .map(|it| it.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, vec!["Unknown type `T`"]);
+        let ItemKind::Fn(f) = &source_file.items[1].kind else {
+            panic!()
+        };
+        assert_eq!(
+            result.get(f.return_ty.as_ref().unwrap().id()),
+            Res::Def(DefKind::TyParam, f.generics[0].id())
+        );
+    }
+
+    #[test]
+    fn resolves_traits_and_self() {
+        let (source_file, result) = resolve_text(
+            "
+            trait Show { fn show(self: *Self) -> i32; }
+            struct Point { x: i32 }
+            impl Show for Point { fn show(self: *Self) -> i32 { Show::show(self) } }
+            fn f<T: Show>(x: *T) -> i32 { Show::hide(x) }
+            impl Point for Point {}
+            ",
+        );
+        let messages = result
+            .diagnostics
+            .iter()
+            .map(|it| it.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "No function `hide` in trait `Show`",
+                "`Point` is not a trait"
+            ]
+        );
+        let ItemKind::Impl(imp) = &source_file.items[2].kind else {
+            panic!()
+        };
+        assert_eq!(
+            result.get(imp.trait_ref.as_ref().unwrap().id()),
+            Res::Def(DefKind::Trait, source_file.items[0].id())
+        );
+        let ItemKind::Fn(f) = &imp.items[0].kind else {
+            panic!()
+        };
+        let ast::TyKind::Ptr(_, self_ty) = &f.params[0].ty.kind else {
+            panic!()
+        };
+        assert_eq!(
+            result.get(self_ty.id()),
+            Res::Def(DefKind::Impl, source_file.items[2].id())
+        );
+        let ItemKind::Trait(t) = &source_file.items[0].kind else {
+            panic!()
+        };
+        assert!(result
+            .res
+            .values()
+            .any(|it| *it == Res::Def(DefKind::TraitFn, t.items[0].id())));
+    }
+
+    #[test]
+    fn resolves_inherent_fns() {
+        let (source_file, result) = resolve_text(
+            "
+            enum Shape { Circle, Square }
+            impl Shape {
+                fn new() -> Self { Self::Circle }
+                fn Square() {}
+                fn f() { Self::new(); Shape::new(); Shape::g(); }
+            }
+            impl i32 {}
+            ",
+        );
+        let messages = result
+            .diagnostics
+            .iter()
+            .map(|it| it.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "`Square` is defined multiple times",
+                "Impls without a trait are only allowed for structs and enums",
+                "No variant `g` in enum `Shape`",
+            ]
+        );
+        let ItemKind::Impl(imp) = &source_file.items[1].kind else {
+            panic!()
+        };
+        let new = imp.items[0].id();
+        assert_eq!(result.assoc_fns[&source_file.items[0].id()]["new"], new);
+        let uses = result
+            .res
+            .values()
+            .filter(|it| **it == Res::Def(DefKind::Fn, new))
+            .count();
+        assert_eq!(uses, 2);
+    }
+
+    #[test]
+    fn resolves_paths_into_modules() {
+        let (source_file, result) = resolve_text(
+            "
+            mod a {
+                pub fn f() {}
+                fn g() {}
+                pub mod b {
+                    pub struct S;
+                    fn h() { super::g(); crate::a::f(); g(); }
+                }
+            }
+            fn main() { a::f(); a::g(); let s: a::b::S = a::b::S; a::c::f(); }
+            ",
+        );
+        let messages = result
+            .diagnostics
+            .iter()
+            .map(|it| it.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "Unbound variable `g`",
+                "`g` is private",
+                "Unresolved path `a::c::f`"
+            ]
+        );
+        let ItemKind::Mod(a) = &source_file.items[0].kind else {
+            panic!()
+        };
+        let ItemKind::Mod(b) = &a.items[2].kind else {
+            panic!()
+        };
+        let s = b.items[0].id();
+        assert_eq!(result.def_path(s), "a::b::S");
+        assert_eq!(result.def_path(source_file.items[1].id()), "main");
+        let uses = result
+            .res
+            .values()
+            .filter(|it| **it == Res::Def(DefKind::Struct, s))
+            .count();
+        assert_eq!(uses, 2);
+    }
+
+    #[test]
+    fn resolves_imports() {
+        let (source_file, result) = resolve_text(
+            "
+            mod a {
+                pub fn f() {}
+                pub use super::b::g;
+                fn hidden() {}
+            }
+            mod b {
+                pub fn g() {}
+                fn i() { f(); }
+            }
+            use a::*;
+            use a::f as ff;
+            use a::hidden;
+            use a::missing;
+            use x as y;
+            use y as x;
+            fn main() { f(); g(); ff(); }
+            ",
+        );
+        let messages = result
+            .diagnostics
+            .iter()
+            .map(|it| it.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "`hidden` is private",
+                "Unresolved import `a::missing`",
+                "Import `x` refers to itself",
+                "Import `y` refers to itself",
+                "Unbound variable `f`",
+            ]
+        );
+        let ItemKind::Mod(a) = &source_file.items[0].kind else {
+            panic!()
+        };
+        let ItemKind::Mod(b) = &source_file.items[1].kind else {
+            panic!()
+        };
+        let f = Res::Def(DefKind::Fn, a.items[0].id());
+        let g = Res::Def(DefKind::Fn, b.items[0].id());
+        let count = |res| result.res.values().filter(|it| **it == res).count();
+        assert_eq!((count(f), count(g)), (2, 1));
+        let imported = |res| result.imports.values().filter(|it| **it == res).count();
+        assert_eq!((imported(f), imported(g)), (1, 1));
+    }
+
+    #[test]
+    fn resolves_libraries_through_the_extern_prelude() {
+        let mut source_file = Parser::new(
+            "
+            mod base { pub fn one() {} }
+            mod util {
+                pub fn two() { base::one(); crate::hidden(); }
+                fn hidden() {}
+            }
+            fn main() { util::two(); util::hidden(); base::one(); }
+            ",
+            PathBuf::from("test.hds"),
+        )
+        .parse_source_file();
+        for (item, deps) in source_file
+            .items
+            .iter_mut()
+            .zip([vec![], vec!["base".into()]])
+        {
+            let ItemKind::Mod(m) = &mut item.kind else {
+                panic!()
+            };
+            m.crate_deps = Some(deps);
+        }
+        source_file.deps = vec!["util".into()];
+        let result = resolve(&source_file);
+        let messages = result
+            .diagnostics
+            .iter()
+            .map(|it| it.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages, vec!["`hidden` is private", "Unknown type `base`"]);
+        let ItemKind::Mod(util) = &source_file.items[1].kind else {
+            panic!()
+        };
+        assert_eq!(result.def_path(util.items[0].id()), "util::two");
+    }
+
+    #[test]
+    fn locals_are_scoped_to_their_block() {
+        let (_, result) = resolve_text("fn f() { { let x = 1; }; x; }");
+        assert_eq!(result.diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn resolves_consts_and_statics_as_values() {
+        let (source_file, result) = resolve_text(
+            "
+            mod sizes { pub const N: usize = 4; }
+            const M: usize = sizes::N * 2;
+            static mut TOTAL: usize = M;
+            fn f(a: [i32; M]) { TOTAL; }
+            ",
+        );
+        assert_eq!(result.diagnostics, vec![]);
+        let ItemKind::Mod(sizes) = &source_file.items[0].kind else {
+            panic!()
+        };
+        assert_eq!(result.def_path(sizes.items[0].id()), "sizes::N");
+        let ItemKind::Static(total) = &source_file.items[2].kind else {
+            panic!()
+        };
+        assert_eq!(
+            result.get(total.expr.id()),
+            Res::Def(DefKind::Const, source_file.items[1].id())
+        );
+        let ItemKind::Fn(f) = &source_file.items[3].kind else {
+            panic!()
+        };
+        let ast::ExprKind::Block(body) = &f.body.as_ref().unwrap().kind else {
+            panic!()
+        };
+        let ast::StmtKind::Expr(expr) = &body.stmts[0].kind else {
+            panic!()
+        };
+        assert_eq!(
+            result.get(expr.id()),
+            Res::Def(DefKind::Static, source_file.items[2].id())
+        );
+    }
+
+    #[test]
+    fn resolves_aliases_and_newtypes_as_types() {
+        let (source_file, result) = resolve_text(
+            "
+            type Pair<T> = (T, T);
+            newtype Fd = i32;
+            fn f(p: Pair<Fd>) {}
+            ",
+        );
+        assert_eq!(result.diagnostics, vec![]);
+        let ItemKind::TyAlias(alias) = &source_file.items[0].kind else {
+            panic!()
+        };
+        let ast::TyKind::Tup(items) = &alias.ty.kind else {
+            panic!()
+        };
+        assert_eq!(
+            result.get(items[0].id()),
+            Res::Def(DefKind::TyParam, alias.generics[0].id())
+        );
+        let ItemKind::Fn(f) = &source_file.items[2].kind else {
+            panic!()
+        };
+        let ast::TyKind::Var(_, args) = &f.params[0].ty.kind else {
+            panic!()
+        };
+        assert_eq!(
+            result.get(f.params[0].ty.id()),
+            Res::Def(DefKind::TyAlias, source_file.items[0].id())
+        );
+        assert_eq!(
+            result.get(args[0].id()),
+            Res::Def(DefKind::Newtype, source_file.items[1].id())
+        );
+    }
 }
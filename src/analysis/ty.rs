@@ -0,0 +1,275 @@
+use std::fmt::{self, Display};
+
+use libsyntax::NodeId;
+
+use crate::ast::{Ident, Mutability};
+
+/// Types nested deeper than this can only come from polymorphic recursion, e.g.
+/// `fn f<T>(x: T) { f((x,)) }`, which would need infinitely many instances.
+pub const MAX_TYPE_DEPTH: usize = 32;
+/// Likewise for types made of more types than this, e.g. the ones that
+/// `fn f<T>(x: T) { f((x, x)) }` doubles in size long before they're too deep.
+pub const MAX_TYPE_SIZE: usize = 1024;
+
+/// A type as understood by the type checker, as opposed to [crate::ast::Ty]
+/// which is the syntax that the user wrote.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+    Prim(PrimTy),
+    /// `()` is the empty tuple
+    Tuple(Vec<Type>),
+    Adt(AdtRef),
+    Fn(Vec<Type>, Box<Type>),
+    /// A raw pointer; `*T` or `*mut T`
+    Ptr(Mutability, Box<Type>),
+    /// `[T; N]`
+    Array(Box<Type>, u64),
+    /// `[T]`
+    Slice(Box<Type>),
+    /// A type parameter of the function or ADT being checked
+    Param(ParamTy),
+    /// `dyn Show`; Unsized, so it only appears behind a pointer
+    Dyn(TraitRef),
+    /// `newtype Handle = *u8;`; Distinct from the type it wraps
+    Newtype(NewtypeRef),
+    /// The type of an expression that failed to type check.
+    /// Compatible with every other type to avoid cascading errors.
+    Error,
+}
+
+/// Reference to a user defined (struct) type
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AdtRef {
+    pub def: NodeId,
+    pub name: Ident,
+    /// Type arguments of a generic struct or enum
+    pub args: Vec<Type>,
+}
+
+/// Reference to a newtype
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NewtypeRef {
+    pub def: NodeId,
+    pub name: Ident,
+}
+
+/// Reference to a trait
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TraitRef {
+    pub def: NodeId,
+    pub name: Ident,
+}
+
+/// `T` in `fn id<T>(x: T) -> T`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ParamTy {
+    /// Position of the parameter in the generics of its item
+    pub index: usize,
+    pub name: Ident,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PrimTy {
+    I8,
+    I16,
+    I32,
+    I64,
+    ISize,
+    U8,
+    U16,
+    U32,
+    U64,
+    USize,
+    Bool,
+}
+
+impl PrimTy {
+    pub const ALL: [PrimTy; 11] = [
+        PrimTy::I8,
+        PrimTy::I16,
+        PrimTy::I32,
+        PrimTy::I64,
+        PrimTy::ISize,
+        PrimTy::U8,
+        PrimTy::U16,
+        PrimTy::U32,
+        PrimTy::U64,
+        PrimTy::USize,
+        PrimTy::Bool,
+    ];
+
+    pub fn name(self) -> &'static str {
+        use PrimTy::*;
+        match self {
+            I8 => "i8",
+            I16 => "i16",
+            I32 => "i32",
+            I64 => "i64",
+            ISize => "isize",
+            U8 => "u8",
+            U16 => "u16",
+            U32 => "u32",
+            U64 => "u64",
+            USize => "usize",
+            Bool => "bool",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PrimTy> {
+        PrimTy::ALL.into_iter().find(|it| it.name() == name)
+    }
+
+    pub fn is_integer(self) -> bool {
+        self != PrimTy::Bool
+    }
+
+    pub fn is_signed(self) -> bool {
+        use PrimTy::*;
+        matches!(self, I8 | I16 | I32 | I64 | ISize)
+    }
+}
+
+impl Type {
+    pub fn unit() -> Type {
+        Type::Tuple(vec![])
+    }
+
+    pub fn is_unit(&self) -> bool {
+        matches!(self, Type::Tuple(items) if items.is_empty())
+    }
+
+    /// Whether this is a pointer to `dyn Trait`, which carries a vtable along
+    /// with the address of the value.
+    pub fn is_dyn_ptr(&self) -> bool {
+        matches!(self, Type::Ptr(_, pointee) if matches!(**pointee, Type::Dyn(_)))
+    }
+
+    /// Replaces type parameters with the types at their index in `args`
+    pub fn subst(&self, args: &[Type]) -> Type {
+        match self {
+            Type::Param(param) => args.get(param.index).cloned().unwrap_or(self.clone()),
+            Type::Prim(_) | Type::Dyn(_) | Type::Newtype(_) | Type::Error => self.clone(),
+            Type::Tuple(items) => Type::Tuple(items.iter().map(|it| it.subst(args)).collect()),
+            Type::Adt(adt) => Type::Adt(AdtRef {
+                def: adt.def,
+                name: adt.name.clone(),
+                args: adt.args.iter().map(|it| it.subst(args)).collect(),
+            }),
+            Type::Fn(params, ret) => Type::Fn(
+                params.iter().map(|it| it.subst(args)).collect(),
+                Box::new(ret.subst(args)),
+            ),
+            Type::Ptr(mutability, inner) => Type::Ptr(*mutability, Box::new(inner.subst(args))),
+            Type::Array(elem, len) => Type::Array(Box::new(elem.subst(args)), *len),
+            Type::Slice(elem) => Type::Slice(Box::new(elem.subst(args))),
+        }
+    }
+
+    /// Whether `pred` holds for this type or any type nested inside it
+    pub fn any(&self, pred: &impl Fn(&Type) -> bool) -> bool {
+        pred(self)
+            || match self {
+                Type::Prim(_) | Type::Param(_) | Type::Dyn(_) | Type::Newtype(_) | Type::Error => {
+                    false
+                }
+                Type::Tuple(items) => items.iter().any(|it| it.any(pred)),
+                Type::Adt(adt) => adt.args.iter().any(|it| it.any(pred)),
+                Type::Fn(params, ret) => params.iter().any(|it| it.any(pred)) || ret.any(pred),
+                Type::Ptr(_, inner) | Type::Array(inner, _) | Type::Slice(inner) => inner.any(pred),
+            }
+    }
+
+    /// How deeply types are nested inside this type; Used to stop polymorphic
+    /// recursion from creating ever larger types.
+    pub fn depth(&self) -> usize {
+        let max_depth = |tys: &mut dyn Iterator<Item = &Type>| tys.map(Type::depth).max();
+        1 + match self {
+            Type::Prim(_) | Type::Param(_) | Type::Dyn(_) | Type::Newtype(_) | Type::Error => None,
+            Type::Tuple(items) => max_depth(&mut items.iter()),
+            Type::Adt(adt) => max_depth(&mut adt.args.iter()),
+            Type::Fn(params, ret) => max_depth(&mut params.iter().chain([&**ret])),
+            Type::Ptr(_, inner) | Type::Array(inner, _) | Type::Slice(inner) => Some(inner.depth()),
+        }
+        .unwrap_or(0)
+    }
+
+    /// How many types this type is made of, itself included
+    pub fn size(&self) -> usize {
+        1 + match self {
+            Type::Prim(_) | Type::Param(_) | Type::Dyn(_) | Type::Newtype(_) | Type::Error => 0,
+            Type::Tuple(items) => items.iter().map(Type::size).sum(),
+            Type::Adt(adt) => adt.args.iter().map(Type::size).sum(),
+            Type::Fn(params, ret) => params.iter().map(Type::size).sum::<usize>() + ret.size(),
+            Type::Ptr(_, inner) | Type::Array(inner, _) | Type::Slice(inner) => inner.size(),
+        }
+    }
+
+    /// Whether a value of type `self` can be used where `expected` is required.
+    pub fn is_compatible_with(&self, expected: &Type) -> bool {
+        match (self, expected) {
+            (Type::Error, _) | (_, Type::Error) => true,
+            (Type::Tuple(a), Type::Tuple(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.is_compatible_with(b))
+            }
+            (Type::Fn(p1, r1), Type::Fn(p2, r2)) => {
+                p1.len() == p2.len()
+                    && p1.iter().zip(p2).all(|(a, b)| a.is_compatible_with(b))
+                    && r1.is_compatible_with(r2)
+            }
+            // `*mut T` can be used where `*T` is expected, but not the other way around
+            (Type::Ptr(m1, a), Type::Ptr(m2, b)) => {
+                (m1 == m2 || *m2 == Mutability::Not) && a.is_compatible_with(b)
+            }
+            (Type::Array(a, n1), Type::Array(b, n2)) => n1 == n2 && a.is_compatible_with(b),
+            (Type::Slice(a), Type::Slice(b)) => a.is_compatible_with(b),
+            (a, b) => a == b,
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Prim(p) => write!(f, "{}", p.name()),
+            Type::Tuple(items) => {
+                write!(f, "(")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                if items.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Type::Adt(adt) => {
+                write!(f, "{}", adt.name)?;
+                if !adt.args.is_empty() {
+                    let args = adt.args.iter().map(|it| it.to_string()).collect::<Vec<_>>();
+                    write!(f, "<{}>", args.join(", "))?;
+                }
+                Ok(())
+            }
+            Type::Fn(params, ret) => {
+                write!(f, "fn(")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", param)?;
+                }
+                write!(f, ") -> {}", ret)
+            }
+            Type::Ptr(Mutability::Not, inner) => write!(f, "*{}", inner),
+            Type::Ptr(Mutability::Mut, inner) => write!(f, "*mut {}", inner),
+            Type::Array(elem, len) => write!(f, "[{}; {}]", elem, len),
+            Type::Slice(elem) => write!(f, "[{}]", elem),
+            Type::Param(param) => write!(f, "{}", param.name),
+            Type::Dyn(trait_ref) => write!(f, "dyn {}", trait_ref.name),
+            Type::Newtype(newtype) => write!(f, "{}", newtype.name),
+            Type::Error => write!(f, "{{error}}"),
+        }
+    }
+}
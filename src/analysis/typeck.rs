@@ -0,0 +1,3507 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use libsyntax::{HasMeta, HasSpan, NodeId, Span};
+
+use crate::{
+    ast::{
+        self, BinOp, ExprKind, Ident, ItemKind, Mutability, SourceFile, StmtKind, TyKind, UnOp,
+        VariantData,
+    },
+    diagnostics::Diagnostic,
+};
+
+use super::{
+    attrs::{check_attrs, check_item_attrs, AttrsResult},
+    const_eval::{self, ConstValue},
+    exhaustiveness::MatchCheckCtx,
+    resolve::{DefKind, Res, ResolveResult},
+    ty::{AdtRef, NewtypeRef, ParamTy, PrimTy, TraitRef, Type, MAX_TYPE_DEPTH, MAX_TYPE_SIZE},
+};
+
+/// The results of type checking a crate, or a function in it with [typeck_fn].
+/// What's declared by the items of the crate is shared by both.
+pub struct TypeckResult {
+    /// The type of every expression
+    pub expr_types: HashMap<NodeId, Type>,
+    /// The types of params, `let` statements and the bindings in patterns
+    pub local_types: HashMap<NodeId, Type>,
+    /// Struct and enum definitions, keyed by the id of their item
+    pub adts: Rc<HashMap<NodeId, AdtDef>>,
+    /// Maps the id of each enum variant to its enum and its index in the enum
+    pub variant_adts: Rc<HashMap<NodeId, (NodeId, usize)>>,
+    /// Signatures of functions and foreign functions, keyed by the id of their item
+    pub fn_sigs: Rc<HashMap<NodeId, FnSig>>,
+    /// Names of the type parameters of generic functions, structs and enums
+    pub generics: Rc<HashMap<NodeId, Vec<Ident>>>,
+    /// The type arguments of each use of a generic function or constructor, keyed
+    /// by the id of the path or struct expression
+    pub generic_args: HashMap<NodeId, Vec<Type>>,
+    /// Trait definitions, keyed by the id of their item
+    pub traits: Rc<HashMap<NodeId, TraitDef>>,
+    /// Impls of traits, keyed by the id of their item
+    pub impls: Rc<HashMap<NodeId, ImplDef>>,
+    /// Maps the id of each function declared in a trait to its trait
+    pub fn_traits: Rc<HashMap<NodeId, NodeId>>,
+    /// Maps the id of each function in an impl to its impl
+    pub fn_impls: Rc<HashMap<NodeId, NodeId>>,
+    /// The type that each impl is for, keyed by the id of the impl item
+    pub self_tys: Rc<HashMap<NodeId, Type>>,
+    /// The functions that method calls resolved to, keyed by the id of the call
+    pub method_calls: HashMap<NodeId, MethodCall>,
+    pub attrs: Rc<AttrsResult>,
+    /// The types of consts and statics
+    pub global_tys: Rc<HashMap<NodeId, Type>>,
+    /// The values of consts and the initial values of statics
+    pub const_values: Rc<HashMap<NodeId, ConstValue>>,
+    /// The type that each newtype wraps, keyed by the id of its item
+    pub newtypes: Rc<HashMap<NodeId, Type>>,
+    /// Arguments passed to the `...` of variadic functions whose type the
+    /// default argument promotions of C change, along with the promoted type
+    pub promoted_args: HashMap<NodeId, Type>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The rest of what [typeck_fn] reads
+    declared: Declared,
+}
+
+/// What the items of a crate declare that checking a function needs, besides
+/// the public fields of [TypeckResult]
+#[derive(Clone)]
+struct Declared {
+    ty_params: Rc<HashMap<NodeId, ParamTy>>,
+    bounds: Rc<HashMap<NodeId, Vec<Vec<NodeId>>>>,
+    statics: Rc<HashMap<NodeId, Mutability>>,
+    const_values: Rc<HashMap<NodeId, Option<ConstValue>>>,
+    alias_tys: Rc<HashMap<NodeId, Type>>,
+    written: Rc<WrittenTys>,
+}
+
+/// Types as written, to name the types of values in diagnostics
+#[derive(Default)]
+struct WrittenTys {
+    /// Types as the user wrote them, keyed by the id of the [ast::Ty], for the
+    /// types that mention aliases
+    tys: HashMap<NodeId, String>,
+    /// The ids of the types written for locals, params, consts and statics,
+    /// keyed by their ids
+    declared: HashMap<NodeId, NodeId>,
+    /// The ids of the param types and the return type written for functions
+    fns: HashMap<NodeId, (Vec<NodeId>, Option<NodeId>)>,
+}
+
+impl TypeckResult {
+    /// Type parameters of an item; Empty if the item isn't generic
+    pub fn generics_of(&self, id: NodeId) -> &[Ident] {
+        self.generics.get(&id).map_or(&[], |it| it.as_slice())
+    }
+
+    /// The impl of a trait for a type without type parameters, along with the
+    /// type arguments of the impl.
+    pub fn find_impl(&self, trait_id: NodeId, ty: &Type) -> Option<(NodeId, Vec<Type>)> {
+        find_impl(&self.impls, ty, trait_id, &|_, _| false)
+    }
+
+    /// The function in an impl that a call to a trait function calls when `Self`
+    /// is `self_ty`, along with the type arguments of the impl.
+    pub fn resolve_trait_fn(&self, trait_fn: NodeId, self_ty: &Type) -> (NodeId, Vec<Type>) {
+        let trait_id = self.fn_traits[&trait_fn];
+        let (impl_id, args) = self
+            .find_impl(trait_id, self_ty)
+            .expect("Trait bounds are checked before monomorphization");
+        let (name, _) = self.traits[&trait_id]
+            .fns
+            .iter()
+            .find(|(_, id)| *id == trait_fn)
+            .unwrap();
+        (self.impls[&impl_id].fns[name], args)
+    }
+
+    pub fn expr_type(&self, expr: &ast::Expr) -> &Type {
+        self.expr_types
+            .get(&expr.id())
+            .expect("Every expression should have been type checked")
+    }
+
+    /// Returns the ADT and variant index constructed by a struct or variant resolution
+    pub fn ctor(&self, res: Res) -> Option<(NodeId, usize)> {
+        ctor(&self.variant_adts, res)
+    }
+}
+
+pub(super) fn ctor(
+    variant_adts: &HashMap<NodeId, (NodeId, usize)>,
+    res: Res,
+) -> Option<(NodeId, usize)> {
+    match res {
+        Res::Def(DefKind::Struct, id) => Some((id, 0)),
+        Res::Def(DefKind::Variant, id) => Some(variant_adts[&id]),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariantKind {
+    Struct,
+    Tuple,
+    Unit,
+}
+impl VariantKind {
+    fn of(data: &VariantData) -> Self {
+        match data {
+            VariantData::Struct(_) => VariantKind::Struct,
+            VariantData::Tuple(_) => VariantKind::Tuple,
+            VariantData::Unit => VariantKind::Unit,
+        }
+    }
+}
+
+/// A struct or an enum. Structs are represented as an ADT with a single variant.
+#[derive(Debug, Clone)]
+pub struct AdtDef {
+    pub name: Ident,
+    pub is_enum: bool,
+    pub variants: Vec<VariantDef>,
+}
+impl AdtDef {
+    pub fn ty(&self, def: NodeId, args: Vec<Type>) -> Type {
+        Type::Adt(AdtRef {
+            def,
+            name: self.name.clone(),
+            args,
+        })
+    }
+
+    /// Name of a variant as the user would write it, e.g. `Option::Some` or `Point`
+    pub fn variant_path(&self, index: usize) -> String {
+        if self.is_enum {
+            format!("{}::{}", self.name, self.variants[index].name)
+        } else {
+            self.name.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VariantDef {
+    pub name: Ident,
+    pub kind: VariantKind,
+    pub fields: Vec<FieldInfo>,
+}
+impl VariantDef {
+    pub fn field(&self, name: &str) -> Option<&FieldInfo> {
+        self.fields.iter().find(|it| it.name == name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldInfo {
+    pub name: Ident,
+    pub ty: Type,
+    /// The id of the type as written, to name type aliases in diagnostics
+    pub ty_id: NodeId,
+}
+
+#[derive(Debug, Clone)]
+pub struct FnSig {
+    pub params: Vec<Type>,
+    pub ret: Type,
+    /// Whether more arguments than `params` can be passed, as with `printf`
+    pub variadic: bool,
+}
+impl FnSig {
+    pub fn ty(&self) -> Type {
+        Type::Fn(self.params.clone(), Box::new(self.ret.clone()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TraitDef {
+    pub name: Ident,
+    /// Functions in the order they're declared, which is also their order in vtables
+    pub fns: Vec<(Ident, NodeId)>,
+}
+
+/// `a.f(b)`, resolved to an inherent or trait function
+#[derive(Debug, Clone, Copy)]
+pub struct MethodCall {
+    pub def: NodeId,
+    pub receiver: Receiver,
+}
+
+/// How the receiver of a method call is passed as the first argument
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Receiver {
+    ByValue,
+    /// By address, for functions taking `*Self` or `*mut Self`
+    AddrOf,
+    /// By the address of a temporary holding the receiver, which isn't a place
+    AddrOfTemp,
+}
+
+/// `impl<T: Show> Show for Pair<T, T>`
+#[derive(Debug, Clone)]
+pub struct ImplDef {
+    pub trait_id: NodeId,
+    /// May mention the type parameters of the impl, which it must all use
+    pub self_ty: Type,
+    /// The traits that each type parameter of the impl is bound by
+    pub bounds: Vec<Vec<NodeId>>,
+    /// Functions by name
+    pub fns: HashMap<Ident, NodeId>,
+}
+
+pub fn typeck<'a>(source_file: &'a SourceFile, resolve: &'a ResolveResult) -> TypeckResult {
+    let mut attrs = check_attrs(source_file);
+    let diagnostics = std::mem::take(&mut attrs.diagnostics);
+    let mut typeck = Typeck::new(resolve, Rc::new(attrs));
+    typeck.diagnostics = diagnostics;
+    typeck.check_source_file(source_file);
+    let attrs = typeck.attrs.clone();
+    typeck.diagnostics.retain(|it| !attrs.is_allowed(it));
+    let const_values = typeck
+        .const_values
+        .iter()
+        .filter_map(|(id, value)| Some((*id, (*value)?)))
+        .collect();
+    let written = std::mem::take(&mut typeck.written);
+    typeck.crate_written = Rc::new(written);
+    typeck.into_result(Rc::new(const_values))
+}
+
+/// Type checks a function item against the items of the crate that
+/// `crate_result` is of, with `resolve` from [super::resolve::resolve_fn].
+/// Only the diagnostics in its body are kept, since the crate has the others.
+pub fn typeck_fn<'a>(
+    crate_result: &TypeckResult,
+    resolve: &'a ResolveResult,
+    item: &'a ast::Item,
+) -> TypeckResult {
+    let ItemKind::Fn(f) = &item.kind else {
+        unreachable!("Only functions are checked on their own")
+    };
+    let mut attrs = check_item_attrs(item);
+    // Lints allowed on the item or the items it's in are allowed in its body
+    let start = Span {
+        start: item.span().start,
+        end: item.span().start,
+    };
+    for (span, lint) in crate_result.attrs.allowed.iter() {
+        if span.contains(&start) {
+            attrs.allowed.push((*item.span(), lint.clone()));
+        }
+    }
+    let mut typeck = Typeck::within(crate_result, resolve);
+    typeck.diagnostics = std::mem::take(&mut attrs.diagnostics);
+    typeck.check_fn_body(item, f);
+    typeck.check_dyn_uses();
+    let body = f.body.as_ref().map_or(item.span(), |it| it.span());
+    typeck
+        .diagnostics
+        .retain(|it| body.contains(&it.span) && !attrs.is_allowed(it));
+    typeck.into_result(crate_result.const_values.clone())
+}
+
+struct Typeck<'a> {
+    resolve: &'a ResolveResult,
+    expr_types: HashMap<NodeId, Type>,
+    local_types: HashMap<NodeId, Type>,
+    adts: Rc<HashMap<NodeId, AdtDef>>,
+    variant_adts: Rc<HashMap<NodeId, (NodeId, usize)>>,
+    fn_sigs: Rc<HashMap<NodeId, FnSig>>,
+    generics: Rc<HashMap<NodeId, Vec<Ident>>>,
+    generic_args: HashMap<NodeId, Vec<Type>>,
+    /// Maps the id of each [ast::GenericParam] to the type it declares, and the
+    /// id of each trait to its `Self` type
+    ty_params: Rc<HashMap<NodeId, ParamTy>>,
+    traits: Rc<HashMap<NodeId, TraitDef>>,
+    impls: Rc<HashMap<NodeId, ImplDef>>,
+    fn_traits: Rc<HashMap<NodeId, NodeId>>,
+    fn_impls: Rc<HashMap<NodeId, NodeId>>,
+    /// The traits that each type parameter of a generic item is bound by
+    bounds: Rc<HashMap<NodeId, Vec<Vec<NodeId>>>>,
+    /// The type that `Self` refers to in each impl
+    self_tys: Rc<HashMap<NodeId, Type>>,
+    method_calls: HashMap<NodeId, MethodCall>,
+    attrs: Rc<AttrsResult>,
+    global_tys: Rc<HashMap<NodeId, Type>>,
+    /// Const and static items by id
+    globals: HashMap<NodeId, &'a ast::Item>,
+    /// Whether each static is `static mut`
+    statics: Rc<HashMap<NodeId, Mutability>>,
+    /// The values of the consts and statics evaluated so far; `None` if the
+    /// evaluation failed
+    const_values: Rc<HashMap<NodeId, Option<ConstValue>>>,
+    /// Consts being evaluated, to detect consts whose values depend on themselves
+    evaluating: Vec<NodeId>,
+    /// Array lengths by the id of the length expression, since some types are
+    /// lowered more than once
+    array_lens: HashMap<NodeId, Option<u64>>,
+    /// Type alias items by id
+    aliases: HashMap<NodeId, &'a ast::Item>,
+    /// The types that the aliases expanded so far stand for
+    alias_tys: Rc<HashMap<NodeId, Type>>,
+    /// Aliases being expanded, to detect aliases that refer to themselves
+    expanding: Vec<NodeId>,
+    newtypes: Rc<HashMap<NodeId, Type>>,
+    written: WrittenTys,
+    /// Types written in the rest of the crate, when checking a function on its own
+    crate_written: Rc<WrittenTys>,
+    promoted_args: HashMap<NodeId, Type>,
+    /// Uses of deprecated items that have been reported, since some types are
+    /// lowered more than once
+    deprecated_uses: HashSet<NodeId>,
+    /// Bounds of the type parameters of the function being checked
+    current_bounds: Vec<Vec<NodeId>>,
+    /// Uses of `dyn Trait`, which are checked once all traits are collected
+    dyn_uses: Vec<(Span, NodeId)>,
+    diagnostics: Vec<Diagnostic>,
+    /// Whether we're inside an `unsafe` block
+    in_unsafe: bool,
+}
+
+impl<'a> Typeck<'a> {
+    fn new(resolve: &'a ResolveResult, attrs: Rc<AttrsResult>) -> Self {
+        Typeck {
+            resolve,
+            expr_types: HashMap::new(),
+            local_types: HashMap::new(),
+            adts: Rc::default(),
+            variant_adts: Rc::default(),
+            fn_sigs: Rc::default(),
+            generics: Rc::default(),
+            generic_args: HashMap::new(),
+            ty_params: Rc::default(),
+            traits: Rc::default(),
+            impls: Rc::default(),
+            fn_traits: Rc::default(),
+            fn_impls: Rc::default(),
+            bounds: Rc::default(),
+            self_tys: Rc::default(),
+            method_calls: HashMap::new(),
+            attrs,
+            global_tys: Rc::default(),
+            globals: HashMap::new(),
+            statics: Rc::default(),
+            const_values: Rc::default(),
+            evaluating: vec![],
+            array_lens: HashMap::new(),
+            aliases: HashMap::new(),
+            alias_tys: Rc::default(),
+            expanding: vec![],
+            newtypes: Rc::default(),
+            written: WrittenTys::default(),
+            crate_written: Rc::default(),
+            promoted_args: HashMap::new(),
+            deprecated_uses: HashSet::new(),
+            current_bounds: vec![],
+            dyn_uses: vec![],
+            diagnostics: vec![],
+            in_unsafe: false,
+        }
+    }
+
+    /// Starts checking a function with what the items of a crate declare
+    fn within(crate_result: &TypeckResult, resolve: &'a ResolveResult) -> Self {
+        let declared = crate_result.declared.clone();
+        Typeck {
+            adts: crate_result.adts.clone(),
+            variant_adts: crate_result.variant_adts.clone(),
+            fn_sigs: crate_result.fn_sigs.clone(),
+            generics: crate_result.generics.clone(),
+            ty_params: declared.ty_params,
+            traits: crate_result.traits.clone(),
+            impls: crate_result.impls.clone(),
+            fn_traits: crate_result.fn_traits.clone(),
+            fn_impls: crate_result.fn_impls.clone(),
+            bounds: declared.bounds,
+            self_tys: crate_result.self_tys.clone(),
+            global_tys: crate_result.global_tys.clone(),
+            statics: declared.statics,
+            const_values: declared.const_values,
+            alias_tys: declared.alias_tys,
+            newtypes: crate_result.newtypes.clone(),
+            crate_written: declared.written,
+            ..Typeck::new(resolve, crate_result.attrs.clone())
+        }
+    }
+
+    fn into_result(self, const_values: Rc<HashMap<NodeId, ConstValue>>) -> TypeckResult {
+        TypeckResult {
+            expr_types: self.expr_types,
+            local_types: self.local_types,
+            adts: self.adts,
+            variant_adts: self.variant_adts,
+            fn_sigs: self.fn_sigs,
+            generics: self.generics,
+            generic_args: self.generic_args,
+            traits: self.traits,
+            impls: self.impls,
+            fn_traits: self.fn_traits,
+            fn_impls: self.fn_impls,
+            self_tys: self.self_tys,
+            method_calls: self.method_calls,
+            attrs: self.attrs,
+            global_tys: self.global_tys,
+            const_values,
+            newtypes: self.newtypes,
+            promoted_args: self.promoted_args,
+            diagnostics: self.diagnostics,
+            declared: Declared {
+                ty_params: self.ty_params,
+                bounds: self.bounds,
+                statics: self.statics,
+                const_values: self.const_values,
+                alias_tys: self.alias_tys,
+                written: self.crate_written,
+            },
+        }
+    }
+
+    fn check_source_file(&mut self, source_file: &'a SourceFile) {
+        for item in source_file.all_items() {
+            self.declare_generics(item);
+        }
+        // Array lengths in the types collected below may refer to consts
+        for item in source_file.all_items() {
+            match &item.kind {
+                ItemKind::Const(_) => {
+                    self.globals.insert(item.id(), item);
+                }
+                ItemKind::Static(s) => {
+                    self.globals.insert(item.id(), item);
+                    Rc::make_mut(&mut self.statics).insert(item.id(), s.mutability);
+                }
+                ItemKind::TyAlias(_) => {
+                    self.aliases.insert(item.id(), item);
+                }
+                _ => {}
+            }
+        }
+        for item in source_file.all_items() {
+            self.collect_global(item);
+        }
+        for item in source_file.all_items() {
+            self.collect_item(item);
+        }
+        for item in source_file.all_items() {
+            if let ItemKind::Newtype(_) = &item.kind {
+                self.check_newtype_is_sized(item);
+            }
+        }
+        for item in source_file.all_items() {
+            if let ItemKind::Struct(_) | ItemKind::Enum(_) = &item.kind {
+                self.check_adt_is_sized(item);
+            }
+        }
+        for item in source_file.all_items() {
+            if let ItemKind::Impl(imp) = &item.kind {
+                self.check_impl(item, imp);
+            }
+        }
+        for item in source_file.all_items() {
+            match &item.kind {
+                ItemKind::Const(_) => {
+                    self.const_value(item.id());
+                }
+                ItemKind::Static(s) => {
+                    let ty = self.global_tys[&item.id()].clone();
+                    let value = self.eval_const(&s.expr, &ty, Some(s.ty.id()));
+                    Rc::make_mut(&mut self.const_values).insert(item.id(), value);
+                }
+                _ => {}
+            }
+        }
+        for (item, f) in source_file.fns() {
+            self.check_fn_body(item, f);
+        }
+        self.check_dyn_uses();
+    }
+
+    /// Records the type parameters of each item, so that types can be checked
+    /// for the right number of type arguments before the items are collected.
+    fn declare_generics(&mut self, item: &ast::Item) {
+        match &item.kind {
+            ItemKind::Fn(f) => self.declare_params(item.id(), &f.generics),
+            ItemKind::Struct(ast::Struct { generics, .. })
+            | ItemKind::Enum(ast::Enum { generics, .. })
+            | ItemKind::TyAlias(ast::TyAlias { generics, .. }) => {
+                if let Some(param) = generics.iter().find(|it| !it.bounds.is_empty()) {
+                    self.error(
+                        param.span(),
+                        "Trait bounds are only allowed on functions and impls".to_string(),
+                    );
+                }
+                self.declare_params(item.id(), generics);
+            }
+            // Functions in a trait are generic over `Self`
+            ItemKind::Trait(t) => {
+                let name = "Self".to_string();
+                let param = ParamTy {
+                    index: 0,
+                    name: name.clone(),
+                };
+                Rc::make_mut(&mut self.ty_params).insert(item.id(), param);
+                for trait_fn in t.items.iter() {
+                    self.check_not_generic(trait_fn);
+                    Rc::make_mut(&mut self.generics).insert(trait_fn.id(), vec![name.clone()]);
+                    Rc::make_mut(&mut self.bounds).insert(trait_fn.id(), vec![vec![item.id()]]);
+                    Rc::make_mut(&mut self.fn_traits).insert(trait_fn.id(), item.id());
+                }
+            }
+            // Functions in an impl are instantiated with the type arguments of the impl
+            ItemKind::Impl(imp) => {
+                self.declare_params(item.id(), &imp.generics);
+                for impl_fn in imp.items.iter() {
+                    self.check_not_generic(impl_fn);
+                    if let Some(names) = self.generics.get(&item.id()).cloned() {
+                        Rc::make_mut(&mut self.generics).insert(impl_fn.id(), names);
+                        let bounds = self.bounds[&item.id()].clone();
+                        Rc::make_mut(&mut self.bounds).insert(impl_fn.id(), bounds);
+                    }
+                    Rc::make_mut(&mut self.fn_impls).insert(impl_fn.id(), item.id());
+                }
+            }
+            ItemKind::ForeignMod(_)
+            | ItemKind::Mod(_)
+            | ItemKind::Use(_)
+            | ItemKind::Const(_)
+            | ItemKind::Static(_)
+            | ItemKind::Newtype(_) => {}
+        }
+    }
+
+    fn declare_params(&mut self, id: NodeId, generics: &[ast::GenericParam]) {
+        if generics.is_empty() {
+            return;
+        }
+        for (index, param) in generics.iter().enumerate() {
+            let name = param.name.clone();
+            Rc::make_mut(&mut self.ty_params).insert(param.id(), ParamTy { index, name });
+        }
+        let names = generics.iter().map(|it| it.name.clone()).collect();
+        Rc::make_mut(&mut self.generics).insert(id, names);
+        let bounds = generics
+            .iter()
+            .map(|param| {
+                param
+                    .bounds
+                    .iter()
+                    .filter_map(|it| self.trait_id(it))
+                    .collect()
+            })
+            .collect();
+        Rc::make_mut(&mut self.bounds).insert(id, bounds);
+    }
+
+    fn check_not_generic(&mut self, item: &ast::Item) {
+        if let ItemKind::Fn(f) = &item.kind {
+            if !f.generics.is_empty() {
+                self.error(
+                    item.span(),
+                    "Functions in traits and impls cannot be generic".to_string(),
+                );
+            }
+        }
+    }
+
+    /// The trait that a trait reference resolved to; Resolution errors have
+    /// already been reported.
+    fn trait_id(&mut self, trait_ref: &ast::TraitRef) -> Option<NodeId> {
+        match self.resolve.get(trait_ref.id()) {
+            Res::Def(DefKind::Trait, id) => {
+                self.check_deprecated(trait_ref.id(), trait_ref.span(), id);
+                Some(id)
+            }
+            _ => None,
+        }
+    }
+
+    /// Warns about a use of an item marked `#[deprecated]`
+    fn check_deprecated(&mut self, use_id: NodeId, span: &Span, def: NodeId) {
+        let Some(note) = self.attrs.deprecated.get(&def) else {
+            return;
+        };
+        if !self.deprecated_uses.insert(use_id) {
+            return;
+        }
+        let name = self.resolve.def_path(def);
+        let message = match note {
+            Some(note) => format!("`{}` is deprecated: {}", name, note),
+            None => format!("`{}` is deprecated", name),
+        };
+        self.diagnostics
+            .push(Diagnostic::lint(*span, "deprecated", message));
+    }
+
+    fn generics_of(&self, id: NodeId) -> &[Ident] {
+        self.generics.get(&id).map_or(&[], |it| it.as_slice())
+    }
+
+    /// Records the signatures of functions and the fields of structs so that
+    /// function bodies can refer to items declared after them.
+    fn collect_item(&mut self, item: &'a ast::Item) {
+        match &item.kind {
+            ItemKind::Fn(f) => {
+                if self.resolve.def_path(item.id()) == "main" && !f.generics.is_empty() {
+                    self.error(item.span(), "`main` cannot be generic".to_string());
+                }
+                self.check_not_variadic(item.span(), f);
+                let sig = self.fn_sig(f);
+                Rc::make_mut(&mut self.fn_sigs).insert(item.id(), sig);
+                self.declare_written_fn(item.id(), f);
+            }
+            ItemKind::ForeignMod(foreign_mod) => {
+                if let Some(abi) = foreign_mod.abi.as_ref().filter(|it| *it != "C") {
+                    let message = format!("Unknown ABI `{}`; Only \"C\" is supported", abi);
+                    self.error(item.span(), message);
+                }
+                for foreign_item in foreign_mod.items.iter() {
+                    match &foreign_item.kind {
+                        ast::ForeignItemKind::Fn(f) => {
+                            if !f.generics.is_empty() {
+                                self.error(
+                                    foreign_item.span(),
+                                    "Foreign functions cannot be generic".to_string(),
+                                );
+                            }
+                            if f.variadic && f.params.is_empty() {
+                                self.error(
+                                    foreign_item.span(),
+                                    "Variadic functions need a parameter before `...`".to_string(),
+                                );
+                            }
+                            let sig = self.fn_sig(f);
+                            Rc::make_mut(&mut self.fn_sigs).insert(foreign_item.id(), sig);
+                            self.declare_written_fn(foreign_item.id(), f);
+                        }
+                    }
+                }
+            }
+            ItemKind::Struct(s) => {
+                let variant = self.variant_def(&item.name, &s.data);
+                Rc::make_mut(&mut self.adts).insert(
+                    item.id(),
+                    AdtDef {
+                        name: self.resolve.def_path(item.id()).to_string(),
+                        is_enum: false,
+                        variants: vec![variant],
+                    },
+                );
+            }
+            ItemKind::Enum(e) => {
+                let mut variants = vec![];
+                for (index, variant) in e.variants.iter().enumerate() {
+                    variants.push(self.variant_def(&variant.name, &variant.data));
+                    Rc::make_mut(&mut self.variant_adts).insert(variant.id(), (item.id(), index));
+                }
+                Rc::make_mut(&mut self.adts).insert(
+                    item.id(),
+                    AdtDef {
+                        name: self.resolve.def_path(item.id()).to_string(),
+                        is_enum: true,
+                        variants,
+                    },
+                );
+            }
+            ItemKind::Trait(t) => {
+                let mut fns = vec![];
+                for trait_fn in t.items.iter() {
+                    let ItemKind::Fn(f) = &trait_fn.kind else {
+                        unreachable!("Traits only contain functions")
+                    };
+                    if f.body.is_some() {
+                        self.error(
+                            trait_fn.span(),
+                            "Functions in traits cannot have a body".to_string(),
+                        );
+                    }
+                    self.check_not_variadic(trait_fn.span(), f);
+                    let sig = self.fn_sig(f);
+                    Rc::make_mut(&mut self.fn_sigs).insert(trait_fn.id(), sig);
+                    fns.push((trait_fn.name.clone(), trait_fn.id()));
+                }
+                let name = self.resolve.def_path(item.id()).to_string();
+                Rc::make_mut(&mut self.traits).insert(item.id(), TraitDef { name, fns });
+            }
+            ItemKind::Impl(imp) => {
+                let self_ty = self.lower_ty(&imp.self_ty);
+                Rc::make_mut(&mut self.self_tys).insert(item.id(), self_ty);
+                for impl_fn in imp.items.iter() {
+                    let ItemKind::Fn(f) = &impl_fn.kind else {
+                        unreachable!("Impls only contain functions")
+                    };
+                    if f.body.is_none() {
+                        self.error(
+                            impl_fn.span(),
+                            "Functions in impls must have a body".to_string(),
+                        );
+                    }
+                    self.check_not_variadic(impl_fn.span(), f);
+                    let sig = self.fn_sig(f);
+                    Rc::make_mut(&mut self.fn_sigs).insert(impl_fn.id(), sig);
+                    self.declare_written_fn(impl_fn.id(), f);
+                }
+            }
+            // Unused aliases are expanded here to report their errors
+            ItemKind::TyAlias(_) => {
+                self.alias_ty(item.id());
+            }
+            ItemKind::Newtype(n) => {
+                let mut inner = self.lower_ty(&n.ty);
+                if inner == Type::unit() {
+                    self.error(n.ty.span(), "Newtypes cannot wrap `()`".to_string());
+                    inner = Type::Error;
+                }
+                Rc::make_mut(&mut self.newtypes).insert(item.id(), inner);
+            }
+            ItemKind::Mod(_) | ItemKind::Use(_) | ItemKind::Const(_) | ItemKind::Static(_) => {}
+        }
+    }
+
+    /// The type that an alias stands for, in terms of the alias's own type
+    /// parameters. Expands the alias if it hasn't been expanded yet.
+    fn alias_ty(&mut self, id: NodeId) -> Type {
+        if let Some(ty) = self.alias_tys.get(&id) {
+            return ty.clone();
+        }
+        let item = self.aliases[&id];
+        if self.expanding.contains(&id) {
+            self.error(
+                item.span(),
+                format!("Type alias `{}` refers to itself", item.name),
+            );
+            Rc::make_mut(&mut self.alias_tys).insert(id, Type::Error);
+            return Type::Error;
+        }
+        let ItemKind::TyAlias(alias) = &item.kind else {
+            unreachable!("Only aliases are expanded")
+        };
+        self.expanding.push(id);
+        let ty = self.lower_ty(&alias.ty);
+        self.expanding.pop();
+        // A cycle through this alias was reported while expanding it
+        Rc::make_mut(&mut self.alias_tys)
+            .entry(id)
+            .or_insert(ty)
+            .clone()
+    }
+
+    /// Records the type of a const or static, which is restricted to the types
+    /// that constant expressions can evaluate to
+    fn collect_global(&mut self, item: &ast::Item) {
+        if self.global_tys.contains_key(&item.id()) {
+            return;
+        }
+        let ty = match &item.kind {
+            ItemKind::Const(ast::Const { ty, .. }) | ItemKind::Static(ast::Static { ty, .. }) => ty,
+            _ => return,
+        };
+        // Other types aren't lowered, since array lengths may refer to consts
+        // that haven't been collected yet
+        let lowered = match &ty.kind {
+            TyKind::Var(..) | TyKind::Path(..) => Some(self.lower_ty(ty)),
+            _ => None,
+        };
+        let lowered = match lowered {
+            Some(lowered @ (Type::Prim(_) | Type::Error)) => lowered,
+            _ => {
+                self.error(
+                    ty.span(),
+                    "Consts and statics must be integers or `bool`".to_string(),
+                );
+                Type::Error
+            }
+        };
+        Rc::make_mut(&mut self.global_tys).insert(item.id(), lowered);
+        self.written.declared.insert(item.id(), ty.id());
+    }
+
+    /// The value of a const, evaluating it and the consts it refers to if they
+    /// haven't been evaluated yet. Returns `None` if there were errors.
+    fn const_value(&mut self, id: NodeId) -> Option<ConstValue> {
+        if let Some(value) = self.const_values.get(&id) {
+            return *value;
+        }
+        let item = self.globals[&id];
+        // An alias in the type of a const being collected may refer to this one
+        self.collect_global(item);
+        // Consts with types other than integers and `bool` are reported when
+        // their type is collected
+        let ty = self.global_tys[&id].clone();
+        if self.evaluating.contains(&id) {
+            self.error(
+                item.span(),
+                format!("The value of `{}` depends on itself", item.name),
+            );
+            Rc::make_mut(&mut self.const_values).insert(id, None);
+            return None;
+        }
+        let ItemKind::Const(c) = &item.kind else {
+            unreachable!("Only consts are referred to by constant expressions")
+        };
+        self.evaluating.push(id);
+        let value = self.eval_const(&c.expr, &ty, Some(c.ty.id()));
+        self.evaluating.pop();
+        // A cycle through this const was reported while evaluating it
+        if let Some(None) = self.const_values.get(&id) {
+            return None;
+        }
+        Rc::make_mut(&mut self.const_values).insert(id, value);
+        value
+    }
+
+    /// Type checks and evaluates a constant expression, reporting errors
+    fn eval_const(
+        &mut self,
+        expr: &ast::Expr,
+        expected: &Type,
+        ty_id: Option<NodeId>,
+    ) -> Option<ConstValue> {
+        let deps = match const_eval::const_deps(expr, self.resolve) {
+            Ok(deps) => deps,
+            Err(diagnostic) => {
+                self.diagnostics.push(diagnostic);
+                return None;
+            }
+        };
+        let errors = self.error_count();
+        let ty = self.check_expr(expr, Some(expected));
+        self.expect_declared(expr, &ty, expected, ty_id);
+        if self.error_count() > errors
+            || *expected == Type::Error
+            || const_eval::has_errors(expr, self.resolve, &self.expr_types)
+        {
+            return None;
+        }
+        let mut values = HashMap::new();
+        for dep in deps {
+            values.insert(dep, self.const_value(dep)?);
+        }
+        match const_eval::eval(expr, self.resolve, &self.expr_types, &values) {
+            Ok(value) => Some(value),
+            Err(diagnostic) => {
+                self.diagnostics.push(diagnostic);
+                None
+            }
+        }
+    }
+
+    fn error_count(&self) -> usize {
+        self.diagnostics.iter().filter(|it| it.is_error()).count()
+    }
+
+    /// Checks that an impl uses all of its type parameters, and for trait impls
+    /// that it defines the functions of its trait with the right signatures, and
+    /// that no other impl of the trait applies to the same types.
+    fn check_impl(&mut self, item: &ast::Item, imp: &ast::Impl) {
+        let self_ty = self.self_tys[&item.id()].clone();
+        let trait_id = imp.trait_ref.as_ref().and_then(|it| self.trait_id(it));
+        if let (Type::Param(param), Some(trait_id)) = (&self_ty, trait_id) {
+            let trait_name = &self.traits[&trait_id].name;
+            self.error(
+                imp.self_ty.span(),
+                format!(
+                    "Cannot implement `{}` for every type `{}`",
+                    trait_name, param.name
+                ),
+            );
+            return;
+        }
+        // Type arguments of the impl are inferred from the self type
+        let generics = self.generics_of(item.id()).to_vec();
+        for (index, name) in generics.iter().enumerate() {
+            if !self_ty.any(&|it| matches!(it, Type::Param(p) if p.index == index)) {
+                self.error(
+                    item.span(),
+                    format!("Type parameter `{}` must be used in `{}`", name, self_ty),
+                );
+                return;
+            }
+        }
+        let Some(trait_id) = trait_id else {
+            return;
+        };
+        let trait_name = self.traits[&trait_id].name.clone();
+        let mut fns = HashMap::new();
+        for impl_fn in imp.items.iter() {
+            let trait_fn = self.traits[&trait_id]
+                .fns
+                .iter()
+                .find(|(name, _)| *name == impl_fn.name)
+                .map(|(_, id)| *id);
+            let Some(trait_fn) = trait_fn else {
+                self.error(
+                    impl_fn.span(),
+                    format!(
+                        "`{}` is not a function of trait `{}`",
+                        impl_fn.name, trait_name
+                    ),
+                );
+                continue;
+            };
+            let expected = self.fn_sigs[&trait_fn]
+                .ty()
+                .subst(std::slice::from_ref(&self_ty));
+            let actual = self.fn_sigs[&impl_fn.id()].ty();
+            let has_errors =
+                expected.any(&|it| *it == Type::Error) || actual.any(&|it| *it == Type::Error);
+            if actual != expected && !has_errors {
+                self.error(
+                    impl_fn.span(),
+                    format!(
+                        "Signature of `{}` doesn't match trait `{}`: expected `{}`, found `{}`",
+                        impl_fn.name, trait_name, expected, actual
+                    ),
+                );
+            }
+            if fns.insert(impl_fn.name.clone(), impl_fn.id()).is_some() {
+                self.error(
+                    impl_fn.span(),
+                    format!("`{}` is defined multiple times", impl_fn.name),
+                );
+            }
+        }
+        let missing = self.traits[&trait_id]
+            .fns
+            .iter()
+            .filter(|(name, _)| !fns.contains_key(name))
+            .map(|(name, _)| format!("`{}`", name))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            self.error(
+                item.span(),
+                format!(
+                    "Missing functions in impl of `{}` for `{}`: {}",
+                    trait_name,
+                    self_ty,
+                    missing.join(", ")
+                ),
+            );
+        }
+        for other in self.impls.values().filter(|it| it.trait_id == trait_id) {
+            // Type parameters of this impl are numbered after those of the other one
+            let shifted = generics
+                .iter()
+                .enumerate()
+                .map(|(index, name)| {
+                    Type::Param(ParamTy {
+                        index: other.bounds.len() + index,
+                        name: name.clone(),
+                    })
+                })
+                .collect::<Vec<_>>();
+            if unify(
+                &other.self_ty,
+                &self_ty.subst(&shifted),
+                &mut HashMap::new(),
+            ) {
+                self.error(
+                    item.span(),
+                    format!(
+                        "Conflicting implementations of `{}` for `{}`",
+                        trait_name, self_ty
+                    ),
+                );
+                return;
+            }
+        }
+        let bounds = self.bounds.get(&item.id()).cloned().unwrap_or_default();
+        Rc::make_mut(&mut self.impls).insert(
+            item.id(),
+            ImplDef {
+                trait_id,
+                self_ty,
+                bounds,
+                fns,
+            },
+        );
+    }
+
+    /// `dyn Trait` calls the functions of the trait through a vtable, so they must
+    /// take `Self` behind a pointer as their first parameter and not use it elsewhere.
+    fn check_dyn_uses(&mut self) {
+        let dyn_uses = std::mem::take(&mut self.dyn_uses);
+        for (span, trait_id) in dyn_uses {
+            let trait_def = &self.traits[&trait_id];
+            // `Self` is the only type parameter in the signatures of trait functions
+            let is_self = |ty: &Type| matches!(ty, Type::Param(_));
+            let unsupported = trait_def.fns.iter().find(|(_, id)| {
+                let sig = &self.fn_sigs[id];
+                let takes_self = matches!(
+                    sig.params.first(),
+                    Some(Type::Ptr(_, pointee)) if is_self(pointee)
+                );
+                !takes_self
+                    || sig.params[1..]
+                        .iter()
+                        .chain([&sig.ret])
+                        .any(|it| it.any(&is_self))
+            });
+            if let Some((name, _)) = unsupported {
+                let message = format!(
+                    "`dyn {}` is not allowed because `{}` must take `*Self` or `*mut Self` as \
+                     its first parameter and not use `Self` elsewhere",
+                    trait_def.name, name
+                );
+                self.error(&span, message);
+            }
+        }
+    }
+
+    fn implements(&self, ty: &Type, trait_id: NodeId) -> bool {
+        let param_impls = |param: &ParamTy, trait_id: NodeId| {
+            self.current_bounds
+                .get(param.index)
+                .is_some_and(|it| it.contains(&trait_id))
+        };
+        implements(&self.impls, ty, trait_id, &param_impls)
+    }
+
+    fn expect_impl(&mut self, ty: &Type, trait_id: NodeId, span: &Span) {
+        if !self.implements(ty, trait_id) {
+            let message = format!(
+                "Type `{}` does not implement `{}`",
+                ty, self.traits[&trait_id].name
+            );
+            self.error(span, message);
+        }
+    }
+
+    fn variant_def(&mut self, name: &Ident, data: &VariantData) -> VariantDef {
+        let mut seen = HashSet::new();
+        let mut fields = vec![];
+        for field in data.fields() {
+            if !seen.insert(field.name.as_str()) {
+                self.error(
+                    field.span(),
+                    format!("Field `{}` is already declared", field.name),
+                );
+            }
+            fields.push(FieldInfo {
+                name: field.name.clone(),
+                ty: self.lower_ty(&field.ty),
+                ty_id: field.ty.id(),
+            });
+        }
+        VariantDef {
+            name: name.clone(),
+            kind: VariantKind::of(data),
+            fields,
+        }
+    }
+
+    fn check_not_variadic(&mut self, span: &Span, f: &ast::Fn) {
+        if f.variadic {
+            let message = "Only foreign functions can be variadic".to_string();
+            self.error(span, message);
+        }
+    }
+
+    fn fn_sig(&mut self, f: &ast::Fn) -> FnSig {
+        let params = f
+            .params
+            .iter()
+            .map(|param| self.lower_ty(&param.ty))
+            .collect();
+        let ret = f
+            .return_ty
+            .as_ref()
+            .map(|ty| self.lower_ty(ty))
+            .unwrap_or_else(Type::unit);
+        FnSig {
+            params,
+            ret,
+            variadic: f.variadic,
+        }
+    }
+
+    /// Structs and enums are lowered to C structs, so a type that contains itself
+    /// by value would have an infinite size.
+    fn check_adt_is_sized(&mut self, item: &ast::Item) {
+        let args = identity_args(self.generics_of(item.id()));
+        let mut stack = vec![AdtRef {
+            def: item.id(),
+            name: item.name.clone(),
+            args,
+        }];
+        let mut visited = HashSet::new();
+        while let Some(adt_ref) = stack.pop() {
+            if !visited.insert(adt_ref.clone()) {
+                continue;
+            }
+            let field_tys = self.adts[&adt_ref.def]
+                .variants
+                .iter()
+                .flat_map(|it| it.fields.iter())
+                .map(|it| it.ty.subst(&adt_ref.args))
+                .collect::<Vec<_>>();
+            for field_ty in field_tys {
+                let mut contained = vec![];
+                collect_adts_by_value(&field_ty, &self.newtypes, &mut contained);
+                for adt in contained {
+                    if adt.def == item.id() {
+                        self.error(
+                            item.span(),
+                            format!("Recursive type `{}` has infinite size", item.name),
+                        );
+                        return;
+                    }
+                    // Polymorphic recursion is reported on the type that recurses
+                    let ty = Type::Adt(adt.clone());
+                    if ty.depth() <= MAX_TYPE_DEPTH && ty.size() <= MAX_TYPE_SIZE {
+                        stack.push(adt);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Newtypes are represented by the type they wrap, so a newtype that wraps
+    /// itself by value would have an infinite size.
+    fn check_newtype_is_sized(&mut self, item: &ast::Item) {
+        let mut stack = vec![self.newtypes[&item.id()].clone()];
+        let mut visited = HashSet::new();
+        while let Some(ty) = stack.pop() {
+            match ty {
+                Type::Newtype(newtype) if newtype.def == item.id() => {
+                    self.error(
+                        item.span(),
+                        format!("Recursive type `{}` has infinite size", item.name),
+                    );
+                    // Later passes look through newtypes, which must terminate
+                    Rc::make_mut(&mut self.newtypes).insert(item.id(), Type::Error);
+                    return;
+                }
+                Type::Newtype(newtype) if visited.insert(newtype.def) => {
+                    stack.push(self.newtypes[&newtype.def].clone());
+                }
+                Type::Tuple(items) => stack.extend(items),
+                Type::Array(elem, _) => stack.push(*elem),
+                _ => {}
+            }
+        }
+    }
+
+    fn check_fn_body(&mut self, item: &ast::Item, f: &ast::Fn) {
+        let sig = self.fn_sigs[&item.id()].clone();
+        self.current_bounds = self.bounds.get(&item.id()).cloned().unwrap_or_default();
+        for (param, ty) in f.params.iter().zip(sig.params.iter()) {
+            self.local_types.insert(param.id(), ty.clone());
+            self.written.declared.insert(param.id(), param.ty.id());
+        }
+        if let Some(body) = &f.body {
+            let ty = self.check_expr(body, Some(&sig.ret));
+            let ret_id = f.return_ty.as_ref().map(|it| it.id());
+            self.expect_declared(body, &ty, &sig.ret, ret_id);
+        }
+    }
+
+    fn lower_ty(&mut self, ty: &ast::Ty) -> Type {
+        let lowered = self.lower_ty_kind(ty);
+        if self.mentions_alias(ty) {
+            self.written.tys.insert(ty.id(), ty.to_string());
+        }
+        lowered
+    }
+
+    fn mentions_alias(&self, ty: &ast::Ty) -> bool {
+        match &ty.kind {
+            TyKind::Var(_, args) | TyKind::Path(_, args) => {
+                matches!(self.resolve.get(ty.id()), Res::Def(DefKind::TyAlias, _))
+                    || args.iter().any(|it| self.mentions_alias(it))
+            }
+            TyKind::Tup(items) => items.iter().any(|it| self.mentions_alias(it)),
+            TyKind::Ptr(_, inner) | TyKind::Array(inner, _) | TyKind::Slice(inner) => {
+                self.mentions_alias(inner)
+            }
+            TyKind::Dyn(_) => false,
+        }
+    }
+
+    fn lower_ty_kind(&mut self, ty: &ast::Ty) -> Type {
+        match &ty.kind {
+            TyKind::Tup(items) => Type::Tuple(items.iter().map(|it| self.lower_ty(it)).collect()),
+            TyKind::Var(name, args) => self.lower_named_ty(ty, name, args),
+            TyKind::Path(path, args) => self.lower_named_ty(ty, &path.to_string(), args),
+            TyKind::Ptr(mutability, inner) => {
+                let inner = match &inner.kind {
+                    TyKind::Dyn(trait_ref) => match self.trait_id(trait_ref) {
+                        Some(def) => {
+                            self.dyn_uses.push((*inner.span(), def));
+                            let name = self.resolve.def_path(def).to_string();
+                            Type::Dyn(TraitRef { def, name })
+                        }
+                        None => Type::Error,
+                    },
+                    _ => self.lower_ty(inner),
+                };
+                Type::Ptr(*mutability, Box::new(inner))
+            }
+            TyKind::Array(elem, len) => {
+                let elem = self.lower_ty(elem);
+                match self.array_len(len) {
+                    Some(len) => Type::Array(Box::new(elem), len),
+                    None => Type::Error,
+                }
+            }
+            TyKind::Slice(elem) => Type::Slice(Box::new(self.lower_ty(elem))),
+            TyKind::Dyn(trait_ref) => {
+                self.error(
+                    ty.span(),
+                    format!("`dyn {}` must be behind a pointer", trait_ref.path),
+                );
+                Type::Error
+            }
+        }
+    }
+
+    /// `Point`, `T` or `shapes::Pair<i32, T>`
+    fn lower_named_ty(&mut self, ty: &ast::Ty, name: &str, args: &[ast::Ty]) -> Type {
+        let res = self.resolve.get(ty.id());
+        if let Res::Def(_, def) = res {
+            self.check_deprecated(ty.id(), ty.span(), def);
+        }
+        let args = args.iter().map(|it| self.lower_ty(it)).collect::<Vec<_>>();
+        let expected_args = match res {
+            Res::Def(DefKind::Struct | DefKind::Enum | DefKind::TyAlias, def) => {
+                self.generics_of(def).len()
+            }
+            _ => 0,
+        };
+        if args.len() != expected_args && res != Res::Err {
+            self.error(
+                ty.span(),
+                format!(
+                    "`{}` expects {} type arguments, found {}",
+                    name,
+                    expected_args,
+                    args.len()
+                ),
+            );
+            return Type::Error;
+        }
+        match res {
+            Res::PrimTy(p) => Type::Prim(p),
+            Res::Def(DefKind::Struct | DefKind::Enum, def) => Type::Adt(AdtRef {
+                def,
+                name: self.resolve.def_path(def).to_string(),
+                args,
+            }),
+            Res::Def(DefKind::TyAlias, def) => self.alias_ty(def).subst(&args),
+            Res::Def(DefKind::Newtype, def) => Type::Newtype(NewtypeRef {
+                def,
+                name: self.resolve.def_path(def).to_string(),
+            }),
+            // Type parameters of functions in traits and impls are reported
+            Res::Def(DefKind::TyParam, id) => self
+                .ty_params
+                .get(&id)
+                .cloned()
+                .map_or(Type::Error, Type::Param),
+            Res::Def(DefKind::Impl, id) => self.self_tys[&id].clone(),
+            Res::Err => Type::Error,
+            Res::Def(..) | Res::Local(_) => {
+                self.error(ty.span(), format!("`{}` is not a type", name));
+                Type::Error
+            }
+        }
+    }
+
+    /// Array lengths are constant expressions of type `usize`
+    fn array_len(&mut self, len: &ast::Expr) -> Option<u64> {
+        if let Some(value) = self.array_lens.get(&len.id()) {
+            return *value;
+        }
+        let value = match self.eval_const(len, &Type::Prim(PrimTy::USize), None) {
+            Some(ConstValue::Int(value)) => Some(value as u64),
+            _ => None,
+        };
+        self.array_lens.insert(len.id(), value);
+        value
+    }
+
+    fn check_expr(&mut self, expr: &ast::Expr, expected: Option<&Type>) -> Type {
+        let ty = self.check_expr_kind(expr, expected);
+        self.expr_types.insert(expr.id(), ty.clone());
+        ty
+    }
+
+    fn check_expr_kind(&mut self, expr: &ast::Expr, expected: Option<&Type>) -> Type {
+        match &expr.kind {
+            ExprKind::Unit => Type::unit(),
+            ExprKind::Lit(lit) => self.check_lit(expr, lit, expected),
+            ExprKind::Block(block) => self.check_block(block, expected),
+            ExprKind::Var(ast::Var { name }) => self.check_value_path(expr, name, expected),
+            ExprKind::Path(path) => self.check_value_path(expr, &path.to_string(), expected),
+            ExprKind::Call(callee, args) => self.check_call(expr, callee, args, expected),
+            ExprKind::MethodCall(receiver, name, args) => {
+                self.check_method_call(expr, receiver, name, args, expected)
+            }
+            ExprKind::Struct(s) => self.check_struct_expr(expr, s, expected),
+            ExprKind::Field(base, name) => {
+                let base_ty = self.check_expr(base, None);
+                match &base_ty {
+                    Type::Adt(adt) if !self.adts[&adt.def].is_enum => {
+                        match self.adts[&adt.def].variants[0].field(name) {
+                            Some(field) => field.ty.subst(&adt.args),
+                            None => {
+                                self.error(
+                                    expr.span(),
+                                    format!(
+                                        "Type `{}` has no field `{}`",
+                                        self.written_ty(base, &base_ty),
+                                        name
+                                    ),
+                                );
+                                Type::Error
+                            }
+                        }
+                    }
+                    Type::Tuple(items) => {
+                        match name.parse::<usize>().ok().and_then(|i| items.get(i)) {
+                            Some(ty) => ty.clone(),
+                            None => {
+                                self.error(
+                                    expr.span(),
+                                    format!(
+                                        "Type `{}` has no field `{}`",
+                                        self.written_ty(base, &base_ty),
+                                        name
+                                    ),
+                                );
+                                Type::Error
+                            }
+                        }
+                    }
+                    Type::Slice(elem) if name == "ptr" => Type::Ptr(Mutability::Mut, elem.clone()),
+                    Type::Slice(_) | Type::Array(..) if name == "len" => Type::Prim(PrimTy::USize),
+                    Type::Error => Type::Error,
+                    _ => {
+                        self.error(
+                            expr.span(),
+                            format!(
+                                "Type `{}` has no field `{}`",
+                                self.written_ty(base, &base_ty),
+                                name
+                            ),
+                        );
+                        Type::Error
+                    }
+                }
+            }
+            ExprKind::Assign(lhs, rhs) => {
+                let lhs_ty = self.check_expr(lhs, None);
+                if !self.is_place(lhs) {
+                    self.error(
+                        lhs.span(),
+                        "Invalid left hand side of assignment".to_string(),
+                    );
+                }
+                self.check_static_mutation(lhs);
+                if let Some(ptr_ty @ Type::Ptr(Mutability::Not, _)) = self.deref_base_ty(lhs) {
+                    self.error(
+                        lhs.span(),
+                        format!("Cannot assign through `{}`; Use a `*mut` pointer", ptr_ty),
+                    );
+                }
+                let rhs_ty = self.check_expr(rhs, Some(&lhs_ty));
+                self.expect_declared(rhs, &rhs_ty, &lhs_ty, self.written_ty_id(lhs));
+                Type::unit()
+            }
+            ExprKind::Match(scrutinee, arms) => {
+                let scrutinee_ty = self.check_expr(scrutinee, None);
+                let mut result_ty = expected.cloned();
+                for arm in arms.iter() {
+                    self.check_pat(&arm.pat, &scrutinee_ty);
+                    if let Some(guard) = &arm.guard {
+                        let bool_ty = Type::Prim(PrimTy::Bool);
+                        let guard_ty = self.check_expr(guard, Some(&bool_ty));
+                        self.expect_declared(guard, &guard_ty, &bool_ty, None);
+                    }
+                    let body_ty = self.check_expr(&arm.body, result_ty.as_ref());
+                    match &result_ty {
+                        Some(result_ty) => {
+                            self.expect_declared(&arm.body, &body_ty, result_ty, None)
+                        }
+                        None => result_ty = Some(body_ty),
+                    }
+                }
+                let cx = MatchCheckCtx {
+                    resolve: self.resolve,
+                    adts: &self.adts,
+                    variant_adts: &self.variant_adts,
+                };
+                let diagnostics = cx.check_match(expr, &scrutinee_ty, arms);
+                self.diagnostics.extend(diagnostics);
+                result_ty.unwrap_or_else(Type::unit)
+            }
+            ExprKind::Tup(exprs) => {
+                let expected_items = match expected {
+                    Some(Type::Tuple(items)) if items.len() == exprs.len() => Some(items),
+                    _ => None,
+                };
+                let items = exprs
+                    .iter()
+                    .enumerate()
+                    .map(|(i, it)| self.check_expr(it, expected_items.map(|items| &items[i])))
+                    .collect();
+                Type::Tuple(items)
+            }
+            ExprKind::Paren(inner) => self.check_expr(inner, expected),
+            ExprKind::Binary(op, lhs, rhs) => self.check_binary(expr, *op, lhs, rhs, expected),
+            ExprKind::Unary(op, operand) => self.check_unary(expr, *op, operand, expected),
+            ExprKind::AddrOf(mutability, place) => {
+                let expected_pointee = match expected {
+                    Some(Type::Ptr(_, pointee)) => Some(&**pointee),
+                    _ => None,
+                };
+                let ty = self.check_expr(place, expected_pointee);
+                if !self.is_place(place) {
+                    self.error(
+                        place.span(),
+                        "Can only take the address of a local, a static, a field or a dereference"
+                            .to_string(),
+                    );
+                }
+                if *mutability == Mutability::Mut {
+                    self.check_static_mutation(place);
+                }
+                Type::Ptr(*mutability, Box::new(ty))
+            }
+            ExprKind::Array(elems) => {
+                let mut elem_ty = match expected {
+                    Some(Type::Array(elem, _)) => Some((**elem).clone()),
+                    _ => None,
+                };
+                for elem in elems {
+                    let ty = self.check_expr(elem, elem_ty.as_ref());
+                    match &elem_ty {
+                        Some(elem_ty) => self.expect_declared(elem, &ty, elem_ty, None),
+                        None => elem_ty = Some(ty),
+                    }
+                }
+                match elem_ty {
+                    Some(elem_ty) => Type::Array(Box::new(elem_ty), elems.len() as u64),
+                    None => {
+                        self.error(
+                            expr.span(),
+                            "Cannot infer the type of an empty array".to_string(),
+                        );
+                        Type::Error
+                    }
+                }
+            }
+            ExprKind::Repeat(elem, len) => {
+                let expected_elem = match expected {
+                    Some(Type::Array(elem, _)) => Some(&**elem),
+                    _ => None,
+                };
+                let elem_ty = self.check_expr(elem, expected_elem);
+                match self.array_len(len) {
+                    Some(len) => Type::Array(Box::new(elem_ty), len),
+                    None => Type::Error,
+                }
+            }
+            ExprKind::Index(base, index) => {
+                let base_ty = self.check_expr(base, None);
+                let usize_ty = Type::Prim(PrimTy::USize);
+                let index_ty = self.check_expr(index, Some(&usize_ty));
+                self.expect_declared(index, &index_ty, &usize_ty, None);
+                match base_ty {
+                    Type::Array(elem, _) | Type::Slice(elem) => *elem,
+                    Type::Ptr(_, elem) if !matches!(*elem, Type::Dyn(_)) => {
+                        self.require_unsafe(expr.span(), "Indexing a raw pointer");
+                        *elem
+                    }
+                    Type::Error => Type::Error,
+                    ty => {
+                        self.error(base.span(), format!("Type `{}` cannot be indexed", ty));
+                        Type::Error
+                    }
+                }
+            }
+            ExprKind::Cast(inner, ty) => {
+                let target = self.lower_ty(ty);
+                let source = self.check_expr(inner, None);
+                if !self.is_valid_cast(&source, &target) {
+                    self.error(
+                        expr.span(),
+                        format!("Cannot cast `{}` as `{}`", source, target),
+                    );
+                } else if let (Type::Ptr(_, pointee), Type::Ptr(_, target_pointee)) =
+                    (&source, &target)
+                {
+                    // `&p as *dyn Show` uses the impl of `Show` for the type of `p`
+                    if let Type::Dyn(trait_ref) = &**target_pointee {
+                        if !matches!(**pointee, Type::Dyn(_)) {
+                            self.expect_impl(pointee, trait_ref.def, expr.span());
+                        }
+                    }
+                } else if let (Type::Ptr(..), Type::Slice(_)) = (&source, &target) {
+                    // Only `&a` is known to point to a whole array
+                    if !matches!(inner.kind, ExprKind::AddrOf(..)) {
+                        self.require_unsafe(expr.span(), "Casting a raw pointer to a slice");
+                    }
+                }
+                target
+            }
+        }
+    }
+
+    /// The type arguments of generic callees are inferred from the expected type
+    /// of the call and from the types of the arguments, in that order.
+    fn check_call(
+        &mut self,
+        expr: &ast::Expr,
+        callee: &ast::Expr,
+        args: &[ast::Expr],
+        expected: Option<&Type>,
+    ) -> Type {
+        let (callee_ty, generics) = match &callee.kind {
+            ExprKind::Var(ast::Var { name }) => self.value_path_ty(callee, name),
+            ExprKind::Path(path) => self.value_path_ty(callee, &path.to_string()),
+            _ => (self.check_expr(callee, None), vec![]),
+        };
+        let variadic = match self.resolve.get(callee.id()) {
+            Res::Def(DefKind::ForeignFn, id) => self.fn_sigs[&id].variadic,
+            _ => false,
+        };
+        let Type::Fn(params, ret) = callee_ty else {
+            if callee_ty != Type::Error {
+                self.error(callee.span(), format!("`{}` is not callable", callee_ty));
+            }
+            self.expr_types.insert(callee.id(), callee_ty);
+            for arg in args {
+                self.check_expr(arg, None);
+            }
+            return Type::Error;
+        };
+        if variadic && args.len() < params.len() {
+            self.error(
+                expr.span(),
+                format!(
+                    "Expected at least {} arguments, found {}",
+                    params.len(),
+                    args.len()
+                ),
+            );
+        } else if !variadic && params.len() != args.len() {
+            self.error(
+                expr.span(),
+                format!("Expected {} arguments, found {}", params.len(), args.len()),
+            );
+        }
+        let mut inferred = vec![None; generics.len()];
+        if let (Some(expected), false) = (expected, generics.is_empty()) {
+            infer_args(&ret, expected, &mut inferred);
+        }
+        let mut arg_tys = vec![];
+        for (i, arg) in args.iter().enumerate() {
+            let param = params.get(i);
+            let expected_arg = param.and_then(|it| try_subst(it, &inferred));
+            let arg_ty = self.check_expr(arg, expected_arg.as_ref());
+            if let Some(param) = param {
+                infer_args(param, &arg_ty, &mut inferred);
+            } else if variadic {
+                self.promote_variadic_arg(arg, &arg_ty);
+            }
+            arg_tys.push(arg_ty);
+        }
+        let mut callee_ty = Type::Fn(params, ret);
+        if !generics.is_empty() {
+            let name = match &callee.kind {
+                ExprKind::Path(path) => path.to_string(),
+                ExprKind::Var(var) => var.name.clone(),
+                _ => unreachable!("Only paths refer to generic items"),
+            };
+            let type_args = self.instantiate(callee, &name, &generics, inferred);
+            callee_ty = callee_ty.subst(&type_args);
+        }
+        self.expr_types.insert(callee.id(), callee_ty.clone());
+        let Type::Fn(params, ret) = callee_ty else {
+            unreachable!()
+        };
+        let param_ty_ids = match self.resolve.get(callee.id()) {
+            Res::Def(_, def) if generics.is_empty() => self.param_ty_ids(def),
+            _ => vec![],
+        };
+        for (i, ((arg, arg_ty), param)) in args
+            .iter()
+            .zip(arg_tys.iter())
+            .zip(params.iter())
+            .enumerate()
+        {
+            let ty_id = param_ty_ids.get(i).copied();
+            self.expect_declared(arg, arg_ty, param, ty_id);
+        }
+        *ret
+    }
+
+    /// Applies the default argument promotions of C to an argument passed to
+    /// the `...` of a variadic function. Only scalars can be passed.
+    fn promote_variadic_arg(&mut self, arg: &ast::Expr, ty: &Type) {
+        use PrimTy::*;
+        match ty {
+            Type::Prim(I8 | I16 | U8 | U16 | Bool) => {
+                self.promoted_args.insert(arg.id(), Type::Prim(I32));
+            }
+            Type::Prim(_) | Type::Error => {}
+            Type::Ptr(..) if !ty.is_dyn_ptr() => {}
+            _ => {
+                let message = format!("`{}` cannot be passed to a variadic function", ty);
+                self.error(arg.span(), message);
+            }
+        }
+    }
+
+    /// `a.f(b)` calls `f` from an inherent impl of the type of `a`, or else from
+    /// a trait that it implements. `a` may also be a pointer to that type, and
+    /// its address is taken if `f` expects a pointer.
+    fn check_method_call(
+        &mut self,
+        expr: &ast::Expr,
+        receiver: &ast::Expr,
+        name: &str,
+        args: &[ast::Expr],
+        expected: Option<&Type>,
+    ) -> Type {
+        let receiver_ty = self.check_expr(receiver, None);
+        let self_ty = match &receiver_ty {
+            Type::Ptr(_, pointee) => (**pointee).clone(),
+            _ => receiver_ty.clone(),
+        };
+        let def = self.lookup_method(&self_ty, &receiver_ty, name, expr.span());
+        if let Some(def) = def {
+            self.check_deprecated(expr.id(), expr.span(), def);
+        }
+        let Some((def, FnSig { params, ret, .. })) = def.map(|it| (it, self.fn_sigs[&it].clone()))
+        else {
+            for arg in args {
+                self.check_expr(arg, None);
+            }
+            return Type::Error;
+        };
+        if params.len() != args.len() + 1 {
+            self.error(
+                expr.span(),
+                format!(
+                    "Expected {} arguments, found {}",
+                    params.len().saturating_sub(1),
+                    args.len()
+                ),
+            );
+        }
+        let generics = self.generics_of(def).to_vec();
+        let mut inferred = vec![None; generics.len()];
+        if let (Some(expected), false) = (expected, generics.is_empty()) {
+            infer_args(&ret, expected, &mut inferred);
+        }
+        // Pass the receiver as is if it fits, and by address otherwise
+        let mut receiver_kind = Receiver::ByValue;
+        let mut adjusted_ty = receiver_ty.clone();
+        if let Some(first) = params.first() {
+            let mut by_value = inferred.clone();
+            infer_args(first, &receiver_ty, &mut by_value);
+            let fits = try_subst(first, &by_value).is_some_and(|it| it == receiver_ty);
+            if let (false, Type::Ptr(mutability, _)) = (fits, first) {
+                receiver_kind = if self.is_place(receiver) {
+                    Receiver::AddrOf
+                } else {
+                    Receiver::AddrOfTemp
+                };
+                adjusted_ty = Type::Ptr(*mutability, Box::new(receiver_ty.clone()));
+            }
+            infer_args(first, &adjusted_ty, &mut inferred);
+        }
+        let method_call = MethodCall {
+            def,
+            receiver: receiver_kind,
+        };
+        self.method_calls.insert(expr.id(), method_call);
+        let mut arg_tys = vec![adjusted_ty];
+        for (i, arg) in args.iter().enumerate() {
+            let param = params.get(i + 1);
+            let expected_arg = param.and_then(|it| try_subst(it, &inferred));
+            let arg_ty = self.check_expr(arg, expected_arg.as_ref());
+            if let Some(param) = param {
+                infer_args(param, &arg_ty, &mut inferred);
+            }
+            arg_tys.push(arg_ty);
+        }
+        let type_args = if generics.is_empty() {
+            vec![]
+        } else {
+            self.instantiate(expr, name, &generics, inferred)
+        };
+        let arg_exprs = [receiver].into_iter().chain(args);
+        let param_ty_ids = if generics.is_empty() {
+            self.param_ty_ids(def)
+        } else {
+            vec![]
+        };
+        for (i, ((arg_ty, param), arg)) in
+            arg_tys.iter().zip(params.iter()).zip(arg_exprs).enumerate()
+        {
+            let ty_id = param_ty_ids.get(i).copied();
+            self.expect_declared(arg, arg_ty, &param.subst(&type_args), ty_id);
+        }
+        ret.subst(&type_args)
+    }
+
+    /// Finds the function that `a.f()` calls when `a` has type `receiver_ty`,
+    /// preferring inherent functions over trait functions.
+    fn lookup_method(
+        &mut self,
+        self_ty: &Type,
+        receiver_ty: &Type,
+        name: &str,
+        span: &Span,
+    ) -> Option<NodeId> {
+        if self_ty.any(&|it| *it == Type::Error) {
+            return None;
+        }
+        if let Type::Adt(adt) = self_ty {
+            let assoc_fn = self
+                .resolve
+                .assoc_fns
+                .get(&adt.def)
+                .and_then(|it| it.get(name));
+            if let Some(f) = assoc_fn {
+                return Some(*f);
+            }
+        }
+        let mut candidates = self
+            .traits
+            .iter()
+            .filter(|(trait_id, _)| self.implements(self_ty, **trait_id))
+            .filter_map(|(trait_id, t)| {
+                let (_, f) = t.fns.iter().find(|(fn_name, _)| fn_name == name)?;
+                Some((*trait_id, *f))
+            })
+            .collect::<Vec<_>>();
+        candidates.sort();
+        match candidates.as_slice() {
+            [(_, f)] => Some(*f),
+            [] => {
+                self.error(
+                    span,
+                    format!("No method `{}` on type `{}`", name, receiver_ty),
+                );
+                None
+            }
+            _ => {
+                let traits = candidates
+                    .iter()
+                    .map(|(trait_id, _)| format!("`{}`", self.traits[trait_id].name))
+                    .collect::<Vec<_>>();
+                self.error(
+                    span,
+                    format!(
+                        "`{}` is ambiguous between traits {}",
+                        name,
+                        traits.join(", ")
+                    ),
+                );
+                None
+            }
+        }
+    }
+
+    /// Reports the type parameters that couldn't be inferred, and records the
+    /// type arguments of a use of a generic item.
+    fn instantiate(
+        &mut self,
+        expr: &ast::Expr,
+        name: &str,
+        generics: &[Ident],
+        inferred: Vec<Option<Type>>,
+    ) -> Vec<Type> {
+        let args = inferred
+            .into_iter()
+            .zip(generics)
+            .map(|(arg, param)| {
+                arg.unwrap_or_else(|| {
+                    self.error(
+                        expr.span(),
+                        format!("Cannot infer type parameter `{}` of `{}`", param, name),
+                    );
+                    Type::Error
+                })
+            })
+            .collect::<Vec<_>>();
+        let def = match self.resolve.get(expr.id()) {
+            Res::Def(_, def) => Some(def),
+            _ => self.method_calls.get(&expr.id()).map(|it| it.def),
+        };
+        if let Some(def) = def {
+            // Only trait functions can be called on `dyn Trait`, through its vtable
+            for arg in args.iter() {
+                if matches!(arg, Type::Dyn(_)) && !self.fn_traits.contains_key(&def) {
+                    self.error(
+                        expr.span(),
+                        format!("`{}` cannot be used as a type argument", arg),
+                    );
+                }
+            }
+            let bounds = self.bounds.get(&def).cloned().unwrap_or_default();
+            for (arg, bounds) in args.iter().zip(bounds.iter()) {
+                for trait_id in bounds {
+                    self.expect_impl(arg, *trait_id, expr.span());
+                }
+            }
+        }
+        self.generic_args.insert(expr.id(), args.clone());
+        args
+    }
+
+    fn check_binary(
+        &mut self,
+        expr: &ast::Expr,
+        op: BinOp,
+        lhs: &ast::Expr,
+        rhs: &ast::Expr,
+        expected: Option<&Type>,
+    ) -> Type {
+        let bool_ty = Type::Prim(PrimTy::Bool);
+        if let BinOp::And | BinOp::Or = op {
+            for operand in [lhs, rhs] {
+                let ty = self.check_expr(operand, Some(&bool_ty));
+                self.expect_declared(operand, &ty, &bool_ty, None);
+            }
+            return bool_ty;
+        }
+        // Arithmetic has the type of its operands, so the expected type can
+        // decide the type of an integer literal on the left.
+        let lhs_ty = self.check_expr(lhs, if op.is_comparison() { None } else { expected });
+        if let (Type::Ptr(_, pointee), BinOp::Add | BinOp::Sub) = (&lhs_ty, op) {
+            if matches!(**pointee, Type::Dyn(_)) {
+                self.error(
+                    expr.span(),
+                    format!(
+                        "Operator `{}` cannot be applied to `{}`",
+                        op.as_str(),
+                        self.written_ty(lhs, &lhs_ty)
+                    ),
+                );
+                self.check_expr(rhs, None);
+                return Type::Error;
+            }
+            self.require_unsafe(expr.span(), "Pointer arithmetic");
+            let offset_ty = self.check_expr(rhs, None);
+            if !matches!(offset_ty, Type::Prim(p) if p.is_integer()) && offset_ty != Type::Error {
+                self.error(
+                    rhs.span(),
+                    format!("Expected an integer offset, found `{}`", offset_ty),
+                );
+            }
+            return lhs_ty;
+        }
+        let rhs_ty = self.check_expr(rhs, Some(&lhs_ty));
+        self.expect_declared(rhs, &rhs_ty, &lhs_ty, self.written_ty_id(lhs));
+        let is_valid = match &lhs_ty {
+            Type::Error => true,
+            Type::Prim(p) if p.is_integer() => true,
+            Type::Prim(PrimTy::Bool) => matches!(op, BinOp::Eq | BinOp::Ne),
+            Type::Ptr(..) if op.is_comparison() && !lhs_ty.is_dyn_ptr() => {
+                self.require_unsafe(expr.span(), "Comparing pointers");
+                true
+            }
+            _ => false,
+        };
+        if !is_valid {
+            self.error(
+                expr.span(),
+                format!(
+                    "Operator `{}` cannot be applied to `{}`",
+                    op.as_str(),
+                    self.written_ty(lhs, &lhs_ty)
+                ),
+            );
+        }
+        if op.is_comparison() {
+            bool_ty
+        } else if is_valid {
+            lhs_ty
+        } else {
+            Type::Error
+        }
+    }
+
+    fn check_unary(
+        &mut self,
+        expr: &ast::Expr,
+        op: UnOp,
+        operand: &ast::Expr,
+        expected: Option<&Type>,
+    ) -> Type {
+        match op {
+            UnOp::Deref => match self.check_expr(operand, None) {
+                Type::Ptr(_, pointee) if !matches!(*pointee, Type::Dyn(_)) => {
+                    self.require_unsafe(expr.span(), "Dereferencing a raw pointer");
+                    *pointee
+                }
+                Type::Error => Type::Error,
+                ty => {
+                    let ty = self.written_ty(operand, &ty);
+                    self.error(expr.span(), format!("Type `{}` cannot be dereferenced", ty));
+                    Type::Error
+                }
+            },
+            UnOp::Neg | UnOp::Not => {
+                let ty = self.check_expr(operand, expected);
+                let is_valid = match (&ty, op) {
+                    (Type::Error, _) => true,
+                    (Type::Prim(p), UnOp::Neg) => p.is_signed(),
+                    (Type::Prim(_), _) => true,
+                    _ => false,
+                };
+                if is_valid {
+                    ty
+                } else {
+                    let op = if op == UnOp::Neg { "-" } else { "!" };
+                    let ty = self.written_ty(operand, &ty);
+                    self.error(
+                        expr.span(),
+                        format!("Operator `{}` cannot be applied to `{}`", op, ty),
+                    );
+                    Type::Error
+                }
+            }
+        }
+    }
+
+    fn require_unsafe(&mut self, span: &Span, what: &str) {
+        if !self.in_unsafe {
+            self.error(span, format!("{} requires an unsafe block", what));
+        }
+    }
+
+    /// The pointer type that a place is accessed through, e.g. the type of `p`
+    /// for `(*p).x`; None if the place is not behind a pointer.
+    fn deref_base_ty(&self, place: &ast::Expr) -> Option<Type> {
+        match &place.kind {
+            ExprKind::Field(base, _) | ExprKind::Paren(base) => self.deref_base_ty(base),
+            ExprKind::Unary(UnOp::Deref, ptr) => self.expr_types.get(&ptr.id()).cloned(),
+            ExprKind::Index(base, _) => match self.expr_types.get(&base.id()) {
+                Some(ty @ Type::Ptr(..)) => Some(ty.clone()),
+                _ => self.deref_base_ty(base),
+            },
+            _ => None,
+        }
+    }
+
+    /// Checks a name in the value namespace; A local, function or constructor.
+    /// The type arguments of generic items are inferred from the expected type.
+    /// Functions and tuple constructors have no values of their own, since
+    /// they're only lowered to calls.
+    fn check_value_path(&mut self, expr: &ast::Expr, name: &str, expected: Option<&Type>) -> Type {
+        if let Res::Def(DefKind::TraitFn, _) = self.resolve.get(expr.id()) {
+            self.error(expr.span(), format!("`{}` can only be called", name));
+            return Type::Error;
+        }
+        let (ty, generics) = self.value_path_ty(expr, name);
+        if let Type::Fn(..) = ty {
+            self.error(expr.span(), format!("`{}` can only be called", name));
+            return Type::Error;
+        }
+        if generics.is_empty() {
+            return ty;
+        }
+        let mut inferred = vec![None; generics.len()];
+        if let Some(expected) = expected {
+            infer_args(&ty, expected, &mut inferred);
+        }
+        let args = self.instantiate(expr, name, &generics, inferred);
+        ty.subst(&args)
+    }
+
+    /// The type of a name in the value namespace along with the type parameters
+    /// of the item it refers to, which the type may mention.
+    fn value_path_ty(&mut self, expr: &ast::Expr, name: &str) -> (Type, Vec<Ident>) {
+        let res = self.resolve.get(expr.id());
+        if let Res::Def(_, def) = res {
+            self.check_deprecated(expr.id(), expr.span(), def);
+        }
+        match res {
+            Res::Local(id) => (self.local_types[&id].clone(), vec![]),
+            Res::Def(DefKind::Fn | DefKind::ForeignFn | DefKind::TraitFn, id) => {
+                let ty = self.fn_sigs[&id].ty();
+                (ty, self.generics_of(id).to_vec())
+            }
+            Res::Def(DefKind::Const, id) => (self.global_tys[&id].clone(), vec![]),
+            Res::Def(DefKind::Static, id) => {
+                if self.static_mutability(id) == Mutability::Mut {
+                    self.require_unsafe(expr.span(), "Use of a mutable static");
+                }
+                (self.global_tys[&id].clone(), vec![])
+            }
+            Res::Def(DefKind::Struct | DefKind::Variant, _) => {
+                let (adt_id, index) = ctor(&self.variant_adts, res).unwrap();
+                let generics = self.generics_of(adt_id).to_vec();
+                let adt = &self.adts[&adt_id];
+                let adt_ty = adt.ty(adt_id, identity_args(&generics));
+                let variant = &adt.variants[index];
+                let ty = match variant.kind {
+                    VariantKind::Unit => adt_ty,
+                    VariantKind::Tuple => Type::Fn(
+                        variant.fields.iter().map(|it| it.ty.clone()).collect(),
+                        Box::new(adt_ty),
+                    ),
+                    VariantKind::Struct => {
+                        self.error(
+                            expr.span(),
+                            format!("`{}` has named fields; Use `{} {{ ... }}`", name, name),
+                        );
+                        return (Type::Error, vec![]);
+                    }
+                };
+                (ty, generics)
+            }
+            Res::PrimTy(_)
+            | Res::Def(
+                DefKind::Enum
+                | DefKind::TyParam
+                | DefKind::Impl
+                | DefKind::TyAlias
+                | DefKind::Newtype,
+                _,
+            ) => {
+                self.error(expr.span(), format!("`{}` is a type", name));
+                (Type::Error, vec![])
+            }
+            Res::Def(DefKind::Trait, _) => {
+                self.error(expr.span(), format!("`{}` is a trait", name));
+                (Type::Error, vec![])
+            }
+            Res::Def(DefKind::Mod, _) => {
+                self.error(expr.span(), format!("`{}` is a module", name));
+                (Type::Error, vec![])
+            }
+            Res::Err => (Type::Error, vec![]),
+        }
+    }
+
+    fn static_mutability(&self, id: NodeId) -> Mutability {
+        self.statics[&id]
+    }
+
+    /// Reports modifying a static that isn't declared `static mut`
+    fn check_static_mutation(&mut self, place: &ast::Expr) {
+        match &place.kind {
+            ExprKind::Paren(inner) => self.check_static_mutation(inner),
+            ExprKind::Var(_) | ExprKind::Path(_) => {
+                if let Res::Def(DefKind::Static, id) = self.resolve.get(place.id()) {
+                    if self.static_mutability(id) == Mutability::Not {
+                        let name = self.resolve.def_path(id);
+                        let message = format!(
+                            "`{}` is immutable; Declare it `static mut` to modify it",
+                            name
+                        );
+                        self.error(place.span(), message);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_pat(&mut self, pat: &ast::Pat, expected: &Type) {
+        use ast::PatKind as P;
+        match &pat.kind {
+            P::Wild => {}
+            P::Binding(_) => {
+                self.local_types.insert(pat.id(), expected.clone());
+            }
+            P::Tuple(pats) => self.check_tuple_pat(pat, pats, expected),
+            P::Lit(lit) => {
+                let ty = match lit.kind {
+                    ast::LitKind::Null => unreachable!("`null` is not a pattern"),
+                    ast::LitKind::Bool => Type::Prim(PrimTy::Bool),
+                    ast::LitKind::Integer => match expected {
+                        Type::Prim(p) if p.is_integer() => {
+                            if !integer_fits(&lit.text, *p) {
+                                self.error(
+                                    pat.span(),
+                                    format!(
+                                        "Integer literal `{}` is out of range for `{}`",
+                                        lit.text,
+                                        p.name()
+                                    ),
+                                );
+                            }
+                            expected.clone()
+                        }
+                        _ => Type::Prim(PrimTy::I32),
+                    },
+                };
+                self.expect_compatible(&ty, expected, pat.span());
+            }
+            P::Path(path) | P::TupleStruct(path, _) | P::Struct(path, _, _) => {
+                let res = self.resolve.get(pat.id());
+                let Some((adt_id, index)) = ctor(&self.variant_adts, res) else {
+                    if res != Res::Err {
+                        self.error(pat.span(), format!("`{}` is not a struct or variant", path));
+                    }
+                    self.check_subpats_with_error(pat);
+                    return;
+                };
+                // The type arguments come from the scrutinee
+                let args = match expected {
+                    Type::Adt(adt) if adt.def == adt_id => adt.args.clone(),
+                    _ => vec![Type::Error; self.generics_of(adt_id).len()],
+                };
+                let adt = &self.adts[&adt_id];
+                let adt_ty = adt.ty(adt_id, args.clone());
+                let variant = &adt.variants[index];
+                let kind = variant.kind;
+                let field_tys = variant
+                    .fields
+                    .iter()
+                    .map(|it| (it.name.clone(), it.ty.subst(&args)))
+                    .collect::<Vec<_>>();
+                self.expect_compatible(&adt_ty, expected, pat.span());
+                match (&pat.kind, kind) {
+                    (P::Path(_), VariantKind::Unit) => {}
+                    (P::TupleStruct(_, pats), VariantKind::Tuple) => {
+                        if pats.len() != field_tys.len() {
+                            self.error(
+                                pat.span(),
+                                format!(
+                                    "Pattern has {} fields, but `{}` has {}",
+                                    pats.len(),
+                                    path,
+                                    field_tys.len()
+                                ),
+                            );
+                        }
+                        for (i, sub) in pats.iter().enumerate() {
+                            let ty = field_tys.get(i).map(|it| it.1.clone());
+                            self.check_pat(sub, &ty.unwrap_or(Type::Error));
+                        }
+                    }
+                    (P::Struct(_, fields, has_rest), VariantKind::Struct) => {
+                        let mut seen = HashSet::new();
+                        for field in fields.iter() {
+                            let ty = field_tys
+                                .iter()
+                                .find(|it| it.0 == field.name)
+                                .map(|it| it.1.clone());
+                            if ty.is_none() {
+                                self.error(
+                                    field.span(),
+                                    format!("`{}` has no field `{}`", path, field.name),
+                                );
+                            }
+                            if !seen.insert(field.name.as_str()) {
+                                self.error(
+                                    field.span(),
+                                    format!("Field `{}` is bound more than once", field.name),
+                                );
+                            }
+                            self.check_pat(&field.pat, &ty.unwrap_or(Type::Error));
+                        }
+                        let missing = field_tys
+                            .iter()
+                            .filter(|(name, _)| !seen.contains(name.as_str()))
+                            .map(|(name, _)| format!("`{}`", name))
+                            .collect::<Vec<_>>();
+                        if !has_rest && !missing.is_empty() {
+                            self.error(
+                                pat.span(),
+                                format!(
+                                    "Pattern does not mention fields {}; Use `..` to ignore them",
+                                    missing.join(", ")
+                                ),
+                            );
+                        }
+                    }
+                    (_, kind) => {
+                        let expected_syntax = match kind {
+                            VariantKind::Struct => "{ .. }",
+                            VariantKind::Tuple => "(..)",
+                            VariantKind::Unit => "",
+                        };
+                        self.error(
+                            pat.span(),
+                            format!(
+                                "Pattern doesn't match the shape of `{}`; Expected `{}{}`",
+                                path, path, expected_syntax
+                            ),
+                        );
+                        self.check_subpats_with_error(pat);
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_tuple_pat(&mut self, pat: &ast::Pat, pats: &[ast::Pat], expected: &Type) {
+        match expected {
+            Type::Tuple(items) if items.len() == pats.len() => {
+                for (sub, ty) in pats.iter().zip(items.iter()) {
+                    self.check_pat(sub, ty);
+                }
+            }
+            _ => {
+                if *expected != Type::Error {
+                    self.error(
+                        pat.span(),
+                        format!(
+                            "Type mismatch: expected `{}`, found a tuple of {} elements",
+                            expected,
+                            pats.len()
+                        ),
+                    );
+                }
+                self.check_subpats_with_error(pat);
+            }
+        }
+    }
+
+    /// Checks sub patterns of a pattern that failed to type check, so that the
+    /// bindings inside them still get a type.
+    fn check_subpats_with_error(&mut self, pat: &ast::Pat) {
+        match &pat.kind {
+            ast::PatKind::TupleStruct(_, pats) | ast::PatKind::Tuple(pats) => {
+                for pat in pats {
+                    self.check_pat(pat, &Type::Error);
+                }
+            }
+            ast::PatKind::Struct(_, fields, _) => {
+                for field in fields {
+                    self.check_pat(&field.pat, &Type::Error);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_lit(&mut self, expr: &ast::Expr, lit: &ast::Lit, expected: Option<&Type>) -> Type {
+        match lit.kind {
+            ast::LitKind::Null => match expected {
+                Some(ty) if ty.is_dyn_ptr() => {
+                    self.error(expr.span(), format!("`null` cannot be used as `{}`", ty));
+                    Type::Error
+                }
+                Some(ty @ Type::Ptr(..)) => ty.clone(),
+                _ => {
+                    self.error(
+                        expr.span(),
+                        "Cannot infer the pointer type of `null`".to_string(),
+                    );
+                    Type::Error
+                }
+            },
+            ast::LitKind::Bool => Type::Prim(PrimTy::Bool),
+            ast::LitKind::Integer => {
+                let prim = match expected {
+                    Some(Type::Prim(p)) if p.is_integer() => *p,
+                    _ => PrimTy::I32,
+                };
+                if !integer_fits(&lit.text, prim) {
+                    self.error(
+                        expr.span(),
+                        format!(
+                            "Integer literal `{}` is out of range for `{}`",
+                            lit.text,
+                            prim.name()
+                        ),
+                    );
+                }
+                Type::Prim(prim)
+            }
+        }
+    }
+
+    fn check_struct_expr(
+        &mut self,
+        expr: &ast::Expr,
+        s: &ast::StructExpr,
+        expected: Option<&Type>,
+    ) -> Type {
+        let res = self.resolve.get(expr.id());
+        let Some((adt_id, index)) = ctor(&self.variant_adts, res) else {
+            if res != Res::Err {
+                self.error(expr.span(), format!("`{}` is not a struct", s.path));
+            }
+            for field in s.fields.iter() {
+                self.check_expr(&field.expr, None);
+            }
+            return Type::Error;
+        };
+        let variant = &self.adts[&adt_id].variants[index];
+        if variant.kind != VariantKind::Struct {
+            let message = format!(
+                "`{}` has no named fields; Use `{}(...)` to construct it",
+                s.path, s.path
+            );
+            self.error(expr.span(), message);
+        }
+        let expected_fields = self.adts[&adt_id].variants[index]
+            .fields
+            .iter()
+            .map(|it| (it.name.clone(), it.ty.clone(), it.ty_id))
+            .collect::<Vec<_>>();
+        let generics = self.generics_of(adt_id).to_vec();
+        let adt_ty = self.adts[&adt_id].ty(adt_id, identity_args(&generics));
+        let mut inferred = vec![None; generics.len()];
+        if let Some(expected) = expected {
+            infer_args(&adt_ty, expected, &mut inferred);
+        }
+        let mut initialized = HashSet::new();
+        let mut field_tys = vec![];
+        for field in s.fields.iter() {
+            let field_ty = expected_fields
+                .iter()
+                .find(|(name, ..)| name == &field.name)
+                .map(|(_, ty, ty_id)| (ty, *ty_id));
+            let expected = field_ty.and_then(|(it, _)| try_subst(it, &inferred));
+            let ty = self.check_expr(&field.expr, expected.as_ref());
+            match field_ty {
+                Some((field_ty, ty_id)) => {
+                    infer_args(field_ty, &ty, &mut inferred);
+                    field_tys.push((field, ty, field_ty.clone(), ty_id));
+                }
+                None => self.error(
+                    field.span(),
+                    format!("`{}` has no field `{}`", s.path, field.name),
+                ),
+            }
+            if !initialized.insert(field.name.as_str()) {
+                self.error(
+                    field.span(),
+                    format!("Field `{}` is initialized more than once", field.name),
+                );
+            }
+        }
+        let missing = expected_fields
+            .iter()
+            .filter(|(name, ..)| !initialized.contains(name.as_str()))
+            .map(|(name, ..)| format!("`{}`", name))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            self.error(
+                expr.span(),
+                format!(
+                    "Missing fields in struct expression for `{}`: {}",
+                    s.path,
+                    missing.join(", ")
+                ),
+            );
+        }
+        let args = if generics.is_empty() {
+            vec![]
+        } else {
+            self.instantiate(expr, &s.path.to_string(), &generics, inferred)
+        };
+        // Fields of generic structs may be written in terms of their type
+        // parameters, which are named differently here
+        let generic = !generics.is_empty();
+        for (field, ty, field_ty, ty_id) in field_tys {
+            let ty_id = Some(ty_id).filter(|_| !generic);
+            self.expect_declared(&field.expr, &ty, &field_ty.subst(&args), ty_id);
+        }
+        adt_ty.subst(&args)
+    }
+
+    fn check_block(&mut self, block: &ast::Block, expected: Option<&Type>) -> Type {
+        let was_unsafe = self.in_unsafe;
+        self.in_unsafe |= block.is_unsafe;
+        let tail = block.tail_expr().map(|it| it.id());
+        let mut ty = Type::unit();
+        for stmt in block.stmts.iter() {
+            match &stmt.kind {
+                StmtKind::Semi => {}
+                StmtKind::Let(local) => self.check_local(local),
+                StmtKind::Expr(expr) if Some(expr.id()) == tail => {
+                    ty = self.check_expr(expr, expected);
+                }
+                StmtKind::Expr(expr) => {
+                    self.check_expr(expr, None);
+                }
+            }
+        }
+        self.in_unsafe = was_unsafe;
+        ty
+    }
+
+    fn check_local(&mut self, local: &ast::Local) {
+        let annotation = local.ty.as_ref().map(|it| self.lower_ty(it));
+        let init_ty = local
+            .init
+            .as_ref()
+            .map(|init| (init, self.check_expr(init, annotation.as_ref())));
+        let ty = match (annotation, init_ty) {
+            (Some(annotation), Some((init, init_ty))) => {
+                let ty_id = local.ty.as_ref().map(|it| it.id());
+                self.expect_declared(init, &init_ty, &annotation, ty_id);
+                annotation
+            }
+            (Some(annotation), None) => annotation,
+            (None, Some((_, init_ty))) => init_ty,
+            (None, None) => {
+                let message = match &local.pat.kind {
+                    ast::PatKind::Binding(name) => format!("Type annotation needed for `{}`", name),
+                    _ => "Type annotation needed".to_string(),
+                };
+                self.error(local.span(), message);
+                Type::Error
+            }
+        };
+        self.check_pat(&local.pat, &ty);
+        if let (Some(ty), ast::PatKind::Binding(_)) = (&local.ty, &local.pat.kind) {
+            self.written.declared.insert(local.pat.id(), ty.id());
+        }
+        let cx = MatchCheckCtx {
+            resolve: self.resolve,
+            adts: &self.adts,
+            variant_adts: &self.variant_adts,
+        };
+        let diagnostics = cx.check_irrefutable(&local.pat, &ty);
+        self.diagnostics.extend(diagnostics);
+        self.local_types.insert(local.id(), ty);
+    }
+
+    /// Whether the expression denotes a memory location that can be assigned to
+    fn is_place(&self, expr: &ast::Expr) -> bool {
+        match &expr.kind {
+            ExprKind::Var(_) | ExprKind::Path(_) => matches!(
+                self.resolve.get(expr.id()),
+                Res::Local(_) | Res::Def(DefKind::Static, _) | Res::Err
+            ),
+            // The pointer and length of a slice can't be changed
+            ExprKind::Field(base, _)
+                if matches!(self.expr_types.get(&base.id()), Some(Type::Slice(_))) =>
+            {
+                false
+            }
+            ExprKind::Field(base, _) | ExprKind::Paren(base) => self.is_place(base),
+            ExprKind::Unary(UnOp::Deref, _) => true,
+            // Slices and pointers refer to memory elsewhere, while arrays are values
+            ExprKind::Index(base, _) => match self.expr_types.get(&base.id()) {
+                Some(Type::Array(..)) => self.is_place(base),
+                _ => true,
+            },
+            _ => false,
+        }
+    }
+
+    fn expect_compatible(&mut self, actual: &Type, expected: &Type, span: &Span) {
+        if !actual.is_compatible_with(expected) {
+            self.error(
+                span,
+                format!("Type mismatch: expected `{}`, found `{}`", expected, actual),
+            );
+        }
+    }
+
+    /// A newtype converts to and from the type it wraps with `as`
+    fn is_valid_cast(&self, source: &Type, target: &Type) -> bool {
+        match (source, target) {
+            (Type::Newtype(a), Type::Newtype(b)) if a.def == b.def => true,
+            (Type::Newtype(newtype), other) | (other, Type::Newtype(newtype)) => {
+                *other == Type::Error || self.newtypes[&newtype.def] == *other
+            }
+            _ => is_valid_cast(source, target),
+        }
+    }
+
+    /// Like [Self::expect_compatible], but names the types as written where
+    /// they were declared, so that mismatches mention the aliases used. The
+    /// expected type was written at `ty_id`, and `actual` is the type of `expr`.
+    fn expect_declared(
+        &mut self,
+        expr: &ast::Expr,
+        actual: &Type,
+        expected: &Type,
+        ty_id: Option<NodeId>,
+    ) {
+        if actual.is_compatible_with(expected) {
+            return;
+        }
+        let expected = match ty_id.and_then(|it| self.written_ty_of(it)) {
+            Some(written) => written,
+            None => expected.to_string(),
+        };
+        let actual = self.written_ty(expr, actual);
+        self.error(
+            expr.span(),
+            format!("Type mismatch: expected `{}`, found `{}`", expected, actual),
+        );
+    }
+
+    /// The type of an expression, named as written where its value was declared
+    fn written_ty(&self, expr: &ast::Expr, ty: &Type) -> String {
+        let written = self
+            .written_ty_id(expr)
+            .filter(|_| self.expr_types.get(&expr.id()) == Some(ty))
+            .and_then(|it| self.written_ty_of(it));
+        match written {
+            Some(written) => written,
+            None => ty.to_string(),
+        }
+    }
+
+    /// The id of the type written for the value of an expression: The type of
+    /// the local, param, const, static or field it reads, or the return type
+    /// of the function it calls, through parentheses and blocks. Types written in terms of type parameters
+    /// name other types where the parameters are substituted, so they're left out.
+    fn written_ty_id(&self, expr: &ast::Expr) -> Option<NodeId> {
+        let return_ty_id = |def: NodeId| match self.generics_of(def) {
+            [] => self.find_written(|it| it.fns.get(&def).map(|(_, ret)| *ret))?,
+            _ => None,
+        };
+        match &expr.kind {
+            ExprKind::Paren(inner) => self.written_ty_id(inner),
+            ExprKind::Block(block) => self.written_ty_id(block.tail_expr()?),
+            ExprKind::Var(_) | ExprKind::Path(_) => match self.resolve.get(expr.id()) {
+                Res::Local(id) | Res::Def(DefKind::Const | DefKind::Static, id) => {
+                    self.find_written(|it| it.declared.get(&id).copied())
+                }
+                _ => None,
+            },
+            ExprKind::Call(callee, _) => match self.resolve.get(callee.id()) {
+                Res::Def(DefKind::Fn | DefKind::ForeignFn, def) => return_ty_id(def),
+                _ => None,
+            },
+            ExprKind::MethodCall(..) => return_ty_id(self.method_calls.get(&expr.id())?.def),
+            ExprKind::Field(base, name) => match self.expr_types.get(&base.id())? {
+                Type::Adt(adt) if adt.args.is_empty() && !self.adts[&adt.def].is_enum => {
+                    Some(self.adts[&adt.def].variants[0].field(name)?.ty_id)
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The ids of the param types of a function as written, for functions
+    /// declared in this crate
+    fn param_ty_ids(&self, def: NodeId) -> Vec<NodeId> {
+        self.find_written(|it| it.fns.get(&def).map(|(params, _)| params.clone()))
+            .unwrap_or_default()
+    }
+
+    fn declare_written_fn(&mut self, id: NodeId, f: &ast::Fn) {
+        let params = f.params.iter().map(|it| it.ty.id()).collect();
+        let ret = f.return_ty.as_ref().map(|it| it.id());
+        self.written.fns.insert(id, (params, ret));
+    }
+
+    /// Looks up a type as written in the function or crate being checked,
+    /// then in the rest of the crate
+    fn find_written<T>(&self, f: impl Fn(&WrittenTys) -> Option<T>) -> Option<T> {
+        f(&self.written).or_else(|| f(&self.crate_written))
+    }
+
+    fn written_ty_of(&self, ty_id: NodeId) -> Option<String> {
+        self.find_written(|it| it.tys.get(&ty_id).cloned())
+    }
+
+    fn error(&mut self, span: &Span, message: String) {
+        self.diagnostics.push(Diagnostic::new(*span, message));
+    }
+}
+
+/// Collects the structs that are stored inline in a value of type `ty`
+fn collect_adts_by_value(ty: &Type, newtypes: &HashMap<NodeId, Type>, out: &mut Vec<AdtRef>) {
+    match ty {
+        Type::Adt(adt) => out.push(adt.clone()),
+        Type::Tuple(items) => items
+            .iter()
+            .for_each(|it| collect_adts_by_value(it, newtypes, out)),
+        Type::Array(elem, _) => collect_adts_by_value(elem, newtypes, out),
+        Type::Newtype(newtype) => collect_adts_by_value(&newtypes[&newtype.def], newtypes, out),
+        Type::Prim(_)
+        | Type::Fn(..)
+        | Type::Ptr(..)
+        | Type::Slice(_)
+        | Type::Param(_)
+        | Type::Dyn(_)
+        | Type::Error => {}
+    }
+}
+
+/// The type parameters of an item as types, e.g. `T` and `U` in `Pair<T, U>`
+fn identity_args(generics: &[Ident]) -> Vec<Type> {
+    generics
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            Type::Param(ParamTy {
+                index,
+                name: name.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Infers type arguments by matching a type that mentions the type parameters
+/// of a generic item against the type of an actual value. Type arguments that
+/// are already known are kept.
+fn infer_args(generic: &Type, actual: &Type, inferred: &mut [Option<Type>]) {
+    let mut infer_all = |generic: &[Type], actual: &[Type]| {
+        if generic.len() == actual.len() {
+            for (generic, actual) in generic.iter().zip(actual) {
+                infer_args(generic, actual, inferred);
+            }
+        }
+    };
+    match (generic, actual) {
+        (_, Type::Error) => {}
+        (Type::Param(param), _) => {
+            if let Some(slot @ None) = inferred.get_mut(param.index) {
+                *slot = Some(actual.clone());
+            }
+        }
+        (Type::Tuple(a), Type::Tuple(b)) => infer_all(a, b),
+        (Type::Adt(a), Type::Adt(b)) if a.def == b.def => infer_all(&a.args, &b.args),
+        (Type::Fn(p1, r1), Type::Fn(p2, r2)) => {
+            infer_all(p1, p2);
+            infer_args(r1, r2, inferred);
+        }
+        (Type::Ptr(_, a), Type::Ptr(_, b))
+        | (Type::Array(a, _), Type::Array(b, _))
+        | (Type::Slice(a), Type::Slice(b)) => infer_args(a, b, inferred),
+        _ => {}
+    }
+}
+
+/// Substitutes the inferred type arguments into `ty`; None if `ty` mentions a
+/// type parameter that hasn't been inferred yet.
+fn try_subst(ty: &Type, inferred: &[Option<Type>]) -> Option<Type> {
+    let is_unknown =
+        |ty: &Type| matches!(ty, Type::Param(p) if matches!(inferred.get(p.index), Some(None)));
+    if ty.any(&is_unknown) {
+        return None;
+    }
+    let args = inferred
+        .iter()
+        .map(|it| it.clone().unwrap_or(Type::Error))
+        .collect::<Vec<_>>();
+    Some(ty.subst(&args))
+}
+
+/// Whether `ty` implements a trait; `param_impls` tells whether a type parameter
+/// does, from the bounds of the function being checked.
+fn implements(
+    impls: &HashMap<NodeId, ImplDef>,
+    ty: &Type,
+    trait_id: NodeId,
+    param_impls: &dyn Fn(&ParamTy, NodeId) -> bool,
+) -> bool {
+    match ty {
+        Type::Param(param) => param_impls(param, trait_id),
+        Type::Dyn(trait_ref) => trait_ref.def == trait_id,
+        _ if ty.any(&|it| *it == Type::Error) => true,
+        _ => find_impl(impls, ty, trait_id, param_impls).is_some(),
+    }
+}
+
+/// Finds the impl of a trait whose self type matches `ty`, along with the type
+/// arguments of the impl. Impls don't overlap, so there's at most one.
+fn find_impl(
+    impls: &HashMap<NodeId, ImplDef>,
+    ty: &Type,
+    trait_id: NodeId,
+    param_impls: &dyn Fn(&ParamTy, NodeId) -> bool,
+) -> Option<(NodeId, Vec<Type>)> {
+    impls
+        .iter()
+        .filter(|(_, imp)| imp.trait_id == trait_id)
+        .find_map(|(id, imp)| {
+            let mut inferred = vec![None; imp.bounds.len()];
+            infer_args(&imp.self_ty, ty, &mut inferred);
+            let args = inferred.into_iter().collect::<Option<Vec<_>>>()?;
+            if imp.self_ty.subst(&args) != *ty {
+                return None;
+            }
+            // The type arguments are smaller than `ty`, so this terminates
+            let bounds_hold = args.iter().zip(imp.bounds.iter()).all(|(arg, bounds)| {
+                bounds
+                    .iter()
+                    .all(|it| implements(impls, arg, *it, param_impls))
+            });
+            bounds_hold.then_some((*id, args))
+        })
+}
+
+/// Whether two types can be made equal by substituting their type parameters,
+/// which are bound in `subst`.
+fn unify(a: &Type, b: &Type, subst: &mut HashMap<usize, Type>) -> bool {
+    let resolve = |ty: &Type, subst: &HashMap<usize, Type>| {
+        let mut ty = ty.clone();
+        while let Type::Param(param) = &ty {
+            match subst.get(&param.index) {
+                Some(bound) => ty = bound.clone(),
+                None => break,
+            }
+        }
+        ty
+    };
+    let unify_all = |a: &[Type], b: &[Type], subst: &mut HashMap<usize, Type>| {
+        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| unify(a, b, subst))
+    };
+    match (resolve(a, subst), resolve(b, subst)) {
+        (Type::Param(p), Type::Param(q)) if p.index == q.index => true,
+        (Type::Param(param), ty) | (ty, Type::Param(param)) => {
+            let occurs = ty.any(&|it| matches!(it, Type::Param(p) if p.index == param.index));
+            if !occurs {
+                subst.insert(param.index, ty);
+            }
+            !occurs
+        }
+        (Type::Tuple(a), Type::Tuple(b)) => unify_all(&a, &b, subst),
+        (Type::Adt(a), Type::Adt(b)) => a.def == b.def && unify_all(&a.args, &b.args, subst),
+        (Type::Fn(p1, r1), Type::Fn(p2, r2)) => {
+            unify_all(&p1, &p2, subst) && unify(&r1, &r2, subst)
+        }
+        (Type::Ptr(m1, a), Type::Ptr(m2, b)) => m1 == m2 && unify(&a, &b, subst),
+        (Type::Array(a, n1), Type::Array(b, n2)) => n1 == n2 && unify(&a, &b, subst),
+        (Type::Slice(a), Type::Slice(b)) => unify(&a, &b, subst),
+        (a, b) => a == b,
+    }
+}
+
+/// `as` converts between integers, from `bool` to integers, between pointers,
+/// between pointers and integers, from pointers to arrays to slices, and from
+/// pointers to `dyn` pointers.
+fn is_valid_cast(source: &Type, target: &Type) -> bool {
+    match (source, target) {
+        (Type::Error, _) | (_, Type::Error) => true,
+        (Type::Prim(_), Type::Prim(to)) => to.is_integer() || source == target,
+        // Whether the pointee implements the trait is checked separately
+        (Type::Ptr(_, a), Type::Ptr(_, b)) if source.is_dyn_ptr() || target.is_dyn_ptr() => {
+            !source.is_dyn_ptr() || a == b
+        }
+        (Type::Ptr(..), Type::Ptr(..)) => true,
+        // `&a as [T]` creates a slice of an array
+        (Type::Ptr(_, array), Type::Slice(b)) => {
+            matches!(&**array, Type::Array(a, _) if a.is_compatible_with(b))
+        }
+        (Type::Ptr(..), Type::Prim(_)) | (Type::Prim(_), Type::Ptr(..))
+            if source.is_dyn_ptr() || target.is_dyn_ptr() =>
+        {
+            false
+        }
+        (Type::Ptr(..), Type::Prim(p)) | (Type::Prim(p), Type::Ptr(..)) => p.is_integer(),
+        _ => source == target,
+    }
+}
+
+pub(super) fn integer_fits(text: &str, ty: PrimTy) -> bool {
+    let Ok(value) = text.parse::<u128>() else {
+        return false;
+    };
+    let max: u128 = match ty {
+        PrimTy::I8 => i8::MAX as u128,
+        PrimTy::I16 => i16::MAX as u128,
+        PrimTy::I32 => i32::MAX as u128,
+        PrimTy::I64 | PrimTy::ISize => i64::MAX as u128,
+        PrimTy::U8 => u8::MAX as u128,
+        PrimTy::U16 => u16::MAX as u128,
+        PrimTy::U32 => u32::MAX as u128,
+        PrimTy::U64 | PrimTy::USize => u64::MAX as u128,
+        PrimTy::Bool => return false,
+    };
+    value <= max
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::{analysis::resolve::resolve, parser::Parser};
+
+    use super::*;
+
+    fn typeck_messages(text: &str) -> Vec<String> {
+        let source_file = Parser::new(text, PathBuf::from("test.hds")).parse_source_file();
+        let resolve_result = resolve(&source_file);
+        assert_eq!(resolve_result.diagnostics, vec![]);
+        typeck(&source_file, &resolve_result)
+            .diagnostics
+            .into_iter()
+            .map(|it| it.message)
+            .collect()
+    }
+
+    #[test]
+    fn checks_variadic_calls() {
+        let messages = typeck_messages(
+            "
+            struct S {}
+            extern {
+                fn printf(fmt: *u8, ...) -> i32;
+                fn bad(...);
+            }
+            fn f(...) {}
+            fn g(fmt: *u8, b: u8, s: S) {
+                unsafe {
+                    printf(fmt, 1, b, fmt, true);
+                    printf(fmt, s, ());
+                    printf();
+                }
+            }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec![
+                "Variadic functions need a parameter before `...`",
+                "Only foreign functions can be variadic",
+                "`S` cannot be passed to a variadic function",
+                "`()` cannot be passed to a variadic function",
+                "Expected at least 1 arguments, found 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn warns_about_deprecated_items_unless_allowed() {
+        let messages = typeck_messages(
+            r#"
+            #[deprecated = "Use `Point`"]
+            struct P {}
+            #[deprecated]
+            fn old() {}
+            fn f(p: P) {
+                old();
+                #[allow(deprecated)]
+                old();
+            }
+            #[allow(deprecated)]
+            fn g(p: P) { old(); }
+            fn h(#[allow(deprecated)] p: P) {}
+            "#,
+        );
+        assert_eq!(
+            messages,
+            vec!["`P` is deprecated: Use `Point`", "`old` is deprecated"]
+        );
+    }
+
+    #[test]
+    fn checks_link_attributes_and_abis() {
+        let messages = typeck_messages(
+            r#"
+            #[link(name = "m")]
+            #[link(lib = "m")]
+            extern "C" {
+                #[link_name = "sqrt"]
+                fn square_root(x: i32) -> i32;
+                #[link_name = "not a symbol"]
+                #[link]
+                fn g();
+            }
+            #[link_name = "f"]
+            #[inline_always]
+            extern "stdcall" {}
+            "#,
+        );
+        assert_eq!(
+            messages,
+            vec![
+                r#"Expected `#[link(name = "...")]`"#,
+                "`not a symbol` is not a valid C identifier",
+                "`#[link]` can only be used on `extern` blocks",
+                "`#[link_name]` can only be used on foreign functions",
+                "Unknown attribute `inline_always`",
+                r#"Unknown ABI `stdcall`; Only "C" is supported"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_struct_fields() {
+        let messages = typeck_messages(
+            "
+            struct Point { x: i32, y: u8 }
+            fn f() -> Point {
+                let p = Point { x: 1, y: 2 };
+                p.y = 3;
+                p
+            }
+            ",
+        );
+        assert_eq!(messages, Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_struct_expr_errors() {
+        let messages = typeck_messages(
+            "
+            struct Point { x: i32, y: i32 }
+            fn f() -> i32 {
+                let p = Point { x: 1, z: 2 };
+                p.z
+            }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec![
+                "`Point` has no field `z`",
+                "Missing fields in struct expression for `Point`: `y`",
+                "Type `Point` has no field `z`",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_tuple_struct_constructors() {
+        let messages = typeck_messages(
+            "
+            struct Pair(i32, i64);
+            fn f() -> i64 {
+                let p = Pair(1, 2);
+                let q: Pair = Pair(1);
+                p.1
+            }
+            ",
+        );
+        assert_eq!(messages, vec!["Expected 2 arguments, found 1"]);
+    }
+
+    #[test]
+    fn checks_pointers() {
+        let messages = typeck_messages(
+            "
+            struct Node { value: i32, next: *mut Node }
+            extern { fn malloc(size: usize) -> *mut u8; }
+            fn f(p: *Node) -> i32 {
+                let n = malloc(16) as *mut Node;
+                let x = 1;
+                let q: *mut i32 = &mut x;
+                unsafe {
+                    (*n).next = null;
+                    *q = *q + 1;
+                    if_null(n == null);
+                    (*(n + 1)).value - (*p).value
+                }
+            }
+            fn if_null(b: bool) {}
+            ",
+        );
+        assert_eq!(messages, Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_pointer_errors() {
+        let messages = typeck_messages(
+            "
+            fn f(p: *i32, q: *mut i32) {
+                let a = *q;
+                let b = q + 1;
+                let c = p == null;
+                let n = null;
+                let r: *mut i32 = p;
+                let i = 1;
+                let x = &(i + 1);
+                unsafe { *p = 1; }
+                let arr = [1, 2];
+                let pa = &arr;
+                let s = &arr as [i32];
+                let t = pa as [i32];
+                unsafe { let u = pa as [i32]; }
+            }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec![
+                "Dereferencing a raw pointer requires an unsafe block",
+                "Pointer arithmetic requires an unsafe block",
+                "Comparing pointers requires an unsafe block",
+                "Cannot infer the pointer type of `null`",
+                "Type mismatch: expected `*mut i32`, found `*i32`",
+                "Can only take the address of a local, a static, a field or a dereference",
+                "Cannot assign through `*i32`; Use a `*mut` pointer",
+                "Casting a raw pointer to a slice requires an unsafe block",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_tuples() {
+        let messages = typeck_messages(
+            "
+            fn swap(p: (i32, bool)) -> (bool, i32) { (p.1, p.0) }
+            fn f() -> u8 {
+                let (b, i) = swap((1, true));
+                let t: (u8, (bool,)) = (2, (b,));
+                let (x, (y,)) = t;
+                match t { (0, (true,)) => i as u8, (n, _) => n + x }
+            }
+            ",
+        );
+        assert_eq!(messages, Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_tuple_errors() {
+        let messages = typeck_messages(
+            "
+            enum Option { Some(i32), None }
+            fn f(t: (i32, i32), o: Option) {
+                let x = t.2;
+                let (a, b, c) = t;
+                let Option::Some(v) = o;
+                let (d, e);
+            }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec![
+                "Type `(i32, i32)` has no field `2`",
+                "Type mismatch: expected `(i32, i32)`, found a tuple of 3 elements",
+                "Refutable pattern in `let`: `Option::None` not covered",
+                "Type annotation needed",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_arrays_and_slices() {
+        let messages = typeck_messages(
+            "
+            struct Buf { data: [u8; 4] }
+            fn sum(s: [i32]) -> i32 { s[0] + s[s.len - 1] }
+            fn f(b: Buf) -> i32 {
+                let a = [1, 2, 3];
+                let zeros: [u8; 4] = [0; 4];
+                b.data[1] = zeros[3];
+                let p = &a as *i32;
+                sum(&a as [i32]) + unsafe { p[2] + (&mut a as [i32]).ptr[0] }
+            }
+            ",
+        );
+        assert_eq!(messages, Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_array_errors() {
+        let messages = typeck_messages(
+            "
+            fn f(s: [i32], n: usize, i: i32) {
+                let a: [i32; n] = [];
+                let b = [1, true];
+                let c = s[i];
+                let d = 1[0];
+                s.len = 2;
+                let p = &b as *bool;
+                let e = p[0];
+            }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec![
+                "`n` is not a constant",
+                "Cannot infer the type of an empty array",
+                "Type mismatch: expected `i32`, found `bool`",
+                "Type mismatch: expected `usize`, found `i32`",
+                "Type `i32` cannot be indexed",
+                "Invalid left hand side of assignment",
+                "Indexing a raw pointer requires an unsafe block",
+            ]
+        );
+    }
+
+    #[test]
+    fn evaluates_consts_in_array_lengths() {
+        let source_file = Parser::new(
+            "
+            const N: usize = M * 2 + 1;
+            const M: usize = (10 - 4) / 4 % 3;
+            const FLAG: bool = !(N > 2) || N as i8 == -1;
+            struct Buf { data: [u8; N] }
+            fn f(b: Buf) -> [u8; N] { b.data }
+            ",
+            PathBuf::from("test.hds"),
+        )
+        .parse_source_file();
+        let resolve_result = resolve(&source_file);
+        let result = typeck(&source_file, &resolve_result);
+        assert_eq!(result.diagnostics, vec![]);
+        let value = |index: usize| result.const_values[&source_file.items[index].id()];
+        assert_eq!(value(0), ConstValue::Int(3));
+        assert_eq!(value(1), ConstValue::Int(1));
+        assert_eq!(value(2), ConstValue::Bool(false));
+        let ItemKind::Fn(f) = &source_file.items[4].kind else {
+            panic!("Expected a function")
+        };
+        assert_eq!(
+            result.fn_sigs[&source_file.items[4].id()].ret,
+            Type::Array(Box::new(Type::Prim(PrimTy::U8)), 3)
+        );
+        assert!(f.return_ty.is_some());
+    }
+
+    #[test]
+    fn reports_const_errors() {
+        let messages = typeck_messages(
+            "
+            const A: i8 = 100 + 28;
+            const B: u32 = 1 / (2 - 2);
+            const C: i32 = -A as i32;
+            const D: i32 = E;
+            const E: i32 = D + 1;
+            const F: *u8 = null;
+            const G: i32 = f();
+            static H: i32 = 1;
+            static mut I: i32 = H;
+            fn f() -> i32 {
+                let x = 1;
+                let a: [i32; x] = [1];
+                H = 2;
+                I
+            }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec![
+                "Consts and statics must be integers or `bool`",
+                "Arithmetic overflow: the result doesn't fit in `i8`",
+                "Division by zero",
+                "The value of `D` depends on itself",
+                "Only literals, constants and operators can be used in constant expressions",
+                "Only literals, constants and operators can be used in constant expressions",
+                "`H` is not a constant",
+                "`x` is not a constant",
+                "`H` is immutable; Declare it `static mut` to modify it",
+                "Use of a mutable static requires an unsafe block",
+            ]
+        );
+        // Initializers with errors reported by resolve aren't evaluated
+        let source_file = Parser::new(
+            "
+            const N: i32 = x;
+            const BIG: i64 = 1;
+            const B: bool = BIG as zz != -1;
+            ",
+            PathBuf::from("test.hds"),
+        )
+        .parse_source_file();
+        let resolve_result = resolve(&source_file);
+        assert_eq!(resolve_result.diagnostics.len(), 2);
+        let result = typeck(&source_file, &resolve_result);
+        assert_eq!(result.diagnostics, vec![]);
+        let ids = [0, 2].map(|index| source_file.items[index].id());
+        assert!(ids.iter().all(|id| !result.const_values.contains_key(id)));
+    }
+
+    #[test]
+    fn expands_type_aliases() {
+        let messages = typeck_messages(
+            "
+            type Fd = i32;
+            type Pair<T> = (T, T);
+            type Fds = Pair<Fd>;
+            type Buf = [u8; LEN];
+            const LEN: usize = 2;
+            const STDOUT: Fd = 1;
+            struct File { fd: Fd }
+            fn open(fd: Fd) -> File { File { fd: fd } }
+            fn f(fds: Fds, b: Buf) -> i32 {
+                let file = open(fds.0);
+                file.fd + fds.1 + STDOUT + b[0] as i32
+            }
+            ",
+        );
+        assert_eq!(messages, Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_alias_errors_with_the_names_written() {
+        let messages = typeck_messages(
+            "
+            type A = B;
+            type B = *A;
+            type Fd = i32;
+            type Pair<T: Show> = (T, T);
+            trait Show {}
+            type Point = (i32, i32);
+            struct File { fd: Fd }
+            const STDIN: Fd = true;
+            fn open(fd: Fd) -> *Fd { fd }
+            fn is_open(fd: Fd) -> bool { fd }
+            fn f(a: Pair<i32, i32>, p: Point) {
+                let fd: Fd = false;
+                open(true);
+                let file = File { fd: true };
+                let closed: bool = file.fd;
+                is_open(open(fd));
+                fd == true;
+                p + p;
+                p.x;
+            }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec![
+                "Trait bounds are only allowed on functions and impls",
+                "Type alias `A` refers to itself",
+                "`Pair` expects 1 type arguments, found 2",
+                "Type mismatch: expected `Fd`, found `bool`",
+                "Type mismatch: expected `*Fd`, found `Fd`",
+                "Type mismatch: expected `bool`, found `Fd`",
+                "Type mismatch: expected `Fd`, found `bool`",
+                "Type mismatch: expected `Fd`, found `bool`",
+                "Type mismatch: expected `Fd`, found `bool`",
+                "Type mismatch: expected `bool`, found `Fd`",
+                "Type mismatch: expected `Fd`, found `*Fd`",
+                "Type mismatch: expected `Fd`, found `bool`",
+                "Operator `+` cannot be applied to `Point`",
+                "Type `Point` has no field `x`",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_newtypes() {
+        let messages = typeck_messages(
+            "
+            newtype Fd = i32;
+            newtype Handle = *u8;
+            newtype Loop = (i32, Loop);
+            newtype Unit = ();
+            extern { fn close(fd: Fd) -> i32; }
+            fn f(h: Handle) -> Fd {
+                close(3);
+                close(3 as Fd);
+                let p = h as *u8;
+                let fd = 1 as Fd;
+                fd + fd;
+                fd as i64;
+                fd
+            }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec![
+                "Newtypes cannot wrap `()`",
+                "Recursive type `Loop` has infinite size",
+                "Type mismatch: expected `Fd`, found `i32`",
+                "Operator `+` cannot be applied to `Fd`",
+                "Cannot cast `Fd` as `i64`",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_generics() {
+        let messages = typeck_messages(
+            "
+            struct Pair<A, B> { first: A, second: B }
+            enum Option<T> { Some(T), None }
+            fn swap<A, B>(p: Pair<A, B>) -> Pair<B, A> {
+                Pair { first: p.second, second: p.first }
+            }
+            fn unwrap_or<T>(o: Option<T>, fallback: T) -> T {
+                match o { Option::Some(x) => x, Option::None => fallback }
+            }
+            fn f() -> i32 {
+                let p: Pair<bool, i32> = swap(Pair { first: 1, second: true });
+                let o: Option<u8> = Option::None;
+                unwrap_or(o, 2) as i32 + unwrap_or(Option::Some(p.second), 3)
+            }
+            ",
+        );
+        assert_eq!(messages, Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_generic_errors() {
+        let messages = typeck_messages(
+            "
+            struct Box<T> { value: T }
+            fn none<T>() -> *T { null }
+            fn f<T>(b: Box, x: T) -> Box<i32, bool> {
+                let p = none();
+                let q: Box<i32> = Box { value: x };
+                b
+            }
+            fn main<T>() {}
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec![
+                "`Box` expects 1 type arguments, found 0",
+                "`Box` expects 1 type arguments, found 2",
+                "`main` cannot be generic",
+                "Cannot infer type parameter `T` of `none`",
+                "Type mismatch: expected `i32`, found `T`",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_traits() {
+        let messages = typeck_messages(
+            "
+            trait Show { fn show(self: *Self) -> i32; }
+            struct Point { x: i32 }
+            struct Pair<T> { a: T, b: T }
+            impl Show for Point { fn show(self: *Point) -> i32 { 1 } }
+            impl<T: Show> Show for Pair<T> {
+                fn show(self: *Self) -> i32 { unsafe { Show::show(&(*self).a) } }
+            }
+            fn twice<T: Show>(x: *T) -> i32 { Show::show(x) + Show::show(x) }
+            fn f(p: Pair<Pair<Point>>) -> i32 {
+                let d = &p as *dyn Show;
+                twice(&p) + Show::show(d)
+            }
+            ",
+        );
+        assert_eq!(messages, Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_trait_errors() {
+        let messages = typeck_messages(
+            "
+            trait Show { fn show(self: *Self) -> i32; fn hide(self: *Self); }
+            trait Make { fn make() -> Self; }
+            struct Point { x: i32 }
+            struct Pair<T> { a: T, b: T }
+            impl Show for Point {
+                fn show(self: *Point) -> bool { true }
+                fn size(self: *Point) {}
+            }
+            impl<T> Show for Pair<T> {
+                fn show(self: *Self) -> i32 { 0 }
+                fn hide(self: *Self) {}
+            }
+            impl Show for Pair<i32> {
+                fn show(self: *Self) -> i32 { 0 }
+                fn hide(self: *Self) {}
+            }
+            fn twice<T: Show>(x: *T) -> i32 { 0 }
+            fn f(m: *dyn Make, d: *dyn Show) {
+                let i = 1;
+                twice(&i);
+                let p = &i as *dyn Show;
+                let v = *d;
+                let n: *dyn Show = null;
+            }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec![
+                "Signature of `show` doesn't match trait `Show`: expected \
+                 `fn(*Point) -> i32`, found `fn(*Point) -> bool`",
+                "`size` is not a function of trait `Show`",
+                "Missing functions in impl of `Show` for `Point`: `hide`",
+                "Conflicting implementations of `Show` for `Pair<i32>`",
+                "Type `i32` does not implement `Show`",
+                "Type `i32` does not implement `Show`",
+                "Type `*dyn Show` cannot be dereferenced",
+                "`null` cannot be used as `*dyn Show`",
+                "`dyn Make` is not allowed because `make` must take `*Self` or `*mut Self` \
+                 as its first parameter and not use `Self` elsewhere",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_method_calls() {
+        let messages = typeck_messages(
+            "
+            trait Show { fn show(self: *Self) -> i32; }
+            struct Point { x: i32 }
+            struct Pair<T> { a: T, b: T }
+            impl Point {
+                fn new(x: i32) -> Self { Point { x: x } }
+                fn get(self: Point) -> i32 { self.x }
+            }
+            impl<T> Pair<T> { fn first(self: *mut Self) -> T { unsafe { (*self).a } } }
+            impl Show for Point { fn show(self: *Point) -> i32 { unsafe { (*self).get() } } }
+            fn twice<T: Show>(x: *T) -> i32 { x.show() + x.show() }
+            fn f(d: *dyn Show) -> i32 {
+                let p = Point::new(1);
+                let pair = Pair { a: p, b: p };
+                pair.first().show() + Point::new(2).show() + d.show() + twice(&p)
+            }
+            ",
+        );
+        assert_eq!(messages, Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_method_errors() {
+        let messages = typeck_messages(
+            "
+            trait A { fn f(self: *Self); }
+            trait B { fn f(self: *Self); }
+            struct Point { x: i32 }
+            impl Point { fn get(self: Point, y: i32) -> i32 { self.x } }
+            impl A for Point { fn f(self: *Point) {} }
+            impl B for Point { fn f(self: *Point) {} }
+            fn g(p: Point, q: *Point) {
+                p.f();
+                p.len();
+                p.get();
+                q.get(1);
+            }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec![
+                "`f` is ambiguous between traits `A`, `B`",
+                "No method `len` on type `Point`",
+                "Expected 1 arguments, found 0",
+                "Type mismatch: expected `Point`, found `*Point`",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_functions_used_as_values() {
+        let messages = typeck_messages(
+            "
+            struct Pair(i32, i32);
+            extern { fn abs(x: i32) -> i32; }
+            fn foo(x: i32) -> i32 { x }
+            fn id<T>(x: T) -> T { x }
+            fn main() -> i32 {
+                let f = foo;
+                let g: i32 = id;
+                abs;
+                Pair;
+                foo(id(abs(1)))
+            }
+            ",
+        );
+        assert_eq!(
+            messages,
+            vec![
+                "`foo` can only be called",
+                "`id` can only be called",
+                "`abs` can only be called",
+                "`Pair` can only be called",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_recursive_structs() {
+        let messages = typeck_messages("struct A { b: B } struct B { a: A }");
+        assert_eq!(
+            messages,
+            vec![
+                "Recursive type `A` has infinite size",
+                "Recursive type `B` has infinite size",
+            ]
+        );
+    }
+}
@@ -1,127 +1,572 @@
 pub mod visit;
-use std::path::PathBuf;
+use std::{
+    fmt::{self, Display},
+    path::PathBuf,
+};
 
 use libsyntax::Meta;
-use libsyntax_derive::HasMeta;
+use libsyntax_derive::{HasMeta, WalkMeta};
 use serde::Serialize;
 
 pub type Ident = String;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct SourceFile {
     pub path: PathBuf,
+    /// Items of the crate root, preceded by the root modules of the libraries
+    /// the crate depends on
     pub items: Vec<Item>,
+    /// Names of the libraries the crate can refer to
+    pub deps: Vec<Ident>,
 }
+impl SourceFile {
+    /// Items of the crate root and of all modules, with each module's items
+    /// following the module item
+    pub fn all_items(&self) -> impl Iterator<Item = &Item> {
+        let mut items = vec![];
+        collect_items(&self.items, &mut items);
+        items.into_iter()
+    }
 
-#[derive(Debug, Serialize, HasMeta)]
+    /// Functions declared in modules and in impl blocks
+    pub fn fns(&self) -> impl Iterator<Item = (&Item, &Fn)> {
+        self.all_items()
+            .flat_map(|item| match &item.kind {
+                ItemKind::Impl(imp) => imp.items.iter().collect(),
+                _ => vec![item],
+            })
+            .filter_map(|item| match &item.kind {
+                ItemKind::Fn(f) => Some((item, &**f)),
+                _ => None,
+            })
+    }
+}
+
+fn collect_items<'a>(items: &'a [Item], out: &mut Vec<&'a Item>) {
+    for item in items {
+        out.push(item);
+        if let ItemKind::Mod(m) = &item.kind {
+            collect_items(&m.items, out);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
 pub struct Item {
     pub meta: Meta,
+    pub attrs: Vec<Attribute>,
     pub kind: ItemKind,
     pub vis: Visibility,
     pub name: Ident,
 }
 
-#[derive(Debug, Serialize)]
+/// `#[name]`, `#[name = "value"]` or `#[name(args)]`, where the args are
+/// themselves in one of these forms without the `#[]`
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
+pub struct Attribute {
+    pub meta: Meta,
+    pub name: Ident,
+    pub args: AttrArgs,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub enum AttrArgs {
+    Empty,
+    /// The unescaped contents of the string
+    Eq(String),
+    List(Vec<Attribute>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
 pub enum Visibility {
     Public,
     Inherited,
 }
 
-#[derive(Debug, Serialize, HasMeta)]
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
 pub struct Fn {
     pub meta: Meta,
+    pub generics: Vec<GenericParam>,
     pub params: Vec<Param>,
+    /// Whether the params end with `...`; Only foreign functions can be variadic
+    pub variadic: bool,
     pub body: Option<Box<Expr>>,
     pub return_ty: Option<Ty>,
 }
 
-#[derive(Debug, Serialize, HasMeta)]
+/// `T` in `fn id<T>(x: T) -> T` or `T: Show + Eq`
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
+pub struct GenericParam {
+    pub meta: Meta,
+    pub name: Ident,
+    pub bounds: Vec<TraitRef>,
+}
+
+/// A use of a trait's name; `Show` in `impl Show for Point`, `T: Show` or
+/// `dyn fmt::Show`
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
+pub struct TraitRef {
+    pub meta: Meta,
+    pub path: Path,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
 pub struct Param {
     pub meta: Meta,
+    pub attrs: Vec<Attribute>,
     pub name: Ident,
     pub ty: Ty,
 }
 
-#[derive(Debug, Serialize, HasMeta)]
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
 pub struct Expr {
     pub meta: Meta,
     pub kind: ExprKind,
 }
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
 pub enum ExprKind {
     Block(Block),
     Call(Box<Expr>, Vec<Expr>),
     Lit(Lit),
     Var(Var),
     Unit,
+    /// A qualified name such as `Option::None`; Single identifiers are [ExprKind::Var]
+    Path(Path),
+    /// `Point { x: 1, y: 2 }`
+    Struct(StructExpr),
+    /// `a.b`; Tuple fields are accessed using their index, e.g. `a.0`
+    Field(Box<Expr>, Ident),
+    /// `a.f(b)`; Calls an inherent or trait function with `a` as the first
+    /// argument, taking its address if the function expects a pointer
+    MethodCall(Box<Expr>, Ident, Vec<Expr>),
+    Assign(Box<Expr>, Box<Expr>),
+    Match(Box<Expr>, Vec<Arm>),
+    /// `(a, b)` or `(a,)`; `()` is [ExprKind::Unit]
+    Tup(Vec<Expr>),
+    /// `(expr)`
+    Paren(Box<Expr>),
+    /// `a + b`, `a == b`, `a && b`
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    /// `-a`, `!a`, `*a`
+    Unary(UnOp, Box<Expr>),
+    /// `&a` or `&mut a`; Creates a raw pointer to a place
+    AddrOf(Mutability, Box<Expr>),
+    /// `expr as ty`
+    Cast(Box<Expr>, Ty),
+    /// `[a, b, c]`
+    Array(Vec<Expr>),
+    /// `[value; len]`
+    Repeat(Box<Expr>, Box<Expr>),
+    /// `a[i]`; Bounds checked for arrays and slices, unchecked for raw pointers
+    Index(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, WalkMeta)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+impl BinOp {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Rem => "%",
+            BinOp::Eq => "==",
+            BinOp::Ne => "!=",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+        }
+    }
+
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, WalkMeta)]
+pub enum UnOp {
+    Neg,
+    Not,
+    /// Dereferences a raw pointer
+    Deref,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, WalkMeta)]
+pub enum Mutability {
+    Not,
+    Mut,
 }
 
-#[derive(Debug, Serialize)]
+/// `Option::Some`
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub struct Path {
+    pub segments: Vec<Ident>,
+}
+impl Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.segments.join("::"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub struct StructExpr {
+    pub path: Path,
+    pub fields: Vec<ExprField>,
+}
+
+/// `pat if guard => body`
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
+pub struct Arm {
+    pub meta: Meta,
+    pub pat: Pat,
+    pub guard: Option<Box<Expr>>,
+    pub body: Box<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
+pub struct Pat {
+    pub meta: Meta,
+    pub kind: PatKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub enum PatKind {
+    /// `_`
+    Wild,
+    /// `x`; Binds the matched value to a new local
+    Binding(Ident),
+    Lit(Lit),
+    /// `Option::None` or a unit struct
+    Path(Path),
+    /// `Option::Some(x)` or `Pair(a, b)`
+    TupleStruct(Path, Vec<Pat>),
+    /// `Point { x, y: 0, .. }`; The bool is true if the pattern ends with `..`
+    Struct(Path, Vec<PatField>, bool),
+    /// `(a, b)`
+    Tuple(Vec<Pat>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
+pub struct PatField {
+    pub meta: Meta,
+    pub name: Ident,
+    pub pat: Pat,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
+pub struct ExprField {
+    pub meta: Meta,
+    pub name: Ident,
+    pub expr: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
 pub struct Var {
     pub name: Ident,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
 pub struct Lit {
     pub kind: LitKind,
     pub text: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
 pub enum LitKind {
     Integer,
+    Bool,
+    /// `null`; A null raw pointer of any type
+    Null,
 }
 
-#[derive(Debug, Serialize, HasMeta)]
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
 pub struct Block {
     pub meta: Meta,
     pub stmts: Vec<Stmt>,
+    /// True for `unsafe { ... }`
+    pub is_unsafe: bool,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
 pub enum ItemKind {
     Fn(Box<Fn>),
     ForeignMod(ForeignMod),
+    Struct(Struct),
+    Enum(Enum),
+    Trait(Trait),
+    Impl(Impl),
+    Mod(Mod),
+    Use(Use),
+    Const(Const),
+    Static(Static),
+    TyAlias(TyAlias),
+    Newtype(Newtype),
+}
+
+/// `type Fd = i32;`; Uses of the alias are the same type as the aliased type.
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub struct TyAlias {
+    pub generics: Vec<GenericParam>,
+    pub ty: Ty,
+}
+
+/// `newtype Handle = *u8;`; A distinct type with the same representation as
+/// the wrapped type, which values are converted to and from with `as`
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub struct Newtype {
+    pub ty: Ty,
+}
+
+/// `const SIZE: usize = 4 * 1024;`, evaluated at compile time
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub struct Const {
+    pub ty: Ty,
+    pub expr: Expr,
+}
+
+/// `static mut COUNTER: u64 = 0;`, a global whose initializer is evaluated at
+/// compile time
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub struct Static {
+    pub mutability: Mutability,
+    pub ty: Ty,
+    pub expr: Expr,
+}
+
+/// `mod shapes { ... }`, or `mod shapes;` whose items are loaded from `shapes.hds`
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub struct Mod {
+    pub items: Vec<Item>,
+    pub inline: bool,
+    /// For the root module of a library, the names of the libraries it can refer
+    /// to; Libraries aren't nested in the modules around them.
+    pub crate_deps: Option<Vec<Ident>>,
 }
 
-#[derive(Debug, Serialize)]
+/// `use shapes::Point;`, `use shapes::Point as P;` or `use shapes::*;`
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub struct Use {
+    pub path: Path,
+    pub kind: UseKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub enum UseKind {
+    /// Imports the last segment of the path, optionally under another name
+    Single(Option<Ident>),
+    /// Imports every public item of the module the path refers to
+    Glob,
+}
+impl Use {
+    /// The name that a single import is bound to
+    pub fn binding(&self) -> Option<&Ident> {
+        match &self.kind {
+            UseKind::Single(rename) => rename.as_ref().or(self.path.segments.last()),
+            UseKind::Glob => None,
+        }
+    }
+}
+
+/// `trait Show { fn show(self: *Self) -> i32; }`
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub struct Trait {
+    /// Functions without a body
+    pub items: Vec<Item>,
+}
+
+/// `impl<T: Show> Show for Pair<T, T> { ... }`, or an inherent impl such as
+/// `impl Point { ... }` when there's no trait
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub struct Impl {
+    pub generics: Vec<GenericParam>,
+    pub trait_ref: Option<TraitRef>,
+    pub self_ty: Ty,
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub struct Enum {
+    pub generics: Vec<GenericParam>,
+    pub variants: Vec<Variant>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
+pub struct Variant {
+    pub meta: Meta,
+    pub name: Ident,
+    pub data: VariantData,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub struct Struct {
+    pub generics: Vec<GenericParam>,
+    pub data: VariantData,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
+pub enum VariantData {
+    /// `struct Point { x: i32, y: i32 }`
+    Struct(Vec<FieldDef>),
+    /// `struct Pair(i32, i32);`
+    Tuple(Vec<FieldDef>),
+    /// `struct Marker;`
+    Unit,
+}
+impl VariantData {
+    pub fn fields(&self) -> &[FieldDef] {
+        match self {
+            VariantData::Struct(fields) | VariantData::Tuple(fields) => fields,
+            VariantData::Unit => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
+pub struct FieldDef {
+    pub meta: Meta,
+    pub vis: Visibility,
+    /// Field name; For tuple structs, this is the index of the field.
+    pub name: Ident,
+    pub ty: Ty,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
 pub struct ForeignMod {
+    /// The string in `extern "C" { ... }`. Only `"C"` is supported, which is
+    /// also the default.
+    pub abi: Option<String>,
     pub items: Vec<ForeignItem>,
 }
 
-#[derive(Debug, Serialize, HasMeta)]
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
 pub struct ForeignItem {
     pub meta: Meta,
+    pub attrs: Vec<Attribute>,
     pub name: Ident,
     pub vis: Visibility,
     pub kind: ForeignItemKind,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
 pub enum ForeignItemKind {
     Fn(Fn),
 }
 
-#[derive(Debug, Serialize, HasMeta)]
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
 pub struct Stmt {
     pub meta: Meta,
+    pub attrs: Vec<Attribute>,
     pub kind: StmtKind,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
 pub enum StmtKind {
     Semi,
     Expr(Box<Expr>),
+    Let(Box<Local>),
+}
+
+/// `let pat: ty = init;`
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
+pub struct Local {
+    pub meta: Meta,
+    pub pat: Pat,
+    pub ty: Option<Ty>,
+    pub init: Option<Box<Expr>>,
 }
 
-#[derive(Debug, Serialize, HasMeta)]
+#[derive(Debug, Clone, PartialEq, Serialize, HasMeta, WalkMeta)]
 pub struct Ty {
     pub meta: Meta,
     pub kind: TyKind,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, WalkMeta)]
 pub enum TyKind {
     Tup(Vec<Ty>),
-    Var(Ident),
+    /// `i32`, `T` or `Pair<i32, T>`
+    Var(Ident, Vec<Ty>),
+    /// `shapes::Point` or `shapes::Pair<i32, T>`; Single segments are [TyKind::Var]
+    Path(Path, Vec<Ty>),
+    /// `*T` or `*mut T`
+    Ptr(Mutability, Box<Ty>),
+    /// `[T; N]`
+    Array(Box<Ty>, Box<Expr>),
+    /// `[T]`; A pointer to the first element along with the number of elements
+    Slice(Box<Ty>),
+    /// `dyn Show`; Only allowed behind a pointer
+    Dyn(TraitRef),
+}
+
+/// Prints a type the way it's written, e.g. for types that mention aliases
+impl Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let write_args = |f: &mut fmt::Formatter<'_>, args: &[Ty]| {
+            if !args.is_empty() {
+                let args = args.iter().map(|it| it.to_string()).collect::<Vec<_>>();
+                write!(f, "<{}>", args.join(", "))?;
+            }
+            Ok(())
+        };
+        match &self.kind {
+            TyKind::Tup(items) => {
+                let items = items.iter().map(|it| it.to_string()).collect::<Vec<_>>();
+                if items.len() == 1 {
+                    write!(f, "({},)", items[0])
+                } else {
+                    write!(f, "({})", items.join(", "))
+                }
+            }
+            TyKind::Var(name, args) => {
+                write!(f, "{}", name)?;
+                write_args(f, args)
+            }
+            TyKind::Path(path, args) => {
+                write!(f, "{}", path)?;
+                write_args(f, args)
+            }
+            TyKind::Ptr(Mutability::Not, inner) => write!(f, "*{}", inner),
+            TyKind::Ptr(Mutability::Mut, inner) => write!(f, "*mut {}", inner),
+            TyKind::Array(elem, len) => match &len.kind {
+                ExprKind::Lit(lit) => write!(f, "[{}; {}]", elem, lit.text),
+                ExprKind::Var(var) => write!(f, "[{}; {}]", elem, var.name),
+                ExprKind::Path(path) => write!(f, "[{}; {}]", elem, path),
+                _ => write!(f, "[{}; _]", elem),
+            },
+            TyKind::Slice(elem) => write!(f, "[{}]", elem),
+            TyKind::Dyn(trait_ref) => write!(f, "dyn {}", trait_ref.path),
+        }
+    }
+}
+
+impl Block {
+    /// The expression that gives this block its value, if any.
+    /// A block ending in a `;` or a `let` evaluates to `()`.
+    pub fn tail_expr(&self) -> Option<&Expr> {
+        match self.stmts.last().map(|it| &it.kind) {
+            Some(StmtKind::Expr(expr)) => Some(expr),
+            _ => None,
+        }
+    }
 }
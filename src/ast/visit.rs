@@ -1,4 +1,7 @@
-use super::{Block, Expr, ExprKind, Fn, ForeignItem, Item, Stmt};
+use super::{
+    Arm, Block, Const, Expr, ExprKind, FieldDef, Fn, ForeignItem, Item, Local, Newtype, Param, Pat,
+    PatKind, Static, Stmt, Ty, TyAlias, TyKind, Variant,
+};
 
 pub trait Visitor: Sized {
     fn visit_item(&mut self, item: &Item) {
@@ -13,6 +16,10 @@ pub trait Visitor: Sized {
         walk_stmt(self, stmt)
     }
 
+    fn visit_local(&mut self, local: &Local) {
+        walk_local(self, local)
+    }
+
     fn visit_expr(&mut self, expr: &Expr) {
         walk_expr(self, expr)
     }
@@ -21,9 +28,33 @@ pub trait Visitor: Sized {
         walk_fn(self, f)
     }
 
+    fn visit_param(&mut self, param: &Param) {
+        walk_param(self, param)
+    }
+
     fn visit_foreign_item(&mut self, f: &ForeignItem) {
         walk_foreign_item(self, f)
     }
+
+    fn visit_field_def(&mut self, field: &FieldDef) {
+        walk_field_def(self, field)
+    }
+
+    fn visit_ty(&mut self, ty: &Ty) {
+        walk_ty(self, ty)
+    }
+
+    fn visit_variant(&mut self, variant: &Variant) {
+        walk_variant(self, variant)
+    }
+
+    fn visit_arm(&mut self, arm: &Arm) {
+        walk_arm(self, arm)
+    }
+
+    fn visit_pat(&mut self, pat: &Pat) {
+        walk_pat(self, pat)
+    }
 }
 
 #[macro_export]
@@ -45,10 +76,63 @@ pub fn walk_expr(visitor: &mut impl Visitor, expr: &Expr) {
         E::Unit => (),
         E::Lit(_) => (),
         E::Var(_) => (),
+        E::Path(_) => (),
         E::Call(callee, args) => {
             visitor.visit_expr(callee);
             walk_list!(visitor, visit_expr, args);
         }
+        E::Struct(s) => {
+            for field in &s.fields {
+                visitor.visit_expr(&field.expr);
+            }
+        }
+        E::Field(base, _) => visitor.visit_expr(base),
+        E::MethodCall(receiver, _, args) => {
+            visitor.visit_expr(receiver);
+            walk_list!(visitor, visit_expr, args);
+        }
+        E::Assign(lhs, rhs) => {
+            visitor.visit_expr(lhs);
+            visitor.visit_expr(rhs);
+        }
+        E::Match(scrutinee, arms) => {
+            visitor.visit_expr(scrutinee);
+            walk_list!(visitor, visit_arm, arms);
+        }
+        E::Paren(inner) | E::Unary(_, inner) | E::AddrOf(_, inner) => visitor.visit_expr(inner),
+        E::Binary(_, lhs, rhs) => {
+            visitor.visit_expr(lhs);
+            visitor.visit_expr(rhs);
+        }
+        E::Cast(inner, ty) => {
+            visitor.visit_expr(inner);
+            visitor.visit_ty(ty);
+        }
+        E::Array(elems) | E::Tup(elems) => walk_list!(visitor, visit_expr, elems),
+        E::Repeat(elem, len) | E::Index(elem, len) => {
+            visitor.visit_expr(elem);
+            visitor.visit_expr(len);
+        }
+    }
+}
+
+pub fn walk_arm(visitor: &mut impl Visitor, arm: &Arm) {
+    visitor.visit_pat(&arm.pat);
+    walk_list!(visitor, visit_expr, &arm.guard);
+    visitor.visit_expr(&arm.body);
+}
+
+pub fn walk_pat(visitor: &mut impl Visitor, pat: &Pat) {
+    match &pat.kind {
+        PatKind::Wild | PatKind::Binding(_) | PatKind::Lit(_) | PatKind::Path(_) => {}
+        PatKind::TupleStruct(_, pats) | PatKind::Tuple(pats) => {
+            walk_list!(visitor, visit_pat, pats)
+        }
+        PatKind::Struct(_, fields, _) => {
+            for field in fields {
+                visitor.visit_pat(&field.pat);
+            }
+        }
     }
 }
 
@@ -59,30 +143,88 @@ pub fn walk_block(visitor: &mut impl Visitor, block: &Block) {
 pub fn walk_item(visitor: &mut impl Visitor, item: &Item) {
     use super::ItemKind as I;
     match &item.kind {
-        I::Fn(f) => visitor.visit_fn(&f),
+        I::Fn(f) => visitor.visit_fn(f),
         I::ForeignMod(f) => {
             walk_list!(visitor, visit_foreign_item, &f.items);
         }
+        I::Struct(s) => {
+            walk_list!(visitor, visit_field_def, s.data.fields());
+        }
+        I::Enum(e) => {
+            walk_list!(visitor, visit_variant, &e.variants);
+        }
+        I::Trait(t) => {
+            walk_list!(visitor, visit_item, &t.items);
+        }
+        I::Impl(imp) => {
+            visitor.visit_ty(&imp.self_ty);
+            walk_list!(visitor, visit_item, &imp.items);
+        }
+        I::Mod(m) => {
+            walk_list!(visitor, visit_item, &m.items);
+        }
+        I::Use(_) => {}
+        I::Const(Const { ty, expr }) | I::Static(Static { ty, expr, .. }) => {
+            visitor.visit_ty(ty);
+            visitor.visit_expr(expr);
+        }
+        I::TyAlias(TyAlias { ty, .. }) | I::Newtype(Newtype { ty }) => visitor.visit_ty(ty),
     }
 }
 
+pub fn walk_variant(visitor: &mut impl Visitor, variant: &Variant) {
+    walk_list!(visitor, visit_field_def, variant.data.fields());
+}
+
 pub fn walk_stmt(visitor: &mut impl Visitor, stmt: &Stmt) {
     use super::StmtKind as S;
     match &stmt.kind {
         S::Expr(expr) => visitor.visit_expr(expr),
+        S::Let(local) => visitor.visit_local(local),
         S::Semi => {}
     }
 }
 
+/// The pattern is visited last since its bindings are not in scope in the initializer
+pub fn walk_local(visitor: &mut impl Visitor, local: &Local) {
+    walk_list!(visitor, visit_ty, &local.ty);
+    walk_list!(visitor, visit_expr, &local.init);
+    visitor.visit_pat(&local.pat);
+}
+
 pub fn walk_fn(visitor: &mut impl Visitor, f: &Fn) {
+    walk_list!(visitor, visit_param, &f.params);
+    walk_list!(visitor, visit_ty, &f.return_ty);
     if let Some(body) = &f.body {
         visitor.visit_expr(body);
     }
 }
 
+pub fn walk_param(visitor: &mut impl Visitor, param: &Param) {
+    visitor.visit_ty(&param.ty);
+}
+
 pub fn walk_foreign_item(visitor: &mut impl Visitor, f: &ForeignItem) {
     use super::ForeignItemKind as F;
     match &f.kind {
         F::Fn(f) => visitor.visit_fn(f),
     }
 }
+
+pub fn walk_field_def(visitor: &mut impl Visitor, field: &FieldDef) {
+    visitor.visit_ty(&field.ty);
+}
+
+pub fn walk_ty(visitor: &mut impl Visitor, ty: &Ty) {
+    match &ty.kind {
+        TyKind::Tup(items) | TyKind::Var(_, items) | TyKind::Path(_, items) => {
+            walk_list!(visitor, visit_ty, items)
+        }
+        TyKind::Ptr(_, inner) | TyKind::Slice(inner) => visitor.visit_ty(inner),
+        TyKind::Array(elem, len) => {
+            visitor.visit_ty(elem);
+            visitor.visit_expr(len);
+        }
+        TyKind::Dyn(_) => {}
+    }
+}
@@ -0,0 +1,577 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context, Result};
+use libsyntax::{HasMeta, HasSpan};
+
+use crate::{
+    analysis::{self, resolve::ResolveResult, typeck::TypeckResult},
+    ast::{Ident, SourceFile},
+    cache::Cache,
+    diagnostics,
+    loader::{self, Library},
+    lower,
+    manifest::{self, Package},
+    mono::{self, FnContext},
+    source_map::SourceMap,
+};
+
+/// Resolves and type checks a crate, adding its rendered diagnostics to `log`.
+/// Returns `None` if there were errors.
+pub fn check(
+    source_file: &SourceFile,
+    source_map: &SourceMap,
+    log: &mut String,
+) -> Option<(ResolveResult, TypeckResult)> {
+    let resolve_result = analysis::resolve::resolve(source_file);
+    let typeck_result = analysis::typeck::typeck(source_file, &resolve_result);
+    let diagnostics = resolve_result
+        .diagnostics
+        .iter()
+        .chain(typeck_result.diagnostics.iter())
+        .cloned()
+        .collect::<Vec<_>>();
+    log.push_str(&diagnostics::render(source_map, &diagnostics));
+    if diagnostics.iter().any(|it| it.is_error()) {
+        return None;
+    }
+    Some((resolve_result, typeck_result))
+}
+
+/// The C code of a crate and the libraries it links to
+#[derive(PartialEq)]
+pub struct Output {
+    pub code: String,
+    /// Libraries named by `#[link]` attributes
+    pub link_libs: Vec<String>,
+}
+
+/// Compiles a crate with a `main` function to C, adding its rendered
+/// diagnostics to `log`. Returns `None` if there were errors.
+pub fn compile(
+    source_file: &SourceFile,
+    source_map: &SourceMap,
+    log: &mut String,
+) -> Option<Output> {
+    let (resolve_result, typeck_result) = check(source_file, source_map, log)?;
+    let mono_result = mono::collect(source_file, &resolve_result, &typeck_result);
+    log.push_str(&diagnostics::render(source_map, &mono_result.diagnostics));
+    if !mono_result.diagnostics.is_empty() {
+        return None;
+    }
+    let code = lower::lower_source_file(
+        source_file,
+        source_map,
+        &resolve_result,
+        &typeck_result,
+        &mono_result,
+    );
+    Some(Output {
+        code,
+        link_libs: typeck_result.attrs.link_libs.clone(),
+    })
+}
+
+/// Builds the binaries of the package in `dir` into `dir/target`, or checks
+/// its library if it has no binaries. With `use_cache`, the results of checking
+/// and compiling each file and the object files of C code are kept in
+/// `target/cache` and reused while what they're made from is unchanged.
+pub fn build_package(dir: &Path, use_cache: bool) -> Result<()> {
+    let packages = manifest::load_packages(dir)?;
+    let (root, _) = packages.split_last().expect("The root package is loaded");
+    let mut libraries = libraries(&packages);
+    let target_dir = root.dir.join("target");
+    let cache = use_cache.then(|| Cache::new(target_dir.join("cache")));
+    // The library of the root package is checked on its own, and binaries of
+    // the package can use it like any other dependency
+    let mut deps = root.deps();
+    if let Some(lib) = libraries.pop_if(|it| it.name == *root.name()) {
+        load_and_check(&lib.entry, lib.deps.clone(), &libraries, cache.as_ref())?;
+        deps.push(lib.name.clone());
+        libraries.push(lib);
+    }
+
+    for bin in root.manifest.bin.iter() {
+        let entry = root.dir.join(&bin.entry);
+        let result = loader::load_crate(&entry, deps.clone(), &libraries)?;
+        report_load_errors(&result)?;
+        let Some(output) = compile_cached(&result, cache.as_ref())? else {
+            bail!("Could not compile `{}`", bin.name);
+        };
+        std::fs::create_dir_all(&target_dir)
+            .with_context(|| format!("Could not create {}", target_dir.display()))?;
+        let c_path = target_dir.join(format!("{}.c", bin.name));
+        std::fs::write(&c_path, includes(&packages) + &output.code)
+            .with_context(|| format!("Could not write {}", c_path.display()))?;
+        let mut inputs = vec![c_path];
+        for package in packages.iter() {
+            let sources = package.manifest.c.sources.iter();
+            inputs.extend(sources.map(|it| package.dir.join(it)));
+        }
+        if let Some(cache) = &cache {
+            let headers = headers_key(&packages)?;
+            inputs = inputs
+                .iter()
+                .map(|it| compile_object(cache, it, &headers))
+                .collect::<Result<_>>()?;
+        }
+        let out = target_dir.join(&bin.name);
+        link(&packages, &output.link_libs, &inputs, &out)?;
+    }
+    Ok(())
+}
+
+/// The libraries among packages loaded by [manifest::load_packages]
+pub fn libraries(packages: &[Package]) -> Vec<Library> {
+    packages
+        .iter()
+        .filter_map(|package| {
+            let lib = package.manifest.lib.as_ref()?;
+            Some(Library {
+                name: package.name().clone(),
+                entry: package.dir.join(&lib.entry),
+                deps: package.deps(),
+            })
+        })
+        .collect()
+}
+
+fn load_and_check(
+    entry: &Path,
+    deps: Vec<Ident>,
+    libraries: &[Library],
+    cache: Option<&Cache>,
+) -> Result<()> {
+    let result = loader::load_crate(entry, deps, libraries)?;
+    report_load_errors(&result)?;
+    let keys = file_keys(&result, cache)?;
+    // Only crates without errors are cached, along with their warnings
+    let cached = cache.and_then(|cache| {
+        let logs = keys.iter().map(|it| cache.get(it, "checked"));
+        logs.collect::<Option<Vec<_>>>()
+    });
+    if let Some(logs) = cached {
+        eprint!("{}", logs.concat());
+        return Ok(());
+    }
+    let (checked, logs) = check_files(&result);
+    eprint!("{}", logs.concat());
+    if checked.is_none() {
+        bail!("Could not compile {}", entry.display());
+    }
+    if let Some(cache) = cache {
+        for (key, log) in keys.iter().zip(logs.iter()) {
+            cache.put(key, "checked", log)?;
+        }
+    }
+    Ok(())
+}
+
+/// Like [check], with the diagnostics of each file of the crate rendered apart
+fn check_files(
+    result: &loader::LoadResult,
+) -> (Option<(ResolveResult, TypeckResult)>, Vec<String>) {
+    let source_map = &result.source_map;
+    let resolve_result = analysis::resolve::resolve(&result.source_file);
+    let typeck_result = analysis::typeck::typeck(&result.source_file, &resolve_result);
+    let diagnostics = resolve_result
+        .diagnostics
+        .iter()
+        .chain(typeck_result.diagnostics.iter());
+    let mut logs = vec![String::new(); source_map.files().len()];
+    for diagnostic in diagnostics.clone() {
+        let log = &mut logs[file_index(source_map, diagnostic.span.start)];
+        log.push_str(&diagnostics::render(
+            source_map,
+            std::slice::from_ref(diagnostic),
+        ));
+    }
+    if diagnostics.clone().any(|it| it.is_error()) {
+        return (None, logs);
+    }
+    (Some((resolve_result, typeck_result)), logs)
+}
+
+/// Like [compile] with the diagnostics printed. With a `cache`, the C code of
+/// the functions of each file is reused while the file's key and the instances
+/// of its functions are unchanged.
+fn compile_cached(result: &loader::LoadResult, cache: Option<&Cache>) -> Result<Option<Output>> {
+    let Some(cache) = cache else {
+        let mut log = String::new();
+        let output = compile(&result.source_file, &result.source_map, &mut log);
+        eprint!("{}", log);
+        return Ok(output);
+    };
+    let keys = file_keys(result, Some(cache))?;
+    let (source_file, source_map) = (&result.source_file, &result.source_map);
+    let (checked, logs) = check_files(result);
+    eprint!("{}", logs.concat());
+    let Some((resolve, typeck)) = checked else {
+        return Ok(None);
+    };
+    let mono = mono::collect(source_file, &resolve, &typeck);
+    eprint!("{}", diagnostics::render(source_map, &mono.diagnostics));
+    if !mono.diagnostics.is_empty() {
+        return Ok(None);
+    }
+    let fns = source_file
+        .fns()
+        .map(|(item, f)| (item.id(), (item, f)))
+        .collect::<HashMap<_, _>>();
+    let mut instances = vec![vec![]; keys.len()];
+    for instance in mono.instances.iter() {
+        let (item, _) = fns[&instance.def];
+        instances[file_index(source_map, item.span().start)].push(instance);
+    }
+    let mut code = lower::lower_interface(source_file, &resolve, &typeck, &mono);
+    for (file_key, instances) in keys.iter().zip(instances) {
+        let names = instances
+            .iter()
+            .map(|it| lower::fn_c_name(&resolve, &typeck, it.def, &it.args))
+            .collect::<Vec<_>>();
+        let key = Cache::key(&[file_key.as_bytes(), names.join("\n").as_bytes()]);
+        if let Some(cached) = cache.get(&key, "c") {
+            code.push_str(&cached);
+            continue;
+        }
+        let mut file_code = String::new();
+        for instance in instances {
+            let (item, f) = fns[&instance.def];
+            let f = FnContext {
+                item,
+                f,
+                resolve: &resolve,
+                typeck: &typeck,
+            };
+            file_code.push_str(&lower::lower_instance(source_map, f, &instance.args));
+        }
+        cache.put(&key, "c", &file_code)?;
+        code.push_str(&file_code);
+    }
+    Ok(Some(Output {
+        code,
+        link_libs: typeck.attrs.link_libs.clone(),
+    }))
+}
+
+/// The cache keys of the files of a crate, in the order of the source map.
+/// Each covers the path and text of its file, the signatures of the other files
+/// and the libraries the crate can refer to, so that editing the body of a
+/// function leaves the keys of the other files unchanged. The signatures are
+/// kept in the cache too, keyed by the text they're taken from.
+fn file_keys(result: &loader::LoadResult, cache: Option<&Cache>) -> Result<Vec<String>> {
+    let files = result.source_map.files();
+    let mut bodies = vec![vec![]; files.len()];
+    for (_, f) in result.source_file.fns() {
+        if let Some(body) = &f.body {
+            let span = *body.span();
+            let index = file_index(&result.source_map, span.start);
+            let start = files[index].start;
+            bodies[index].push((span.start - start)..(span.end - start));
+        }
+    }
+    let mut interfaces = vec![];
+    for (file, bodies) in files.iter().zip(bodies.iter()) {
+        let path = file.path.as_os_str().as_encoded_bytes();
+        let text_key = Cache::key(&[path, file.text.as_bytes()]);
+        let signature = match cache.and_then(|it| it.get(&text_key, "sig")) {
+            Some(signature) => signature,
+            None => {
+                let signature = signature(&file.text, bodies);
+                if let Some(cache) = cache {
+                    cache.put(&text_key, "sig", &signature)?;
+                }
+                signature
+            }
+        };
+        interfaces.push(Cache::key(&[path, signature.as_bytes()]));
+    }
+    let deps = result.source_file.deps.iter().map(|it| it.as_bytes());
+    let keys = files.iter().enumerate().map(|(i, file)| {
+        let mut inputs = vec![
+            file.path.as_os_str().as_encoded_bytes(),
+            file.text.as_bytes(),
+        ];
+        let others = interfaces.iter().enumerate().filter(|(j, _)| *j != i);
+        inputs.extend(others.map(|(_, it)| it.as_bytes()));
+        inputs.extend(deps.clone());
+        Cache::key(&inputs)
+    });
+    Ok(keys.collect())
+}
+
+/// The text of a file with the bodies of its functions left out. Diagnostics
+/// and runtime errors are located within their own file, so how the items
+/// of the other files move doesn't matter.
+fn signature(text: &str, bodies: &[Range<usize>]) -> String {
+    text.chars()
+        .enumerate()
+        .filter(|(i, _)| !bodies.iter().any(|it| it.start < *i && *i + 1 < it.end))
+        .map(|(_, c)| c)
+        .collect()
+}
+
+/// The index in the source map of the file containing `offset`
+fn file_index(source_map: &SourceMap, offset: usize) -> usize {
+    let files = source_map.files();
+    files.iter().rposition(|it| it.start <= offset).unwrap_or(0)
+}
+
+/// The paths and contents of the C headers of all packages, which any C file
+/// may include
+fn headers_key(packages: &[Package]) -> Result<Vec<u8>> {
+    let mut key = vec![];
+    for package in packages {
+        for header in package.manifest.c.headers.iter() {
+            let path = package.dir.join(header);
+            let text = std::fs::read(&path)
+                .with_context(|| format!("Could not read {}", path.display()))?;
+            key.extend(path.as_os_str().as_encoded_bytes());
+            key.extend(Cache::key(&[&text]).bytes());
+        }
+    }
+    Ok(key)
+}
+
+/// Compiles a C file to an object file in the cache, unless the cache already
+/// has one for the same file, headers and C compiler
+fn compile_object(cache: &Cache, source: &Path, headers: &[u8]) -> Result<PathBuf> {
+    let text =
+        std::fs::read(source).with_context(|| format!("Could not read {}", source.display()))?;
+    let compiler = c_compiler();
+    let key = Cache::key(&[compiler.as_bytes(), &text, headers]);
+    let object = cache.path(&key, "o");
+    if object.exists() {
+        return Ok(object);
+    }
+    let temp = cache.temp_path(&object);
+    let status = Command::new(&compiler)
+        .arg("-c")
+        .arg(source)
+        .arg("-o")
+        .arg(&temp)
+        .status()
+        .with_context(|| format!("Could not run the C compiler `{}`", compiler))?;
+    if !status.success() {
+        bail!("The C compiler failed to compile {}", source.display());
+    }
+    std::fs::rename(&temp, &object)
+        .with_context(|| format!("Could not write {}", object.display()))?;
+    Ok(object)
+}
+
+fn report_load_errors(result: &loader::LoadResult) -> Result<()> {
+    eprint!(
+        "{}",
+        diagnostics::render(&result.source_map, &result.diagnostics)
+    );
+    if !result.diagnostics.is_empty() {
+        bail!("Could not load {}", result.source_file.path.display());
+    }
+    Ok(())
+}
+
+/// `#include`s for the C headers of all packages
+fn includes(packages: &[Package]) -> String {
+    packages
+        .iter()
+        .flat_map(|package| {
+            let dir = &package.dir;
+            package
+                .manifest
+                .c
+                .headers
+                .iter()
+                .map(move |it| format!("#include \"{}\"\n", dir.join(it).display()))
+        })
+        .collect()
+}
+
+/// Compiles and links C files and object files, using the compiler in
+/// `$CC` or `cc`. `link_libs` are the libraries named in the code, which are
+/// linked along with those in the manifests.
+fn link(packages: &[Package], link_libs: &[String], inputs: &[PathBuf], out: &Path) -> Result<()> {
+    let compiler = c_compiler();
+    let mut command = Command::new(&compiler);
+    command.args(inputs).arg("-o").arg(out);
+    for package in packages {
+        let libs = package.manifest.c.libs.iter();
+        command.args(libs.map(|it| format!("-l{}", it)));
+    }
+    command.args(link_libs.iter().map(|it| format!("-l{}", it)));
+    let status = command
+        .status()
+        .with_context(|| format!("Could not run the C compiler `{}`", compiler))?;
+    if !status.success() {
+        bail!("The C compiler failed to build {}", out.display());
+    }
+    Ok(())
+}
+
+fn c_compiler() -> String {
+    std::env::var("CC").unwrap_or_else(|_| "cc".to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, time::SystemTime};
+
+    use super::*;
+    use crate::testing::write_files;
+
+    /// The artifacts in the cache with an extension, along with when they
+    /// were written
+    fn artifacts(dir: &Path, extension: &str) -> Vec<(PathBuf, SystemTime)> {
+        let mut artifacts = std::fs::read_dir(dir.join("target/cache"))
+            .unwrap()
+            .map(|it| it.unwrap().path())
+            .filter(|it| it.extension().is_some_and(|it| it == extension))
+            .map(|it| {
+                let modified = it.metadata().unwrap().modified().unwrap();
+                (it, modified)
+            })
+            .collect::<Vec<_>>();
+        artifacts.sort();
+        artifacts
+    }
+
+    const MARKER: &str = "/* from the cache */";
+
+    /// Marks the cached C code, so builds that reuse it can be told apart
+    fn mark_cached_code(dir: &Path) {
+        for (path, _) in artifacts(dir, "c") {
+            let code = std::fs::read_to_string(&path).unwrap();
+            std::fs::write(path, code + MARKER).unwrap();
+        }
+    }
+
+    /// The distinct signatures in the cache
+    fn signatures(dir: &Path) -> HashSet<String> {
+        let signatures = artifacts(dir, "sig").into_iter();
+        signatures
+            .map(|(path, _)| std::fs::read_to_string(path).unwrap())
+            .collect()
+    }
+
+    fn built_code(dir: &Path) -> String {
+        std::fs::read_to_string(dir.join("target/app.c")).unwrap()
+    }
+
+    #[test]
+    fn test_files_print_their_expected_output() {
+        let dir = write_files("test-files", &[]);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut paths = std::fs::read_dir("test")
+            .unwrap()
+            .map(|it| it.unwrap().path())
+            .filter(|it| it.extension().is_some_and(|it| it == "hds"))
+            .filter(|it| it.with_extension("stdout").exists())
+            .collect::<Vec<_>>();
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let result = loader::load_crate(&path, vec![], &[]).unwrap();
+            report_load_errors(&result).unwrap();
+            let mut log = String::new();
+            let Some(output) = compile(&result.source_file, &result.source_map, &mut log) else {
+                panic!("{} didn't compile:\n{}", path.display(), log);
+            };
+            let name = path.file_stem().unwrap();
+            let c_path = dir.join(name).with_extension("c");
+            std::fs::write(&c_path, &output.code).unwrap();
+            let out = dir.join(name);
+            link(&[], &output.link_libs, &[c_path], &out).unwrap();
+            let run = Command::new(&out).output().unwrap();
+            let expected = std::fs::read_to_string(path.with_extension("stdout")).unwrap();
+            assert_eq!(
+                String::from_utf8_lossy(&run.stdout),
+                expected,
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn reuses_cached_artifacts_until_sources_change() {
+        let dir = write_files(
+            "cache",
+            &[
+                (
+                    "hades.toml",
+                    r#"
+                    [package]
+                    name = "app"
+
+                    [lib]
+                    entry = "src/lib.hds"
+
+                    [[bin]]
+                    name = "app"
+                    entry = "src/main.hds"
+
+                    [c]
+                    sources = ["native/seven.c"]
+                    headers = ["native/seven.h"]
+                    "#,
+                ),
+                ("native/seven.h", "int seven(void);\n"),
+                ("native/seven.c", "int seven(void) { return 7; }\n"),
+                (
+                    "src/lib.hds",
+                    "extern {\n    fn seven() -> i32;\n}\n\npub fn get() -> i32 {\n    unsafe { seven() }\n}\n",
+                ),
+                ("src/main.hds", "fn main() -> () {\n    app::get();\n}\n"),
+            ],
+        );
+        build_package(&dir, true).unwrap();
+        assert!(dir.join("target/app").exists());
+        let checked = artifacts(&dir, "checked");
+        let objects = artifacts(&dir, "o");
+        assert_eq!((checked.len(), objects.len()), (1, 2));
+
+        // The C code is reused, and so is the object of the unchanged C source
+        mark_cached_code(&dir);
+        build_package(&dir, true).unwrap();
+        assert_eq!(built_code(&dir).matches(MARKER).count(), 2);
+        assert_eq!(artifacts(&dir, "checked"), checked);
+        let rebuilt = artifacts(&dir, "o");
+        assert_eq!(rebuilt.len(), 3);
+        assert!(objects.iter().all(|it| rebuilt.contains(it)));
+
+        build_package(&dir, false).unwrap();
+        assert!(!built_code(&dir).contains(MARKER));
+
+        // Editing the binary only compiles its file again
+        let main = dir.join("src/main.hds");
+        std::fs::write(
+            &main,
+            "fn main() -> () {\n    app::get();\n    app::get();\n}\n",
+        )
+        .unwrap();
+        build_package(&dir, true).unwrap();
+        assert_eq!(built_code(&dir).matches(MARKER).count(), 1);
+        assert_eq!(artifacts(&dir, "checked"), checked);
+        assert_eq!(artifacts(&dir, "c").len(), 3);
+
+        // Editing the body of a function of the library leaves its signature
+        // as it was, so the code of the binary's file is reused
+        let before = signatures(&dir);
+        mark_cached_code(&dir);
+        std::fs::write(
+            dir.join("src/lib.hds"),
+            "extern {\n    fn seven() -> i32;\n}\n\npub fn get() -> i32 {\n    let it = unsafe { seven() };\n    it\n}\n",
+        )
+        .unwrap();
+        build_package(&dir, true).unwrap();
+        assert_eq!(built_code(&dir).matches(MARKER).count(), 1);
+        assert!(built_code(&dir).contains("int32_t it = "));
+        assert_eq!(artifacts(&dir, "checked").len(), 2);
+        assert_eq!(artifacts(&dir, "c").len(), 4);
+        assert_eq!(signatures(&dir), before);
+    }
+}
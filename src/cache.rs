@@ -0,0 +1,94 @@
+//! Artifacts of earlier builds, kept in `target/cache` and reused while what
+//! they're made from is unchanged. Each artifact is named by a key hashing its
+//! inputs along with the version of the compiler, so changed inputs give a new
+//! key rather than invalidating an old one.
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf) -> Self {
+        Cache { dir }
+    }
+
+    /// A key for the artifacts made from `inputs` by this compiler
+    pub fn key(inputs: &[&[u8]]) -> String {
+        let mut hash = Fnv::new();
+        hash.add(compiler_version().as_bytes());
+        inputs.iter().for_each(|it| hash.add(it));
+        format!("{:016x}", hash.0)
+    }
+
+    /// Where the artifact with a key and an extension is stored
+    pub fn path(&self, key: &str, extension: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", key, extension))
+    }
+
+    /// The contents of an artifact, if it has been stored
+    pub fn get(&self, key: &str, extension: &str) -> Option<String> {
+        std::fs::read_to_string(self.path(key, extension)).ok()
+    }
+
+    /// Stores an artifact, replacing it at once so that builds running at the
+    /// same time never see part of it
+    pub fn put(&self, key: &str, extension: &str, contents: &str) -> Result<()> {
+        let path = self.path(key, extension);
+        let temp = self.temp_path(&path);
+        std::fs::write(&temp, contents)
+            .and_then(|_| std::fs::rename(&temp, &path))
+            .with_context(|| format!("Could not write {}", path.display()))
+    }
+
+    /// A path in the cache to build an artifact at before it's moved to `path`
+    pub fn temp_path(&self, path: &Path) -> PathBuf {
+        let _ = std::fs::create_dir_all(&self.dir);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        self.dir
+            .join(format!("{}.{}.tmp", name, std::process::id()))
+    }
+}
+
+/// Identifies the compiler. The size and modification time of the executable
+/// tell apart builds of the compiler with the same version number.
+fn compiler_version() -> String {
+    let exe = std::env::current_exe().and_then(std::fs::metadata);
+    let build = exe.map_or(String::new(), |it| {
+        format!("{} {:?}", it.len(), it.modified().ok())
+    });
+    format!("{} {}", env!("CARGO_PKG_VERSION"), build)
+}
+
+/// The 64 bit FNV-1a hash, which unlike the hashers of the standard library is
+/// the same across Rust versions. Each input is preceded by its length, so that
+/// moving bytes from one input to the next changes the hash.
+struct Fnv(u64);
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn add(&mut self, bytes: &[u8]) {
+        let len = (bytes.len() as u64).to_le_bytes();
+        for byte in len.iter().chain(bytes) {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keys_depend_on_how_inputs_are_split() {
+        let key = Cache::key(&[b"ab", b"c"]);
+        assert_eq!(key, Cache::key(&[b"ab", b"c"]));
+        assert_ne!(key, Cache::key(&[b"a", b"bc"]));
+        assert_ne!(key, Cache::key(&[b"abc"]));
+    }
+}
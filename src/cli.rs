@@ -1,15 +1,38 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct CliArgs {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// A single file to compile. Without it, the package described by the
+    /// hades.toml in the current directory is built.
     #[arg(short, long)]
-    pub input: PathBuf,
+    pub input: Option<PathBuf>,
     #[arg(short, long)]
     pub output: Option<String>,
+    /// Rebuilds everything instead of reusing the artifacts of earlier builds
+    /// in target/cache
+    #[arg(long)]
+    pub no_cache: bool,
 }
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Runs the language server, speaking LSP over stdin and stdout
+    Lsp,
+    /// Formats .hds files in place, searching directories for them
+    Fmt {
+        /// Files and directories to format; The current directory by default
+        paths: Vec<PathBuf>,
+        /// Lists the files that aren't formatted instead of formatting them,
+        /// and fails if there are any
+        #[arg(long)]
+        check: bool,
+    },
+}
+
 impl CliArgs {
     // To avoid having to import Parser trait by name, which conflicts
     // with the syntax parser, we re-export the parse method here.
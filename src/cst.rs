@@ -0,0 +1,498 @@
+//! A lossless concrete syntax tree: the tokens of a file along with the
+//! whitespace and comments around them, grouped into nodes that cover the same
+//! tokens as the nodes of the AST. Printing a tree gives back the text it was
+//! parsed from, character for character, even if it has syntax errors.
+
+use std::{
+    cmp::Reverse,
+    fmt::{self, Display},
+    path::PathBuf,
+};
+
+use libsyntax::{HasSpan, Span};
+
+use crate::{
+    ast::{
+        self,
+        visit::{self, Visitor},
+        SourceFile,
+    },
+    diagnostics::Diagnostic,
+    lexer::{Lexer, TokenKind, Trivia, TriviaKind},
+    parser::Parser,
+};
+
+#[derive(Debug)]
+pub struct SyntaxToken {
+    pub span: Span,
+    pub text: String,
+    /// The trivia from the end of the previous token's line up to the token
+    pub leading: Vec<Trivia>,
+    /// The trivia after the token up to the end of its line
+    pub trailing: Vec<Trivia>,
+}
+
+#[derive(Debug)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+/// The tokens in the span of an AST node, or of the whole file for the root.
+/// Nodes of the AST with the same span, such as an expression statement and
+/// its expression, are a single node.
+#[derive(Debug)]
+pub struct SyntaxNode {
+    pub span: Span,
+    pub children: Vec<SyntaxElement>,
+}
+
+pub struct Parse {
+    pub tree: SyntaxNode,
+    pub source_file: SourceFile,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Parses a file into both its syntax tree and its AST, without loading the
+/// files of its modules
+pub fn parse(text: &str, path: PathBuf) -> Parse {
+    let mut parser = Parser::new(text, path);
+    let source_file = parser.parse_source_file();
+    let diagnostics = parser.take_diagnostics();
+    let mut spans = NodeSpans(vec![]);
+    for item in &source_file.items {
+        spans.visit_item(item);
+    }
+    let len = text.chars().count();
+    Parse {
+        tree: build(lex(text), spans.0, len),
+        source_file,
+        diagnostics,
+    }
+}
+
+/// The tokens of a text with their trivia, ending with an EOF token
+pub fn lex(text: &str) -> Vec<SyntaxToken> {
+    let mut lexer = Lexer::new(text, PathBuf::new()).keeping_trivia();
+    let mut tokens: Vec<SyntaxToken> = vec![];
+    loop {
+        let token = lexer.next_token();
+        let mut leading = lexer.take_trivia();
+        if let Some(prev) = tokens.last_mut() {
+            prev.trailing = split_line(&mut leading);
+        }
+        let mut trailing = vec![];
+        if token.kind == TokenKind::EOF {
+            // The lexer stops at a `\0`
+            let rest = text.chars().skip(token.span.start).collect::<String>();
+            if !rest.is_empty() {
+                trailing.push(Trivia {
+                    kind: TriviaKind::Skipped,
+                    span: Span {
+                        start: token.span.start,
+                        end: token.span.start + rest.chars().count(),
+                    },
+                    text: rest,
+                });
+            }
+        }
+        tokens.push(SyntaxToken {
+            span: token.span,
+            text: token.text,
+            leading,
+            trailing,
+        });
+        if token.kind == TokenKind::EOF {
+            return tokens;
+        }
+    }
+}
+
+/// Removes the trivia up to the first line break from `trivia`
+fn split_line(trivia: &mut Vec<Trivia>) -> Vec<Trivia> {
+    let mut line = vec![];
+    while let Some(first) = trivia.first_mut() {
+        if let Some(i) = first.text.find('\n') {
+            if i > 0 {
+                let len = first.text[..i].chars().count();
+                let start = first.span.start;
+                line.push(Trivia {
+                    kind: first.kind,
+                    span: Span {
+                        start,
+                        end: start + len,
+                    },
+                    text: first.text.drain(..i).collect(),
+                });
+                first.span.start += len;
+            }
+            break;
+        }
+        line.push(trivia.remove(0));
+    }
+    line
+}
+
+/// Groups tokens into nodes with the given spans, which are nested or disjoint
+fn build(tokens: Vec<SyntaxToken>, mut spans: Vec<Span>, len: usize) -> SyntaxNode {
+    spans.retain(|it| it.start < it.end);
+    spans.sort_by_key(|it| (it.start, Reverse(it.end)));
+    spans.dedup();
+    let mut stack = vec![SyntaxNode {
+        span: Span { start: 0, end: len },
+        children: vec![],
+    }];
+    let mut spans = spans.into_iter().peekable();
+    for token in tokens {
+        while stack.len() > 1 && stack.last().unwrap().span.end <= token.span.start {
+            close(&mut stack);
+        }
+        while let Some(span) = spans.next_if(|it| it.start <= token.span.start) {
+            // Spans that start inside a token or overlap their parent are left out
+            let parent = stack.last().unwrap().span;
+            if span.start == token.span.start && span.end <= parent.end {
+                stack.push(SyntaxNode {
+                    span,
+                    children: vec![],
+                });
+            }
+        }
+        stack
+            .last_mut()
+            .unwrap()
+            .children
+            .push(SyntaxElement::Token(token));
+    }
+    while stack.len() > 1 {
+        close(&mut stack);
+    }
+    stack.pop().unwrap()
+}
+
+fn close(stack: &mut Vec<SyntaxNode>) {
+    let node = stack.pop().unwrap();
+    let parent = stack.last_mut().unwrap();
+    parent.children.push(SyntaxElement::Node(node));
+}
+
+/// The spans of the AST nodes of a file
+struct NodeSpans(Vec<Span>);
+
+impl Visitor for NodeSpans {
+    fn visit_item(&mut self, item: &ast::Item) {
+        self.0.push(*item.span());
+        visit::walk_item(self, item);
+    }
+
+    fn visit_block(&mut self, block: &ast::Block) {
+        self.0.push(*block.span());
+        visit::walk_block(self, block);
+    }
+
+    fn visit_stmt(&mut self, stmt: &ast::Stmt) {
+        self.0.push(*stmt.span());
+        visit::walk_stmt(self, stmt);
+    }
+
+    fn visit_local(&mut self, local: &ast::Local) {
+        self.0.push(*local.span());
+        visit::walk_local(self, local);
+    }
+
+    fn visit_expr(&mut self, expr: &ast::Expr) {
+        self.0.push(*expr.span());
+        visit::walk_expr(self, expr);
+    }
+
+    fn visit_fn(&mut self, f: &ast::Fn) {
+        self.0.push(*f.span());
+        visit::walk_fn(self, f);
+    }
+
+    fn visit_param(&mut self, param: &ast::Param) {
+        self.0.push(*param.span());
+        visit::walk_param(self, param);
+    }
+
+    fn visit_foreign_item(&mut self, f: &ast::ForeignItem) {
+        self.0.push(*f.span());
+        visit::walk_foreign_item(self, f);
+    }
+
+    fn visit_field_def(&mut self, field: &ast::FieldDef) {
+        self.0.push(*field.span());
+        visit::walk_field_def(self, field);
+    }
+
+    fn visit_ty(&mut self, ty: &ast::Ty) {
+        self.0.push(*ty.span());
+        visit::walk_ty(self, ty);
+    }
+
+    fn visit_variant(&mut self, variant: &ast::Variant) {
+        self.0.push(*variant.span());
+        visit::walk_variant(self, variant);
+    }
+
+    fn visit_arm(&mut self, arm: &ast::Arm) {
+        self.0.push(*arm.span());
+        visit::walk_arm(self, arm);
+    }
+
+    fn visit_pat(&mut self, pat: &ast::Pat) {
+        self.0.push(*pat.span());
+        visit::walk_pat(self, pat);
+    }
+}
+
+impl SyntaxNode {
+    /// The tokens of the node in order
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut tokens = vec![];
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'a>(&'a self, out: &mut Vec<&'a SyntaxToken>) {
+        for child in &self.children {
+            match child {
+                SyntaxElement::Node(node) => node.collect_tokens(out),
+                SyntaxElement::Token(token) => out.push(token),
+            }
+        }
+    }
+}
+
+impl HasSpan for SyntaxElement {
+    fn span(&self) -> &Span {
+        match self {
+            SyntaxElement::Node(node) => &node.span,
+            SyntaxElement::Token(token) => &token.span,
+        }
+    }
+}
+
+impl Display for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for trivia in &self.leading {
+            write!(f, "{}", trivia.text)?;
+        }
+        write!(f, "{}", self.text)?;
+        for trivia in &self.trailing {
+            write!(f, "{}", trivia.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints the text the tree was parsed from
+impl Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for token in self.tokens() {
+            write!(f, "{}", token)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    fn round_trips(text: &str) {
+        let tokens = lex(text);
+        assert_eq!(
+            tokens.iter().map(|it| it.to_string()).collect::<String>(),
+            text
+        );
+        let tree = parse(text, PathBuf::from("test.hds")).tree;
+        assert_eq!(tree.to_string(), text, "{:?}", text);
+        // Spans are where the text of tokens and trivia is
+        let chars = text.chars().collect::<Vec<_>>();
+        for token in tree.tokens() {
+            let trivia = token.leading.iter().chain(&token.trailing);
+            for (span, text) in trivia
+                .map(|it| (it.span, &it.text))
+                .chain([(token.span, &token.text)])
+            {
+                assert_eq!(
+                    chars[span.start..span.end].iter().collect::<String>(),
+                    *text
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn attaches_trivia_to_tokens() {
+        let tokens = lex("fn main() { // Starts\n    x; // Ends\n\n}\n");
+        let texts = |trivia: &[Trivia]| trivia.iter().map(|it| it.text.clone()).collect::<Vec<_>>();
+        let brace = &tokens[4];
+        assert_eq!(brace.text, "{");
+        assert_eq!(texts(&brace.trailing), [" ", "// Starts"]);
+        let x = &tokens[5];
+        assert_eq!(
+            (texts(&x.leading), texts(&x.trailing)),
+            (vec!["\n    ".to_string()], vec![])
+        );
+        let semi = &tokens[6];
+        assert_eq!(texts(&semi.trailing), [" ", "// Ends"]);
+        assert_eq!(semi.trailing[1].kind, TriviaKind::Comment);
+        assert_eq!(texts(&tokens[7].leading), ["\n\n"]);
+        let eof = tokens.last().unwrap();
+        assert_eq!(
+            (eof.text.as_str(), texts(&eof.leading)),
+            ("", vec!["\n".to_string()])
+        );
+    }
+
+    /// The spans of the token at `offset` and of the nodes around it,
+    /// innermost first
+    fn spans_at(tree: &SyntaxNode, offset: usize) -> Vec<Span> {
+        let mut spans = vec![tree.span];
+        let mut node = tree;
+        loop {
+            let child = node.children.iter().find(|it| {
+                let span = it.span();
+                span.start <= offset && offset < span.end
+            });
+            match child {
+                Some(SyntaxElement::Node(child)) => {
+                    spans.push(child.span);
+                    node = child;
+                }
+                Some(SyntaxElement::Token(token)) => {
+                    spans.push(token.span);
+                    break;
+                }
+                None => break,
+            }
+        }
+        spans.dedup();
+        spans.reverse();
+        spans
+    }
+
+    #[test]
+    fn groups_tokens_like_the_ast() {
+        let text = "fn f(a: i32) -> i32 { a + 1 }";
+        let tree = parse(text, PathBuf::from("test.hds")).tree;
+        let texts = |offset| {
+            spans_at(&tree, offset)
+                .into_iter()
+                .map(|span| {
+                    text.chars()
+                        .skip(span.start)
+                        .take(span.end - span.start)
+                        .collect()
+                })
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(
+            texts(22),
+            ["a", "a + 1", "{ a + 1 }", "fn f(a: i32) -> i32 { a + 1 }"]
+        );
+        assert_eq!(texts(9), ["i32", "a: i32", "fn f(a: i32) -> i32 { a + 1 }"]);
+    }
+
+    /// A tiny xorshift generator, for reproducible random texts
+    pub(crate) struct Random(pub u64);
+
+    impl Random {
+        pub fn next(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+    }
+
+    #[test]
+    fn prints_random_texts_back() {
+        let pieces = [
+            "fn",
+            "main",
+            "(",
+            ")",
+            "{",
+            "}",
+            "[",
+            "]",
+            "#",
+            ";",
+            ",",
+            ":",
+            "::",
+            "->",
+            "=",
+            "=>",
+            ".",
+            "..",
+            "...",
+            "&",
+            "&&",
+            "|",
+            "||",
+            "+",
+            "-",
+            "*",
+            "/",
+            "//",
+            "///",
+            "!",
+            "<",
+            ">",
+            "\"",
+            "\\",
+            "\"a\\\"b\"",
+            "1",
+            "23",
+            "x_1",
+            "é",
+            "🦀",
+            "let",
+            "match",
+            "struct",
+            " ",
+            "  ",
+            "\t",
+            "\n",
+            "\r\n",
+            "\n\n",
+            "\0",
+            "\u{a0}",
+        ];
+        let mut random = Random(0x2545f4914f6cdd1d);
+        for _ in 0..2000 {
+            let len = random.next(40);
+            let text = (0..len)
+                .map(|_| pieces[random.next(pieces.len())])
+                .collect::<String>();
+            round_trips(&text);
+        }
+    }
+
+    #[test]
+    fn prints_the_test_files_back_with_edits() {
+        let mut random = Random(0x9e3779b97f4a7c15);
+        for entry in std::fs::read_dir("test").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|it| it != "hds") {
+                continue;
+            }
+            let text = std::fs::read_to_string(&path).unwrap();
+            round_trips(&text);
+            // Deleting parts of files leaves syntax errors
+            let chars = text.chars().collect::<Vec<_>>();
+            for _ in 0..20 {
+                let start = random.next(chars.len());
+                let end = start + random.next(20).min(chars.len() - start);
+                let edited = chars[..start]
+                    .iter()
+                    .chain(&chars[end..])
+                    .collect::<String>();
+                round_trips(&edited);
+            }
+        }
+    }
+}
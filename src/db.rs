@@ -0,0 +1,1002 @@
+//! A demand-driven compiler: each step of compiling a crate is a query whose
+//! result is memoized along with the queries it read, so that after an edit a
+//! result is only recomputed if a query it read has changed. When a recomputed
+//! result is the same as before, the queries that read it aren't recomputed,
+//! which is what keeps an edit local:
+//!
+//! - Every item of the crate is moved to coordinates of its own by
+//!   [Database::item_tree], so that editing one item doesn't change the others.
+//! - The signature of an item is its tree with function bodies left out, and
+//!   [Database::skeleton] is the crate made of signatures, which is resolved
+//!   and type checked once for everything that items may refer to.
+//! - The body of a function is resolved and type checked on its own against
+//!   the results for the skeleton. Editing a body only changes that item's
+//!   queries, the lowering of the functions in its file, and the queries that
+//!   combine all items.
+//!
+//! In the skeleton, each item of [ItemList] has a range of offsets as long as
+//! its signature, which its spans are relative to. The whole item is at the
+//! same offset when its body is checked, so the spans in the body may reach
+//! past that range; Their diagnostics are mapped back to the source map by
+//! the item they come from rather than by their spans. The node ids of an item
+//! start at the sum of the number of ids in the signatures before it, and ids
+//! of bodies come after those of all signatures.
+//!
+//! A query reading the keys of items always reads [Database::item_list] first,
+//! so it's recomputed rather than checked when the items of the crate change.
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use libsyntax::{HasMeta, HasSpan, Meta, NodeId, Span, WalkMeta};
+
+use crate::{
+    analysis::{
+        resolve::{self, ResolveResult},
+        typeck::{self, TypeckResult},
+    },
+    ast::{Block, Expr, ExprKind, Item, ItemKind, SourceFile},
+    build::Output,
+    diagnostics::Diagnostic,
+    loader::{self, LoadResult},
+    lower,
+    mono::{self, FnContext, Instance, MonoResult},
+    parser::ParsedFile,
+    source_map::SourceMap,
+};
+
+/// Identifies an item by its path in the crate, e.g. `shapes::Point`. The items
+/// of a module that have the same name, such as impls, are told apart by their
+/// position, e.g. `shapes::impl#1` for the second impl.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ItemKey(pub String);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Query {
+    FileText(PathBuf),
+    ParseFile(PathBuf),
+    CrateTree,
+    ItemList,
+    ItemTree(ItemKey),
+    ItemSignature(ItemKey),
+    ItemSource(ItemKey),
+    Skeleton,
+    ResolveSkeleton,
+    TypeckSkeleton,
+    ItemBody(ItemKey),
+    ResolveItem(ItemKey),
+    TypeckItem(ItemKey),
+    ItemDiagnostics(ItemKey),
+    Diagnostics,
+    Mono,
+    Interface,
+    FileSourceMap(PathBuf),
+    LowerInstance(Instance),
+    Compile,
+}
+
+pub struct Database {
+    /// The file of the crate root
+    root: PathBuf,
+    /// Increases whenever an input changes
+    revision: u64,
+    memos: RefCell<HashMap<Query, Memo>>,
+    /// For each query being computed, the queries it has read so far
+    active: RefCell<Vec<Vec<Query>>>,
+    #[cfg(test)]
+    executed: RefCell<Vec<Query>>,
+}
+
+struct Memo {
+    value: Rc<dyn Any>,
+    /// The revision the value last changed in
+    changed_at: u64,
+    /// The last revision the value is known to be up to date in
+    verified_at: u64,
+    /// The queries read to compute the value, in the order they were read
+    deps: Vec<Query>,
+}
+
+/// A result that isn't compared with the previous one, so the queries that
+/// read it are recomputed whenever it is
+pub struct Opaque<T>(pub T);
+impl<T> PartialEq for Opaque<T> {
+    fn eq(&self, _: &Self) -> bool {
+        false
+    }
+}
+
+impl Database {
+    pub fn new(root: PathBuf) -> Self {
+        Database {
+            root,
+            revision: 0,
+            memos: RefCell::new(HashMap::new()),
+            active: RefCell::new(vec![]),
+            #[cfg(test)]
+            executed: RefCell::new(vec![]),
+        }
+    }
+
+    /// Sets the text of a file instead of reading it from disk, which is how
+    /// the tests edit files. Paths are as the source map has them.
+    #[cfg(test)]
+    pub fn set_file_text(&mut self, path: &Path, text: String) {
+        let query = Query::FileText(path.to_path_buf());
+        let memos = self.memos.get_mut();
+        let same = |memo: &Memo| memo.value.downcast_ref() == Some(&Some(text.clone()));
+        if memos.get(&query).is_some_and(same) {
+            return;
+        }
+        self.revision += 1;
+        let memo = Memo {
+            value: Rc::new(Some(text)),
+            changed_at: self.revision,
+            verified_at: self.revision,
+            deps: vec![],
+        };
+        memos.insert(query, memo);
+    }
+
+    fn get<T: 'static>(&self, query: Query) -> Rc<T> {
+        if let Some(reads) = self.active.borrow_mut().last_mut() {
+            reads.push(query.clone());
+        }
+        self.refresh(&query);
+        let value = self.memos.borrow()[&query].value.clone();
+        value.downcast().expect("Queries always give the same type")
+    }
+
+    /// Brings the memo of a query up to date, returning the revision its value
+    /// last changed in
+    fn refresh(&self, query: &Query) -> u64 {
+        let memo = self.memos.borrow().get(query).map(|it| {
+            let deps = it.deps.clone();
+            (it.changed_at, it.verified_at, deps)
+        });
+        if let Some((changed_at, verified_at, deps)) = memo {
+            // Files are only changed by setting their text
+            if verified_at == self.revision
+                || matches!(query, Query::FileText(_))
+                || deps.iter().all(|dep| self.refresh(dep) <= verified_at)
+            {
+                let mut memos = self.memos.borrow_mut();
+                memos.get_mut(query).unwrap().verified_at = self.revision;
+                return changed_at;
+            }
+        }
+        self.active.borrow_mut().push(vec![]);
+        let (value, same) = self.execute(query);
+        let deps = self.active.borrow_mut().pop().unwrap();
+        #[cfg(test)]
+        self.executed.borrow_mut().push(query.clone());
+        let mut memos = self.memos.borrow_mut();
+        let changed_at = match memos.get(query) {
+            Some(old) if same(&*old.value, &*value) => old.changed_at,
+            _ => self.revision,
+        };
+        let memo = Memo {
+            value,
+            changed_at,
+            verified_at: self.revision,
+            deps,
+        };
+        memos.insert(query.clone(), memo);
+        changed_at
+    }
+
+    fn execute(&self, query: &Query) -> Computed {
+        match query {
+            Query::FileText(path) => erase(Rc::new(std::fs::read_to_string(path).ok())),
+            Query::ParseFile(path) => erase(self.compute_parse_file(path)),
+            Query::CrateTree => erase(self.compute_crate_tree()),
+            Query::ItemList => erase(self.compute_item_list()),
+            Query::ItemTree(key) => erase(self.compute_item_tree(key)),
+            Query::ItemSignature(key) => erase(self.item_tree(key).signature.clone()),
+            Query::ItemSource(key) => erase(self.compute_item_source(key)),
+            Query::Skeleton => erase(self.compute_skeleton()),
+            Query::ResolveSkeleton => {
+                erase(Rc::new(resolve::resolve(&self.skeleton().source_file)))
+            }
+            Query::TypeckSkeleton => {
+                let source_file = &self.skeleton().source_file;
+                let resolve = self.resolve_skeleton();
+                erase(Rc::new(Opaque(typeck::typeck(source_file, &resolve))))
+            }
+            Query::ItemBody(key) => erase(self.compute_item_body(key)),
+            Query::ResolveItem(key) => erase(self.compute_resolve_item(key)),
+            Query::TypeckItem(key) => erase(self.compute_typeck_item(key)),
+            Query::ItemDiagnostics(key) => erase(self.compute_item_diagnostics(key)),
+            Query::Diagnostics => erase(self.compute_diagnostics()),
+            Query::Mono => erase(self.compute_mono()),
+            Query::Interface => erase(self.compute_interface()),
+            Query::FileSourceMap(path) => erase(self.compute_file_source_map(path)),
+            Query::LowerInstance(instance) => erase(self.compute_lower_instance(instance)),
+            Query::Compile => erase(self.compute_compile()),
+        }
+    }
+
+    /// The text of a file, or `None` if it can't be read
+    pub fn file_text(&self, path: &Path) -> Rc<Option<String>> {
+        self.get(Query::FileText(path.to_path_buf()))
+    }
+
+    /// A file parsed at offset 0; Empty if it can't be read
+    pub fn parse_file(&self, path: &Path) -> Rc<ParsedFile> {
+        self.get(Query::ParseFile(path.to_path_buf()))
+    }
+
+    fn compute_parse_file(&self, path: &Path) -> Rc<ParsedFile> {
+        let text = self.file_text(path);
+        let text = text.as_deref().unwrap_or_default();
+        Rc::new(ParsedFile::parse(text, path.to_path_buf()))
+    }
+
+    /// The crate as [loader::load_crate] gives it, or the error reading its root file
+    pub fn crate_tree(&self) -> Rc<Result<LoadResult, String>> {
+        self.get(Query::CrateTree)
+    }
+
+    fn compute_crate_tree(&self) -> Rc<Result<LoadResult, String>> {
+        let parse_file = |path: &Path| {
+            let text = (*self.file_text(path)).clone()?;
+            Some((text, self.parse_file(path)))
+        };
+        let result = loader::load_with(&self.root, &parse_file).map_err(|err| err.to_string());
+        Rc::new(result)
+    }
+
+    /// Every item of the crate, including those in modules, traits and impls,
+    /// in the order they're in in the source
+    pub fn item_list(&self) -> Rc<ItemList> {
+        self.get(Query::ItemList)
+    }
+
+    fn compute_item_list(&self) -> Rc<ItemList> {
+        let mut list = ItemList::default();
+        if let Ok(tree) = &*self.crate_tree() {
+            list.add_items(&tree.source_file.items, "", &mut vec![]);
+        }
+        Rc::new(list)
+    }
+
+    fn item_tree(&self, key: &ItemKey) -> Rc<ItemTree> {
+        self.get(Query::ItemTree(key.clone()))
+    }
+
+    fn compute_item_tree(&self, key: &ItemKey) -> Rc<ItemTree> {
+        let list = self.item_list();
+        let tree = self.crate_tree();
+        let Ok(tree) = &*tree else {
+            unreachable!("Items are only listed for crates that are loaded");
+        };
+        let item = item_at(&tree.source_file.items, list.position(key));
+        Rc::new(ItemTree::new(item))
+    }
+
+    fn item_signature(&self, key: &ItemKey) -> Rc<Signature> {
+        self.get(Query::ItemSignature(key.clone()))
+    }
+
+    /// The file an item is in and where it starts in it, for the locations of
+    /// runtime errors
+    fn item_source(&self, key: &ItemKey) -> Rc<(PathBuf, usize)> {
+        self.get(Query::ItemSource(key.clone()))
+    }
+
+    fn compute_item_source(&self, key: &ItemKey) -> Rc<(PathBuf, usize)> {
+        let list = self.item_list();
+        let tree = self.crate_tree();
+        let Ok(tree) = &*tree else {
+            unreachable!("Items are only listed for crates that are loaded");
+        };
+        let item = item_at(&tree.source_file.items, list.position(key));
+        let (file, offset) = tree.source_map.lookup(item_start(item));
+        Rc::new((file.path.clone(), offset))
+    }
+
+    /// The crate made of the signatures of its items
+    pub fn skeleton(&self) -> Rc<Skeleton> {
+        self.get(Query::Skeleton)
+    }
+
+    fn compute_skeleton(&self) -> Rc<Skeleton> {
+        let list = self.item_list();
+        let mut skeleton = Skeleton {
+            source_file: Rc::new(SourceFile {
+                path: self.root.clone(),
+                items: vec![],
+                deps: vec![],
+            }),
+            id_bases: vec![],
+            next_node_id: 0,
+            bases: vec![],
+            placeholders: vec![],
+            len: 0,
+        };
+        let items = self.skeleton_items(&list, &mut 0, list.keys.len(), &mut skeleton);
+        Rc::get_mut(&mut skeleton.source_file).unwrap().items = items;
+        Rc::new(skeleton)
+    }
+
+    /// The signatures of the items of the list from `index` to `end`, with the
+    /// items they contain
+    fn skeleton_items(
+        &self,
+        list: &ItemList,
+        index: &mut usize,
+        end: usize,
+        skeleton: &mut Skeleton,
+    ) -> Vec<Item> {
+        let mut items = vec![];
+        while *index < end {
+            let k = *index;
+            *index += 1;
+            let signature = self.item_signature(&list.keys[k]);
+            let mut item = signature.item.clone();
+            let base = skeleton.len;
+            move_item(&mut item, base, skeleton.next_node_id, 0, u32::MAX);
+            skeleton.id_bases.push(skeleton.next_node_id);
+            skeleton.next_node_id += signature.node_ids;
+            skeleton.bases.push(base);
+            skeleton.len += signature.len;
+            if let Some(placeholder) = signature.placeholder {
+                skeleton.placeholders.push(base + placeholder);
+            }
+            let inline = !matches!(&item.kind, ItemKind::Mod(m) if !m.inline);
+            if let Some(children) = children_mut(&mut item) {
+                *children = self.skeleton_items(list, index, list.ends[k], skeleton);
+                // Attributes like `#[allow(...)]` apply to the items inside
+                if inline {
+                    item.meta.span.end = skeleton.len;
+                }
+            }
+            items.push(item);
+        }
+        items
+    }
+
+    pub fn resolve_skeleton(&self) -> Rc<ResolveResult> {
+        self.get(Query::ResolveSkeleton)
+    }
+
+    pub fn typeck_skeleton(&self) -> Rc<Opaque<TypeckResult>> {
+        self.get(Query::TypeckSkeleton)
+    }
+
+    /// An item with a body at its place in the skeleton
+    pub fn item_body(&self, key: &ItemKey) -> Rc<Item> {
+        self.get(Query::ItemBody(key.clone()))
+    }
+
+    fn compute_item_body(&self, key: &ItemKey) -> Rc<Item> {
+        let list = self.item_list();
+        let skeleton = self.skeleton();
+        let tree = self.item_tree(key);
+        let k = list.indices[key];
+        let mut item = tree.item.clone();
+        move_item(
+            &mut item,
+            skeleton.bases[k],
+            skeleton.id_bases[k],
+            skeleton.next_node_id,
+            tree.signature.node_ids,
+        );
+        Rc::new(item)
+    }
+
+    pub fn resolve_item(&self, key: &ItemKey) -> Rc<ResolveResult> {
+        self.get(Query::ResolveItem(key.clone()))
+    }
+
+    fn compute_resolve_item(&self, key: &ItemKey) -> Rc<ResolveResult> {
+        let list = self.item_list();
+        if !self.item_tree(key).has_body() {
+            return self.resolve_skeleton();
+        }
+        let skeleton = self.skeleton();
+        let position = list.position(key);
+        let ancestors = (1..position.len())
+            .map(|len| item_at(&skeleton.source_file.items, &position[..len]))
+            .collect::<Vec<_>>();
+        let resolve = self.resolve_skeleton();
+        Rc::new(resolve::resolve_fn(
+            &resolve,
+            &ancestors,
+            &self.item_body(key),
+        ))
+    }
+
+    pub fn typeck_item(&self, key: &ItemKey) -> Rc<Opaque<TypeckResult>> {
+        self.get(Query::TypeckItem(key.clone()))
+    }
+
+    fn compute_typeck_item(&self, key: &ItemKey) -> Rc<Opaque<TypeckResult>> {
+        if !self.item_tree(key).has_body() {
+            return self.typeck_skeleton();
+        }
+        let typeck = self.typeck_skeleton();
+        let resolve = self.resolve_item(key);
+        let item = self.item_body(key);
+        Rc::new(Opaque(typeck::typeck_fn(&typeck.0, &resolve, &item)))
+    }
+
+    /// Diagnostics in the body of an item, at their spans in [Database::item_body]
+    fn item_diagnostics(&self, key: &ItemKey) -> Rc<Vec<Diagnostic>> {
+        self.get(Query::ItemDiagnostics(key.clone()))
+    }
+
+    fn compute_item_diagnostics(&self, key: &ItemKey) -> Rc<Vec<Diagnostic>> {
+        if !self.item_tree(key).has_body() {
+            return Rc::new(vec![]);
+        }
+        let resolve = self.resolve_item(key);
+        let typeck = self.typeck_item(key);
+        let diagnostics = resolve.diagnostics.iter().chain(&typeck.0.diagnostics);
+        Rc::new(diagnostics.cloned().collect())
+    }
+
+    /// The diagnostics of resolving, type checking and monomorphizing the crate,
+    /// in the order of their spans. Errors of loading the crate are in
+    /// [Database::crate_tree].
+    pub fn diagnostics(&self) -> Rc<Vec<Diagnostic>> {
+        self.get(Query::Diagnostics)
+    }
+
+    fn compute_diagnostics(&self) -> Rc<Vec<Diagnostic>> {
+        let list = self.item_list();
+        let tree = self.crate_tree();
+        let Ok(tree) = &*tree else {
+            return Rc::new(vec![]);
+        };
+        let skeleton = self.skeleton();
+        let resolve = self.resolve_skeleton();
+        let typeck = self.typeck_skeleton();
+        // The index of the item each diagnostic comes from, and whether its
+        // span is in the skeleton rather than in the body of the item
+        let mut diagnostics = resolve
+            .diagnostics
+            .iter()
+            .chain(&typeck.0.diagnostics)
+            .filter(|it| !skeleton.is_placeholder(&it.span))
+            .map(|it| (skeleton.index_at(it.span.start), true, it.clone()))
+            .collect::<Vec<_>>();
+        for (k, key) in list.keys.iter().enumerate() {
+            let item_diagnostics = self.item_diagnostics(key);
+            diagnostics.extend(item_diagnostics.iter().map(|it| (k, false, it.clone())));
+        }
+        // Monomorphization expects a crate without errors
+        if !diagnostics.iter().any(|(_, _, it)| it.is_error()) {
+            let mono = self.mono();
+            let fns = mono.diagnostic_fns.iter().map(|it| skeleton.index_of(*it));
+            let mono_diagnostics = fns.zip(&mono.diagnostics);
+            diagnostics.extend(mono_diagnostics.map(|(k, it)| (k, false, it.clone())));
+        }
+        let mut diagnostics = diagnostics
+            .into_iter()
+            .map(|(k, in_skeleton, mut diagnostic)| {
+                let item = item_at(&tree.source_file.items, &list.positions[k]);
+                let (start, end) = (item_start(item), item.span().end);
+                let base = skeleton.bases[k];
+                let span = &mut diagnostic.span;
+                // Spans of whole items end at the end of their range, or past
+                // it for modules, traits and impls, which cover the items inside
+                span.end = match in_skeleton && span.end >= skeleton.end_of(k) {
+                    true => end,
+                    false => start + span.end - base,
+                };
+                span.start = start + span.start - base;
+                diagnostic
+            })
+            .collect::<Vec<_>>();
+        diagnostics.sort_by_key(|it| it.span.start);
+        Rc::new(diagnostics)
+    }
+
+    /// The function instances to emit, each found by walking the function in
+    /// its item's body
+    pub fn mono(&self) -> Rc<MonoResult> {
+        self.get(Query::Mono)
+    }
+
+    fn compute_mono(&self) -> Rc<MonoResult> {
+        let list = self.item_list();
+        let skeleton = self.skeleton();
+        let checked = skeleton
+            .source_file
+            .fns()
+            .map(|(item, _)| {
+                let key = &list.keys[skeleton.index_of(item.id())];
+                let body = self.item_tree(key).has_body().then(|| self.item_body(key));
+                (item, body, self.resolve_item(key), self.typeck_item(key))
+            })
+            .collect::<Vec<_>>();
+        let fns = checked
+            .iter()
+            .map(|(item, body, resolve, typeck)| {
+                fn_context(body.as_deref().unwrap_or(item), resolve, &typeck.0)
+            })
+            .collect::<Vec<_>>();
+        Rc::new(mono::collect_fns(&fns))
+    }
+
+    /// The C declarations that the definitions of functions refer to
+    fn interface(&self) -> Rc<String> {
+        self.get(Query::Interface)
+    }
+
+    fn compute_interface(&self) -> Rc<String> {
+        let skeleton = self.skeleton();
+        let resolve = self.resolve_skeleton();
+        let typeck = self.typeck_skeleton();
+        let mono = self.mono();
+        let code = lower::lower_interface(&skeleton.source_file, &resolve, &typeck.0, &mono);
+        Rc::new(code)
+    }
+
+    /// The C definition of an instance of a function
+    pub fn lower_instance(&self, instance: &Instance) -> Rc<String> {
+        self.get(Query::LowerInstance(instance.clone()))
+    }
+
+    fn compute_lower_instance(&self, instance: &Instance) -> Rc<String> {
+        let list = self.item_list();
+        let skeleton = self.skeleton();
+        let k = skeleton.index_of(instance.def);
+        let key = &list.keys[k];
+        let (path, start) = &*self.item_source(key);
+        let source_map = self.file_source_map(path);
+        // Moved to where it is in its file
+        let mut item = (*self.item_body(key)).clone();
+        let base = skeleton.bases[k];
+        item.walk_meta(&mut |meta| {
+            meta.span.start = meta.span.start - base + start;
+            meta.span.end = meta.span.end - base + start;
+        });
+        let resolve = self.resolve_item(key);
+        let typeck = self.typeck_item(key);
+        let f = fn_context(&item, &resolve, &typeck.0);
+        Rc::new(lower::lower_instance(&source_map, f, &instance.args))
+    }
+
+    /// A source map of one file of the crate, starting at offset 0
+    fn file_source_map(&self, path: &Path) -> Rc<SourceMap> {
+        self.get(Query::FileSourceMap(path.to_path_buf()))
+    }
+
+    fn compute_file_source_map(&self, path: &Path) -> Rc<SourceMap> {
+        let tree = self.crate_tree();
+        let Ok(tree) = &*tree else {
+            unreachable!("Files are only lowered for crates that are loaded");
+        };
+        let mut source_map = SourceMap::new();
+        let file = tree.source_map.files().iter().find(|it| it.path == path);
+        let file = file.expect("Items are in the files of the crate");
+        source_map.add_file(file.path.clone(), file.text.clone());
+        Rc::new(source_map)
+    }
+
+    /// The C code of the crate, or `None` if it has errors
+    pub fn compile(&self) -> Rc<Option<Output>> {
+        self.get(Query::Compile)
+    }
+
+    fn compute_compile(&self) -> Rc<Option<Output>> {
+        if self.diagnostics().iter().any(|it| it.is_error()) {
+            return Rc::new(None);
+        }
+        let mut code = (*self.interface()).clone();
+        for instance in self.mono().instances.iter() {
+            code.push_str(&self.lower_instance(instance));
+        }
+        let link_libs = self.typeck_skeleton().0.attrs.link_libs.clone();
+        Rc::new(Some(Output { code, link_libs }))
+    }
+}
+
+/// The result of a query along with how to tell if it's the same as the last one
+type Computed = (Rc<dyn Any>, fn(&dyn Any, &dyn Any) -> bool);
+
+fn erase<T: PartialEq + 'static>(value: Rc<T>) -> Computed {
+    (value, same_value::<T>)
+}
+
+fn same_value<T: PartialEq + 'static>(a: &dyn Any, b: &dyn Any) -> bool {
+    a.downcast_ref::<T>() == b.downcast_ref::<T>()
+}
+
+#[derive(Default, PartialEq)]
+pub struct ItemList {
+    pub keys: Vec<ItemKey>,
+    indices: HashMap<ItemKey, usize>,
+    /// For each item, the index after the last item inside it
+    ends: Vec<usize>,
+    /// For each item, its index in the items of the crate root, followed by its
+    /// index in the items of each module, trait or impl it's in
+    positions: Vec<Vec<usize>>,
+}
+impl ItemList {
+    fn add_items(&mut self, items: &[Item], prefix: &str, position: &mut Vec<usize>) {
+        let mut counts = HashMap::<&str, usize>::new();
+        for (i, item) in items.iter().enumerate() {
+            let count = counts.entry(&item.name).or_default();
+            let key = match *count {
+                0 => ItemKey(format!("{}{}", prefix, item.name)),
+                n => ItemKey(format!("{}{}#{}", prefix, item.name, n)),
+            };
+            *count += 1;
+            let index = self.keys.len();
+            position.push(i);
+            self.keys.push(key.clone());
+            self.indices.insert(key.clone(), index);
+            self.ends.push(index + 1);
+            self.positions.push(position.clone());
+            if let Some(children) = children(item) {
+                self.add_items(children, &format!("{}::", key.0), position);
+                self.ends[index] = self.keys.len();
+            }
+            position.pop();
+        }
+    }
+
+    fn position(&self, key: &ItemKey) -> &[usize] {
+        &self.positions[self.indices[key]]
+    }
+}
+
+/// An item in coordinates of its own, so that it's the same wherever it is:
+/// spans are relative to its start and node ids count from 0, those of its
+/// signature first. The items in a module, trait or impl are left out, since
+/// they're items of their own.
+#[derive(PartialEq)]
+struct ItemTree {
+    item: Item,
+    signature: Rc<Signature>,
+}
+
+/// An item with the body of its function swapped for a placeholder
+#[derive(PartialEq)]
+struct Signature {
+    item: Item,
+    /// The number of node ids in the signature, which the ids of the body
+    /// come after
+    node_ids: u32,
+    /// The length of the range of offsets the signature takes in the skeleton
+    len: usize,
+    /// The offset of the placeholder body, which marks the errors about it,
+    /// e.g. that `{}` isn't of the return type
+    placeholder: Option<usize>,
+}
+
+impl ItemTree {
+    fn new(item: &Item) -> Self {
+        let mut item = item.clone();
+        if let Some(children) = children_mut(&mut item) {
+            children.clear();
+        }
+        let start = item_start(&item);
+        item.walk_meta(&mut |meta| {
+            meta.span.start -= start;
+            meta.span.end -= start;
+        });
+        let mut next_id = 0;
+        let body = match &mut item.kind {
+            ItemKind::Fn(f) => f.body.as_mut(),
+            _ => None,
+        };
+        let Some(body) = body else {
+            number_nodes(&mut item, &mut next_id);
+            let signature = Signature {
+                item: item.clone(),
+                node_ids: next_id,
+                // Empty spans at the end of the item are still in its range
+                len: item.span().end + 1,
+                placeholder: None,
+            };
+            let signature = Rc::new(signature);
+            return ItemTree { item, signature };
+        };
+        let body_start = body.span().start;
+        let mut body = std::mem::replace(&mut **body, placeholder(body_start + 1));
+        number_nodes(&mut item, &mut next_id);
+        let mut signature = item.clone();
+        // Keep the signature the same when the length of the body changes,
+        // ending at the end of its range to mark spans of the whole item
+        signature.meta.span.end = body_start + 2;
+        if let ItemKind::Fn(f) = &mut signature.kind {
+            f.meta.span.end = body_start + 2;
+        }
+        let signature = Rc::new(Signature {
+            item: signature,
+            node_ids: next_id,
+            len: body_start + 2,
+            placeholder: Some(body_start + 1),
+        });
+        number_nodes(&mut body, &mut next_id);
+        if let ItemKind::Fn(f) = &mut item.kind {
+            f.body = Some(Box::new(body));
+        }
+        ItemTree { item, signature }
+    }
+
+    fn has_body(&self) -> bool {
+        matches!(&self.item.kind, ItemKind::Fn(f) if f.body.is_some())
+    }
+}
+
+fn placeholder(offset: usize) -> Expr {
+    let meta = Meta {
+        span: Span {
+            start: offset,
+            end: offset,
+        },
+        id: NodeId(0),
+    };
+    let block = Block {
+        meta,
+        stmts: vec![],
+        is_unsafe: false,
+    };
+    Expr {
+        meta,
+        kind: ExprKind::Block(block),
+    }
+}
+
+fn number_nodes(node: &mut impl WalkMeta, next_id: &mut u32) {
+    node.walk_meta(&mut |meta| {
+        meta.id = NodeId(*next_id);
+        *next_id += 1;
+    });
+}
+
+#[derive(PartialEq)]
+pub struct Skeleton {
+    pub source_file: Rc<SourceFile>,
+    /// The first node id of each item of the list
+    id_bases: Vec<u32>,
+    /// The first node id after all signatures
+    next_node_id: u32,
+    /// The offset that the spans of each item of the list are relative to
+    bases: Vec<usize>,
+    /// The offsets of placeholder bodies, in increasing order
+    placeholders: Vec<usize>,
+    /// The end of the range of offsets of the last item
+    len: usize,
+}
+impl Skeleton {
+    /// The index in the list of the item with a node in its signature
+    fn index_of(&self, id: NodeId) -> usize {
+        self.id_bases.partition_point(|it| *it <= id.0) - 1
+    }
+
+    /// The index in the list of the item whose range has an offset
+    fn index_at(&self, offset: usize) -> usize {
+        self.bases.partition_point(|it| *it <= offset) - 1
+    }
+
+    /// The end of the range of the item at `index` of the list
+    fn end_of(&self, index: usize) -> usize {
+        self.bases.get(index + 1).copied().unwrap_or(self.len)
+    }
+
+    fn is_placeholder(&self, span: &Span) -> bool {
+        self.placeholders.binary_search(&span.start).is_ok()
+    }
+}
+
+/// Moves an item to its place in the skeleton at `base`; Its node ids from
+/// `signature_ids` on are in its body.
+fn move_item(item: &mut Item, base: usize, id_base: u32, body_id_base: u32, signature_ids: u32) {
+    item.walk_meta(&mut |meta| {
+        meta.span.start += base;
+        meta.span.end += base;
+        meta.id = match meta.id.0 {
+            id if id < signature_ids => NodeId(id_base + id),
+            id => NodeId(body_id_base + id - signature_ids),
+        };
+    });
+}
+
+/// Where an item starts, including its attributes
+fn item_start(item: &Item) -> usize {
+    let attrs = item.attrs.first().map(|it| it.span().start);
+    attrs.map_or(item.span().start, |it| it.min(item.span().start))
+}
+
+fn children(item: &Item) -> Option<&Vec<Item>> {
+    match &item.kind {
+        ItemKind::Mod(m) => Some(&m.items),
+        ItemKind::Trait(t) => Some(&t.items),
+        ItemKind::Impl(imp) => Some(&imp.items),
+        _ => None,
+    }
+}
+
+fn children_mut(item: &mut Item) -> Option<&mut Vec<Item>> {
+    match &mut item.kind {
+        ItemKind::Mod(m) => Some(&mut m.items),
+        ItemKind::Trait(t) => Some(&mut t.items),
+        ItemKind::Impl(imp) => Some(&mut imp.items),
+        _ => None,
+    }
+}
+
+fn item_at<'a>(items: &'a [Item], position: &[usize]) -> &'a Item {
+    let item = &items[position[0]];
+    match &position[1..] {
+        [] => item,
+        rest => item_at(children(item).unwrap(), rest),
+    }
+}
+
+fn fn_context<'a>(
+    item: &'a Item,
+    resolve: &'a ResolveResult,
+    typeck: &'a TypeckResult,
+) -> FnContext<'a> {
+    let ItemKind::Fn(f) = &item.kind else {
+        unreachable!("Instances are of functions");
+    };
+    FnContext {
+        item,
+        f,
+        resolve,
+        typeck,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        analysis::{resolve::resolve, typeck::typeck},
+        loader::load_crate,
+    };
+
+    use super::*;
+
+    fn database(text: &str) -> Database {
+        let path = PathBuf::from("test.hds");
+        let mut db = Database::new(path.clone());
+        db.set_file_text(&path, text.to_string());
+        db
+    }
+
+    fn executed(db: &Database) -> Vec<Query> {
+        std::mem::take(&mut *db.executed.borrow_mut())
+    }
+
+    fn key(name: &str) -> ItemKey {
+        ItemKey(name.to_string())
+    }
+
+    #[test]
+    fn compiles_the_test_files_like_the_whole_crate() {
+        for entry in std::fs::read_dir("test").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|it| it != "hds") {
+                continue;
+            }
+            let result = load_crate(&path, vec![], &[]).unwrap();
+            let resolve_result = resolve(&result.source_file);
+            let typeck_result = typeck(&result.source_file, &resolve_result);
+            let db = Database::new(path.clone());
+            let mut diagnostics = [
+                &resolve_result.diagnostics[..],
+                &typeck_result.diagnostics[..],
+            ]
+            .concat();
+            diagnostics.sort_by_key(|it| it.span.start);
+            assert_eq!(*db.diagnostics(), diagnostics, "{}", path.display());
+            if !diagnostics.is_empty() {
+                continue;
+            }
+            let mono_result = mono::collect(&result.source_file, &resolve_result, &typeck_result);
+            let code = lower::lower_source_file(
+                &result.source_file,
+                &result.source_map,
+                &resolve_result,
+                &typeck_result,
+                &mono_result,
+            );
+            let Some(output) = &*db.compile() else {
+                panic!("{} didn't compile", path.display());
+            };
+            assert!(output.code == code, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn reports_diagnostics_like_the_whole_crate() {
+        let text = "
+            #[deprecated]
+            fn old() -> i32 { 1 }
+            struct S { x: Missing }
+            #[allow(deprecated)]
+            impl S {
+                fn f() -> i32 { old() }
+                fn g() -> bool { 1 }
+            }
+            mod m {
+                fn h(x: i32) -> i32 {
+                    let y: bool = x;
+                    old() + z
+                }
+            }
+            trait T {
+                fn t(x: i32) -> i32;
+            }
+            impl T for S {
+                fn t(x: bool) -> i32 { 1 }
+            }
+            fn v(x: i32, ...) -> i32 { x }
+            #[test]
+            fn generic_test<U>() {}
+            fn main() -> i32 {}
+        ";
+        let result = loader::load_edited(Path::new("test.hds"), text.to_string(), HashMap::new());
+        let resolve_result = resolve(&result.source_file);
+        let typeck_result = typeck(&result.source_file, &resolve_result);
+        let mut expected = [resolve_result.diagnostics, typeck_result.diagnostics].concat();
+        expected.sort_by_key(|it| it.span.start);
+        assert_eq!(expected.len(), 9, "{:#?}", expected);
+        assert_eq!(*database(text).diagnostics(), expected);
+    }
+
+    #[test]
+    fn recomputes_only_the_queries_of_an_edited_body() {
+        let text = "
+            mod m;
+            struct P { x: i32 }
+            fn main() { let p = P { x: m::b() + m::c() }; }
+        ";
+        let module = "
+            fn a() -> i32 { 1 }
+            pub fn b() -> i32 { a() + 2 }
+            pub fn c() -> i32 { 3 }
+        ";
+        let mut db = database(text);
+        db.set_file_text(Path::new("m.hds"), module.to_string());
+        db.compile();
+        executed(&db);
+
+        let edited = module.replace("a() + 2", "a() * 20");
+        db.set_file_text(Path::new("m.hds"), edited.clone());
+        db.compile();
+        let executed = executed(&db);
+        let per_item = executed.iter().filter(|it| {
+            matches!(
+                it,
+                Query::ResolveItem(_) | Query::TypeckItem(_) | Query::ItemDiagnostics(_)
+            )
+        });
+        assert_eq!(
+            per_item.cloned().collect::<Vec<_>>(),
+            vec![
+                Query::ResolveItem(key("m::b")),
+                Query::TypeckItem(key("m::b")),
+                Query::ItemDiagnostics(key("m::b")),
+            ]
+        );
+        // The instances in the edited file share its source map
+        let lowered = executed.iter().filter_map(|it| match it {
+            Query::LowerInstance(instance) => Some(instance.def),
+            _ => None,
+        });
+        let list = db.item_list();
+        let names = lowered.map(|id| &list.keys[db.skeleton().index_of(id)].0);
+        let mut names = names.cloned().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["m::a", "m::b", "m::c"]);
+        for query in [Query::Skeleton, Query::TypeckSkeleton, Query::Interface] {
+            assert!(!executed.contains(&query), "{:?}", query);
+        }
+        let mut fresh = database(text);
+        fresh.set_file_text(Path::new("m.hds"), edited);
+        assert!(*db.compile() == *fresh.compile());
+    }
+}
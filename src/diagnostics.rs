@@ -0,0 +1,122 @@
+use std::fmt::Write;
+
+use libsyntax::Span;
+
+use crate::source_map::SourceMap;
+
+/// Names of the lints, which produce warnings
+pub const LINTS: [&str; 2] = ["deprecated", "unreachable_patterns"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+    pub severity: Severity,
+    /// The lint that a warning comes from, which `#[allow(...)]` can turn off
+    pub lint: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+impl Diagnostic {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            span,
+            message: message.into(),
+            severity: Severity::Error,
+            lint: None,
+        }
+    }
+
+    /// A warning from `lint`, one of [LINTS]
+    pub fn lint(span: Span, lint: &'static str, message: impl Into<String>) -> Self {
+        debug_assert!(LINTS.contains(&lint));
+        Diagnostic {
+            span,
+            message: message.into(),
+            severity: Severity::Warning,
+            lint: Some(lint),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+/// Returns the 1 based (line, column) of a character offset into `text`.
+pub fn line_col(text: &str, offset: usize) -> (usize, usize) {
+    let mut line = 1;
+    let mut column = 1;
+    for c in text.chars().take(offset) {
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    (line, column)
+}
+
+/// Renders diagnostics as `path:line:column: error: message` lines.
+pub fn render(source_map: &SourceMap, diagnostics: &[Diagnostic]) -> String {
+    let mut out = String::new();
+    for diagnostic in diagnostics {
+        let (file, offset) = source_map.lookup(diagnostic.span.start);
+        let (line, column) = line_col(&file.text, offset);
+        let severity = match diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        writeln!(
+            out,
+            "{}:{}:{}: {}: {}",
+            file.path.display(),
+            line,
+            column,
+            severity,
+            diagnostic.message
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line_col() {
+        let text = "fn main() {\n  foo\n}";
+        assert_eq!(line_col(text, 0), (1, 1));
+        assert_eq!(line_col(text, 3), (1, 4));
+        assert_eq!(line_col(text, 14), (2, 3));
+    }
+
+    #[test]
+    fn renders_locations_in_each_file() {
+        let mut source_map = SourceMap::new();
+        source_map.add_file("main.hds".into(), "mod a;\nfn main() {}".into());
+        let start = source_map.add_file("a.hds".into(), "fn f() {\n  x\n}".into());
+        let diagnostics = [
+            Diagnostic::new(Span { start: 7, end: 9 }, "first"),
+            Diagnostic::lint(
+                Span {
+                    start: start + 11,
+                    end: start + 12,
+                },
+                "deprecated",
+                "second",
+            ),
+        ];
+        assert_eq!(
+            render(&source_map, &diagnostics),
+            "main.hds:2:1: error: first\na.hds:2:3: warning: second\n"
+        );
+    }
+}
@@ -0,0 +1,1154 @@
+//! Prints a source file in the canonical style: four spaces of indentation,
+//! one item, statement or match arm per line, and lists of parameters,
+//! arguments and fields wrapped one per line when they don't fit in 100
+//! columns. Comments are kept, on their own line before the code that follows
+//! them, or after the code they end the line of. At most one blank line is
+//! kept between the items and statements that had blank lines between them.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use libsyntax::{HasSpan, Span};
+
+use crate::{
+    ast,
+    ast::{
+        Arm, AttrArgs, Attribute, Block, Expr, ExprKind, FieldDef, ForeignItemKind, GenericParam,
+        Item, ItemKind, Local, Mutability, Pat, PatKind, Stmt, StmtKind, TraitRef, Ty, TyKind,
+        UnOp, UseKind, VariantData, Visibility,
+    },
+    cst,
+    diagnostics::{self, Diagnostic},
+    lexer::TriviaKind,
+    source_map::SourceMap,
+};
+
+const INDENT: usize = 4;
+const MAX_WIDTH: usize = 100;
+
+/// Formats the text of a file, or returns its syntax errors. The files of its
+/// modules aren't formatted.
+pub fn format(text: &str, path: PathBuf) -> Result<String, Vec<Diagnostic>> {
+    let parse = cst::parse(text, path);
+    if !parse.diagnostics.is_empty() {
+        return Err(parse.diagnostics);
+    }
+    let chars = text.chars().collect::<Vec<_>>();
+    let comments = parse
+        .tree
+        .tokens()
+        .into_iter()
+        .flat_map(|it| it.leading.iter().chain(&it.trailing))
+        .filter(|it| it.kind == TriviaKind::Comment)
+        .map(|it| it.span)
+        .collect::<Vec<_>>();
+    let mut printer = Printer {
+        chars: &chars,
+        comments: &comments,
+        next_comment: 0,
+        out: String::new(),
+        indent: 0,
+        start_col: 0,
+    };
+    printer.items(&parse.source_file.items);
+    printer.comments_before(usize::MAX);
+    if !printer.out.is_empty() {
+        printer.out.push('\n');
+    }
+    Ok(printer.out)
+}
+
+/// Formats the .hds files at `paths`, or in the current directory if there
+/// are none. With `check`, the files that aren't formatted are listed instead.
+/// Returns false if a file had syntax errors or wasn't formatted.
+pub fn format_paths(paths: &[PathBuf], check: bool) -> Result<bool> {
+    let mut files = vec![];
+    let current_dir = [PathBuf::from(".")];
+    let paths = if paths.is_empty() {
+        &current_dir
+    } else {
+        paths
+    };
+    for path in paths {
+        collect_files(path, &mut files)?;
+    }
+    let mut ok = true;
+    for path in files {
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Couldn't read {}", path.display()))?;
+        let formatted = match format(&text, path.clone()) {
+            Ok(it) => it,
+            Err(diagnostics) => {
+                let mut source_map = SourceMap::new();
+                source_map.add_file(path.clone(), text);
+                eprint!("{}", diagnostics::render(&source_map, &diagnostics));
+                ok = false;
+                continue;
+            }
+        };
+        if formatted == text {
+            continue;
+        }
+        if check {
+            println!("{}", path.display());
+            ok = false;
+        } else {
+            std::fs::write(&path, formatted)
+                .with_context(|| format!("Couldn't write {}", path.display()))?;
+        }
+    }
+    Ok(ok)
+}
+
+/// The .hds files at a path, in the order of their names
+fn collect_files(path: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    if !path.is_dir() {
+        out.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries = std::fs::read_dir(path)
+        .with_context(|| format!("Couldn't read {}", path.display()))?
+        .map(|it| Ok(it?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() || entry.extension().is_some_and(|it| it == "hds") {
+            collect_files(&entry, out)?;
+        }
+    }
+    Ok(())
+}
+
+struct Printer<'a> {
+    chars: &'a [char],
+    comments: &'a [Span],
+    /// The index of the first comment that hasn't been printed
+    next_comment: usize,
+    out: String,
+    indent: usize,
+    /// The column the output starts at, for the output of a list being
+    /// printed on one line to see if it fits
+    start_col: usize,
+}
+
+/// How a list of parameters, arguments, fields or elements is delimited
+struct List<'s> {
+    open: &'s str,
+    close: &'s str,
+    /// From the start of the list to the end of its closing delimiter
+    span: Span,
+    /// Spaces inside the delimiters, as in `Point { x, y }`
+    pad: bool,
+    /// A comma after a single element, as in the tuple `(a,)`
+    comma_after_one: bool,
+    /// Printed after the elements without a comma, like `...` or `..`
+    rest: Option<&'s str>,
+    /// The width of what follows the list on its last line
+    reserve: usize,
+}
+
+impl<'s> List<'s> {
+    fn new(open: &'s str, close: &'s str, span: Span) -> Self {
+        List {
+            open,
+            close,
+            span,
+            pad: false,
+            comma_after_one: false,
+            rest: None,
+            reserve: 0,
+        }
+    }
+}
+
+impl Printer<'_> {
+    fn word(&mut self, text: &str) {
+        self.out.push_str(text);
+    }
+
+    fn col(&self) -> usize {
+        match self.out.rfind('\n') {
+            Some(i) => self.out[i + 1..].chars().count(),
+            None => self.start_col + self.out.chars().count(),
+        }
+    }
+
+    /// Starts a line, after a blank line if `blank` and the previous line
+    /// doesn't open a block or list
+    fn new_line(&mut self, blank: bool) {
+        if self.out.is_empty() {
+            return;
+        }
+        if blank && !self.out.ends_with(['{', '(', '[']) {
+            self.out.push('\n');
+        }
+        self.out.push('\n');
+        self.out
+            .extend(std::iter::repeat_n(' ', self.indent * INDENT));
+    }
+
+    /// Starts the line of something starting at `start`, printing the
+    /// comments before it on their own lines
+    fn line_before(&mut self, start: usize, keep_blank: bool) {
+        self.comments_before(start);
+        self.new_line(keep_blank && self.blank_before(start));
+    }
+
+    fn comments_before(&mut self, offset: usize) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.start >= offset {
+                break;
+            }
+            self.next_comment += 1;
+            self.new_line(self.blank_before(comment.start));
+            self.comment(*comment);
+        }
+    }
+
+    /// Prints the comment ending the line of something ending at `end`, if any
+    fn trailing_comment(&mut self, end: usize) {
+        let Some(comment) = self.comments.get(self.next_comment) else {
+            return;
+        };
+        if comment.start < end {
+            return;
+        }
+        let between = &self.chars[end..comment.start];
+        if between.iter().all(|c| matches!(c, ' ' | '\t' | ',' | ';')) {
+            self.next_comment += 1;
+            self.word(" ");
+            self.comment(*comment);
+        }
+    }
+
+    fn comment(&mut self, comment: Span) {
+        let text = self.chars[comment.start..comment.end]
+            .iter()
+            .collect::<String>();
+        self.word(text.trim_end());
+    }
+
+    fn has_comments(&self, start: usize, end: usize) -> bool {
+        self.comments[self.next_comment..]
+            .iter()
+            .any(|it| start < it.start && it.start < end)
+    }
+
+    /// Whether there's a blank line right before `offset` in the source
+    fn blank_before(&self, offset: usize) -> bool {
+        let mut newlines = 0;
+        for c in self.chars[..offset.min(self.chars.len())].iter().rev() {
+            match c {
+                '\n' => newlines += 1,
+                c if c.is_whitespace() => {}
+                _ => break,
+            }
+        }
+        newlines >= 2 && offset > 0
+    }
+
+    /// Where something with attributes starts, at the `#` of its first attribute
+    fn start_of(&self, attrs: &[Attribute], span: &Span) -> usize {
+        match attrs.first() {
+            Some(attr) => {
+                let mut start = attr.span().start;
+                while start > 0 && self.chars[start - 1] != '#' {
+                    start -= 1;
+                }
+                start.saturating_sub(1)
+            }
+            None => span.start,
+        }
+    }
+
+    /// A printer for trying to print something on the rest of the line
+    fn sub_printer(&self) -> Self {
+        Printer {
+            out: String::new(),
+            start_col: self.col(),
+            ..*self
+        }
+    }
+
+    /// Keeps what a sub printer printed
+    fn append(&mut self, printer: Printer) {
+        self.out.push_str(&printer.out);
+        self.next_comment = printer.next_comment;
+    }
+
+    /// Whether the output of a sub printer fits within the line width,
+    /// followed by `reserve` more columns
+    fn fits(&self, printer: &Printer, reserve: usize) -> bool {
+        let mut lines = printer.out.split('\n').peekable();
+        let mut col = printer.start_col;
+        while let Some(line) = lines.next() {
+            let width = line.chars().count() + if lines.peek().is_none() { reserve } else { 0 };
+            if col + width > MAX_WIDTH {
+                return false;
+            }
+            col = 0;
+        }
+        true
+    }
+
+    /// Prints a list on one line if it fits and only its last element spans
+    /// several lines, or one element per line with trailing commas otherwise
+    fn list<T: HasSpan>(&mut self, items: &[T], list: List, print: impl Fn(&mut Self, &T)) {
+        // Comments in the list, except in its last element, are kept on their own lines
+        let (start, end) = (list.span.start, list.span.end);
+        let has_comments = match items.last() {
+            Some(last) => {
+                self.has_comments(start, last.span().start)
+                    || self.has_comments(last.span().end, end)
+            }
+            None => self.has_comments(start, end),
+        };
+        if !has_comments {
+            let mut flat = self.sub_printer();
+            let mut ok = true;
+            let padded = list.pad && (!items.is_empty() || list.rest.is_some());
+            flat.word(list.open);
+            if padded {
+                flat.word(" ");
+            }
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    flat.word(", ");
+                }
+                print(&mut flat, item);
+                if i + 1 < items.len() && flat.out.contains('\n') {
+                    ok = false;
+                    break;
+                }
+            }
+            if list.comma_after_one && items.len() == 1 {
+                flat.word(",");
+            }
+            if let Some(rest) = list.rest {
+                if !items.is_empty() {
+                    flat.word(", ");
+                }
+                flat.word(rest);
+            }
+            if padded {
+                flat.word(" ");
+            }
+            flat.word(list.close);
+            if ok && self.fits(&flat, list.reserve) {
+                self.append(flat);
+                return;
+            }
+        }
+
+        self.word(list.open);
+        self.indent += 1;
+        for item in items {
+            self.line_before(item.span().start, false);
+            print(self, item);
+            self.word(",");
+            self.trailing_comment(item.span().end);
+        }
+        if let Some(rest) = list.rest {
+            self.new_line(false);
+            self.word(rest);
+        }
+        self.close(end, list.close);
+    }
+
+    /// Prints the comments left in a block or list on their own lines, and
+    /// its closing delimiter on the next one
+    fn close(&mut self, end: usize, delim: &str) {
+        self.comments_before(end);
+        self.indent -= 1;
+        self.new_line(false);
+        self.word(delim);
+    }
+
+    fn items(&mut self, items: &[Item]) {
+        for item in items {
+            self.line_before(self.start_of(&item.attrs, item.span()), true);
+            self.item(item);
+            self.trailing_comment(item.span().end);
+        }
+    }
+
+    /// Prints the items of a module, trait or impl between braces
+    fn item_block(&mut self, items: &[Item], span: &Span) {
+        if items.is_empty() && !self.has_comments(span.start, span.end) {
+            self.word(" {}");
+            return;
+        }
+        self.word(" {");
+        self.indent += 1;
+        self.items(items);
+        self.close(span.end, "}");
+    }
+
+    fn attrs(&mut self, attrs: &[Attribute]) {
+        for attr in attrs {
+            self.word("#[");
+            self.attr(attr);
+            self.word("]");
+            self.new_line(false);
+        }
+    }
+
+    fn attr(&mut self, attr: &Attribute) {
+        self.word(&attr.name);
+        match &attr.args {
+            AttrArgs::Empty => {}
+            AttrArgs::Eq(value) => {
+                self.word(" = ");
+                self.string(value);
+            }
+            AttrArgs::List(args) => {
+                self.word("(");
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        self.word(", ");
+                    }
+                    self.attr(arg);
+                }
+                self.word(")");
+            }
+        }
+    }
+
+    /// Prints a string literal, escaping its contents
+    fn string(&mut self, value: &str) {
+        self.word("\"");
+        for c in value.chars() {
+            match c {
+                '"' => self.word("\\\""),
+                '\\' => self.word("\\\\"),
+                '\n' => self.word("\\n"),
+                '\t' => self.word("\\t"),
+                '\0' => self.word("\\0"),
+                c => self.out.push(c),
+            }
+        }
+        self.word("\"");
+    }
+
+    fn vis(&mut self, vis: &Visibility) {
+        if let Visibility::Public = vis {
+            self.word("pub ");
+        }
+    }
+
+    fn item(&mut self, item: &Item) {
+        self.attrs(&item.attrs);
+        self.vis(&item.vis);
+        match &item.kind {
+            ItemKind::Fn(f) => self.func(&item.name, f),
+            ItemKind::ForeignMod(foreign_mod) => {
+                self.word("extern");
+                if let Some(abi) = &foreign_mod.abi {
+                    self.word(" ");
+                    self.string(abi);
+                }
+                let span = item.span();
+                if foreign_mod.items.is_empty() && !self.has_comments(span.start, span.end) {
+                    self.word(" {}");
+                    return;
+                }
+                self.word(" {");
+                self.indent += 1;
+                for foreign_item in &foreign_mod.items {
+                    let start = self.start_of(&foreign_item.attrs, foreign_item.span());
+                    self.line_before(start, true);
+                    self.attrs(&foreign_item.attrs);
+                    self.vis(&foreign_item.vis);
+                    let ForeignItemKind::Fn(f) = &foreign_item.kind;
+                    self.func(&foreign_item.name, f);
+                    self.trailing_comment(foreign_item.span().end);
+                }
+                self.close(span.end, "}");
+            }
+            ItemKind::Struct(s) => {
+                self.word("struct ");
+                self.word(&item.name);
+                self.generics(&s.generics);
+                match &s.data {
+                    VariantData::Struct(fields) => self.field_block(fields, item.span()),
+                    data => {
+                        self.variant_data(data, item.span());
+                        self.word(";");
+                    }
+                }
+            }
+            ItemKind::Enum(e) => {
+                self.word("enum ");
+                self.word(&item.name);
+                self.generics(&e.generics);
+                let span = item.span();
+                if e.variants.is_empty() && !self.has_comments(span.start, span.end) {
+                    self.word(" {}");
+                    return;
+                }
+                self.word(" {");
+                self.indent += 1;
+                for variant in &e.variants {
+                    self.line_before(variant.span().start, true);
+                    self.word(&variant.name);
+                    self.variant_data(&variant.data, variant.span());
+                    self.word(",");
+                    self.trailing_comment(variant.span().end);
+                }
+                self.close(span.end, "}");
+            }
+            ItemKind::Trait(t) => {
+                self.word("trait ");
+                self.word(&item.name);
+                self.item_block(&t.items, item.span());
+            }
+            ItemKind::Impl(imp) => {
+                self.word("impl");
+                self.generics(&imp.generics);
+                self.word(" ");
+                if let Some(trait_ref) = &imp.trait_ref {
+                    self.trait_ref(trait_ref);
+                    self.word(" for ");
+                }
+                self.ty(&imp.self_ty);
+                self.item_block(&imp.items, item.span());
+            }
+            ItemKind::Mod(m) => {
+                self.word("mod ");
+                self.word(&item.name);
+                if m.inline {
+                    self.item_block(&m.items, item.span());
+                } else {
+                    self.word(";");
+                }
+            }
+            ItemKind::Use(u) => {
+                self.word("use ");
+                self.path(&u.path);
+                match &u.kind {
+                    UseKind::Single(Some(name)) => {
+                        self.word(" as ");
+                        self.word(name);
+                    }
+                    UseKind::Single(None) => {}
+                    UseKind::Glob => self.word("::*"),
+                }
+                self.word(";");
+            }
+            ItemKind::Const(c) => {
+                self.word("const ");
+                self.word(&item.name);
+                self.word(": ");
+                self.ty(&c.ty);
+                self.word(" = ");
+                self.expr(&c.expr);
+                self.word(";");
+            }
+            ItemKind::Static(s) => {
+                self.word("static ");
+                if s.mutability == Mutability::Mut {
+                    self.word("mut ");
+                }
+                self.word(&item.name);
+                self.word(": ");
+                self.ty(&s.ty);
+                self.word(" = ");
+                self.expr(&s.expr);
+                self.word(";");
+            }
+            ItemKind::TyAlias(alias) => {
+                self.word("type ");
+                self.word(&item.name);
+                self.generics(&alias.generics);
+                self.word(" = ");
+                self.ty(&alias.ty);
+                self.word(";");
+            }
+            ItemKind::Newtype(newtype) => {
+                self.word("newtype ");
+                self.word(&item.name);
+                self.word(" = ");
+                self.ty(&newtype.ty);
+                self.word(";");
+            }
+        }
+    }
+
+    fn func(&mut self, name: &str, f: &ast::Fn) {
+        self.word("fn ");
+        self.word(name);
+        self.generics(&f.generics);
+        // What follows the `)` on its line
+        let mut rest = self.sub_printer();
+        if let Some(ty) = &f.return_ty {
+            rest.word(" -> ");
+            rest.ty(ty);
+        }
+        rest.word(if f.body.is_some() { " {" } else { ";" });
+        let end = match (&f.return_ty, &f.body) {
+            (Some(ty), _) => ty.span().start,
+            (None, Some(body)) => body.span().start,
+            (None, None) => f.span().end,
+        };
+        let start = self.start_after_name(f.span().start);
+        let mut list = List::new("(", ")", Span { start, end });
+        list.rest = f.variadic.then_some("...");
+        list.reserve = rest.out.chars().count();
+        self.list(&f.params, list, |this, param| {
+            for attr in &param.attrs {
+                this.word("#[");
+                this.attr(attr);
+                this.word("] ");
+            }
+            this.word(&param.name);
+            this.word(": ");
+            this.ty(&param.ty);
+        });
+        if let Some(ty) = &f.return_ty {
+            self.word(" -> ");
+            self.ty(ty);
+        }
+        match &f.body {
+            Some(body) => {
+                self.word(" ");
+                let ExprKind::Block(block) = &body.kind else {
+                    unreachable!("The body of a function is a block");
+                };
+                self.block(block, true);
+            }
+            None => self.word(";"),
+        }
+    }
+
+    /// The offset of the `(` of the parameters of a function starting at `start`
+    fn start_after_name(&self, start: usize) -> usize {
+        let mut offset = start;
+        while offset < self.chars.len() && self.chars[offset] != '(' {
+            offset += 1;
+        }
+        offset
+    }
+
+    fn generics(&mut self, generics: &[GenericParam]) {
+        if generics.is_empty() {
+            return;
+        }
+        self.word("<");
+        for (i, param) in generics.iter().enumerate() {
+            if i > 0 {
+                self.word(", ");
+            }
+            self.word(&param.name);
+            for (j, bound) in param.bounds.iter().enumerate() {
+                self.word(if j == 0 { ": " } else { " + " });
+                self.trait_ref(bound);
+            }
+        }
+        self.word(">");
+    }
+
+    fn trait_ref(&mut self, trait_ref: &TraitRef) {
+        self.path(&trait_ref.path);
+    }
+
+    fn path(&mut self, path: &ast::Path) {
+        self.word(&path.to_string());
+    }
+
+    /// Prints the named fields of a struct, one per line
+    fn field_block(&mut self, fields: &[FieldDef], span: &Span) {
+        if fields.is_empty() && !self.has_comments(span.start, span.end) {
+            self.word(" {}");
+            return;
+        }
+        self.word(" {");
+        self.indent += 1;
+        for field in fields {
+            self.line_before(field.span().start, true);
+            self.field_def(field);
+            self.word(",");
+            self.trailing_comment(field.span().end);
+        }
+        self.close(span.end, "}");
+    }
+
+    /// Prints the fields of an enum variant, or of a tuple struct
+    fn variant_data(&mut self, data: &VariantData, span: &Span) {
+        match data {
+            VariantData::Struct(fields) => {
+                let mut list = List::new(" {", "}", *span);
+                list.pad = true;
+                self.list(fields, list, Self::field_def);
+            }
+            VariantData::Tuple(fields) => {
+                self.list(fields, List::new("(", ")", *span), |this, field| {
+                    this.vis(&field.vis);
+                    this.ty(&field.ty);
+                });
+            }
+            VariantData::Unit => {}
+        }
+    }
+
+    fn field_def(&mut self, field: &FieldDef) {
+        self.vis(&field.vis);
+        self.word(&field.name);
+        self.word(": ");
+        self.ty(&field.ty);
+    }
+
+    /// Prints a block, on one line if it's a single expression that fits and
+    /// it isn't the body of a function
+    fn block(&mut self, block: &Block, is_fn_body: bool) {
+        if block.is_unsafe {
+            self.word("unsafe ");
+        }
+        let span = block.span();
+        let has_comments = self.has_comments(span.start, span.end);
+        if block.stmts.is_empty() && !has_comments {
+            self.word("{}");
+            return;
+        }
+        if let [stmt] = &block.stmts[..] {
+            if let (StmtKind::Expr(expr), true) = (&stmt.kind, stmt.attrs.is_empty()) {
+                if !is_fn_body && !has_comments {
+                    let mut flat = self.sub_printer();
+                    flat.word("{ ");
+                    flat.expr(expr);
+                    flat.word(" }");
+                    if !flat.out.contains('\n') && self.fits(&flat, 0) {
+                        self.append(flat);
+                        return;
+                    }
+                }
+            }
+        }
+        self.word("{");
+        self.indent += 1;
+        self.stmts(&block.stmts);
+        self.close(span.end, "}");
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        let mut stmts = stmts.iter().peekable();
+        while let Some(stmt) = stmts.next() {
+            let mut end = stmt.span().end;
+            match &stmt.kind {
+                // A `;` that doesn't end an expression statement does nothing
+                StmtKind::Semi => continue,
+                StmtKind::Expr(expr) => {
+                    self.line_before(self.start_of(&stmt.attrs, stmt.span()), true);
+                    self.attrs(&stmt.attrs);
+                    self.expr(expr);
+                    if let Some(semi) = stmts.next_if(|it| matches!(it.kind, StmtKind::Semi)) {
+                        self.word(";");
+                        end = semi.span().end;
+                    }
+                }
+                StmtKind::Let(local) => {
+                    self.line_before(self.start_of(&stmt.attrs, stmt.span()), true);
+                    self.attrs(&stmt.attrs);
+                    self.local(local);
+                }
+            }
+            self.trailing_comment(end);
+        }
+    }
+
+    fn local(&mut self, local: &Local) {
+        self.word("let ");
+        self.pat(&local.pat);
+        if let Some(ty) = &local.ty {
+            self.word(": ");
+            self.ty(ty);
+        }
+        if let Some(init) = &local.init {
+            self.word(" = ");
+            self.expr(init);
+        }
+        self.word(";");
+    }
+
+    fn exprs(&mut self, exprs: &[Expr], list: List) {
+        self.list(exprs, list, Self::expr);
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        let span = *expr.span();
+        match &expr.kind {
+            ExprKind::Block(block) => self.block(block, false),
+            ExprKind::Call(callee, args) => {
+                self.expr(callee);
+                let start = callee.span().end;
+                self.exprs(args, List::new("(", ")", Span { start, ..span }));
+            }
+            ExprKind::Lit(lit) => self.word(&lit.text),
+            ExprKind::Var(var) => self.word(&var.name),
+            ExprKind::Unit => self.word("()"),
+            ExprKind::Path(path) => self.path(path),
+            ExprKind::Struct(s) => {
+                self.path(&s.path);
+                let mut list = List::new(" {", "}", span);
+                list.pad = true;
+                self.list(&s.fields, list, |this, field| {
+                    this.word(&field.name);
+                    let shorthand = match &field.expr.kind {
+                        ExprKind::Var(var) => var.name == field.name,
+                        _ => false,
+                    };
+                    if !shorthand {
+                        this.word(": ");
+                        this.expr(&field.expr);
+                    }
+                });
+            }
+            ExprKind::Field(base, name) => {
+                self.expr(base);
+                self.word(".");
+                self.word(name);
+            }
+            ExprKind::MethodCall(receiver, name, args) => {
+                self.expr(receiver);
+                self.word(".");
+                self.word(name);
+                let start = receiver.span().end;
+                self.exprs(args, List::new("(", ")", Span { start, ..span }));
+            }
+            ExprKind::Assign(lhs, rhs) => {
+                self.expr(lhs);
+                self.word(" = ");
+                self.expr(rhs);
+            }
+            ExprKind::Match(scrutinee, arms) => {
+                self.word("match ");
+                self.expr(scrutinee);
+                if arms.is_empty() && !self.has_comments(scrutinee.span().end, span.end) {
+                    self.word(" {}");
+                    return;
+                }
+                self.word(" {");
+                self.indent += 1;
+                for arm in arms {
+                    self.line_before(arm.span().start, true);
+                    self.arm(arm);
+                    self.trailing_comment(arm.span().end);
+                }
+                self.close(span.end, "}");
+            }
+            ExprKind::Tup(exprs) => {
+                let mut list = List::new("(", ")", span);
+                list.comma_after_one = true;
+                self.exprs(exprs, list);
+            }
+            ExprKind::Paren(inner) => {
+                self.word("(");
+                self.expr(inner);
+                self.word(")");
+            }
+            ExprKind::Binary(op, lhs, rhs) => {
+                self.expr(lhs);
+                self.word(" ");
+                self.word(op.as_str());
+                self.word(" ");
+                self.expr(rhs);
+            }
+            ExprKind::Unary(op, operand) => {
+                self.word(match op {
+                    UnOp::Neg => "-",
+                    UnOp::Not => "!",
+                    UnOp::Deref => "*",
+                });
+                self.expr(operand);
+            }
+            ExprKind::AddrOf(mutability, operand) => {
+                self.word("&");
+                match mutability {
+                    Mutability::Mut => self.word("mut "),
+                    // `&&` is a single token
+                    Mutability::Not if matches!(operand.kind, ExprKind::AddrOf(..)) => {
+                        self.word(" ")
+                    }
+                    Mutability::Not => {}
+                }
+                self.expr(operand);
+            }
+            ExprKind::Cast(inner, ty) => {
+                self.expr(inner);
+                self.word(" as ");
+                self.ty(ty);
+            }
+            ExprKind::Array(exprs) => self.exprs(exprs, List::new("[", "]", span)),
+            ExprKind::Repeat(value, len) => {
+                self.word("[");
+                self.expr(value);
+                self.word("; ");
+                self.expr(len);
+                self.word("]");
+            }
+            ExprKind::Index(base, index) => {
+                self.expr(base);
+                self.word("[");
+                self.expr(index);
+                self.word("]");
+            }
+        }
+    }
+
+    fn arm(&mut self, arm: &Arm) {
+        self.pat(&arm.pat);
+        if let Some(guard) = &arm.guard {
+            self.word(" if ");
+            self.expr(guard);
+        }
+        self.word(" => ");
+        self.expr(&arm.body);
+        if !matches!(arm.body.kind, ExprKind::Block(_) | ExprKind::Match(..)) {
+            self.word(",");
+        }
+    }
+
+    fn pat(&mut self, pat: &Pat) {
+        let span = *pat.span();
+        match &pat.kind {
+            PatKind::Wild => self.word("_"),
+            PatKind::Binding(name) => self.word(name),
+            PatKind::Lit(lit) => self.word(&lit.text),
+            PatKind::Path(path) => self.path(path),
+            PatKind::TupleStruct(path, pats) => {
+                self.path(path);
+                self.list(pats, List::new("(", ")", span), Self::pat);
+            }
+            PatKind::Struct(path, fields, has_rest) => {
+                self.path(path);
+                let mut list = List::new(" {", "}", span);
+                list.pad = true;
+                list.rest = has_rest.then_some("..");
+                self.list(fields, list, |this, field| {
+                    this.word(&field.name);
+                    let shorthand = match &field.pat.kind {
+                        PatKind::Binding(name) => *name == field.name,
+                        _ => false,
+                    };
+                    if !shorthand {
+                        this.word(": ");
+                        this.pat(&field.pat);
+                    }
+                });
+            }
+            PatKind::Tuple(pats) => {
+                let mut list = List::new("(", ")", span);
+                list.comma_after_one = true;
+                self.list(pats, list, Self::pat);
+            }
+        }
+    }
+
+    fn ty(&mut self, ty: &Ty) {
+        match &ty.kind {
+            TyKind::Tup(tys) => {
+                self.word("(");
+                for (i, ty) in tys.iter().enumerate() {
+                    if i > 0 {
+                        self.word(", ");
+                    }
+                    self.ty(ty);
+                }
+                if tys.len() == 1 {
+                    self.word(",");
+                }
+                self.word(")");
+            }
+            TyKind::Var(name, args) => {
+                self.word(name);
+                self.ty_args(args);
+            }
+            TyKind::Path(path, args) => {
+                self.path(path);
+                self.ty_args(args);
+            }
+            TyKind::Ptr(mutability, inner) => {
+                self.word("*");
+                if *mutability == Mutability::Mut {
+                    self.word("mut ");
+                }
+                self.ty(inner);
+            }
+            TyKind::Array(elem, len) => {
+                self.word("[");
+                self.ty(elem);
+                self.word("; ");
+                self.expr(len);
+                self.word("]");
+            }
+            TyKind::Slice(elem) => {
+                self.word("[");
+                self.ty(elem);
+                self.word("]");
+            }
+            TyKind::Dyn(trait_ref) => {
+                self.word("dyn ");
+                self.trait_ref(trait_ref);
+            }
+        }
+    }
+
+    fn ty_args(&mut self, args: &[Ty]) {
+        if args.is_empty() {
+            return;
+        }
+        self.word("<");
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                self.word(", ");
+            }
+            self.ty(arg);
+        }
+        self.word(">");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::Value;
+
+    use super::*;
+
+    fn fmt(text: &str) -> String {
+        format(text, PathBuf::from("test.hds")).unwrap()
+    }
+
+    #[test]
+    fn indents_and_spaces_code() {
+        let text = "\
+fn main(){let p=Point{x:x,y:2};match p{Point{x,y:_}if x>0=>{x}_=>-p.x,}
+  unsafe{puts(&mut p.x as *u8)};;}
+struct Point{pub x:i32,y:(i32,)}
+enum Shape{Circle(i32),Square{side:i32}}
+";
+        assert_eq!(
+            fmt(text),
+            "\
+fn main() {
+    let p = Point { x, y: 2 };
+    match p {
+        Point { x, y: _ } if x > 0 => { x }
+        _ => -p.x,
+    }
+    unsafe { puts(&mut p.x as *u8) };
+}
+struct Point {
+    pub x: i32,
+    y: (i32,),
+}
+enum Shape {
+    Circle(i32),
+    Square { side: i32 },
+}
+"
+        );
+    }
+
+    #[test]
+    fn wraps_long_lists() {
+        let text = "\
+fn add(first_parameter_name: i32, second_parameter_name: i32, third_parameter: i32, fourth: i32) -> i32 { 0 }
+fn main() { add(the_first_argument_value_name, second_argument_value_name, add(1, 2, 3), fourth_argument_value); }
+";
+        assert_eq!(
+            fmt(text),
+            "\
+fn add(
+    first_parameter_name: i32,
+    second_parameter_name: i32,
+    third_parameter: i32,
+    fourth: i32,
+) -> i32 {
+    0
+}
+fn main() {
+    add(
+        the_first_argument_value_name,
+        second_argument_value_name,
+        add(1, 2, 3),
+        fourth_argument_value,
+    );
+}
+"
+        );
+    }
+
+    #[test]
+    fn keeps_comments_and_blank_lines() {
+        let text = "\
+// The entry point
+fn main() { // Starts here
+  let x = 1; // One
+
+
+  // Then a call
+  f(x, // The argument
+    2);
+  // At the end
+}
+/// Does nothing
+fn f(a: i32, b: i32) {}
+";
+        assert_eq!(
+            fmt(text),
+            "\
+// The entry point
+fn main() {
+    // Starts here
+    let x = 1; // One
+
+    // Then a call
+    f(
+        x, // The argument
+        2,
+    );
+    // At the end
+}
+/// Does nothing
+fn f(a: i32, b: i32) {}
+"
+        );
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        let diagnostics = format("fn main( {}", PathBuf::from("test.hds")).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+    }
+
+    /// The tree of a file without spans and ids, which formatting changes
+    fn tree(text: &str) -> Value {
+        fn strip(value: &mut Value) {
+            match value {
+                Value::Object(map) => {
+                    map.remove("meta");
+                    map.values_mut().for_each(strip);
+                }
+                Value::Array(values) => values.iter_mut().for_each(strip),
+                _ => {}
+            }
+        }
+        let source_file = cst::parse(text, PathBuf::from("test.hds")).source_file;
+        let mut value = serde_json::to_value(&source_file.items).unwrap();
+        strip(&mut value);
+        value
+    }
+
+    #[test]
+    fn formats_the_test_files_without_changing_them() {
+        let mut files = vec![];
+        collect_files(Path::new("test"), &mut files).unwrap();
+        assert!(files.len() > 10);
+        for path in files {
+            let text = std::fs::read_to_string(&path).unwrap();
+            let formatted = fmt(&text);
+            assert_eq!(tree(&formatted), tree(&text), "{}", path.display());
+            assert_eq!(fmt(&formatted), formatted, "{}", path.display());
+            assert_eq!(formatted, text, "{} isn't formatted", path.display());
+        }
+    }
+}
@@ -5,29 +5,79 @@ use lazy_static::lazy_static;
 use libsyntax::Span;
 use libsyntax_derive::HasSpan;
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum TokenKind {
     IDENT,
     INT,
+    /// A string literal, including its quotes
+    STRING,
 
     // Keyworkds
     FN,
     PUB,
     EXTERN,
     UNSAFE,
+    STRUCT,
+    LET,
+    ENUM,
+    MATCH,
+    IF,
+    TRUE,
+    FALSE,
+    MUT,
+    AS,
+    NULL,
+    TRAIT,
+    IMPL,
+    FOR,
+    DYN,
+    MOD,
+    USE,
+    CONST,
+    STATIC,
+    TYPE,
+    NEWTYPE,
 
     // Punctuation
     LPAREN,
     RPAREN,
     LBRACE,
     RBRACE,
+    LBRACKET,
+    RBRACKET,
     SEMI,
+    COMMA,
+    DOT,
+    POUND,
 
     // Non punctuation Operators
     ARROW,
     COLON,
     COLONCOLON,
-
+    EQ,
+    FATARROW,
+    DOTDOT,
+    DOTDOTDOT,
+    STAR,
+    AMP,
+    ANDAND,
+    OROR,
+    PLUS,
+    MINUS,
+    SLASH,
+    PERCENT,
+    BANG,
+    EQEQ,
+    BANGEQ,
+    LT,
+    LE,
+    GT,
+    GE,
+
+    /// A character that doesn't start a token, or a string missing its closing
+    /// quote, which the parser reports
+    ERROR,
     EOF,
 }
 #[derive(Debug, HasSpan)]
@@ -37,6 +87,23 @@ pub struct Token {
     pub text: String,
 }
 
+/// Whitespace or a comment between tokens
+#[derive(Debug, Clone, PartialEq, Eq, HasSpan)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub span: Span,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+    Whitespace,
+    /// A `//` comment, up to the end of its line
+    Comment,
+    /// Text the lexer stops at, after a `\0` character
+    Skipped,
+}
+
 lazy_static! {
     static ref TOKEN_KINDS: HashMap<&'static str, TokenKind> = {
         let mut m = HashMap::new();
@@ -46,6 +113,26 @@ lazy_static! {
         i("pub", PUB);
         i("extern", EXTERN);
         i("unsafe", UNSAFE);
+        i("struct", STRUCT);
+        i("let", LET);
+        i("enum", ENUM);
+        i("match", MATCH);
+        i("if", IF);
+        i("true", TRUE);
+        i("false", FALSE);
+        i("mut", MUT);
+        i("as", AS);
+        i("null", NULL);
+        i("trait", TRAIT);
+        i("impl", IMPL);
+        i("for", FOR);
+        i("dyn", DYN);
+        i("mod", MOD);
+        i("use", USE);
+        i("const", CONST);
+        i("static", STATIC);
+        i("type", TYPE);
+        i("newtype", NEWTYPE);
         m
     };
 }
@@ -59,7 +146,15 @@ lazy_static! {
         i(')', RPAREN);
         i('{', LBRACE);
         i('}', RBRACE);
+        i('[', LBRACKET);
+        i(']', RBRACKET);
         i(';', SEMI);
+        i(',', COMMA);
+        i('*', STAR);
+        i('+', PLUS);
+        i('/', SLASH);
+        i('%', PERCENT);
+        i('#', POUND);
         m
     };
 }
@@ -71,8 +166,8 @@ pub(crate) struct Lexer<'chars> {
     lexeme: String,
     _path: Rc<PathBuf>,
     position: usize,
-    line: usize,
-    column: usize,
+    /// The whitespace and comments skipped since the last token, in lossless mode
+    trivia: Option<Vec<Trivia>>,
 }
 impl<'chars> Lexer<'chars> {
     pub fn new(text: &'chars str, path: PathBuf) -> Self {
@@ -84,11 +179,29 @@ impl<'chars> Lexer<'chars> {
             _path: Rc::new(path),
             lexeme: String::new(),
             position: 0,
-            line: 1,
-            column: 1,
+            trivia: None,
         }
     }
 
+    /// Makes spans start at `offset`, for files that aren't the first in the
+    /// [crate::source_map::SourceMap]
+    pub fn starting_at(mut self, offset: usize) -> Self {
+        self.position = offset;
+        self
+    }
+
+    /// Keeps the whitespace and comments before each token, which
+    /// [Lexer::take_trivia] returns
+    pub fn keeping_trivia(mut self) -> Self {
+        self.trivia = Some(vec![]);
+        self
+    }
+
+    /// The whitespace and comments skipped since this was last called
+    pub fn take_trivia(&mut self) -> Vec<Trivia> {
+        self.trivia.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
     pub fn next_token(&mut self) -> Token {
         self.skip_whitespace();
         self.start_token();
@@ -96,9 +209,26 @@ impl<'chars> Lexer<'chars> {
             '\0' => self.make_token(TokenKind::EOF),
             '-' => {
                 self.advance();
-                self.expect('>', "Expected `>` after `-`");
-                self.make_token(TokenKind::ARROW)
+                if self.current_char == '>' {
+                    self.advance();
+                    self.make_token(TokenKind::ARROW)
+                } else {
+                    self.make_token(TokenKind::MINUS)
+                }
             }
+            '&' => {
+                self.advance();
+                if self.current_char == '&' {
+                    self.advance();
+                    self.make_token(TokenKind::ANDAND)
+                } else {
+                    self.make_token(TokenKind::AMP)
+                }
+            }
+            '|' => self.one_or_two('|', TokenKind::ERROR, TokenKind::OROR),
+            '!' => self.one_or_two('=', TokenKind::BANG, TokenKind::BANGEQ),
+            '<' => self.one_or_two('=', TokenKind::LT, TokenKind::LE),
+            '>' => self.one_or_two('=', TokenKind::GT, TokenKind::GE),
             ':' => {
                 self.advance();
                 if self.current_char == ':' {
@@ -108,7 +238,33 @@ impl<'chars> Lexer<'chars> {
                     self.make_token(TokenKind::COLON)
                 }
             }
-            c if c.is_digit(10) => self.integer(),
+            '=' => {
+                self.advance();
+                if self.current_char == '>' {
+                    self.advance();
+                    self.make_token(TokenKind::FATARROW)
+                } else if self.current_char == '=' {
+                    self.advance();
+                    self.make_token(TokenKind::EQEQ)
+                } else {
+                    self.make_token(TokenKind::EQ)
+                }
+            }
+            '.' => {
+                self.advance();
+                if self.current_char == '.' {
+                    self.advance();
+                    if self.current_char == '.' {
+                        self.advance();
+                        return self.make_token(TokenKind::DOTDOTDOT);
+                    }
+                    self.make_token(TokenKind::DOTDOT)
+                } else {
+                    self.make_token(TokenKind::DOT)
+                }
+            }
+            '"' => self.string(),
+            c if c.is_ascii_digit() => self.integer(),
             c if is_ident_starter(c) => self.ident_or_keyword(),
             c if SINGLE_CHAR_TOKENS.contains_key(&c) => {
                 self.advance();
@@ -118,23 +274,48 @@ impl<'chars> Lexer<'chars> {
                         .expect("Should not panic because of `contains_key` check above"),
                 )
             }
-            c => todo!(
-                "Unexpected character at line: {}:{}: '{}'",
-                self.line,
-                self.column,
-                c
-            ),
+            _ => {
+                self.advance();
+                self.make_token(TokenKind::ERROR)
+            }
+        }
+    }
+
+    /// Lexes a one character token, or a two character token if it is
+    /// followed by `second`.
+    fn one_or_two(&mut self, second: char, one: TokenKind, two: TokenKind) -> Token {
+        self.advance();
+        if self.current_char == second {
+            self.advance();
+            self.make_token(two)
+        } else {
+            self.make_token(one)
         }
     }
 
     fn integer(&mut self) -> Token {
-        assert!(self.current_char.is_digit(10));
-        while self.current_char.is_digit(10) {
+        assert!(self.current_char.is_ascii_digit());
+        while self.current_char.is_ascii_digit() {
             self.advance();
         }
         self.make_token(TokenKind::INT)
     }
 
+    /// Lexes a string up to the closing quote, skipping over escaped characters
+    fn string(&mut self) -> Token {
+        self.advance();
+        while self.current_char != '"' {
+            if self.eof() || self.current_char == '\n' {
+                return self.make_token(TokenKind::ERROR);
+            }
+            if self.advance() == '\\' && !self.eof() {
+                self.advance();
+            }
+        }
+        self.advance();
+        self.make_token(TokenKind::STRING)
+    }
+
     fn ident_or_keyword(&mut self) -> Token {
         assert!(is_ident_starter(self.current_char));
         while is_ident_char(self.current_char) {
@@ -152,22 +333,47 @@ impl<'chars> Lexer<'chars> {
     }
 
     fn make_token(&mut self, kind: TokenKind) -> Token {
-        let text = std::mem::replace(&mut self.lexeme, String::new());
+        let text = std::mem::take(&mut self.lexeme);
         Token {
             kind,
             span: Span {
-                start: self.position - text.len(),
+                start: self.position - text.chars().count(),
                 end: self.position,
             },
             text,
         }
     }
     fn skip_whitespace(&mut self) {
-        while self.current_char.is_whitespace() && !self.eof() {
-            self.advance();
+        loop {
+            self.start_token();
+            let kind = if self.current_char.is_whitespace() {
+                while self.current_char.is_whitespace() {
+                    self.advance();
+                }
+                TriviaKind::Whitespace
+            } else if self.current_char == '/' && self.peek() == '/' {
+                while self.current_char != '\n' && !self.eof() {
+                    self.advance();
+                }
+                TriviaKind::Comment
+            } else {
+                break;
+            };
+            let token = self.make_token(TokenKind::EOF);
+            if let Some(trivia) = &mut self.trivia {
+                trivia.push(Trivia {
+                    kind,
+                    span: token.span,
+                    text: token.text,
+                });
+            }
         }
     }
 
+    fn peek(&self) -> char {
+        self.text.clone().next().unwrap_or('\0')
+    }
+
     fn eof(&self) -> bool {
         self.current_char == '\0'
     }
@@ -179,24 +385,8 @@ impl<'chars> Lexer<'chars> {
         self.current_char = self.text.next().unwrap_or('\0');
         self.lexeme.push(current_char);
         self.position += 1;
-        if current_char == '\n' {
-            self.line += 1;
-            self.column = 1;
-        } else {
-            self.column += 1;
-        }
         current_char
     }
-    fn expect(&mut self, c: char, message: &str) -> char {
-        if self.current_char != c {
-            eprintln!("Current position: {}", self.position);
-            panic!(
-                "{}; Expected: '{}'; Found: '{}'",
-                message, c, self.current_char
-            );
-        }
-        self.advance()
-    }
 }
 
 fn is_ident_starter(c: char) -> bool {
@@ -281,6 +471,163 @@ mod test {
         assert_eq!(t.next_token().kind, TokenKind::ARROW);
     }
 
+    #[test]
+    fn tokenizes_struct_literal() {
+        let mut t = mk_tokenizer("struct P { x: i32 } p.x = P { x: 1, };");
+        use TokenKind as k;
+        for kind in [
+            k::STRUCT,
+            k::IDENT,
+            k::LBRACE,
+            k::IDENT,
+            k::COLON,
+            k::IDENT,
+            k::RBRACE,
+            k::IDENT,
+            k::DOT,
+            k::IDENT,
+            k::EQ,
+            k::IDENT,
+            k::LBRACE,
+            k::IDENT,
+            k::COLON,
+            k::INT,
+            k::COMMA,
+            k::RBRACE,
+            k::SEMI,
+            k::EOF,
+        ] {
+            assert_eq!(t.next_token().kind, kind);
+        }
+    }
+
+    #[test]
+    fn tokenizes_match_arms() {
+        let mut t = mk_tokenizer("match x { E::A(..) => true, _ = false }");
+        use TokenKind as k;
+        for kind in [
+            k::MATCH,
+            k::IDENT,
+            k::LBRACE,
+            k::IDENT,
+            k::COLONCOLON,
+            k::IDENT,
+            k::LPAREN,
+            k::DOTDOT,
+            k::RPAREN,
+            k::FATARROW,
+            k::TRUE,
+            k::COMMA,
+            k::IDENT,
+            k::EQ,
+            k::FALSE,
+            k::RBRACE,
+            k::EOF,
+        ] {
+            assert_eq!(t.next_token().kind, kind);
+        }
+    }
+
+    #[test]
+    fn tokenizes_operators_and_skips_comments() {
+        let mut t =
+            mk_tokenizer("*mut p = &x - 1; // done\n a == b != c <= d && !e || f >= g -> h");
+        use TokenKind as k;
+        for kind in [
+            k::STAR,
+            k::MUT,
+            k::IDENT,
+            k::EQ,
+            k::AMP,
+            k::IDENT,
+            k::MINUS,
+            k::INT,
+            k::SEMI,
+            k::IDENT,
+            k::EQEQ,
+            k::IDENT,
+            k::BANGEQ,
+            k::IDENT,
+            k::LE,
+            k::IDENT,
+            k::ANDAND,
+            k::BANG,
+            k::IDENT,
+            k::OROR,
+            k::IDENT,
+            k::GE,
+            k::IDENT,
+            k::ARROW,
+            k::IDENT,
+            k::EOF,
+        ] {
+            assert_eq!(t.next_token().kind, kind);
+        }
+    }
+
+    #[test]
+    fn tokenizes_trait_keywords() {
+        let mut t = mk_tokenizer("trait impl for dyn Self");
+        use TokenKind as k;
+        for kind in [k::TRAIT, k::IMPL, k::FOR, k::DYN, k::IDENT, k::EOF] {
+            assert_eq!(t.next_token().kind, kind);
+        }
+    }
+
+    #[test]
+    fn tokenizes_module_keywords_at_an_offset() {
+        let mut t = Lexer::new("mod a; use a::b;", PathBuf::from("test.hds")).starting_at(20);
+        use TokenKind as k;
+        let token = t.next_token();
+        assert_eq!(
+            (token.kind, token.span.start, token.span.end),
+            (k::MOD, 20, 23)
+        );
+        for kind in [k::IDENT, k::SEMI, k::USE, k::IDENT, k::COLONCOLON, k::IDENT] {
+            assert_eq!(t.next_token().kind, kind);
+        }
+    }
+
+    #[test]
+    fn tokenizes_strings_and_attributes() {
+        let mut t = mk_tokenizer(r#"#[link_name = "a\"é"] x"#);
+        use TokenKind as k;
+        for kind in [k::POUND, k::LBRACKET, k::IDENT, k::EQ] {
+            assert_eq!(t.next_token().kind, kind);
+        }
+        let token = t.next_token();
+        assert_eq!(token.kind, k::STRING);
+        assert_eq!(token.text, r#""a\"é""#);
+        assert_eq!(t.next_token().kind, k::RBRACKET);
+        // Spans count characters, not bytes
+        assert_eq!(t.next_token().span.start, 22);
+    }
+
+    #[test]
+    fn keeps_trivia_when_asked() {
+        let mut t = mk_tokenizer("a // c\n  b").keeping_trivia();
+        assert_eq!(t.next_token().text, "a");
+        assert!(t.take_trivia().is_empty());
+        assert_eq!(t.next_token().text, "b");
+        let trivia = t.take_trivia();
+        let kinds = trivia.iter().map(|it| it.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                TriviaKind::Whitespace,
+                TriviaKind::Comment,
+                TriviaKind::Whitespace
+            ]
+        );
+        assert_eq!(trivia[1].text, "// c");
+        assert_eq!(trivia[1].span.start, 2);
+        // Without trivia mode nothing is kept
+        let mut t = mk_tokenizer("a // c\n  b");
+        t.next_token();
+        t.next_token();
+        assert!(t.take_trivia().is_empty());
+    }
+
     fn mk_tokenizer(s: &str) -> Lexer<'_> {
         Lexer::new(s, PathBuf::from("test.hds"))
     }
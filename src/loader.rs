@@ -0,0 +1,578 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use libsyntax::{HasSpan, Meta, NodeId, Span};
+
+use crate::{
+    ast::{Ident, Item, ItemKind, Mod, SourceFile, Visibility},
+    diagnostics::Diagnostic,
+    manifest::MANIFEST_NAME,
+    parser::{ParsedFile, Parser},
+    source_map::SourceMap,
+};
+
+#[derive(PartialEq)]
+pub struct LoadResult {
+    pub source_file: SourceFile,
+    pub source_map: SourceMap,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// A library package that a crate uses, directly or through other libraries
+pub struct Library {
+    pub name: Ident,
+    pub entry: PathBuf,
+    /// Names of the libraries it depends on
+    pub deps: Vec<Ident>,
+}
+
+/// Parses the file at `path` along with the files of the modules it declares.
+/// `mod foo;` loads `foo.hds` from the directory of the file declaring it, or
+/// from a subdirectory named after the enclosing module for `mod foo;` inside
+/// `mod bar { ... }`. The crate can refer to the libraries named in `deps`, and
+/// each of `libs` is loaded as a module of the crate root, named after the
+/// library.
+pub fn load_crate(path: &Path, deps: Vec<Ident>, libs: &[Library]) -> io::Result<LoadResult> {
+    let text = read_file(path)?;
+    let (mut source_file, mut loader) = load_root(path, text, Files::Disk(HashMap::new()));
+    source_file.deps = deps;
+    let mut lib_items = vec![];
+    for lib in libs {
+        lib_items.push(loader.load_lib(lib)?);
+    }
+    lib_items.append(&mut source_file.items);
+    source_file.items = lib_items;
+    Ok(LoadResult {
+        source_file,
+        source_map: loader.source_map,
+        diagnostics: loader.diagnostics,
+    })
+}
+
+/// Like [load_crate] without libraries, but with the text of the root file
+/// given instead of read from `path`, e.g. for a file with unsaved changes in
+/// an editor. The files of modules in `edited` are given by their text there
+/// rather than read from disk too. Keys are the paths of the files as the source map has them.
+pub fn load_edited(path: &Path, text: String, edited: HashMap<PathBuf, String>) -> LoadResult {
+    let (source_file, loader) = load_root(path, text, Files::Disk(edited));
+    LoadResult {
+        source_file,
+        source_map: loader.source_map,
+        diagnostics: loader.diagnostics,
+    }
+}
+
+/// Like [load_edited] for the crate with its root at `root`, but with the file
+/// of the crate root or of one of its modules already parsed, e.g. by
+/// [ParsedFile::reparse] after an edit. That file comes first in the source
+/// map, so its spans are the same whichever file the crate's root is. A file
+/// that the root doesn't load is loaded as a crate root itself. The crate can
+/// use libraries like with [load_crate], and those that can't be read are
+/// reported at the start of the file.
+pub fn load_parsed(
+    root: &Path,
+    text: String,
+    parsed: &ParsedFile,
+    edited: HashMap<PathBuf, String>,
+    deps: Vec<Ident>,
+    libs: &[Library],
+) -> LoadResult {
+    let root_text = match edited.get(root) {
+        _ if root == parsed.path => None,
+        Some(text) => Some(text.clone()),
+        None => read_file(root).ok(),
+    };
+    let loaded = root_text.and_then(|root_text| {
+        let start = text.chars().count() + 1;
+        let mut parser = Parser::for_module(&root_text, root.to_path_buf(), start, 0);
+        let items = parser.parse_items();
+        let (diagnostics, next_node_id) = (parser.take_diagnostics(), parser.next_node_id());
+        let (source_file, loader) = load_items(
+            root,
+            root_text,
+            items,
+            diagnostics,
+            next_node_id,
+            Files::Disk(edited.clone()),
+            Some((text.clone(), parsed)),
+        );
+        loader.first.is_none().then_some((source_file, loader))
+    });
+    let (mut source_file, mut loader) = loaded.unwrap_or_else(|| {
+        load_items(
+            &parsed.path,
+            text,
+            parsed.items.clone(),
+            parsed.diagnostics(),
+            parsed.next_node_id(),
+            Files::Disk(edited),
+            None,
+        )
+    });
+    source_file.deps = deps;
+    let mut lib_items = vec![];
+    for lib in libs {
+        match loader.load_lib(lib) {
+            Ok(item) => lib_items.push(item),
+            Err(err) => {
+                let message = format!("Could not load library `{}`: {}", lib.name, err);
+                let span = Span { start: 0, end: 0 };
+                loader.diagnostics.push(Diagnostic::new(span, message));
+            }
+        }
+    }
+    lib_items.append(&mut source_file.items);
+    source_file.items = lib_items;
+    LoadResult {
+        source_file,
+        source_map: loader.source_map,
+        diagnostics: loader.diagnostics,
+    }
+}
+
+/// The root of the crate that the file at `path` belongs to: the file that
+/// declares it as a module, or the root that file belongs to in turn. Files
+/// are looked for in the directories above `path` up to the one with the
+/// package's manifest, and are given by their text in `edited` if they're
+/// there. A file that no file declares is a crate root itself.
+pub fn crate_root(path: &Path, edited: &HashMap<PathBuf, String>) -> PathBuf {
+    let mut root = path.to_path_buf();
+    let mut seen = vec![root.clone()];
+    while let Some(file) = declaring_file(&root, edited, &seen) {
+        seen.push(file.clone());
+        root = file;
+    }
+    root
+}
+
+/// The file declaring the module in the file at `path`, other than the `seen`
+/// ones, which have declared it or the modules it declares
+fn declaring_file(
+    path: &Path,
+    edited: &HashMap<PathBuf, String>,
+    seen: &[PathBuf],
+) -> Option<PathBuf> {
+    for dir in path.ancestors().skip(1) {
+        let mut files = std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|it| it.extension().is_some_and(|it| it == "hds") && !seen.contains(it))
+            .collect::<Vec<_>>();
+        files.sort();
+        for file in files {
+            let text = match edited.get(&file) {
+                Some(text) => text.clone(),
+                None => std::fs::read_to_string(&file).unwrap_or_default(),
+            };
+            let items = Parser::new(&text, file.clone()).parse_items();
+            if declares(&items, dir_of(&file), path) {
+                return Some(file);
+            }
+        }
+        if dir.join(MANIFEST_NAME).exists() {
+            break;
+        }
+    }
+    None
+}
+
+/// Whether `items`, declared in a module whose files live in `dir`, declare
+/// the module in the file at `path`
+fn declares(items: &[Item], dir: &Path, path: &Path) -> bool {
+    items.iter().any(|item| match &item.kind {
+        ItemKind::Mod(m) if m.inline => declares(&m.items, &dir.join(&item.name), path),
+        ItemKind::Mod(_) => dir.join(format!("{}.hds", item.name)) == path,
+        _ => false,
+    })
+}
+
+/// Like [load_crate] without libraries, but with every file read and parsed by
+/// `parse_file`, which gives `None` for files that can't be read; e.g. by a
+/// [crate::db::Database] that keeps them between builds
+pub fn load_with(path: &Path, parse_file: &ParseFile) -> io::Result<LoadResult> {
+    let (text, parsed) = parse_file(path).ok_or_else(|| {
+        let message = format!("{}: could not read the file", path.display());
+        io::Error::new(io::ErrorKind::NotFound, message)
+    })?;
+    let (source_file, loader) = load_items(
+        path,
+        text,
+        parsed.items.clone(),
+        parsed.diagnostics(),
+        parsed.next_node_id(),
+        Files::Parsed(parse_file),
+        None,
+    );
+    Ok(LoadResult {
+        source_file,
+        source_map: loader.source_map,
+        diagnostics: loader.diagnostics,
+    })
+}
+
+/// Gives the text of a file along with the file parsed at offset 0
+pub type ParseFile<'a> = dyn Fn(&Path) -> Option<(String, Rc<ParsedFile>)> + 'a;
+
+/// Where the loader gets the files of modules from
+enum Files<'a> {
+    /// Read from disk unless they're in the map, and parsed by the loader
+    Disk(HashMap<PathBuf, String>),
+    Parsed(&'a ParseFile<'a>),
+}
+
+fn load_root<'a>(path: &Path, text: String, files: Files<'a>) -> (SourceFile, Loader<'a>) {
+    let mut parser = Parser::new(&text, path.to_path_buf());
+    let items = parser.parse_items();
+    let (diagnostics, next_node_id) = (parser.take_diagnostics(), parser.next_node_id());
+    load_items(path, text, items, diagnostics, next_node_id, files, None)
+}
+
+/// Loads the modules declared among the items of a root file. `first` is the
+/// text of a module's file and the file parsed, to put first in the source map
+/// before the root.
+fn load_items<'a>(
+    path: &Path,
+    text: String,
+    items: Vec<Item>,
+    diagnostics: Vec<Diagnostic>,
+    next_node_id: u32,
+    files: Files<'a>,
+    first: Option<(String, &'a ParsedFile)>,
+) -> (SourceFile, Loader<'a>) {
+    let mut source_map = SourceMap::new();
+    let first = first.map(|(text, parsed)| {
+        source_map.add_file(parsed.path.clone(), text);
+        (canonical(&parsed.path), parsed)
+    });
+    source_map.add_file(path.to_path_buf(), text);
+    let mut source_file = SourceFile {
+        path: path.to_path_buf(),
+        items,
+        deps: vec![],
+    };
+    let mut loader = Loader {
+        source_map,
+        next_node_id,
+        stack: vec![canonical(path)],
+        diagnostics,
+        files,
+        first,
+    };
+    loader.load_mods(&mut source_file.items, dir_of(path));
+    (source_file, loader)
+}
+
+/// Like [std::fs::read_to_string], but mentions the path in errors
+fn read_file(path: &Path) -> io::Result<String> {
+    std::fs::read_to_string(path)
+        .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
+}
+
+/// The canonical path of a file, or the path itself for files that editors
+/// haven't saved anywhere yet
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn dir_of(path: &Path) -> &Path {
+    path.parent().unwrap_or(Path::new(""))
+}
+
+struct Loader<'a> {
+    source_map: SourceMap,
+    next_node_id: u32,
+    /// Canonical paths of the files being loaded, to detect modules that
+    /// include themselves
+    stack: Vec<PathBuf>,
+    diagnostics: Vec<Diagnostic>,
+    files: Files<'a>,
+    /// The canonical path of the module file that's first in the source map,
+    /// and the file parsed, until the file is loaded
+    first: Option<(PathBuf, &'a ParsedFile)>,
+}
+
+impl Loader<'_> {
+    /// Adds a file to the source map, parsing it unless it's already `parsed`
+    fn parse_file(
+        &mut self,
+        path: &Path,
+        text: String,
+        parsed: Option<Rc<ParsedFile>>,
+    ) -> Vec<Item> {
+        let start = self.source_map.add_file(path.to_path_buf(), text.clone());
+        if let Some(parsed) = parsed {
+            return self.add_parsed(&parsed, start);
+        }
+        let mut parser = Parser::for_module(&text, path.to_path_buf(), start, self.next_node_id);
+        let items = parser.parse_items();
+        self.next_node_id = parser.next_node_id();
+        self.diagnostics.extend(parser.take_diagnostics());
+        items
+    }
+
+    /// The items of a parsed file at offset `start`, with ids after the
+    /// files loaded so far
+    fn add_parsed(&mut self, parsed: &ParsedFile, start: usize) -> Vec<Item> {
+        let (items, diagnostics) = parsed.moved_to(start, self.next_node_id);
+        self.next_node_id += parsed.next_node_id();
+        self.diagnostics.extend(diagnostics);
+        items
+    }
+
+    /// The text of a file, and the file parsed if it already is
+    fn read(&self, path: &Path) -> io::Result<(String, Option<Rc<ParsedFile>>)> {
+        match &self.files {
+            Files::Disk(edited) => match edited.get(path) {
+                Some(text) => Ok(text.clone()),
+                None => read_file(path),
+            }
+            .map(|text| (text, None)),
+            Files::Parsed(parse_file) => parse_file(path)
+                .map(|(text, parsed)| (text, Some(parsed)))
+                .ok_or_else(|| io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    /// Loads a library as a module named after it
+    fn load_lib(&mut self, lib: &Library) -> io::Result<Item> {
+        let (text, parsed) = self.read(&lib.entry)?;
+        self.stack = vec![lib.entry.canonicalize()?];
+        let start = self.source_map.next_start();
+        let mut items = self.parse_file(&lib.entry, text, parsed);
+        self.load_mods(&mut items, dir_of(&lib.entry));
+        let id = NodeId(self.next_node_id);
+        self.next_node_id += 1;
+        Ok(Item {
+            meta: Meta {
+                span: Span { start, end: start },
+                id,
+            },
+            attrs: vec![],
+            kind: ItemKind::Mod(Mod {
+                items,
+                inline: false,
+                crate_deps: Some(lib.deps.clone()),
+            }),
+            vis: Visibility::Public,
+            name: lib.name.clone(),
+        })
+    }
+
+    /// Fills in the items of the out of line modules among `items`, which were
+    /// declared in a module whose files live in `dir`
+    fn load_mods(&mut self, items: &mut [Item], dir: &Path) {
+        for item in items.iter_mut() {
+            let span = *item.span();
+            let ItemKind::Mod(m) = &mut item.kind else {
+                continue;
+            };
+            if m.inline {
+                self.load_mods(&mut m.items, &dir.join(&item.name));
+                continue;
+            }
+            let path = dir.join(format!("{}.hds", item.name));
+            if let Some(items) = self.load_mod_file(&item.name, &path, span) {
+                m.items = items;
+                self.stack.pop();
+            }
+        }
+    }
+
+    /// Loads the items of a module and the modules it declares. Leaves the file
+    /// on the stack unless an error was reported.
+    fn load_mod_file(&mut self, name: &str, path: &Path, span: Span) -> Option<Vec<Item>> {
+        let Ok((text, parsed)) = self.read(path) else {
+            let message = format!("File not found for module `{}`: {}", name, path.display());
+            self.diagnostics.push(Diagnostic::new(span, message));
+            return None;
+        };
+        let canonical = canonical(path);
+        if let Some(index) = self.stack.iter().position(|it| *it == canonical) {
+            let cycle = self.stack[index..]
+                .iter()
+                .chain([&canonical])
+                .map(|it| it.file_name().unwrap_or_default().to_string_lossy())
+                .collect::<Vec<_>>();
+            let message = format!("Module `{}` includes itself: {}", name, cycle.join(" -> "));
+            self.diagnostics.push(Diagnostic::new(span, message));
+            return None;
+        }
+        let first = self.first.take_if(|(first, _)| *first == canonical);
+        self.stack.push(canonical);
+        let mut items = match first {
+            Some((_, first)) => self.add_parsed(first, 0),
+            None => self.parse_file(path, text, parsed),
+        };
+        self.load_mods(&mut items, dir_of(path));
+        Some(items)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use libsyntax::HasMeta;
+
+    use super::*;
+    use crate::testing::write_files;
+
+    fn mod_items<'a>(items: &'a [Item], name: &str) -> &'a [Item] {
+        items
+            .iter()
+            .find_map(|it| match &it.kind {
+                ItemKind::Mod(m) if it.name == name => Some(&m.items[..]),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn loads_module_files() {
+        let dir = write_files(
+            "modules",
+            &[
+                ("main.hds", "mod a; mod b { mod c; } fn main() {}"),
+                ("a.hds", "pub fn f() {}"),
+                ("b/c.hds", "pub fn g() {}"),
+            ],
+        );
+        let path = dir.join("main.hds");
+        let result = load_crate(&path, vec![], &[]).unwrap();
+        assert!(result.diagnostics.is_empty());
+        let items = &result.source_file.items;
+        assert_eq!(mod_items(items, "a")[0].name, "f");
+        assert_eq!(mod_items(mod_items(items, "b"), "c")[0].name, "g");
+        // Node ids and spans don't overlap between files
+        let f = &mod_items(items, "a")[0];
+        let main = &items[2];
+        assert_ne!(f.id(), main.id());
+        let (file, _) = result.source_map.lookup(f.span().start);
+        assert!(file.path.ends_with("a.hds"));
+    }
+
+    #[test]
+    fn loads_edited_files_instead_of_the_files_on_disk() {
+        let dir = write_files(
+            "edited",
+            &[("main.hds", "mod a;"), ("a.hds", "pub fn f() {}")],
+        );
+        let path = dir.join("main.hds");
+        let a = dir.join("a.hds");
+        let edited = HashMap::from([(a, "pub fn g() {}".to_string())]);
+        let result = load_edited(&path, "mod a; fn main() {}".into(), edited);
+        let items = &result.source_file.items;
+        assert_eq!(mod_items(items, "a")[0].name, "g");
+        assert_eq!(items[1].name, "main");
+    }
+
+    #[test]
+    fn finds_the_crate_roots_of_module_files() {
+        let dir = write_files(
+            "roots",
+            &[
+                ("main.hds", "mod a; mod b { mod c; } fn main() {}"),
+                ("a.hds", "mod d;"),
+                ("d.hds", "pub fn f() {}"),
+                ("b/c.hds", "pub fn g() {}"),
+                ("other.hds", "fn main() {}"),
+            ],
+        );
+        let path = dir.join("main.hds");
+        let no_edits = HashMap::new();
+        assert_eq!(crate_root(&dir.join("d.hds"), &no_edits), path);
+        assert_eq!(crate_root(&dir.join("b/c.hds"), &no_edits), path);
+        assert_eq!(crate_root(&path, &no_edits), path);
+        assert_eq!(
+            crate_root(&dir.join("other.hds"), &no_edits),
+            dir.join("other.hds")
+        );
+        // Edited files declare the modules of their text
+        let edited = HashMap::from([(dir.join("a.hds"), String::new())]);
+        assert_eq!(crate_root(&dir.join("d.hds"), &edited), dir.join("d.hds"));
+
+        // The parsed module comes first, with its items loaded from the root
+        let d = dir.join("d.hds");
+        let parsed = ParsedFile::parse("pub fn h() {}", d.clone());
+        let result = load_parsed(
+            &path,
+            "pub fn h() {}".into(),
+            &parsed,
+            HashMap::new(),
+            vec![],
+            &[],
+        );
+        assert!(result.diagnostics.is_empty());
+        assert_eq!(result.source_map.files()[0].path, d);
+        let h = &mod_items(mod_items(&result.source_file.items, "a"), "d")[0];
+        assert_eq!((h.name.as_str(), h.span().start), ("h", 0));
+        // Files that the root doesn't load are roots themselves
+        let other = dir.join("other.hds");
+        let parsed = ParsedFile::parse("fn main() {}", other.clone());
+        let result = load_parsed(
+            &path,
+            "fn main() {}".into(),
+            &parsed,
+            HashMap::new(),
+            vec![],
+            &[],
+        );
+        assert_eq!(result.source_file.path, other);
+    }
+
+    #[test]
+    fn reports_missing_and_cyclic_modules() {
+        let dir = write_files(
+            "errors",
+            &[
+                ("main.hds", "mod a; mod missing;"),
+                ("a.hds", "mod b;"),
+                ("b.hds", "mod a;"),
+            ],
+        );
+        let path = dir.join("main.hds");
+        let result = load_crate(&path, vec![], &[]).unwrap();
+        let messages = result
+            .diagnostics
+            .iter()
+            .map(|it| it.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0],
+            "Module `a` includes itself: a.hds -> b.hds -> a.hds"
+        );
+        assert!(messages[1].starts_with("File not found for module `missing`: "));
+    }
+
+    #[test]
+    fn loads_libraries_as_crate_root_modules() {
+        let dir = write_files(
+            "libraries",
+            &[
+                ("app/main.hds", "fn main() {}"),
+                ("util/lib.hds", "mod inner; pub fn f() {}"),
+                ("util/inner.hds", "pub fn g() {}"),
+            ],
+        );
+        let path = dir.join("app/main.hds");
+        let util = Library {
+            name: "util".into(),
+            entry: dir.join("util/lib.hds"),
+            deps: vec![],
+        };
+        let result = load_crate(&path, vec!["util".into()], &[util]).unwrap();
+        assert!(result.diagnostics.is_empty());
+        let source_file = &result.source_file;
+        assert_eq!(source_file.deps, vec!["util"]);
+        let ItemKind::Mod(m) = &source_file.items[0].kind else {
+            panic!()
+        };
+        assert_eq!(m.crate_deps, Some(vec![]));
+        assert_eq!(mod_items(&m.items, "inner")[0].name, "g");
+        assert_eq!(source_file.items[1].name, "main");
+    }
+}
@@ -1,43 +1,467 @@
-use crate::ast::{self, visit::Visitor, ForeignItem, SourceFile};
+mod runtime;
 
-pub fn lower_source_file(source_file: SourceFile) {
-    let buffer = String::new();
-    let lowerer = LowerInterfaceCtx::new(buffer);
-    let buffer = lowerer.lower(&source_file);
-    let lowerer = LowerImplCtx::new(buffer);
-    let out = lowerer.lower(source_file);
-    todo!("Output: {}", out)
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use libsyntax::{HasMeta, HasSpan, NodeId};
+
+use crate::{
+    analysis::{
+        const_eval::ConstValue,
+        resolve::{DefKind, Res, ResolveResult},
+        ty::{AdtRef, PrimTy, TraitRef, Type},
+        typeck::{FnSig, Receiver, TypeckResult, VariantDef, VariantKind},
+    },
+    ast::{
+        self, visit::Visitor, BinOp, ExprKind, ForeignItem, ItemKind, Mutability, SourceFile,
+        StmtKind, UnOp,
+    },
+    diagnostics::line_col,
+    mono::{FnContext, MonoResult},
+    source_map::SourceMap,
+};
+
+/// `source_map` holds the source of `source_file`, used to report the location
+/// of runtime errors.
+pub fn lower_source_file(
+    source_file: &SourceFile,
+    source_map: &SourceMap,
+    resolve: &ResolveResult,
+    typeck: &TypeckResult,
+    mono: &MonoResult,
+) -> String {
+    let buffer = lower_interface(source_file, resolve, typeck, mono);
+    let lowerer = LowerImplCtx::new(buffer, source_map, resolve, typeck);
+    lowerer.lower(source_file, mono)
+}
+
+/// The declarations that the definitions of the instances in `mono` refer to
+pub fn lower_interface(
+    source_file: &SourceFile,
+    resolve: &ResolveResult,
+    typeck: &TypeckResult,
+    mono: &MonoResult,
+) -> String {
+    LowerInterfaceCtx::new(String::new(), resolve, typeck, mono).lower(source_file)
+}
+
+/// The definition of an instance of `f`, to follow [lower_interface];
+/// `source_map` holds the source of `f`.
+pub fn lower_instance(source_map: &SourceMap, f: FnContext, args: &[Type]) -> String {
+    let mut lowerer = LowerImplCtx::new(String::new(), source_map, f.resolve, f.typeck);
+    lowerer.lower_fn(f.item, f.f, args);
+    lowerer.buffer
 }
 
-struct LowerInterfaceCtx {
+/// Emits everything that function bodies may refer to: struct and enum typedefs,
+/// the structs representing arrays, slices, tuples, instances of generic types
+/// and `dyn` pointers, function prototypes and vtables.
+struct LowerInterfaceCtx<'a> {
     buffer: String,
+    resolve: &'a ResolveResult,
+    typeck: &'a TypeckResult,
+    mono: &'a MonoResult,
+    emitted_adts: HashSet<AdtRef>,
+    /// C names of the array, slice and tuple structs that have been typedef'd
+    declared_tys: HashSet<String>,
+    /// C names of the array, slice and tuple structs that have been defined
+    defined_tys: HashSet<String>,
+    /// Array, slice and tuple types that are used behind pointers; They're
+    /// defined after the ADTs because they may contain the ADT pointing to them.
+    pending_tys: Vec<Type>,
+    /// Whether any array or slice is used, so the bounds check runtime is needed
+    needs_bounds_checks: bool,
 }
-impl LowerInterfaceCtx {
-    fn new(buffer: String) -> Self {
-        LowerInterfaceCtx { buffer }
+impl<'a> LowerInterfaceCtx<'a> {
+    fn new(
+        buffer: String,
+        resolve: &'a ResolveResult,
+        typeck: &'a TypeckResult,
+        mono: &'a MonoResult,
+    ) -> Self {
+        LowerInterfaceCtx {
+            buffer,
+            resolve,
+            typeck,
+            mono,
+            emitted_adts: HashSet::new(),
+            declared_tys: HashSet::new(),
+            defined_tys: HashSet::new(),
+            pending_tys: vec![],
+            needs_bounds_checks: false,
+        }
     }
 
     fn lower(mut self, source_file: &SourceFile) -> String {
+        self.buffer.push_str("#include <stdint.h>\n");
+        self.buffer.push_str("#include <stdbool.h>\n");
+        self.buffer.push_str("#include <stddef.h>\n\n");
+        // Declared up front so that structs can point to each other; Instances
+        // of generic types are declared when they're used.
+        let adts = source_file
+            .all_items()
+            .filter(|it| matches!(it.kind, ItemKind::Struct(_) | ItemKind::Enum(_)))
+            .filter(|it| self.typeck.generics_of(it.id()).is_empty())
+            .map(|it| AdtRef {
+                def: it.id(),
+                name: self.typeck.adts[&it.id()].name.clone(),
+                args: vec![],
+            })
+            .collect::<Vec<_>>();
+        for adt in adts.iter() {
+            let name = lower_ty(&Type::Adt(adt.clone()));
+            writeln!(self.buffer, "typedef struct {} {};", name, name).unwrap();
+        }
+        for adt in adts.iter() {
+            self.lower_adt(adt);
+        }
+        self.define_used_tys();
+        if self.needs_bounds_checks {
+            self.push_bounds_check_runtime(source_file);
+        }
         for item in source_file.items.iter() {
-            self.visit_item(&item);
+            self.visit_item(item);
+        }
+        for (trait_id, ty) in self.mono.vtables.iter() {
+            self.push_vtable(*trait_id, ty);
         }
         self.buffer
     }
+
+    /// Defines the types used by function signatures and by the function
+    /// instances found by monomorphization.
+    fn define_used_tys(&mut self) {
+        let typeck = self.typeck;
+        let mut sigs = typeck
+            .fn_sigs
+            .iter()
+            .filter(|(id, _)| typeck.generics_of(**id).is_empty())
+            .collect::<Vec<_>>();
+        sigs.sort_by_key(|(id, _)| **id);
+        for (_, sig) in sigs {
+            sig.params.iter().for_each(|it| self.define_ty(it));
+            self.define_ty(&sig.ret);
+        }
+        for ty in self.mono.tys.iter() {
+            self.define_ty(ty);
+        }
+        while let Some(ty) = self.pending_tys.pop() {
+            self.define_ty(&ty);
+        }
+    }
+
+    /// Makes `ty` usable by value, defining it after the types it contains.
+    fn define_ty(&mut self, ty: &Type) {
+        match ty {
+            Type::Adt(adt) => {
+                if !adt.args.is_empty() {
+                    self.declare_ty(ty);
+                }
+                self.lower_adt(adt);
+            }
+            Type::Ptr(..) => self.declare_ty(ty),
+            Type::Array(elem, len) => {
+                self.needs_bounds_checks = true;
+                self.define_ty(elem);
+                let name = lower_ty(ty);
+                if self.defined_tys.insert(name.clone()) {
+                    self.declare_ty(ty);
+                    // Zero length arrays are not allowed in standard C
+                    let c_len = (*len).max(1);
+                    if elem.is_unit() {
+                        writeln!(self.buffer, "struct {} {{ char _unused; }};", name).unwrap();
+                    } else {
+                        let elem = lower_ty(elem);
+                        writeln!(
+                            self.buffer,
+                            "struct {} {{ {} items[{}]; }};",
+                            name, elem, c_len
+                        )
+                        .unwrap();
+                    }
+                }
+            }
+            Type::Slice(elem) => {
+                self.needs_bounds_checks = true;
+                self.declare_ty(elem);
+                let name = lower_ty(ty);
+                if self.defined_tys.insert(name.clone()) {
+                    self.declare_ty(ty);
+                    let ptr = lower_ty(&Type::Ptr(Mutability::Mut, elem.clone()));
+                    writeln!(
+                        self.buffer,
+                        "struct {} {{ {} ptr; size_t len; }};",
+                        name, ptr
+                    )
+                    .unwrap();
+                }
+            }
+            Type::Tuple(items) if items.is_empty() => {}
+            Type::Tuple(items) => {
+                items.iter().for_each(|it| self.define_ty(it));
+                let name = lower_ty(ty);
+                if self.defined_tys.insert(name.clone()) {
+                    self.declare_ty(ty);
+                    let fields = items
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, it)| !it.is_unit())
+                        .map(|(i, it)| format!("{} _{};", lower_ty(it), i))
+                        .collect::<Vec<_>>();
+                    let fields = if fields.is_empty() {
+                        "char _unused;".to_string()
+                    } else {
+                        fields.join(" ")
+                    };
+                    writeln!(self.buffer, "struct {} {{ {} }};", name, fields).unwrap();
+                }
+            }
+            Type::Newtype(newtype) => {
+                self.declare_ty(ty);
+                self.define_ty(&self.typeck.newtypes[&newtype.def]);
+            }
+            Type::Prim(_) | Type::Fn(..) | Type::Dyn(_) | Type::Error => {}
+            Type::Param(_) => unreachable!("Type parameters are substituted before lowering"),
+        }
+    }
+
+    /// Makes `ty` usable behind a pointer
+    fn declare_ty(&mut self, ty: &Type) {
+        match ty {
+            Type::Tuple(items) if items.is_empty() => {}
+            Type::Adt(adt) if adt.args.is_empty() => {}
+            Type::Array(..) | Type::Slice(_) | Type::Tuple(_) | Type::Adt(_) => {
+                let name = lower_ty(ty);
+                if self.declared_tys.insert(name.clone()) {
+                    writeln!(self.buffer, "typedef struct {} {};", name, name).unwrap();
+                    self.pending_tys.push(ty.clone());
+                }
+            }
+            Type::Ptr(_, pointee) => match &**pointee {
+                Type::Dyn(trait_ref) => self.define_dyn(trait_ref),
+                _ => self.declare_ty(pointee),
+            },
+            // A typedef of the type it wraps, e.g. `typedef int32_t Fd;`
+            Type::Newtype(newtype) => {
+                let name = lower_ty(ty);
+                if self.declared_tys.insert(name.clone()) {
+                    let inner = &self.typeck.newtypes[&newtype.def];
+                    self.declare_ty(inner);
+                    writeln!(self.buffer, "typedef {} {};", lower_ty(inner), name).unwrap();
+                }
+            }
+            // ADTs are declared up front
+            _ => {}
+        }
+    }
+
+    /// `*dyn Trait` is a pointer to the value along with a pointer to the vtable
+    /// of the trait for the value's type, e.g. `struct Dyn_Show { void* data;
+    /// const Show_vtable* vtable; }`. `Self` is passed to the vtable as `void*`.
+    fn define_dyn(&mut self, trait_ref: &TraitRef) {
+        let name = format!("Dyn_{}", c_ident(&trait_ref.name));
+        if !self.defined_tys.insert(name.clone()) {
+            return;
+        }
+        let typeck = self.typeck;
+        let mut fields = vec![];
+        for (fn_name, id) in typeck.traits[&trait_ref.def].fns.iter() {
+            let sig = &typeck.fn_sigs[id];
+            // Function pointer types only need their params to be declared
+            for ty in sig.params.iter().chain([&sig.ret]) {
+                self.declare_ty(&ty.subst(&[Type::unit()]));
+            }
+            fields.push(format!("{};", vtable_fn_ty(sig, fn_name)));
+        }
+        if fields.is_empty() {
+            fields.push("char _unused;".to_string());
+        }
+        let vtable = format!("{}_vtable", c_ident(&trait_ref.name));
+        writeln!(
+            self.buffer,
+            "typedef struct {} {{ {} }} {};",
+            vtable,
+            fields.join(" "),
+            vtable
+        )
+        .unwrap();
+        writeln!(
+            self.buffer,
+            "typedef struct {} {{ void* data; const {}* vtable; }} {};",
+            name, vtable, name
+        )
+        .unwrap();
+    }
+
+    /// Emits the vtable of a trait for a type, which points to the functions of
+    /// the impl cast to take `void*` as `Self`.
+    fn push_vtable(&mut self, trait_id: NodeId, ty: &Type) {
+        let typeck = self.typeck;
+        let trait_def = &typeck.traits[&trait_id];
+        let fns = trait_def
+            .fns
+            .iter()
+            .map(|(name, trait_fn)| {
+                let (def, args) = typeck.resolve_trait_fn(*trait_fn, ty);
+                let fn_ty = vtable_fn_ty(&typeck.fn_sigs[trait_fn], "");
+                let c_name = fn_c_name(self.resolve, typeck, def, &args);
+                format!(".{} = ({}){}", name, fn_ty, c_name)
+            })
+            .collect::<Vec<_>>();
+        writeln!(
+            self.buffer,
+            "static const {}_vtable {} = {};",
+            c_ident(&trait_def.name),
+            vtable_name(trait_def.name.as_str(), ty),
+            struct_literal_body(fns)
+        )
+        .unwrap();
+    }
+
+    fn push_bounds_check_runtime(&mut self, source_file: &SourceFile) {
+        self.buffer.push_str(runtime::BOUNDS_CHECK);
+        let defines_hook = source_file.items.iter().any(|item| {
+            matches!(item.kind, ItemKind::Fn(_)) && item.name == runtime::PANIC_BOUNDS_HOOK
+        });
+        if !defines_hook {
+            self.buffer.push_str(runtime::DEFAULT_PANIC_BOUNDS);
+        }
+        self.buffer.push('\n');
+    }
+
+    /// Emits the definition of a struct or enum after the definitions of all the
+    /// types it contains by value, since C requires complete types for fields.
+    ///
+    /// Enums are lowered to a tag and a union of structs holding the fields of
+    /// each variant, e.g. `enum Option { Some(i32), None }` becomes
+    /// `struct Option { uint32_t tag; union { struct { int32_t _0; } Some; } as; }`
+    fn lower_adt(&mut self, adt_ref: &AdtRef) {
+        if !self.emitted_adts.insert(adt_ref.clone()) {
+            return;
+        }
+        let adt = &self.typeck.adts[&adt_ref.def];
+        let args = &adt_ref.args;
+        for field in adt.variants.iter().flat_map(|it| it.fields.iter()) {
+            self.define_ty(&field.ty.subst(args));
+        }
+        let name = lower_ty(&Type::Adt(adt_ref.clone()));
+        writeln!(self.buffer, "struct {} {{", name).unwrap();
+        if adt.is_enum {
+            self.buffer.push_str("    uint32_t tag;\n");
+            let variants = adt
+                .variants
+                .iter()
+                .filter(|it| has_c_fields(it, args))
+                .collect::<Vec<_>>();
+            if !variants.is_empty() {
+                self.buffer.push_str("    union {\n");
+                for variant in variants {
+                    self.buffer.push_str("        struct {\n");
+                    self.push_fields(variant, args, 3);
+                    writeln!(self.buffer, "        }} {};", variant.name).unwrap();
+                }
+                self.buffer.push_str("    } as;\n");
+            }
+        } else if has_c_fields(&adt.variants[0], args) {
+            self.push_fields(&adt.variants[0], args, 1);
+        } else {
+            // Empty structs are not allowed in standard C
+            self.buffer.push_str("    char _unused;\n");
+        }
+        self.buffer.push_str("};\n");
+    }
+
+    fn push_fields(&mut self, variant: &VariantDef, args: &[Type], indent: usize) {
+        for field in variant.fields.iter() {
+            let ty = field.ty.subst(args);
+            if ty.is_unit() {
+                continue;
+            }
+            writeln!(
+                self.buffer,
+                "{}{} {};",
+                "    ".repeat(indent),
+                lower_ty(&ty),
+                field_name(variant.kind, &field.name)
+            )
+            .unwrap();
+        }
+    }
+
     fn lower_foreign_fn(&mut self, item: &ForeignItem, f: &ast::Fn) {
-        let return_ty = f
-            .return_ty
-            .as_ref()
-            .map(lower_ty)
-            .unwrap_or("void".to_string());
         assert!(f.body.is_none(), "Foreign functions cannot have bodies");
-        let name = item.name.as_str();
-        self.buffer.push_str(&return_ty);
-        self.buffer.push_str(" ");
-        self.buffer.push_str(name);
-        self.buffer.push_str("();");
+        let sig = &self.typeck.fn_sigs[&item.id()];
+        let params = sig
+            .params
+            .iter()
+            .filter(|it| !it.is_unit())
+            .map(lower_ty)
+            .chain(sig.variadic.then(|| "...".to_string()))
+            .collect::<Vec<_>>();
+        let name = fn_c_name(self.resolve, self.typeck, item.id(), &[]);
+        self.push_prototype(&sig.ret, &name, params, false);
+    }
+
+    fn push_prototype(&mut self, ret: &Type, name: &str, params: Vec<String>, cold: bool) {
+        let params = if params.is_empty() {
+            "void".to_string()
+        } else {
+            params.join(", ")
+        };
+        let attrs = if cold { " __attribute__((cold))" } else { "" };
+        writeln!(
+            self.buffer,
+            "{} {}({}){};",
+            lower_ty(ret),
+            name,
+            params,
+            attrs
+        )
+        .unwrap();
     }
 }
-impl Visitor for LowerInterfaceCtx {
+impl<'a> Visitor for LowerInterfaceCtx<'a> {
+    fn visit_item(&mut self, item: &ast::Item) {
+        match &item.kind {
+            ItemKind::Fn(_) if is_main(self.resolve, self.typeck, item) => {}
+            ItemKind::Fn(_) => {
+                let mono = self.mono;
+                let sig = &self.typeck.fn_sigs[&item.id()];
+                for instance in mono.instances.iter().filter(|it| it.def == item.id()) {
+                    let params = sig
+                        .params
+                        .iter()
+                        .map(|it| it.subst(&instance.args))
+                        .filter(|it| !it.is_unit())
+                        .map(|it| lower_ty(&it))
+                        .collect();
+                    let name = fn_c_name(self.resolve, self.typeck, item.id(), &instance.args);
+                    let cold = self.typeck.attrs.cold.contains(&item.id());
+                    self.push_prototype(&sig.ret.subst(&instance.args), &name, params, cold);
+                }
+            }
+            // Uses of consts are replaced with their values
+            ItemKind::Const(_) => {}
+            ItemKind::Static(s) => {
+                let ty = &self.typeck.global_tys[&item.id()];
+                let qualifier = match s.mutability {
+                    Mutability::Mut => "",
+                    Mutability::Not => "const ",
+                };
+                writeln!(
+                    self.buffer,
+                    "{}{} {} = {};",
+                    qualifier,
+                    lower_ty(ty),
+                    c_ident(self.resolve.def_path(item.id())),
+                    const_c_value(self.typeck.const_values[&item.id()], ty)
+                )
+                .unwrap();
+            }
+            _ => ast::visit::walk_item(self, item),
+        }
+    }
+
     fn visit_foreign_item(&mut self, f: &ast::ForeignItem) {
         use ast::ForeignItemKind::*;
         match &f.kind {
@@ -46,28 +470,1458 @@ impl Visitor for LowerInterfaceCtx {
     }
 }
 
-struct LowerImplCtx {
+/// Emits function definitions. Hades is expression oriented while C isn't, so
+/// expressions are lowered into statements that compute their value into
+/// temporaries, and [LowerImplCtx::lower_expr] returns a C expression that refers
+/// to the computed value.
+struct LowerImplCtx<'a> {
     buffer: String,
+    /// The source files, for the locations of runtime errors
+    source_map: &'a SourceMap,
+    resolve: &'a ResolveResult,
+    typeck: &'a TypeckResult,
+    /// Type arguments of the function instance being lowered
+    args: Vec<Type>,
+    indent: usize,
+    /// C names of the params and locals of the current function
+    local_names: HashMap<NodeId, String>,
+    used_names: HashSet<String>,
+    next_temp: usize,
 }
-impl LowerImplCtx {
-    fn new(buffer: String) -> Self {
-        LowerImplCtx { buffer }
+impl<'a> LowerImplCtx<'a> {
+    fn new(
+        buffer: String,
+        source_map: &'a SourceMap,
+        resolve: &'a ResolveResult,
+        typeck: &'a TypeckResult,
+    ) -> Self {
+        LowerImplCtx {
+            buffer,
+            source_map,
+            resolve,
+            typeck,
+            args: vec![],
+            indent: 0,
+            local_names: HashMap::new(),
+            used_names: HashSet::new(),
+            next_temp: 0,
+        }
     }
 
-    fn lower(mut self, source_file: SourceFile) -> String {
-        for item in source_file.items {
-            self.visit_item(&item);
+    fn lower(mut self, source_file: &SourceFile, mono: &MonoResult) -> String {
+        let fns = source_file
+            .fns()
+            .map(|(item, f)| (item.id(), (item, f)))
+            .collect::<HashMap<_, _>>();
+        for instance in mono.instances.iter() {
+            let (item, f) = fns[&instance.def];
+            self.lower_fn(item, f, &instance.args);
         }
         self.buffer
     }
+
+    fn lower_fn(&mut self, item: &ast::Item, f: &ast::Fn, args: &[Type]) {
+        let Some(body) = &f.body else {
+            return;
+        };
+        self.local_names.clear();
+        self.used_names.clear();
+        self.next_temp = 0;
+        self.args = args.to_vec();
+        let sig = &self.typeck.fn_sigs[&item.id()];
+        let params = sig
+            .params
+            .iter()
+            .map(|it| it.subst(args))
+            .collect::<Vec<_>>();
+        let ret = &sig.ret.subst(args);
+        let is_main = is_main(self.resolve, self.typeck, item);
+
+        let mut c_params = vec![];
+        for (param, ty) in f.params.iter().zip(params.iter()) {
+            if ty.is_unit() {
+                continue;
+            }
+            let name = self.declare_local(param.id(), &param.name);
+            c_params.push(format!("{} {}", lower_ty(ty), name));
+        }
+        let c_params = if c_params.is_empty() {
+            "void".to_string()
+        } else {
+            c_params.join(", ")
+        };
+        let c_ret = if is_main && ret.is_unit() {
+            "int".to_string()
+        } else {
+            lower_ty(ret)
+        };
+        let name = fn_c_name(self.resolve, self.typeck, item.id(), args);
+        // The prototype isn't `inline`, so this is still an external definition
+        let inline = if self.typeck.attrs.inline.contains(&item.id()) && !is_main {
+            "inline "
+        } else {
+            ""
+        };
+        writeln!(
+            self.buffer,
+            "\n{}{} {}({}) {{",
+            inline, c_ret, name, c_params
+        )
+        .unwrap();
+        self.indent += 1;
+        let value = self.lower_expr(body);
+        match value {
+            Some(value) => self.line(&format!("return {};", value)),
+            None if is_main => self.line("return 0;"),
+            None => {}
+        }
+        self.indent -= 1;
+        self.buffer.push_str("}\n");
+    }
+
+    /// Returns `None` if the expression has type `()`, which has no C representation
+    fn lower_expr(&mut self, expr: &ast::Expr) -> Option<String> {
+        let ty = &self.expr_ty(expr);
+        match &expr.kind {
+            ExprKind::Unit => None,
+            ExprKind::Lit(lit) => match lit.kind {
+                ast::LitKind::Null => Some("NULL".to_string()),
+                ast::LitKind::Integer | ast::LitKind::Bool => Some(lit.text.clone()),
+            },
+            ExprKind::Var(_) | ExprKind::Path(_) => {
+                let res = self.resolve.get(expr.id());
+                match res {
+                    _ if ty.is_unit() => None,
+                    Res::Local(id) => Some(self.local_names[&id].clone()),
+                    Res::Def(DefKind::Const, id) => {
+                        Some(const_c_value(self.typeck.const_values[&id], ty))
+                    }
+                    Res::Def(DefKind::Static, id) => Some(c_ident(self.resolve.def_path(id))),
+                    Res::Def(DefKind::Struct | DefKind::Variant, _) => {
+                        let (_, index) = self.typeck.ctor(res).unwrap();
+                        Some(self.construct(ty, index, vec![]))
+                    }
+                    _ => Some(self.fn_name(expr)),
+                }
+            }
+            ExprKind::Block(block) => self.lower_block(block, ty),
+            ExprKind::Call(callee, args) => {
+                let (call, is_ctor) = self.lower_call(callee, args);
+                if ty.is_unit() {
+                    self.line(&format!("{};", call));
+                    None
+                } else if is_ctor {
+                    Some(call)
+                } else {
+                    // Calls may have side effects, so their results are computed
+                    // eagerly to preserve evaluation order.
+                    Some(self.temp(ty, &call))
+                }
+            }
+            ExprKind::MethodCall(receiver, name, args) => {
+                let call = self.lower_method_call(expr, receiver, name, args);
+                if ty.is_unit() {
+                    self.line(&format!("{};", call));
+                    None
+                } else {
+                    Some(self.temp(ty, &call))
+                }
+            }
+            ExprKind::Struct(s) => {
+                let (_, index) = self
+                    .typeck
+                    .ctor(self.resolve.get(expr.id()))
+                    .expect("Struct expressions construct a struct or a variant");
+                let mut fields = vec![];
+                for field in s.fields.iter() {
+                    if let Some(value) = self.lower_expr(&field.expr) {
+                        fields.push((field.name.clone(), value));
+                    }
+                }
+                Some(self.construct(ty, index, fields))
+            }
+            ExprKind::Field(base, name) => {
+                let base_ty = &self.expr_ty(base);
+                let value = self.lower_expr(base);
+                let adt = match base_ty {
+                    Type::Adt(adt) => adt,
+                    Type::Array(_, len) => return Some(len.to_string()),
+                    Type::Slice(_) => return Some(format!("{}.{}", value.unwrap(), name)),
+                    Type::Tuple(_) if ty.is_unit() => return None,
+                    Type::Tuple(_) => return Some(format!("{}._{}", value.unwrap(), name)),
+                    _ => unreachable!("Field access on a non struct type"),
+                };
+                let kind = self.typeck.adts[&adt.def].variants[0].kind;
+                let base = value.expect("Structs are never unit");
+                if ty.is_unit() {
+                    None
+                } else {
+                    Some(format!("{}.{}", base, field_name(kind, name)))
+                }
+            }
+            ExprKind::Assign(lhs, rhs) => {
+                let rhs = self.lower_expr(rhs);
+                let lhs = self.lower_expr(lhs);
+                if let (Some(lhs), Some(rhs)) = (lhs, rhs) {
+                    self.line(&format!("{} = {};", lhs, rhs));
+                }
+                None
+            }
+            ExprKind::Match(scrutinee, arms) => self.lower_match(scrutinee, arms, ty),
+            // Compound C expressions are always emitted in parentheses
+            ExprKind::Paren(inner) => self.lower_expr(inner),
+            ExprKind::Tup(exprs) => {
+                let mut fields = vec![];
+                for (i, expr) in exprs.iter().enumerate() {
+                    if let Some(value) = self.lower_expr(expr) {
+                        fields.push(format!("._{} = {}", i, value));
+                    }
+                }
+                Some(struct_literal(&lower_ty(ty), fields))
+            }
+            ExprKind::Binary(op @ (BinOp::And | BinOp::Or), lhs, rhs) => {
+                // The right operand may lower to statements, which must only run
+                // when the left operand doesn't decide the result.
+                let lhs = self.lower_expr(lhs).expect("Operands of `&&` are bools");
+                let result = self.temp(ty, &lhs);
+                let negation = if *op == BinOp::And { "" } else { "!" };
+                self.line(&format!("if ({}{}) {{", negation, result));
+                self.indent += 1;
+                let rhs = self.lower_expr(rhs).expect("Operands of `&&` are bools");
+                self.line(&format!("{} = {};", result, rhs));
+                self.indent -= 1;
+                self.line("}");
+                Some(result)
+            }
+            ExprKind::Binary(op, lhs, rhs) => {
+                let lhs = self.lower_expr(lhs).expect("Operands are never unit");
+                let rhs = self.lower_expr(rhs).expect("Operands are never unit");
+                Some(format!("({} {} {})", lhs, op.as_str(), rhs))
+            }
+            ExprKind::Unary(op, operand) => {
+                let c_op = match op {
+                    UnOp::Neg => "-",
+                    UnOp::Not if *ty == Type::Prim(PrimTy::Bool) => "!",
+                    UnOp::Not => "~",
+                    UnOp::Deref => "*",
+                };
+                let operand = self.lower_expr(operand).expect("Operands are never unit");
+                if ty.is_unit() {
+                    None
+                } else {
+                    Some(format!("({}{})", c_op, operand))
+                }
+            }
+            ExprKind::AddrOf(_, place) => match self.lower_expr(place) {
+                Some(place) => Some(format!("(&{})", place)),
+                // Unit values have no storage; any pointer to them will do
+                None => Some("NULL".to_string()),
+            },
+            ExprKind::Cast(inner, _) => {
+                let inner_ty = &self.expr_ty(inner);
+                let inner = self.lower_expr(inner).expect("Unit can't be cast");
+                match (inner_ty, ty) {
+                    // A newtype has the same representation as the type it wraps
+                    (Type::Newtype(_), _) | (_, Type::Newtype(_)) => Some(inner),
+                    // Only the mutability changes
+                    (Type::Ptr(..), Type::Ptr(..)) if inner_ty.is_dyn_ptr() => Some(inner),
+                    (Type::Ptr(_, pointee), Type::Ptr(_, target)) if ty.is_dyn_ptr() => {
+                        let Type::Dyn(trait_ref) = &**target else {
+                            unreachable!("`dyn` pointers point to `dyn` types")
+                        };
+                        let vtable = vtable_name(&trait_ref.name, pointee);
+                        Some(format!(
+                            "({}){{ .data = {}, .vtable = &{} }}",
+                            lower_ty(ty),
+                            inner,
+                            vtable
+                        ))
+                    }
+                    (Type::Ptr(_, array), Type::Slice(_)) => {
+                        let Type::Array(_, len) = &**array else {
+                            unreachable!("Only pointers to arrays can be cast to slices")
+                        };
+                        Some(format!("({}){{ {}->items, {} }}", lower_ty(ty), inner, len))
+                    }
+                    _ => Some(format!("(({}){})", lower_ty(ty), inner)),
+                }
+            }
+            ExprKind::Array(elems) => {
+                let values = elems
+                    .iter()
+                    .filter_map(|it| self.lower_expr(it))
+                    .collect::<Vec<_>>();
+                if values.is_empty() {
+                    Some(format!("({}){{0}}", lower_ty(ty)))
+                } else {
+                    Some(format!(
+                        "({}){{ {{ {} }} }}",
+                        lower_ty(ty),
+                        values.join(", ")
+                    ))
+                }
+            }
+            ExprKind::Repeat(elem, _) => {
+                let Type::Array(_, len) = ty else {
+                    unreachable!("Repeat expressions are arrays")
+                };
+                let elem_ty = &self.expr_ty(elem);
+                let value = self.lower_expr(elem);
+                let result = self.fresh_temp();
+                match value {
+                    Some(value) => {
+                        let value = self.temp(elem_ty, &value);
+                        self.line(&format!("{} {};", lower_ty(ty), result));
+                        let i = self.fresh_temp();
+                        self.line(&format!(
+                            "for (size_t {i} = 0; {i} < {}; {i}++) {{ {}.items[{i}] = {}; }}",
+                            len, result, value
+                        ));
+                    }
+                    None => self.line(&format!("{} {} = {{0}};", lower_ty(ty), result)),
+                }
+                Some(result)
+            }
+            ExprKind::Index(base, index) => {
+                let base_ty = &self.expr_ty(base);
+                let base = self
+                    .lower_expr(base)
+                    .expect("Indexed values are never unit");
+                let index = self.lower_expr(index).expect("Indices are usize");
+                let (items, len) = match base_ty {
+                    Type::Ptr(..) => return Some(format!("{}[{}]", base, index)),
+                    Type::Array(_, len) => (format!("{}.items", base), len.to_string()),
+                    Type::Slice(_) => {
+                        // The slice expression is used twice
+                        let slice = self.temp(base_ty, &base);
+                        (format!("{}.ptr", slice), format!("{}.len", slice))
+                    }
+                    _ => unreachable!("Only arrays, slices and pointers can be indexed"),
+                };
+                let (file, offset) = self.source_map.lookup(expr.span().start);
+                let (line, column) = line_col(&file.text, offset);
+                let path = c_string_literal(&file.path.display().to_string());
+                let checked = format!(
+                    "hades_bounds_check({}, {}, (uint8_t*){}, {}, {})",
+                    index, len, path, line, column
+                );
+                if ty.is_unit() {
+                    self.line(&format!("{};", checked));
+                    None
+                } else {
+                    Some(format!("{}[{}]", items, checked))
+                }
+            }
+        }
+    }
+
+    /// Returns a C compound literal for a variant of the struct or enum type `ty`,
+    /// given the values of its fields by name.
+    fn construct(&self, ty: &Type, index: usize, fields: Vec<(String, String)>) -> String {
+        let Type::Adt(adt_ref) = ty else {
+            unreachable!("Constructors create structs and enums")
+        };
+        let adt = &self.typeck.adts[&adt_ref.def];
+        let c_name = lower_ty(ty);
+        let variant = &adt.variants[index];
+        let fields = fields
+            .into_iter()
+            .map(|(name, value)| format!(".{} = {}", field_name(variant.kind, &name), value))
+            .collect::<Vec<_>>();
+        if !adt.is_enum {
+            return struct_literal(&c_name, fields);
+        }
+        if fields.is_empty() {
+            format!("({}){{ .tag = {} }}", c_name, index)
+        } else {
+            format!(
+                "({}){{ .tag = {}, .as.{} = {{ {} }} }}",
+                c_name,
+                index,
+                variant.name,
+                fields.join(", ")
+            )
+        }
+    }
+
+    /// Returns the C call expression, and whether it constructs a tuple struct
+    /// or variant instead of calling a function.
+    fn lower_call(&mut self, callee: &ast::Expr, args: &[ast::Expr]) -> (String, bool) {
+        let ctor = match &callee.kind {
+            ExprKind::Var(_) | ExprKind::Path(_) => self.typeck.ctor(self.resolve.get(callee.id())),
+            _ => None,
+        };
+        if let Some((_, index)) = ctor {
+            let mut fields = vec![];
+            for (i, arg) in args.iter().enumerate() {
+                if let Some(value) = self.lower_expr(arg) {
+                    fields.push((i.to_string(), value));
+                }
+            }
+            let Type::Fn(_, adt_ty) = self.expr_ty(callee) else {
+                unreachable!("Constructors of tuple structs and variants are functions")
+            };
+            return (self.construct(&adt_ty, index, fields), true);
+        }
+        if let (ExprKind::Path(path), Res::Def(DefKind::TraitFn, _)) =
+            (&callee.kind, self.resolve.get(callee.id()))
+        {
+            let self_ty = &self.typeck.generic_args[&callee.id()][0];
+            if matches!(self_ty.subst(&self.args), Type::Dyn(_)) {
+                let receiver_ty = self.expr_ty(&args[0]);
+                let values = args.iter().filter_map(|arg| self.lower_expr(arg)).collect();
+                let name = path.segments.last().unwrap();
+                return (self.lower_dyn_call(name, &receiver_ty, values), false);
+            }
+        }
+        let callee = self.lower_expr(callee).expect("Callee can't be unit");
+        let args = args
+            .iter()
+            .filter_map(|arg| {
+                let value = self.lower_expr(arg)?;
+                Some(match self.typeck.promoted_args.get(&arg.id()) {
+                    Some(ty) => format!("({}){}", lower_ty(ty), value),
+                    None => value,
+                })
+            })
+            .collect::<Vec<_>>();
+        (format!("{}({})", callee, args.join(", ")), false)
+    }
+
+    /// Calls a trait function through the vtable of the `dyn` pointer passed as
+    /// its first argument.
+    fn lower_dyn_call(
+        &mut self,
+        name: &str,
+        receiver_ty: &Type,
+        mut values: Vec<String>,
+    ) -> String {
+        // The receiver is used twice
+        let receiver = self.temp(receiver_ty, &values[0]);
+        values[0] = format!("{}.data", receiver);
+        format!("{}.vtable->{}({})", receiver, name, values.join(", "))
+    }
+
+    fn lower_method_call(
+        &mut self,
+        expr: &ast::Expr,
+        receiver: &ast::Expr,
+        name: &str,
+        args: &[ast::Expr],
+    ) -> String {
+        let method_call = self.typeck.method_calls[&expr.id()];
+        let receiver_ty = self.expr_ty(receiver);
+        let value = self.lower_expr(receiver);
+        let value = match (method_call.receiver, value) {
+            (Receiver::ByValue, value) => value,
+            (Receiver::AddrOf, Some(place)) => Some(format!("(&{})", place)),
+            (Receiver::AddrOfTemp, Some(value)) => {
+                let temp = self.temp(&receiver_ty, &value);
+                Some(format!("(&{})", temp))
+            }
+            // Unit values have no storage; any pointer to them will do
+            (_, None) => Some("NULL".to_string()),
+        };
+        let mut values = value.into_iter().collect::<Vec<_>>();
+        values.extend(args.iter().filter_map(|arg| self.lower_expr(arg)));
+        let args: Vec<_> = self
+            .typeck
+            .generic_args
+            .get(&expr.id())
+            .map(|args| args.iter().map(|it| it.subst(&self.args)).collect())
+            .unwrap_or_default();
+        let callee = if !self.typeck.fn_traits.contains_key(&method_call.def) {
+            fn_c_name(self.resolve, self.typeck, method_call.def, &args)
+        } else if matches!(args[0], Type::Dyn(_)) {
+            // `dyn` pointers are always passed by value
+            return self.lower_dyn_call(name, &receiver_ty, values);
+        } else {
+            let (def, args) = self.typeck.resolve_trait_fn(method_call.def, &args[0]);
+            fn_c_name(self.resolve, self.typeck, def, &args)
+        };
+        format!("{}({})", callee, values.join(", "))
+    }
+
+    /// Matches are lowered to a `switch` when every arm is a single test of an enum
+    /// tag or an integer, and to a chain of `if`s that jump past the remaining
+    /// arms once an arm matches otherwise.
+    fn lower_match(
+        &mut self,
+        scrutinee: &ast::Expr,
+        arms: &[ast::Arm],
+        ty: &Type,
+    ) -> Option<String> {
+        let result = if ty.is_unit() {
+            None
+        } else {
+            let name = self.fresh_temp();
+            self.line(&format!("{} {};", lower_ty(ty), name));
+            Some(name)
+        };
+        let scrutinee_ty = &self.expr_ty(scrutinee);
+        let place = match self.lower_expr(scrutinee) {
+            Some(value) => self.temp(scrutinee_ty, &value),
+            None => String::new(),
+        };
+        if self.can_lower_to_switch(scrutinee_ty, arms) {
+            let is_enum = matches!(scrutinee_ty, Type::Adt(_));
+            let discriminant = if is_enum {
+                format!("{}.tag", place)
+            } else {
+                place.clone()
+            };
+            self.line(&format!("switch ({}) {{", discriminant));
+            let mut seen = HashSet::new();
+            for arm in arms {
+                let label = match &arm.pat.kind {
+                    ast::PatKind::Lit(lit) => format!("case {}:", lit.text),
+                    ast::PatKind::Wild | ast::PatKind::Binding(_) => "default:".to_string(),
+                    _ => {
+                        let (_, index) = self.typeck.ctor(self.resolve.get(arm.pat.id())).unwrap();
+                        format!("case {}:", index)
+                    }
+                };
+                // Unreachable arms have already been reported
+                if !seen.insert(label.clone()) || seen.contains("default:") && label != "default:" {
+                    continue;
+                }
+                self.line(&format!("{} {{", label));
+                self.indent += 1;
+                self.lower_arm_body(arm, &place, &result);
+                self.line("break;");
+                self.indent -= 1;
+                self.line("}");
+            }
+            self.line("}");
+        } else {
+            let end_label = format!("_match_end{}", self.next_temp);
+            self.next_temp += 1;
+            for arm in arms {
+                let mut conds = vec![];
+                self.pat_conds(&arm.pat, &place, &mut conds);
+                let cond = if conds.is_empty() {
+                    "1".to_string()
+                } else {
+                    conds.join(" && ")
+                };
+                self.line(&format!("if ({}) {{", cond));
+                self.indent += 1;
+                self.lower_arm_body(arm, &place, &result);
+                self.line(&format!("goto {};", end_label));
+                if arm.guard.is_some() {
+                    self.indent -= 1;
+                    self.line("}");
+                }
+                self.indent -= 1;
+                self.line("}");
+            }
+            self.line(&format!("{}:;", end_label));
+        }
+        result
+    }
+
+    /// Binds the pattern variables of an arm and evaluates its body; If the arm has a
+    /// guard, this leaves an `if (guard) {` block open for the caller to close.
+    fn lower_arm_body(&mut self, arm: &ast::Arm, place: &str, result: &Option<String>) {
+        self.pat_bindings(&arm.pat, place);
+        if let Some(guard) = &arm.guard {
+            let guard = self.lower_expr(guard).expect("Guards are bools");
+            self.line(&format!("if ({}) {{", guard));
+            self.indent += 1;
+        }
+        let value = self.lower_expr(&arm.body);
+        if let (Some(result), Some(value)) = (result, value) {
+            self.line(&format!("{} = {};", result, value));
+        }
+    }
+
+    fn can_lower_to_switch(&self, scrutinee_ty: &Type, arms: &[ast::Arm]) -> bool {
+        let is_switchable_ty = match scrutinee_ty {
+            Type::Adt(adt) => self.typeck.adts[&adt.def].is_enum,
+            Type::Prim(p) => p.is_integer(),
+            _ => false,
+        };
+        let is_irrefutable =
+            |pat: &ast::Pat| matches!(pat.kind, ast::PatKind::Wild | ast::PatKind::Binding(_));
+        is_switchable_ty
+            && arms.iter().all(|arm| {
+                arm.guard.is_none()
+                    && match &arm.pat.kind {
+                        ast::PatKind::Wild | ast::PatKind::Binding(_) | ast::PatKind::Lit(_) => {
+                            true
+                        }
+                        ast::PatKind::Path(_) => true,
+                        ast::PatKind::TupleStruct(_, pats) => pats.iter().all(is_irrefutable),
+                        ast::PatKind::Struct(_, fields, _) => {
+                            fields.iter().all(|it| is_irrefutable(&it.pat))
+                        }
+                        ast::PatKind::Tuple(_) => false,
+                    }
+            })
+    }
+
+    /// Collects the C conditions that must hold for `pat` to match the value at `place`
+    fn pat_conds(&self, pat: &ast::Pat, place: &str, conds: &mut Vec<String>) {
+        use ast::PatKind as P;
+        match &pat.kind {
+            P::Wild | P::Binding(_) => {}
+            P::Lit(lit) => match lit.kind {
+                ast::LitKind::Bool if lit.text == "true" => conds.push(place.to_string()),
+                ast::LitKind::Bool => conds.push(format!("!{}", place)),
+                ast::LitKind::Integer => conds.push(format!("{} == {}", place, lit.text)),
+                ast::LitKind::Null => unreachable!("`null` is not a pattern"),
+            },
+            P::Path(_) | P::TupleStruct(..) | P::Struct(..) => {
+                let (adt_id, index) = self.typeck.ctor(self.resolve.get(pat.id())).unwrap();
+                if self.typeck.adts[&adt_id].is_enum {
+                    conds.push(format!("{}.tag == {}", place, index));
+                }
+                for (sub, sub_place) in self.subpats(pat, place) {
+                    self.pat_conds(sub, &sub_place, conds);
+                }
+            }
+            P::Tuple(_) => {
+                for (sub, sub_place) in self.subpats(pat, place) {
+                    self.pat_conds(sub, &sub_place, conds);
+                }
+            }
+        }
+    }
+
+    /// Declares the locals bound by `pat` when matched against the value at `place`
+    fn pat_bindings(&mut self, pat: &ast::Pat, place: &str) {
+        use ast::PatKind as P;
+        match &pat.kind {
+            P::Binding(name) => {
+                let ty = &self.local_ty(pat.id());
+                if !ty.is_unit() {
+                    let c_ty = lower_ty(ty);
+                    let c_name = self.declare_local(pat.id(), name);
+                    self.line(&format!("{} {} = {};", c_ty, c_name, place));
+                }
+            }
+            P::Wild | P::Lit(_) => {}
+            P::Path(_) | P::TupleStruct(..) | P::Struct(..) | P::Tuple(_) => {
+                for (sub, sub_place) in self.subpats(pat, place) {
+                    self.pat_bindings(sub, &sub_place);
+                }
+            }
+        }
+    }
+
+    /// Sub patterns of a struct, variant or tuple pattern, along with the C place
+    /// that they match against.
+    fn subpats<'p>(&self, pat: &'p ast::Pat, place: &str) -> Vec<(&'p ast::Pat, String)> {
+        if let ast::PatKind::Tuple(pats) = &pat.kind {
+            // Patterns matching unit items never read their place
+            return pats
+                .iter()
+                .enumerate()
+                .map(|(i, sub)| (sub, format!("{}._{}", place, i)))
+                .collect();
+        }
+        let (adt_id, index) = self.typeck.ctor(self.resolve.get(pat.id())).unwrap();
+        let adt = &self.typeck.adts[&adt_id];
+        let variant = &adt.variants[index];
+        let base = if adt.is_enum {
+            format!("{}.as.{}", place, variant.name)
+        } else {
+            place.to_string()
+        };
+        let field_place = |name: &str| {
+            let is_unit = variant.field(name).map(|it| it.ty.is_unit()) == Some(true);
+            if is_unit {
+                String::new()
+            } else {
+                format!("{}.{}", base, field_name(variant.kind, name))
+            }
+        };
+        match &pat.kind {
+            ast::PatKind::TupleStruct(_, pats) => pats
+                .iter()
+                .enumerate()
+                .map(|(i, sub)| (sub, field_place(&i.to_string())))
+                .collect(),
+            ast::PatKind::Struct(_, fields, _) => fields
+                .iter()
+                .map(|field| (&field.pat, field_place(&field.name)))
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// Lowers an expression whose value is discarded
+    fn lower_expr_stmt(&mut self, expr: &ast::Expr) {
+        match &expr.kind {
+            ExprKind::Call(callee, args) => {
+                let (call, _) = self.lower_call(callee, args);
+                self.line(&format!("{};", call));
+            }
+            _ => {
+                // Evaluated for its effects, e.g. of dereferencing or dividing
+                if let Some(value) = self.lower_expr(expr) {
+                    self.line(&format!("(void){};", value));
+                }
+            }
+        }
+    }
+
+    fn lower_block(&mut self, block: &ast::Block, ty: &Type) -> Option<String> {
+        let result = if ty.is_unit() {
+            None
+        } else {
+            let name = self.fresh_temp();
+            self.line(&format!("{} {};", lower_ty(ty), name));
+            Some(name)
+        };
+        let tail = block.tail_expr().map(|it| it.id());
+        self.line("{");
+        self.indent += 1;
+        for stmt in block.stmts.iter() {
+            match &stmt.kind {
+                StmtKind::Semi => {}
+                StmtKind::Let(local) => self.lower_local(local),
+                StmtKind::Expr(expr) if Some(expr.id()) == tail => {
+                    let value = self.lower_expr(expr);
+                    if let (Some(result), Some(value)) = (&result, value) {
+                        self.line(&format!("{} = {};", result, value));
+                    }
+                }
+                StmtKind::Expr(expr) => self.lower_expr_stmt(expr),
+            }
+        }
+        self.indent -= 1;
+        self.line("}");
+        result
+    }
+
+    fn lower_local(&mut self, local: &ast::Local) {
+        let ty = &self.local_ty(local.id());
+        let init = local.init.as_ref().and_then(|it| self.lower_expr(it));
+        if ty.is_unit() {
+            return;
+        }
+        let c_ty = lower_ty(ty);
+        let ast::PatKind::Binding(name) = &local.pat.kind else {
+            // Destructuring reads the value from a temporary
+            let place = match init {
+                Some(init) => self.temp(ty, &init),
+                None => {
+                    let place = self.fresh_temp();
+                    self.line(&format!("{} {};", c_ty, place));
+                    place
+                }
+            };
+            self.pat_bindings(&local.pat, &place);
+            return;
+        };
+        let name = self.declare_local(local.pat.id(), name);
+        match init {
+            Some(init) => self.line(&format!("{} {} = {};", c_ty, name, init)),
+            None => self.line(&format!("{} {};", c_ty, name)),
+        }
+    }
+
+    /// The type of an expression in the function instance being lowered
+    fn expr_ty(&self, expr: &ast::Expr) -> Type {
+        self.typeck.expr_type(expr).subst(&self.args)
+    }
+
+    fn local_ty(&self, id: NodeId) -> Type {
+        self.typeck.local_types[&id].subst(&self.args)
+    }
+
+    /// The C name of the function that a path refers to, which is an instance
+    /// of a generic function if the path has type arguments.
+    /// The C name of the function instance that a path expression refers to
+    fn fn_name(&self, expr: &ast::Expr) -> String {
+        let args: Vec<_> = self
+            .typeck
+            .generic_args
+            .get(&expr.id())
+            .map(|args| args.iter().map(|it| it.subst(&self.args)).collect())
+            .unwrap_or_default();
+        match self.resolve.get(expr.id()) {
+            // Calls on types other than `dyn` are dispatched statically
+            Res::Def(DefKind::TraitFn, def) => {
+                let (def, args) = self.typeck.resolve_trait_fn(def, &args[0]);
+                fn_c_name(self.resolve, self.typeck, def, &args)
+            }
+            Res::Def(_, def) => fn_c_name(self.resolve, self.typeck, def, &args),
+            _ => unreachable!("Only functions are referred to by name"),
+        }
+    }
+
+    /// Picks a C name for a local that doesn't clash with the other locals of
+    /// the function; Hades allows shadowing in the same scope while C doesn't.
+    fn declare_local(&mut self, id: NodeId, name: &str) -> String {
+        let mut c_name = name.to_string();
+        let mut suffix = 0;
+        while self.used_names.contains(&c_name) {
+            suffix += 1;
+            c_name = format!("{}_{}", name, suffix);
+        }
+        self.used_names.insert(c_name.clone());
+        self.local_names.insert(id, c_name.clone());
+        c_name
+    }
+
+    fn temp(&mut self, ty: &Type, value: &str) -> String {
+        let name = self.fresh_temp();
+        self.line(&format!("{} {} = {};", lower_ty(ty), name, value));
+        name
+    }
+
+    fn fresh_temp(&mut self) -> String {
+        let name = format!("_t{}", self.next_temp);
+        self.next_temp += 1;
+        name
+    }
+
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.indent {
+            self.buffer.push_str("    ");
+        }
+        self.buffer.push_str(line);
+        self.buffer.push('\n');
+    }
+}
+
+/// Only `main` in the crate root is the entry point; Functions in modules and
+/// impls may be called `main` too
+fn is_main(resolve: &ResolveResult, typeck: &TypeckResult, item: &ast::Item) -> bool {
+    resolve.def_path(item.id()) == "main" && !typeck.fn_impls.contains_key(&item.id())
+}
+
+fn has_c_fields(variant: &VariantDef, args: &[Type]) -> bool {
+    variant.fields.iter().any(|it| !it.ty.subst(args).is_unit())
+}
+
+/// A C expression for a constant of type `ty`. Literals are unsigned so that
+/// the most negative value of a type can be written.
+fn const_c_value(value: ConstValue, ty: &Type) -> String {
+    match value {
+        ConstValue::Bool(value) => value.to_string(),
+        ConstValue::Int(value) if value < 0 => {
+            format!("(({})-{}ull)", lower_ty(ty), value.unsigned_abs())
+        }
+        ConstValue::Int(value) => format!("(({}){}ull)", lower_ty(ty), value),
+    }
+}
+
+fn struct_literal(name: &str, fields: Vec<String>) -> String {
+    format!("({}){}", name, struct_literal_body(fields))
+}
+
+fn struct_literal_body(fields: Vec<String>) -> String {
+    if fields.is_empty() {
+        "{0}".to_string()
+    } else {
+        format!("{{ {} }}", fields.join(", "))
+    }
+}
+
+/// Tuple struct fields are named by their index, which isn't a valid C identifier
+fn field_name(kind: VariantKind, name: &str) -> String {
+    match kind {
+        VariantKind::Tuple => format!("_{}", name),
+        VariantKind::Struct | VariantKind::Unit => name.to_string(),
+    }
+}
+
+fn c_string_literal(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Items in modules are named by their path, e.g. `shapes__Point` for `shapes::Point`
+fn c_ident(path: &str) -> String {
+    path.replace("::", "__")
+}
+
+/// The C name of an instance of a generic function or type, e.g. `Pair_i32_bool`
+fn instance_name(name: &str, args: &[Type]) -> String {
+    let mut c_name = c_ident(name);
+    for arg in args {
+        c_name.push('_');
+        c_name.push_str(&mangle_ty(arg));
+    }
+    c_name
+}
+
+/// The C name of an instance of a function; Functions in impls are named after
+/// their type and trait, e.g. `Point__len` or `Point__Show__show`, since their
+/// names aren't unique.
+pub fn fn_c_name(
+    resolve: &ResolveResult,
+    typeck: &TypeckResult,
+    def: NodeId,
+    args: &[Type],
+) -> String {
+    if let Some(symbol) = typeck.attrs.symbols.get(&def) {
+        return symbol.clone();
+    }
+    let name = resolve.def_path(def);
+    let Some(impl_id) = typeck.fn_impls.get(&def) else {
+        return instance_name(name, args);
+    };
+    let self_ty = mangle_ty(&typeck.self_tys[impl_id].subst(args));
+    match typeck.impls.get(impl_id) {
+        Some(imp) => format!(
+            "{}__{}__{}",
+            self_ty,
+            c_ident(&typeck.traits[&imp.trait_id].name),
+            name
+        ),
+        None => format!("{}__{}", self_ty, name),
+    }
+}
+
+fn vtable_name(trait_name: &str, ty: &Type) -> String {
+    format!("{}_vtable_{}", c_ident(trait_name), mangle_ty(ty))
+}
+
+/// The C type of a function in a vtable, declaring `name` if it isn't empty
+fn vtable_fn_ty(sig: &FnSig, name: &str) -> String {
+    let params = sig
+        .params
+        .iter()
+        .map(|it| it.subst(&[Type::unit()]))
+        .filter(|it| !it.is_unit())
+        .map(|it| lower_ty(&it))
+        .collect::<Vec<_>>();
+    format!("{} (*{})({})", lower_ty(&sig.ret), name, params.join(", "))
+}
+
+/// A C identifier for a type, used to name the structs for arrays, slices, tuples
+/// and instances of generic types.
+fn mangle_ty(ty: &Type) -> String {
+    match ty {
+        Type::Prim(p) => p.name().to_string(),
+        Type::Tuple(items) if items.is_empty() => "unit".to_string(),
+        Type::Adt(adt) => instance_name(&adt.name, &adt.args),
+        Type::Ptr(Mutability::Not, pointee) => format!("ptr_{}", mangle_ty(pointee)),
+        Type::Ptr(Mutability::Mut, pointee) => format!("ptrmut_{}", mangle_ty(pointee)),
+        Type::Dyn(trait_ref) => format!("dyn_{}", c_ident(&trait_ref.name)),
+        Type::Array(..) | Type::Slice(_) | Type::Tuple(_) | Type::Newtype(_) => lower_ty(ty),
+        Type::Fn(..) => unreachable!("Functions can only be called, so they have no values"),
+        Type::Param(_) => unreachable!("Type parameters are substituted before lowering"),
+        Type::Error => unreachable!("Type errors are reported before lowering"),
+    }
 }
-impl Visitor for LowerImplCtx {}
 
-fn lower_ty(ty: &ast::Ty) -> String {
-    use ast::TyKind::*;
-    match &ty.kind {
-        Tup(items) if items.is_empty() => String::from("void"),
-        Var(ident) => todo!("Cant lower type {}", ident),
-        _ => todo!(),
+fn lower_ty(ty: &Type) -> String {
+    match ty {
+        Type::Prim(p) => match p {
+            PrimTy::I8 => "int8_t",
+            PrimTy::I16 => "int16_t",
+            PrimTy::I32 => "int32_t",
+            PrimTy::I64 => "int64_t",
+            PrimTy::ISize => "ptrdiff_t",
+            PrimTy::U8 => "uint8_t",
+            PrimTy::U16 => "uint16_t",
+            PrimTy::U32 => "uint32_t",
+            PrimTy::U64 => "uint64_t",
+            PrimTy::USize => "size_t",
+            PrimTy::Bool => "bool",
+        }
+        .to_string(),
+        Type::Tuple(items) if items.is_empty() => String::from("void"),
+        Type::Adt(adt) => instance_name(&adt.name, &adt.args),
+        Type::Newtype(newtype) => c_ident(&newtype.name),
+        Type::Ptr(_, inner) => match &**inner {
+            Type::Dyn(trait_ref) => format!("Dyn_{}", c_ident(&trait_ref.name)),
+            // `*()` and `*mut ()` are C's `void*`
+            _ => format!("{}*", lower_ty(inner)),
+        },
+        Type::Array(elem, len) => format!("Array_{}_{}", mangle_ty(elem), len),
+        Type::Slice(elem) => format!("Slice_{}", mangle_ty(elem)),
+        // Prefixed with the arity so that nested tuples can't collide
+        Type::Tuple(items) => {
+            let items = items.iter().map(mangle_ty).collect::<Vec<_>>();
+            format!("Tuple{}_{}", items.len(), items.join("_"))
+        }
+        Type::Fn(..) => unreachable!("Functions can only be called, so they have no values"),
+        Type::Dyn(_) => unreachable!("`dyn` types are only used behind pointers"),
+        Type::Param(_) => unreachable!("Type parameters are substituted before lowering"),
+        Type::Error => unreachable!("Type errors are reported before lowering"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::{
+        analysis::{resolve::resolve, typeck::typeck},
+        mono,
+        parser::Parser,
+    };
+
+    use super::*;
+
+    fn lower_text(text: &str) -> String {
+        let source_file = Parser::new(text, PathBuf::from("test.hds")).parse_source_file();
+        let resolve_result = resolve(&source_file);
+        let typeck_result = typeck(&source_file, &resolve_result);
+        assert_eq!(resolve_result.diagnostics, vec![]);
+        assert_eq!(typeck_result.diagnostics, vec![]);
+        let mono_result = mono::collect(&source_file, &resolve_result, &typeck_result);
+        assert_eq!(mono_result.diagnostics, vec![]);
+        let mut source_map = SourceMap::new();
+        source_map.add_file(PathBuf::from("test.hds"), text.to_string());
+        lower_source_file(
+            &source_file,
+            &source_map,
+            &resolve_result,
+            &typeck_result,
+            &mono_result,
+        )
+    }
+
+    #[test]
+    fn emits_structs_in_dependency_order() {
+        let c = lower_text(
+            "
+            struct Line { start: Point, end: Point }
+            struct Point { x: i32, y: i32 }
+            ",
+        );
+        let point = c.find("struct Point {").unwrap();
+        let line = c.find("struct Line {").unwrap();
+        assert!(point < line, "{}", c);
+    }
+
+    #[test]
+    fn lowers_pointers() {
+        let c = lower_text(
+            "
+            struct Node { value: i32, next: *mut Node }
+            extern {
+                fn malloc(size: usize) -> *mut ();
+                fn free(p: *mut ());
+            }
+            fn push(head: *mut Node, value: i32) -> *mut Node {
+                let node = malloc(16) as *mut Node;
+                unsafe {
+                    (*node).value = -value;
+                    (*node).next = head;
+                }
+                node
+            }
+            fn second(head: *Node) -> bool {
+                unsafe { head != null && (*head).next != null }
+            }
+            ",
+        );
+        assert!(c.contains("typedef struct Node Node;"), "{}", c);
+        assert!(c.contains("Node* next;"), "{}", c);
+        assert!(c.contains("void* malloc(size_t);"), "{}", c);
+        assert!(c.contains("Node* node = ((Node*)_t1);"), "{}", c);
+        assert!(c.contains("(*node).value = (-value);"), "{}", c);
+        assert!(
+            c.contains("bool _t2 = (head != NULL);\n            if (_t2) {\n                _t2 = ((*head).next != NULL);"),
+            "{}",
+            c
+        );
+    }
+
+    #[test]
+    fn evaluates_expression_statements() {
+        let c = lower_text(
+            "
+            fn f(p: *i32, x: i32) {
+                unsafe { *p; }
+                x / 2;
+                x;
+            }
+            ",
+        );
+        assert!(c.contains("(void)(*p);"), "{}", c);
+        assert!(c.contains("(void)(x / 2);"), "{}", c);
+        assert!(c.contains("(void)x;"), "{}", c);
+    }
+
+    #[test]
+    fn lowers_arrays_to_wrapped_structs() {
+        let c = lower_text(
+            "
+            struct Buf { data: [u8; 4], next: *[Buf; 2] }
+            fn first(s: [u8]) -> u8 {
+                s[0]
+            }
+            fn f() -> [u8; 4] {
+                let zeros: [u8; 4] = [0; 4];
+                let b = Buf { data: [1, 2, 3, 4], next: null };
+                b.data[1] = first(&zeros as [u8]);
+                b.data
+            }
+            ",
+        );
+        assert!(
+            c.contains("struct Array_u8_4 { uint8_t items[4]; };"),
+            "{}",
+            c
+        );
+        assert!(
+            c.contains("typedef struct Array_Buf_2 Array_Buf_2;"),
+            "{}",
+            c
+        );
+        let buf = c.find("struct Buf {").unwrap();
+        let pointee = c.find("struct Array_Buf_2 {").unwrap();
+        assert!(buf < pointee, "{}", c);
+        assert!(
+            c.contains("struct Slice_u8 { uint8_t* ptr; size_t len; };"),
+            "{}",
+            c
+        );
+        assert!(c.contains("Array_u8_4 f(void);"), "{}", c);
+        assert!(
+            c.contains(
+                "_t0 = _t1.ptr[hades_bounds_check(0, _t1.len, (uint8_t*)\"test.hds\", 4, 17)];"
+            ),
+            "{}",
+            c
+        );
+        assert!(
+            c.contains("for (size_t _t3 = 0; _t3 < 4; _t3++) { _t1.items[_t3] = _t2; }"),
+            "{}",
+            c
+        );
+        assert!(c.contains("(Array_u8_4){ { 1, 2, 3, 4 } }"), "{}", c);
+        assert!(c.contains("(Slice_u8){ (&zeros)->items, 4 }"), "{}", c);
+        assert!(c.contains("b.data.items[hades_bounds_check(1, 4,"), "{}", c);
+    }
+
+    #[test]
+    fn lowers_tuples_to_generated_structs() {
+        let c = lower_text(
+            "
+            fn swap(p: (i32, bool)) -> (bool, i32) { (p.1, p.0) }
+            fn f() -> i32 {
+                let (b, i) = swap((1, true));
+                let nested: ((), (u8, u8)) = ((), (2, 3));
+                match nested.1 { (0, y) => i + y as i32, _ => i }
+            }
+            ",
+        );
+        assert!(
+            c.contains("struct Tuple2_i32_bool { int32_t _0; bool _1; };"),
+            "{}",
+            c
+        );
+        assert!(
+            c.contains("struct Tuple2_unit_Tuple2_u8_u8 { Tuple2_u8_u8 _1; };"),
+            "{}",
+            c
+        );
+        assert!(
+            c.contains("Tuple2_bool_i32 swap(Tuple2_i32_bool);"),
+            "{}",
+            c
+        );
+        assert!(
+            c.contains("(Tuple2_bool_i32){ ._0 = p._1, ._1 = p._0 }"),
+            "{}",
+            c
+        );
+        assert!(c.contains("bool b = _t2._0;"), "{}", c);
+        assert!(c.contains("int32_t i = _t2._1;"), "{}", c);
+        assert!(
+            c.contains("._1 = (Tuple2_u8_u8){ ._0 = 2, ._1 = 3 }"),
+            "{}",
+            c
+        );
+        assert!(!c.contains("hades_bounds_check"), "{}", c);
+    }
+
+    #[test]
+    fn lowers_generics_to_instances() {
+        let c = lower_text(
+            "
+            struct Pair<A, B> { first: A, second: B }
+            fn id<T>(x: T) -> T { x }
+            fn f() -> i32 {
+                let p = Pair { first: id(1), second: id(true) };
+                let q = Pair { first: 2, second: false };
+                p.first + q.first
+            }
+            ",
+        );
+        assert_eq!(c.matches("struct Pair_i32_bool {").count(), 1, "{}", c);
+        assert!(!c.contains("struct Pair {"), "{}", c);
+        assert!(c.contains("int32_t id_i32(int32_t);"), "{}", c);
+        assert!(c.contains("bool id_bool(bool);"), "{}", c);
+        assert!(c.contains("bool id_bool(bool x) {"), "{}", c);
+        assert!(c.contains("bool _t2 = id_bool(true);"), "{}", c);
+        assert!(
+            c.contains("Pair_i32_bool q = (Pair_i32_bool){ .first = 2, .second = false };"),
+            "{}",
+            c
+        );
+    }
+
+    #[test]
+    fn lowers_trait_calls_statically_and_through_vtables() {
+        let c = lower_text(
+            "
+            trait Show { fn show(self: *Self, base: i32) -> i32; }
+            struct Point { x: i32 }
+            impl Show for Point { fn show(self: *Self, base: i32) -> i32 { base } }
+            fn f(p: Point) -> i32 {
+                let d = &p as *dyn Show;
+                Show::show(&p, 1) + Show::show(d, 2)
+            }
+            ",
+        );
+        assert!(
+            c.contains(
+                "typedef struct Show_vtable { int32_t (*show)(void*, int32_t); } Show_vtable;"
+            ),
+            "{}",
+            c
+        );
+        assert!(
+            c.contains(
+                "typedef struct Dyn_Show { void* data; const Show_vtable* vtable; } Dyn_Show;"
+            ),
+            "{}",
+            c
+        );
+        assert!(
+            c.contains("int32_t Point__Show__show(Point*, int32_t);"),
+            "{}",
+            c
+        );
+        assert!(
+            c.contains(
+                "static const Show_vtable Show_vtable_Point = \
+                 { .show = (int32_t (*)(void*, int32_t))Point__Show__show };"
+            ),
+            "{}",
+            c
+        );
+        assert!(
+            c.contains("Dyn_Show d = (Dyn_Show){ .data = (&p), .vtable = &Show_vtable_Point };"),
+            "{}",
+            c
+        );
+        assert!(c.contains("Point__Show__show((&p), 1);"), "{}", c);
+        assert!(c.contains("_t2.vtable->show(_t2.data, 2);"), "{}", c);
+    }
+
+    #[test]
+    fn lowers_methods_to_free_functions() {
+        let c = lower_text(
+            "
+            struct Point { x: i32 }
+            impl Point {
+                fn new(x: i32) -> Point { Point { x: x } }
+                fn len(self: *Point) -> i32 { 0 }
+            }
+            fn f(p: Point, q: *Point) -> i32 { p.len() + q.len() + Point::new(1).len() }
+            ",
+        );
+        assert!(c.contains("int32_t Point__len(Point*);"), "{}", c);
+        assert!(c.contains("Point Point__new(int32_t x) {"), "{}", c);
+        assert!(c.contains("int32_t _t1 = Point__len((&p));"), "{}", c);
+        assert!(c.contains("int32_t _t2 = Point__len(q);"), "{}", c);
+        assert!(c.contains("Point _t3 = Point__new(1);"), "{}", c);
+        assert!(c.contains("Point__len((&_t4));"), "{}", c);
+    }
+
+    #[test]
+    fn names_items_in_modules_by_their_path() {
+        let c = lower_text(
+            "
+            mod geo {
+                pub struct Point { pub x: i32 }
+                pub fn origin() -> Point { Point { x: 0 } }
+                pub fn main() {}
+                extern { pub fn abs(x: i32) -> i32; }
+            }
+            use geo::abs;
+            struct Point { y: i32 }
+            fn main() { let p: geo::Point = geo::origin(); abs(p.x); }
+            ",
+        );
+        assert!(c.contains("typedef struct geo__Point geo__Point;"), "{}", c);
+        assert!(c.contains("typedef struct Point Point;"), "{}", c);
+        assert!(c.contains("geo__Point geo__origin(void) {"), "{}", c);
+        assert!(c.contains("void geo__main(void) {"), "{}", c);
+        assert!(c.contains("int32_t abs(int32_t);"), "{}", c);
+        assert!(c.contains("int main(void) {"), "{}", c);
+    }
+
+    #[test]
+    fn passes_structs_to_foreign_fns_by_value() {
+        let c = lower_text(
+            "
+            struct Point { x: i32, y: i32 }
+            extern { fn move_point(p: Point, dx: i32) -> Point; }
+            fn main() {
+                let p = move_point(Point { x: 1, y: 2 }, 3);
+                p.x = p.y;
+            }
+            ",
+        );
+        assert!(c.contains("Point move_point(Point, int32_t);"), "{}", c);
+        assert!(c.contains("(Point){ .x = 1, .y = 2 }"), "{}", c);
+        assert!(c.contains("p.x = p.y;"), "{}", c);
+    }
+
+    #[test]
+    fn calls_foreign_functions_by_their_link_name() {
+        let c = lower_text(
+            r#"
+            extern "C" {
+                #[link_name = "putchar"]
+                fn put(c: i32) -> i32;
+            }
+            fn main() { unsafe { put(65); } }
+            "#,
+        );
+        assert!(c.contains("int32_t putchar(int32_t);"), "{}", c);
+        assert!(c.contains("putchar(65)"), "{}", c);
+        assert!(!c.contains("put("), "{}", c);
+    }
+
+    #[test]
+    fn promotes_variadic_arguments() {
+        let c = lower_text(
+            "
+            extern { fn printf(fmt: *u8, ...) -> i32; }
+            fn f(fmt: *u8, b: u8) { unsafe { printf(fmt, b, 1); } }
+            fn main() {}
+            ",
+        );
+        assert!(c.contains("int32_t printf(uint8_t*, ...);"), "{}", c);
+        assert!(c.contains("printf(fmt, (int32_t)b, 1)"), "{}", c);
+    }
+
+    #[test]
+    fn honors_codegen_attributes() {
+        let c = lower_text(
+            r#"
+            #[inline]
+            fn fast() {}
+            #[cold]
+            fn slow() {}
+            #[export_name = "hades_entry"]
+            fn entry() {}
+            fn main() { fast(); slow(); entry(); }
+            "#,
+        );
+        assert!(c.contains("\ninline void fast(void) {"), "{}", c);
+        assert!(
+            c.contains("void slow(void) __attribute__((cold));"),
+            "{}",
+            c
+        );
+        assert!(c.contains("void hades_entry(void) {"), "{}", c);
+        assert!(c.contains("hades_entry();"), "{}", c);
+    }
+
+    #[test]
+    fn lowers_consts_to_values_and_statics_to_globals() {
+        let c = lower_text(
+            "
+            mod limits { pub const MIN: i64 = -9223372036854775807 - 1; }
+            const STEP: i64 = 2;
+            static mut COUNT: i64 = limits::MIN;
+            static READY: bool = true;
+            fn main() { unsafe { COUNT = COUNT + STEP; } let r = READY; }
+            ",
+        );
+        assert!(
+            c.contains("int64_t COUNT = ((int64_t)-9223372036854775808ull);"),
+            "{}",
+            c
+        );
+        assert!(c.contains("const bool READY = true;"), "{}", c);
+        assert!(c.contains("COUNT = (COUNT + ((int64_t)2ull));"), "{}", c);
+    }
+
+    #[test]
+    fn lowers_newtypes_to_typedefs() {
+        let c = lower_text(
+            "
+            type Pair<T> = (T, T);
+            newtype Fd = i32;
+            newtype Fds = Pair<Fd>;
+            fn open(fds: *Fds) -> Fd { 3 as Fd }
+            fn main() { let fd = open(null); let n = fd as i32; }
+            ",
+        );
+        assert!(c.contains("typedef int32_t Fd;"), "{}", c);
+        assert!(c.contains("typedef Tuple2_Fd_Fd Fds;"), "{}", c);
+        assert!(c.contains("Fd open(Fds* fds)"), "{}", c);
+        assert!(c.contains("int32_t n = fd;"), "{}", c);
+    }
+
+    #[test]
+    fn lowers_enums_to_tagged_unions() {
+        let c = lower_text(
+            "
+            enum Option { Some(i32), None }
+            fn some() -> Option { Option::Some(1) }
+            fn none() -> Option { Option::None }
+            ",
+        );
+        assert!(
+            c.contains("uint32_t tag;\n    union {\n        struct {\n            int32_t _0;\n        } Some;\n    } as;"),
+            "{}",
+            c
+        );
+        assert!(
+            c.contains("(Option){ .tag = 0, .as.Some = { ._0 = 1 } }"),
+            "{}",
+            c
+        );
+        assert!(c.contains("(Option){ .tag = 1 }"), "{}", c);
+    }
+
+    #[test]
+    fn lowers_simple_matches_to_switch() {
+        let c = lower_text(
+            "
+            enum Option { Some(i32), None }
+            fn get(o: Option) -> i32 { match o { Option::Some(x) => x, Option::None => 0 } }
+            ",
+        );
+        assert!(c.contains("switch (_t2.tag) {"), "{}", c);
+        assert!(c.contains("case 0: {"), "{}", c);
+        assert!(c.contains("int32_t x = _t2.as.Some._0;"), "{}", c);
+    }
+
+    #[test]
+    fn lowers_guarded_matches_to_if_chains() {
+        let c = lower_text(
+            "
+            enum Option { Some(i32), None }
+            fn get(o: Option, b: bool) -> i32 {
+                match o { Option::Some(1) => 1, Option::Some(x) if b => x, _ => 0 }
+            }
+            ",
+        );
+        assert!(
+            c.contains("if (_t2.tag == 0 && _t2.as.Some._0 == 1) {"),
+            "{}",
+            c
+        );
+        assert!(c.contains("if (b) {"), "{}", c);
+        assert!(c.contains("goto _match_end3;"), "{}", c);
     }
 }
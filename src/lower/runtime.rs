@@ -0,0 +1,59 @@
+//! C support code that generated programs depend on.
+
+/// Name of the function that is called when an index is out of bounds. Programs
+/// can replace the default hook by defining a function with this name and the
+/// signature `fn(file: *u8, line: u32, column: u32, index: usize, len: usize)`.
+pub const PANIC_BOUNDS_HOOK: &str = "hades_panic_bounds";
+
+/// Emitted when the program indexes arrays or slices; `hades_bounds_check`
+/// returns the index if it's in bounds, and calls the panic hook otherwise.
+pub const BOUNDS_CHECK: &str = r#"
+void hades_panic_bounds(uint8_t* file, uint32_t line, uint32_t column, size_t index, size_t len);
+static inline size_t hades_bounds_check(size_t index, size_t len, uint8_t* file, uint32_t line, uint32_t column) {
+    if (index >= len) {
+        hades_panic_bounds(file, line, column, index, len);
+        __builtin_trap();
+    }
+    return index;
+}
+"#;
+
+/// The default panic hook; Prints the location and aborts. It's weak so that
+/// it can also be replaced at link time. `write` is declared under another
+/// name to avoid clashing with the program's own declaration of it.
+pub const DEFAULT_PANIC_BOUNDS: &str = r#"
+extern ptrdiff_t hades_rt_write(int32_t fd, const void* buf, size_t len) __asm__("write");
+static size_t hades_rt_append_str(char* buf, size_t n, const char* s) {
+    while (*s && n < 400) {
+        buf[n++] = *s++;
+    }
+    return n;
+}
+static size_t hades_rt_append_u64(char* buf, size_t n, uint64_t value) {
+    char digits[20];
+    size_t count = 0;
+    do {
+        digits[count++] = (char)('0' + value % 10);
+        value /= 10;
+    } while (value);
+    while (count) {
+        buf[n++] = digits[--count];
+    }
+    return n;
+}
+__attribute__((weak)) void hades_panic_bounds(uint8_t* file, uint32_t line, uint32_t column, size_t index, size_t len) {
+    char buf[512];
+    size_t n = hades_rt_append_str(buf, 0, (const char*)file);
+    n = hades_rt_append_str(buf, n, ":");
+    n = hades_rt_append_u64(buf, n, line);
+    n = hades_rt_append_str(buf, n, ":");
+    n = hades_rt_append_u64(buf, n, column);
+    n = hades_rt_append_str(buf, n, ": panic: index out of bounds: the len is ");
+    n = hades_rt_append_u64(buf, n, len);
+    n = hades_rt_append_str(buf, n, " but the index is ");
+    n = hades_rt_append_u64(buf, n, index);
+    n = hades_rt_append_str(buf, n, "\n");
+    hades_rt_write(2, buf, n);
+    __builtin_trap();
+}
+"#;
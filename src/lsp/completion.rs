@@ -0,0 +1,309 @@
+//! Completion of the names in scope at the cursor, and help with the
+//! parameters of the function being called. Both work in files with syntax
+//! errors, as the parser leaves out only the statements with errors.
+
+use std::{collections::HashSet, path::PathBuf};
+
+use libsyntax::{HasMeta, HasSpan, NodeId, Span};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, Documentation, MarkupContent, MarkupKind,
+    ParameterInformation, ParameterLabel, SignatureHelp, SignatureInformation,
+};
+
+use crate::{
+    analysis::typeck::TypeckResult,
+    ast::{
+        self,
+        visit::{self, Visitor},
+        ItemKind, PatKind, StmtKind,
+    },
+    lexer::{Lexer, Token, TokenKind},
+};
+
+use super::index::{Decl, DeclKind, Index};
+
+const ITEM_KEYWORDS: [&str; 13] = [
+    "fn", "struct", "enum", "trait", "impl", "mod", "use", "const", "static", "type", "newtype",
+    "extern", "pub",
+];
+
+const EXPR_KEYWORDS: [&str; 7] = ["let", "match", "unsafe", "mut", "true", "false", "null"];
+
+/// The names in scope at `offset` in a document, which is the first file of
+/// the index, innermost first
+pub fn completions(
+    text: &str,
+    offset: usize,
+    items: &[ast::Item],
+    index: &Index,
+    typeck: &TypeckResult,
+) -> Vec<CompletionItem> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut start = offset.min(chars.len());
+    while start > 0 && is_ident_char(chars[start - 1]) {
+        start -= 1;
+    }
+    // Fields, methods and the items of paths aren't completed
+    if start > 0 && (chars[start - 1] == '.' || chars[start - 1] == ':') {
+        return vec![];
+    }
+
+    let mut scopes = Scopes {
+        offset,
+        items,
+        in_fn: false,
+        locals: vec![],
+    };
+    walk_items(&mut scopes, items);
+
+    let mut seen = HashSet::new();
+    let mut completions = vec![];
+    let items = scopes.items.iter().flat_map(|item| match &item.kind {
+        ItemKind::ForeignMod(foreign_mod) => foreign_mod.items.iter().map(|it| it.id()).collect(),
+        ItemKind::Impl(_) | ItemKind::Use(_) => vec![],
+        _ => vec![item.id()],
+    });
+    // Later locals shadow earlier ones, and locals shadow items
+    for id in scopes.locals.iter().rev().copied().chain(items) {
+        let Some(decl) = index.decls.get(&id) else {
+            continue;
+        };
+        if seen.insert(decl.name.clone()) {
+            completions.push(completion(id, decl, typeck));
+        }
+    }
+    let keywords = if scopes.in_fn {
+        &EXPR_KEYWORDS[..]
+    } else {
+        &ITEM_KEYWORDS[..]
+    };
+    completions.extend(keywords.iter().map(|keyword| CompletionItem {
+        label: keyword.to_string(),
+        kind: Some(CompletionItemKind::KEYWORD),
+        ..CompletionItem::default()
+    }));
+    completions
+}
+
+fn completion(id: NodeId, decl: &Decl, typeck: &TypeckResult) -> CompletionItem {
+    let kind = match decl.kind {
+        DeclKind::Fn | DeclKind::ForeignFn => CompletionItemKind::FUNCTION,
+        DeclKind::Struct | DeclKind::Newtype => CompletionItemKind::STRUCT,
+        DeclKind::Enum => CompletionItemKind::ENUM,
+        DeclKind::Variant => CompletionItemKind::ENUM_MEMBER,
+        DeclKind::Field => CompletionItemKind::FIELD,
+        DeclKind::Trait => CompletionItemKind::INTERFACE,
+        DeclKind::Mod => CompletionItemKind::MODULE,
+        DeclKind::Const => CompletionItemKind::CONSTANT,
+        DeclKind::TyAlias | DeclKind::TyParam => CompletionItemKind::TYPE_PARAMETER,
+        DeclKind::Static | DeclKind::Param | DeclKind::Local => CompletionItemKind::VARIABLE,
+    };
+    let detail = decl
+        .signature
+        .clone()
+        .or_else(|| Some(typeck.local_types.get(&id)?.to_string()));
+    CompletionItem {
+        label: decl.name.clone(),
+        kind: Some(kind),
+        detail,
+        ..CompletionItem::default()
+    }
+}
+
+/// Finds the module, function, blocks and match arms around the cursor
+struct Scopes<'a> {
+    offset: usize,
+    /// The items of the innermost module
+    items: &'a [ast::Item],
+    in_fn: bool,
+    /// Params and bindings in scope, in the order they're declared
+    locals: Vec<NodeId>,
+}
+
+impl Scopes<'_> {
+    fn contains(&self, span: &Span) -> bool {
+        span.start < self.offset && self.offset <= span.end
+    }
+}
+
+fn walk_items<'a>(scopes: &mut Scopes<'a>, items: &'a [ast::Item]) {
+    for item in items {
+        if !scopes.contains(item.span()) {
+            continue;
+        }
+        match &item.kind {
+            ItemKind::Mod(m) => {
+                scopes.items = &m.items;
+                walk_items(scopes, &m.items);
+            }
+            ItemKind::Impl(imp) => walk_items(scopes, &imp.items),
+            ItemKind::Trait(t) => walk_items(scopes, &t.items),
+            ItemKind::Fn(f) => {
+                let Some(body) = &f.body else {
+                    continue;
+                };
+                if scopes.contains(body.span()) {
+                    scopes.in_fn = true;
+                    scopes.locals.extend(f.params.iter().map(|it| it.id()));
+                    scopes.visit_expr(body);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Visitor for Scopes<'_> {
+    fn visit_block(&mut self, block: &ast::Block) {
+        if !self.contains(block.span()) {
+            return;
+        }
+        for stmt in &block.stmts {
+            // Bindings are in scope after the statement declaring them
+            if stmt.span().end <= self.offset {
+                if let StmtKind::Let(local) = &stmt.kind {
+                    collect_bindings(&local.pat, &mut self.locals);
+                }
+            } else if self.contains(stmt.span()) {
+                self.visit_stmt(stmt);
+            }
+        }
+    }
+
+    fn visit_arm(&mut self, arm: &ast::Arm) {
+        if self.contains(arm.span()) {
+            collect_bindings(&arm.pat, &mut self.locals);
+            visit::walk_arm(self, arm);
+        }
+    }
+}
+
+fn collect_bindings(pat: &ast::Pat, out: &mut Vec<NodeId>) {
+    struct Bindings<'a>(&'a mut Vec<NodeId>);
+    impl Visitor for Bindings<'_> {
+        fn visit_pat(&mut self, pat: &ast::Pat) {
+            if let PatKind::Binding(_) = pat.kind {
+                self.0.push(pat.id());
+            }
+            visit::walk_pat(self, pat);
+        }
+    }
+    Bindings(out).visit_pat(pat);
+}
+
+/// The signature of the function called by the innermost unclosed call before
+/// `offset`, with the parameter of the argument at `offset` highlighted
+pub fn signature_help(text: &str, offset: usize, index: &Index) -> Option<SignatureHelp> {
+    let mut lexer = Lexer::new(text, PathBuf::new());
+    let mut tokens = vec![];
+    loop {
+        let token = lexer.next_token();
+        if token.kind == TokenKind::EOF || token.span.end > offset {
+            break;
+        }
+        tokens.push(token);
+    }
+
+    // Finds the `(` of the call, counting the commas between arguments
+    let mut depth = 0;
+    let mut commas = 0;
+    let mut lparen = None;
+    for (i, token) in tokens.iter().enumerate().rev() {
+        match token.kind {
+            TokenKind::RPAREN | TokenKind::RBRACKET => depth += 1,
+            TokenKind::LPAREN | TokenKind::LBRACKET if depth > 0 => depth -= 1,
+            TokenKind::LPAREN => {
+                lparen = Some(i);
+                break;
+            }
+            TokenKind::COMMA if depth == 0 => commas += 1,
+            TokenKind::LBRACE | TokenKind::RBRACE | TokenKind::SEMI | TokenKind::LBRACKET => {
+                return None
+            }
+            _ => {}
+        }
+    }
+    let lparen = lparen?;
+    let callee = tokens.get(lparen.checked_sub(1)?)?;
+    if callee.kind != TokenKind::IDENT {
+        return None;
+    }
+    let before = lparen.checked_sub(2).map(|it| tokens[it].kind);
+    if before == Some(TokenKind::FN) {
+        return None;
+    }
+    let is_method = before == Some(TokenKind::DOT);
+    let decl = callee_decl(callee, is_method, index)?;
+    let signature = decl.signature.clone()?;
+
+    // Label offsets count UTF-16 code units
+    let utf16_len = |text: &str| text.encode_utf16().count() as u32;
+    let mut search = signature.find('(')?;
+    let mut parameters = vec![];
+    for param in &decl.params {
+        let start = search + signature[search..].find(param.as_str())?;
+        search = start + param.len();
+        let start = utf16_len(&signature[..start]);
+        parameters.push(ParameterInformation {
+            label: ParameterLabel::LabelOffsets([start, start + utf16_len(param)]),
+            documentation: None,
+        });
+    }
+    // Methods called with `.` are passed `self` before the arguments
+    let skips_self = is_method
+        && decl
+            .params
+            .first()
+            .is_some_and(|it| it.starts_with("self:"));
+    let active_parameter = commas + skips_self as u32;
+    let documentation = index.doc_comment(decl).map(|value| {
+        Documentation::MarkupContent(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value,
+        })
+    });
+    Some(SignatureHelp {
+        signatures: vec![SignatureInformation {
+            label: signature,
+            documentation,
+            parameters: Some(parameters),
+            active_parameter: Some(active_parameter),
+        }],
+        active_signature: Some(0),
+        active_parameter: Some(active_parameter),
+    })
+}
+
+/// The function a callee refers to, or the first function with its name when
+/// the call was left out of the tree because of a syntax error
+fn callee_decl<'a>(callee: &Token, is_method: bool, index: &'a Index) -> Option<&'a Decl> {
+    let resolved = index
+        .refs
+        .iter()
+        .find(|it| it.span.end == callee.span.end)
+        .and_then(|it| index.decls.get(&it.def));
+    if resolved.is_some() {
+        return resolved;
+    }
+    let mut candidates = index
+        .decls
+        .values()
+        .filter(|it| matches!(it.kind, DeclKind::Fn | DeclKind::ForeignFn))
+        .filter(|it| it.name == callee.text)
+        .collect::<Vec<_>>();
+    candidates.sort_by_key(|it| it.name_span.start);
+    let takes_self = |decl: &&Decl| {
+        decl.params
+            .first()
+            .is_some_and(|it| it.starts_with("self:"))
+    };
+    candidates
+        .iter()
+        .find(|it| takes_self(it) == is_method)
+        .or(candidates.first())
+        .copied()
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
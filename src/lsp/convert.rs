@@ -0,0 +1,74 @@
+//! Conversions between the character offsets that spans use and the positions
+//! of the protocol, which count lines and UTF-16 code units within a line.
+
+use lsp_types::{Position, Range};
+
+/// The position of a character offset into `text`
+pub fn position(text: &str, offset: usize) -> Position {
+    let mut line = 0;
+    let mut character = 0;
+    for c in text.chars().take(offset) {
+        if c == '\n' {
+            line += 1;
+            character = 0;
+        } else {
+            character += c.len_utf16() as u32;
+        }
+    }
+    Position { line, character }
+}
+
+/// The range of the characters from `start` to `end` in `text`
+pub fn range(text: &str, start: usize, end: usize) -> Range {
+    Range {
+        start: position(text, start),
+        end: position(text, end),
+    }
+}
+
+/// The character offset of a position in `text`. Positions past the end of a
+/// line refer to the end of the line.
+pub fn offset(text: &str, position: Position) -> usize {
+    let mut line = 0;
+    let mut character = 0;
+    for (offset, c) in text.chars().enumerate() {
+        if line == position.line && (character >= position.character || c == '\n') {
+            return offset;
+        }
+        if c == '\n' {
+            line += 1;
+        } else if line == position.line {
+            character += c.len_utf16() as u32;
+        }
+    }
+    text.chars().count()
+}
+
+/// The byte index of a character offset into `text`
+pub fn byte_index(text: &str, offset: usize) -> usize {
+    text.char_indices()
+        .nth(offset)
+        .map_or(text.len(), |(index, _)| index)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn converts_offsets_to_positions_and_back() {
+        let text = "fn a() {}\nlet s = \"é😀\";\n";
+        let pos = |line, character| Position { line, character };
+        assert_eq!(position(text, 3), pos(0, 3));
+        assert_eq!(position(text, 10), pos(1, 0));
+        // `😀` is two UTF-16 code units
+        assert_eq!(position(text, 20), pos(1, 10));
+        assert_eq!(position(text, 21), pos(1, 12));
+        for offset in 0..text.chars().count() {
+            assert_eq!(super::offset(text, position(text, offset)), offset);
+        }
+        assert_eq!(super::offset(text, pos(0, 100)), 9);
+        assert_eq!(super::offset(text, pos(5, 0)), text.chars().count());
+        assert_eq!(byte_index(text, 20), 21);
+    }
+}
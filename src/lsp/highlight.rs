@@ -0,0 +1,175 @@
+//! Semantic tokens, which color the tokens of a document by what they are and
+//! what the names among them refer to
+
+use std::{collections::HashMap, path::PathBuf};
+
+use lsp_types::{SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokensLegend};
+
+use crate::{
+    analysis::ty::PrimTy,
+    lexer::{Lexer, TokenKind},
+};
+
+use super::index::{DeclKind, Index};
+
+const TOKEN_TYPES: [SemanticTokenType; 14] = [
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::PARAMETER,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::TYPE,
+    SemanticTokenType::STRUCT,
+    SemanticTokenType::ENUM,
+    SemanticTokenType::ENUM_MEMBER,
+    SemanticTokenType::INTERFACE,
+    SemanticTokenType::NAMESPACE,
+    SemanticTokenType::TYPE_PARAMETER,
+    SemanticTokenType::PROPERTY,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::STRING,
+];
+
+const DECLARATION: u32 = 1 << 0;
+const READONLY: u32 = 1 << 1;
+const STATIC: u32 = 1 << 2;
+/// Functions declared in `extern` blocks
+const FOREIGN: u32 = 1 << 3;
+
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.to_vec(),
+        token_modifiers: vec![
+            SemanticTokenModifier::DECLARATION,
+            SemanticTokenModifier::READONLY,
+            SemanticTokenModifier::STATIC,
+            SemanticTokenModifier::new("foreign"),
+        ],
+    }
+}
+
+/// The semantic tokens of a document that parsed, which is the first file of
+/// the index
+pub fn semantic_tokens(text: &str, index: &Index) -> Vec<SemanticToken> {
+    let decls = index
+        .decls
+        .values()
+        .map(|decl| (decl.name_span.start, decl.kind))
+        .collect::<HashMap<_, _>>();
+    // Paths are colored by their last segment
+    let refs = index
+        .refs
+        .iter()
+        .filter_map(|it| Some((it.span.end, index.decls.get(&it.def)?.kind)))
+        .collect::<HashMap<_, _>>();
+
+    let mut tokens = vec![];
+    let mut lexer = Lexer::new(text, PathBuf::new());
+    let mut chars = text.chars();
+    let (mut offset, mut line, mut character) = (0, 0, 0);
+    let (mut prev_line, mut prev_character) = (0, 0);
+    loop {
+        let token = lexer.next_token();
+        if token.kind == TokenKind::EOF {
+            break;
+        }
+        let classified = match token.kind {
+            TokenKind::IDENT if token.text == "Self" => {
+                Some((token_type(&SemanticTokenType::KEYWORD), 0))
+            }
+            TokenKind::IDENT => match decls.get(&token.span.start) {
+                Some(kind) => Some(classify(*kind, DECLARATION)),
+                None => match refs.get(&token.span.end) {
+                    Some(kind) => Some(classify(*kind, 0)),
+                    None => PrimTy::from_name(&token.text)
+                        .map(|_| (token_type(&SemanticTokenType::TYPE), 0)),
+                },
+            },
+            TokenKind::INT => Some((token_type(&SemanticTokenType::NUMBER), 0)),
+            TokenKind::STRING => Some((token_type(&SemanticTokenType::STRING), 0)),
+            kind if is_keyword(kind) => Some((token_type(&SemanticTokenType::KEYWORD), 0)),
+            _ => None,
+        };
+        let Some((token_type, modifiers)) = classified else {
+            continue;
+        };
+        // Positions count UTF-16 code units, and tokens never span lines
+        for c in chars.by_ref().take(token.span.start - offset) {
+            if c == '\n' {
+                line += 1;
+                character = 0;
+            } else {
+                character += c.len_utf16() as u32;
+            }
+        }
+        offset = token.span.start;
+        let length = token.text.chars().map(|c| c.len_utf16() as u32).sum();
+        tokens.push(SemanticToken {
+            delta_line: line - prev_line,
+            delta_start: if line == prev_line {
+                character - prev_character
+            } else {
+                character
+            },
+            length,
+            token_type,
+            token_modifiers_bitset: modifiers,
+        });
+        (prev_line, prev_character) = (line, character);
+    }
+    tokens
+}
+
+fn token_type(ty: &SemanticTokenType) -> u32 {
+    TOKEN_TYPES.iter().position(|it| it == ty).unwrap() as u32
+}
+
+/// The token type and modifiers of a name referring to a declaration
+fn classify(kind: DeclKind, modifiers: u32) -> (u32, u32) {
+    let (ty, extra) = match kind {
+        DeclKind::Fn => (SemanticTokenType::FUNCTION, 0),
+        DeclKind::ForeignFn => (SemanticTokenType::FUNCTION, FOREIGN),
+        DeclKind::Struct => (SemanticTokenType::STRUCT, 0),
+        DeclKind::Enum => (SemanticTokenType::ENUM, 0),
+        DeclKind::Variant => (SemanticTokenType::ENUM_MEMBER, 0),
+        DeclKind::Field => (SemanticTokenType::PROPERTY, 0),
+        DeclKind::Trait => (SemanticTokenType::INTERFACE, 0),
+        DeclKind::Mod => (SemanticTokenType::NAMESPACE, 0),
+        DeclKind::Const => (SemanticTokenType::VARIABLE, READONLY),
+        DeclKind::Static => (SemanticTokenType::VARIABLE, STATIC),
+        DeclKind::TyAlias | DeclKind::Newtype => (SemanticTokenType::TYPE, 0),
+        DeclKind::TyParam => (SemanticTokenType::TYPE_PARAMETER, 0),
+        DeclKind::Param => (SemanticTokenType::PARAMETER, 0),
+        DeclKind::Local => (SemanticTokenType::VARIABLE, 0),
+    };
+    (token_type(&ty), modifiers | extra)
+}
+
+fn is_keyword(kind: TokenKind) -> bool {
+    use TokenKind::*;
+    matches!(
+        kind,
+        FN | PUB
+            | EXTERN
+            | UNSAFE
+            | STRUCT
+            | LET
+            | ENUM
+            | MATCH
+            | IF
+            | TRUE
+            | FALSE
+            | MUT
+            | AS
+            | NULL
+            | TRAIT
+            | IMPL
+            | FOR
+            | DYN
+            | MOD
+            | USE
+            | CONST
+            | STATIC
+            | TYPE
+            | NEWTYPE
+    )
+}
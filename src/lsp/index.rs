@@ -0,0 +1,598 @@
+//! Where names are declared and used in a crate, for finding what's under the
+//! cursor. The AST doesn't record the spans of names, so they're found in the
+//! source text within the spans of the nodes that declare or use them.
+
+use std::collections::HashMap;
+
+use libsyntax::{HasMeta, HasSpan, NodeId, Span};
+
+use crate::{
+    analysis::resolve::{Res, ResolveResult},
+    ast::{
+        self,
+        visit::{self, Visitor},
+        ExprKind, Ident, ItemKind, Mutability, PatKind, SourceFile, TyKind, UseKind, VariantData,
+    },
+    source_map::SourceMap,
+    walk_list,
+};
+
+pub struct Index {
+    /// Declarations by the id of the node declaring them
+    pub decls: HashMap<NodeId, Decl>,
+    /// Names referring to declarations, in the order they appear
+    pub refs: Vec<Ref>,
+    /// Spans of expressions; Inner expressions come after the outer ones
+    exprs: Vec<(Span, NodeId)>,
+    /// The text of all files, indexed by the offsets that spans use
+    chars: Vec<char>,
+}
+
+pub struct Decl {
+    pub kind: DeclKind,
+    pub name: Ident,
+    pub name_span: Span,
+    /// The span of the whole declaration
+    pub span: Span,
+    /// How the declaration is written, e.g. `fn add(a: i32, b: i32) -> i32`;
+    /// `None` for params and bindings, whose types are inferred
+    pub signature: Option<String>,
+    /// The parameters of a function as its signature writes them, e.g. `a: i32`
+    pub params: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeclKind {
+    Fn,
+    ForeignFn,
+    Struct,
+    Enum,
+    Variant,
+    Field,
+    Trait,
+    Mod,
+    Const,
+    Static,
+    TyAlias,
+    Newtype,
+    TyParam,
+    Param,
+    Local,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ref {
+    pub span: Span,
+    pub def: NodeId,
+}
+
+impl Index {
+    pub fn new(source_file: &SourceFile, source_map: &SourceMap, resolve: &ResolveResult) -> Self {
+        let mut chars = vec![];
+        for file in source_map.files() {
+            chars.extend(file.text.chars());
+            // Spans of the next file start after the EOF token of this one
+            chars.push('\n');
+        }
+        let mut collector = Collector {
+            resolve,
+            index: Index {
+                decls: HashMap::new(),
+                refs: vec![],
+                exprs: vec![],
+                chars,
+            },
+            enum_name: None,
+        };
+        walk_list!(collector, visit_item, &source_file.items);
+        collector.index
+    }
+
+    /// The declaration whose name is at `offset`, or that the name at `offset`
+    /// refers to
+    pub fn def_at(&self, offset: usize) -> Option<(NodeId, Span)> {
+        if let Some(it) = self.refs.iter().find(|it| touches(&it.span, offset)) {
+            return Some((it.def, it.span));
+        }
+        self.decls
+            .iter()
+            .find(|(_, decl)| touches(&decl.name_span, offset))
+            .map(|(id, decl)| (*id, decl.name_span))
+    }
+
+    /// The text of all files, indexed by the offsets that spans use
+    pub fn chars(&self) -> &[char] {
+        &self.chars
+    }
+
+    /// The names referring to a declaration
+    pub fn refs_to(&self, def: NodeId) -> impl Iterator<Item = &Ref> {
+        self.refs.iter().filter(move |it| it.def == def)
+    }
+
+    /// The innermost expression containing `offset`
+    pub fn expr_at(&self, offset: usize) -> Option<(NodeId, Span)> {
+        self.exprs
+            .iter()
+            .filter(|(span, _)| touches(span, offset))
+            .min_by_key(|(span, _)| span.end - span.start)
+            .map(|(span, id)| (*id, *span))
+    }
+
+    /// The `///` comments on the lines above a declaration, without the slashes
+    pub fn doc_comment(&self, decl: &Decl) -> Option<String> {
+        let mut line_start = decl.span.start;
+        while line_start > 0 && self.chars[line_start - 1] != '\n' {
+            line_start -= 1;
+        }
+        // Only declarations starting a line have doc comments, not parameters
+        if !self.chars[line_start..decl.span.start]
+            .iter()
+            .all(|c| c.is_whitespace())
+        {
+            return None;
+        }
+        let mut lines = vec![];
+        while line_start > 0 {
+            let end = line_start - 1;
+            let mut start = end;
+            while start > 0 && self.chars[start - 1] != '\n' {
+                start -= 1;
+            }
+            let line = self.chars[start..end].iter().collect::<String>();
+            let line = line.trim();
+            if let Some(doc) = line.strip_prefix("///") {
+                lines.push(doc.strip_prefix(' ').unwrap_or(doc).to_string());
+            } else if !line.starts_with("#[") {
+                break;
+            }
+            line_start = start;
+        }
+        if lines.is_empty() {
+            return None;
+        }
+        lines.reverse();
+        Some(lines.join("\n"))
+    }
+}
+
+/// Whether `offset` is in `span` or right after it, where the cursor is after
+/// typing a name
+fn touches(span: &Span, offset: usize) -> bool {
+    span.start <= offset && offset <= span.end
+}
+
+struct Collector<'a> {
+    resolve: &'a ResolveResult,
+    index: Index,
+    /// The enum whose variants are being visited
+    enum_name: Option<Ident>,
+}
+
+impl Collector<'_> {
+    fn declare(
+        &mut self,
+        id: NodeId,
+        kind: DeclKind,
+        span: &Span,
+        name: &str,
+        signature: Option<String>,
+    ) {
+        let Some(name_span) = self.find_name(span, name) else {
+            return;
+        };
+        let decl = Decl {
+            kind,
+            name: name.to_string(),
+            name_span,
+            span: *span,
+            signature,
+            params: vec![],
+        };
+        self.index.decls.insert(id, decl);
+    }
+
+    /// The first occurrence of `name` as a whole word within `span`
+    fn find_name(&self, span: &Span, name: &str) -> Option<Span> {
+        let name = name.chars().collect::<Vec<_>>();
+        let chars = &self.index.chars;
+        let end = span.end.min(chars.len());
+        (span.start..end.saturating_sub(name.len() - 1))
+            .find(|&start| {
+                chars[start..start + name.len()] == name[..]
+                    && (start == 0 || !is_ident_char(chars[start - 1]))
+                    && !chars
+                        .get(start + name.len())
+                        .is_some_and(|it| is_ident_char(*it))
+            })
+            .map(|start| Span {
+                start,
+                end: start + name.len(),
+            })
+    }
+
+    /// Records a use of a name; `span` starts with the path naming it
+    fn add_ref(&mut self, id: NodeId, span: &Span) {
+        let def = match self.resolve.get(id) {
+            Res::Def(_, def) | Res::Local(def) => def,
+            Res::PrimTy(_) | Res::Err => return,
+        };
+        let span = Span {
+            start: span.start,
+            end: self.path_end(span.start).min(span.end),
+        };
+        self.index.refs.push(Ref { span, def });
+    }
+
+    /// Records the last segment of a single import as a use of what it imports
+    fn add_import_ref(&mut self, id: NodeId, span: &Span, use_item: &ast::Use) {
+        let (UseKind::Single(_), Some(name)) = (&use_item.kind, use_item.path.segments.last())
+        else {
+            return;
+        };
+        let (Some(Res::Def(_, def)), Some(keyword)) =
+            (self.resolve.imports.get(&id), self.find_name(span, "use"))
+        else {
+            return;
+        };
+        let chars = &self.index.chars;
+        let mut start = keyword.end;
+        while start < chars.len() && chars[start].is_whitespace() {
+            start += 1;
+        }
+        let end = self.path_end(start);
+        let span = Span {
+            start: end - name.chars().count(),
+            end,
+        };
+        self.index.refs.push(Ref { span, def: *def });
+    }
+
+    /// The end of the path starting at `start`, e.g. `a :: b` in `a :: b<T>`
+    fn path_end(&self, start: usize) -> usize {
+        let chars = &self.index.chars;
+        let ident_end = |mut offset: usize| {
+            while offset < chars.len() && is_ident_char(chars[offset]) {
+                offset += 1;
+            }
+            offset
+        };
+        let mut end = ident_end(start);
+        loop {
+            let mut next = end;
+            while next < chars.len() && chars[next].is_whitespace() {
+                next += 1;
+            }
+            if chars.get(next..next + 2) != Some(&[':', ':']) {
+                return end;
+            }
+            next += 2;
+            while next < chars.len() && chars[next].is_whitespace() {
+                next += 1;
+            }
+            end = ident_end(next);
+        }
+    }
+
+    fn declare_generics(&mut self, generics: &[ast::GenericParam]) {
+        for param in generics {
+            self.declare(
+                param.id(),
+                DeclKind::TyParam,
+                param.span(),
+                &param.name,
+                Some(generic_param(param)),
+            );
+            for bound in param.bounds.iter() {
+                self.add_ref(bound.id(), bound.span());
+            }
+        }
+    }
+
+    fn declare_params(&mut self, id: NodeId, f: &ast::Fn) {
+        if let Some(decl) = self.index.decls.get_mut(&id) {
+            decl.params = fn_params(f);
+        }
+    }
+
+    fn declare_fields(&mut self, data: &VariantData) {
+        if let VariantData::Struct(fields) = data {
+            for field in fields {
+                let signature = format!("{}: {}", field.name, field.ty);
+                self.declare(
+                    field.id(),
+                    DeclKind::Field,
+                    field.span(),
+                    &field.name,
+                    Some(signature),
+                );
+            }
+        }
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl Visitor for Collector<'_> {
+    fn visit_item(&mut self, item: &ast::Item) {
+        let decl = match &item.kind {
+            ItemKind::Fn(f) => Some((DeclKind::Fn, fn_signature(&item.name, f))),
+            ItemKind::Struct(s) => Some((
+                DeclKind::Struct,
+                format!(
+                    "struct {}{}{}",
+                    item.name,
+                    generics(&s.generics),
+                    fields(&s.data)
+                ),
+            )),
+            ItemKind::Enum(e) => Some((
+                DeclKind::Enum,
+                format!("enum {}{}", item.name, generics(&e.generics)),
+            )),
+            ItemKind::Trait(_) => Some((DeclKind::Trait, format!("trait {}", item.name))),
+            ItemKind::Mod(_) => Some((DeclKind::Mod, format!("mod {}", item.name))),
+            ItemKind::Const(c) => Some((DeclKind::Const, format!("const {}: {}", item.name, c.ty))),
+            ItemKind::Static(s) => {
+                let mutability = match s.mutability {
+                    Mutability::Mut => "mut ",
+                    Mutability::Not => "",
+                };
+                Some((
+                    DeclKind::Static,
+                    format!("static {}{}: {}", mutability, item.name, s.ty),
+                ))
+            }
+            ItemKind::TyAlias(alias) => Some((
+                DeclKind::TyAlias,
+                format!(
+                    "type {}{} = {}",
+                    item.name,
+                    generics(&alias.generics),
+                    alias.ty
+                ),
+            )),
+            ItemKind::Newtype(n) => Some((
+                DeclKind::Newtype,
+                format!("newtype {} = {}", item.name, n.ty),
+            )),
+            // Not named in the source
+            ItemKind::ForeignMod(_) | ItemKind::Impl(_) | ItemKind::Use(_) => None,
+        };
+        if let Some((kind, signature)) = decl {
+            self.declare(item.id(), kind, item.span(), &item.name, Some(signature));
+        }
+        if let ItemKind::Fn(f) = &item.kind {
+            self.declare_params(item.id(), f);
+        }
+        match &item.kind {
+            ItemKind::Struct(s) => {
+                self.declare_generics(&s.generics);
+                self.declare_fields(&s.data);
+            }
+            ItemKind::Enum(e) => {
+                self.declare_generics(&e.generics);
+                self.enum_name = Some(item.name.clone());
+            }
+            ItemKind::TyAlias(alias) => self.declare_generics(&alias.generics),
+            ItemKind::Use(use_item) => self.add_import_ref(item.id(), item.span(), use_item),
+            ItemKind::Impl(imp) => {
+                self.declare_generics(&imp.generics);
+                if let Some(trait_ref) = &imp.trait_ref {
+                    self.add_ref(trait_ref.id(), trait_ref.span());
+                }
+            }
+            _ => {}
+        }
+        visit::walk_item(self, item);
+    }
+
+    fn visit_fn(&mut self, f: &ast::Fn) {
+        self.declare_generics(&f.generics);
+        visit::walk_fn(self, f);
+    }
+
+    fn visit_foreign_item(&mut self, item: &ast::ForeignItem) {
+        let signature = match &item.kind {
+            ast::ForeignItemKind::Fn(f) => fn_signature(&item.name, f),
+        };
+        self.declare(
+            item.id(),
+            DeclKind::ForeignFn,
+            item.span(),
+            &item.name,
+            Some(signature),
+        );
+        let ast::ForeignItemKind::Fn(f) = &item.kind;
+        self.declare_params(item.id(), f);
+        visit::walk_foreign_item(self, item);
+    }
+
+    fn visit_variant(&mut self, variant: &ast::Variant) {
+        let enum_name = self.enum_name.clone().unwrap_or_default();
+        let signature = format!("{}::{}{}", enum_name, variant.name, fields(&variant.data));
+        self.declare(
+            variant.id(),
+            DeclKind::Variant,
+            variant.span(),
+            &variant.name,
+            Some(signature),
+        );
+        self.declare_fields(&variant.data);
+        visit::walk_variant(self, variant);
+    }
+
+    fn visit_param(&mut self, param: &ast::Param) {
+        let span = match param.attrs.last() {
+            Some(attr) => Span {
+                start: attr.span().end,
+                end: param.span().end,
+            },
+            None => *param.span(),
+        };
+        self.declare(param.id(), DeclKind::Param, &span, &param.name, None);
+        visit::walk_param(self, param);
+    }
+
+    fn visit_expr(&mut self, expr: &ast::Expr) {
+        self.index.exprs.push((*expr.span(), expr.id()));
+        if let ExprKind::Var(_) | ExprKind::Path(_) | ExprKind::Struct(_) = &expr.kind {
+            self.add_ref(expr.id(), expr.span());
+        }
+        visit::walk_expr(self, expr);
+    }
+
+    fn visit_pat(&mut self, pat: &ast::Pat) {
+        match &pat.kind {
+            PatKind::Binding(name) => {
+                self.declare(pat.id(), DeclKind::Local, pat.span(), name, None)
+            }
+            PatKind::Path(_) | PatKind::TupleStruct(..) | PatKind::Struct(..) => {
+                self.add_ref(pat.id(), pat.span())
+            }
+            PatKind::Wild | PatKind::Lit(_) | PatKind::Tuple(_) => {}
+        }
+        visit::walk_pat(self, pat);
+    }
+
+    fn visit_ty(&mut self, ty: &ast::Ty) {
+        match &ty.kind {
+            TyKind::Var(..) | TyKind::Path(..) => self.add_ref(ty.id(), ty.span()),
+            TyKind::Dyn(trait_ref) => self.add_ref(trait_ref.id(), trait_ref.span()),
+            _ => {}
+        }
+        visit::walk_ty(self, ty);
+    }
+}
+
+/// `fn add<T: Num>(a: T, b: T) -> T`
+fn fn_signature(name: &str, f: &ast::Fn) -> String {
+    let mut params = fn_params(f);
+    if f.variadic {
+        params.push("...".to_string());
+    }
+    let ret = match &f.return_ty {
+        Some(ty) => format!(" -> {}", ty),
+        None => String::new(),
+    };
+    format!(
+        "fn {}{}({}){}",
+        name,
+        generics(&f.generics),
+        params.join(", "),
+        ret
+    )
+}
+
+fn fn_params(f: &ast::Fn) -> Vec<String> {
+    f.params
+        .iter()
+        .map(|it| format!("{}: {}", it.name, it.ty))
+        .collect()
+}
+
+fn generics(generics: &[ast::GenericParam]) -> String {
+    if generics.is_empty() {
+        return String::new();
+    }
+    let params = generics.iter().map(generic_param).collect::<Vec<_>>();
+    format!("<{}>", params.join(", "))
+}
+
+fn generic_param(param: &ast::GenericParam) -> String {
+    if param.bounds.is_empty() {
+        return param.name.clone();
+    }
+    let bounds = param
+        .bounds
+        .iter()
+        .map(|it| it.path.to_string())
+        .collect::<Vec<_>>();
+    format!("{}: {}", param.name, bounds.join(" + "))
+}
+
+/// ` { x: i32, y: i32 }` or `(i32, i32)`
+fn fields(data: &VariantData) -> String {
+    match data {
+        VariantData::Struct(fields) => {
+            let fields = fields
+                .iter()
+                .map(|it| format!("{}: {}", it.name, it.ty))
+                .collect::<Vec<_>>();
+            format!(" {{ {} }}", fields.join(", "))
+        }
+        VariantData::Tuple(fields) => {
+            let fields = fields
+                .iter()
+                .map(|it| it.ty.to_string())
+                .collect::<Vec<_>>();
+            format!("({})", fields.join(", "))
+        }
+        VariantData::Unit => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::{analysis::resolve::resolve, loader};
+
+    use super::*;
+
+    /// The names that the names at each of `offsets` refer to
+    fn defs_at(text: &str, offsets: &[&str]) -> Vec<Option<String>> {
+        let result = loader::load_edited(Path::new("test.hds"), text.to_string(), HashMap::new());
+        let resolve_result = resolve(&result.source_file);
+        let index = Index::new(&result.source_file, &result.source_map, &resolve_result);
+        offsets
+            .iter()
+            .map(|needle| {
+                let offset = text.find(needle).unwrap();
+                let (def, _) = index.def_at(offset)?;
+                Some(index.decls[&def].name.clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn indexes_names_in_items_patterns_and_types() {
+        let text = "\
+trait Show { fn show(self: *Self) -> i32; }
+enum Shape { Circle(i32), Square(i32) }
+impl Show for Shape { fn show(self: *Self) -> i32 { 0 } }
+fn area<T: Show>(shape: Shape, t: T) -> i32 {
+    match shape {
+        Shape::Circle(r) => r * 3,
+        Shape::Square(side) => side * side,
+    }
+}
+";
+        let defs = defs_at(
+            text,
+            &[
+                "Show for",
+                "T: Show",
+                "T)",
+                "Shape::Circle(r)",
+                "Square(side)",
+                "r * 3",
+                "side * side",
+                "match",
+            ],
+        );
+        let expected = [
+            Some("Show"),
+            Some("T"),
+            Some("T"),
+            Some("Circle"),
+            Some("Square"),
+            Some("r"),
+            Some("side"),
+            None,
+        ];
+        assert_eq!(defs, expected.map(|it| it.map(String::from)).to_vec());
+    }
+}
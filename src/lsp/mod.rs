@@ -0,0 +1,1606 @@
+//! A language server for editors, speaking the Language Server Protocol over
+//! stdin and stdout. Open documents are checked whenever they change, and
+//! their diagnostics are published to the client.
+
+mod completion;
+mod convert;
+mod highlight;
+mod index;
+mod outline;
+mod rename;
+
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use libsyntax::{HasSpan, Span};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+        PublishDiagnostics,
+    },
+    request::{
+        Completion, DocumentSymbolRequest, FoldingRangeRequest, Formatting, GotoDefinition,
+        HoverRequest, PrepareRenameRequest, References, Rename, Request as _,
+        SemanticTokensFullRequest, SignatureHelpRequest,
+    },
+    CompletionOptions, CompletionParams, CompletionResponse, DiagnosticSeverity,
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DocumentFormattingParams, DocumentSymbolParams, DocumentSymbolResponse, FoldingRangeParams,
+    FoldingRangeProviderCapability, GotoDefinitionParams, GotoDefinitionResponse, Hover,
+    HoverContents, HoverParams, HoverProviderCapability, InitializeResult, Location, MarkupContent,
+    MarkupKind, NumberOrString, OneOf, PrepareRenameResponse, PublishDiagnosticsParams, Range,
+    ReferenceParams, RenameOptions, RenameParams, SemanticTokens, SemanticTokensFullOptions,
+    SemanticTokensOptions, SemanticTokensParams, SemanticTokensServerCapabilities,
+    ServerCapabilities, ServerInfo, SignatureHelpOptions, SignatureHelpParams,
+    TextDocumentContentChangeEvent, TextDocumentPositionParams, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextEdit, Url, WorkspaceEdit,
+};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    analysis::{
+        const_eval::ConstValue,
+        resolve::{resolve, ResolveResult},
+        typeck::typeck,
+        typeck::TypeckResult,
+    },
+    ast::{Ident, Item, ItemKind, SourceFile},
+    build,
+    diagnostics::{Diagnostic, Severity},
+    formatter,
+    loader::{self, Library},
+    manifest::{self, MANIFEST_NAME},
+    mono,
+    parser::ParsedFile,
+    source_map::SourceMap,
+};
+
+use self::index::Index;
+
+/// Runs the server on stdin and stdout until the client exits
+pub fn run_stdio() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    // Dropping the connection stops the thread writing to stdout
+    run(connection)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+/// Serves a client until it shuts the server down
+pub fn run(connection: Connection) -> Result<()> {
+    let (id, _) = connection.initialize_start()?;
+    let result = InitializeResult {
+        capabilities: ServerCapabilities {
+            text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                TextDocumentSyncKind::INCREMENTAL,
+            )),
+            definition_provider: Some(OneOf::Left(true)),
+            references_provider: Some(OneOf::Left(true)),
+            hover_provider: Some(HoverProviderCapability::Simple(true)),
+            semantic_tokens_provider: Some(
+                SemanticTokensServerCapabilities::SemanticTokensOptions(SemanticTokensOptions {
+                    legend: highlight::legend(),
+                    full: Some(SemanticTokensFullOptions::Bool(true)),
+                    ..SemanticTokensOptions::default()
+                }),
+            ),
+            document_symbol_provider: Some(OneOf::Left(true)),
+            folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+            rename_provider: Some(OneOf::Right(RenameOptions {
+                prepare_provider: Some(true),
+                work_done_progress_options: Default::default(),
+            })),
+            document_formatting_provider: Some(OneOf::Left(true)),
+            completion_provider: Some(CompletionOptions::default()),
+            signature_help_provider: Some(SignatureHelpOptions {
+                trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+                ..SignatureHelpOptions::default()
+            }),
+            ..ServerCapabilities::default()
+        },
+        server_info: Some(ServerInfo {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        }),
+    };
+    connection.initialize_finish(id, serde_json::to_value(result)?)?;
+    let mut server = Server {
+        connection: &connection,
+        documents: HashMap::new(),
+    };
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                server.handle_request(request)?;
+            }
+            Message::Notification(notification) => server.handle_notification(notification)?,
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+struct Server<'a> {
+    connection: &'a Connection,
+    /// The documents open in the client, which may differ from the files on disk
+    documents: HashMap<Url, Document>,
+}
+
+struct Document {
+    text: String,
+    version: i32,
+    /// The root of the crate the document is a module of, from which it's
+    /// analyzed
+    root: PathBuf,
+    /// Kept to reparse only the items that edits touch
+    parsed: ParsedFile,
+    /// The message of the panic if the document couldn't be analyzed
+    analysis: Result<Analysis, String>,
+}
+
+/// The results of checking a document, kept to answer requests about it
+struct Analysis {
+    source_file: SourceFile,
+    source_map: SourceMap,
+    diagnostics: Vec<Diagnostic>,
+    resolve: ResolveResult,
+    typeck: TypeckResult,
+    index: Index,
+}
+
+impl Analysis {
+    /// The items in the document, which are those of one of the crate's
+    /// modules unless the document is the crate root
+    fn items(&self) -> &[Item] {
+        let len = self.source_map.files()[0].text.chars().count();
+        document_items(&self.source_file.items, len).unwrap_or_default()
+    }
+}
+
+impl Server<'_> {
+    /// Answers a request, with an error if its params are invalid
+    fn handle_request(&mut self, request: Request) -> Result<()> {
+        let id = request.id.clone();
+        let response = self.respond(request).unwrap_or_else(|err| {
+            Response::new_err(id, ErrorCode::InvalidParams as i32, err.to_string())
+        });
+        self.send(response.into())
+    }
+
+    fn respond(&mut self, request: Request) -> serde_json::Result<Response> {
+        Ok(match request.method.as_str() {
+            GotoDefinition::METHOD => {
+                let params: GotoDefinitionParams = serde_json::from_value(request.params)?;
+                let result = self.definition(&params.text_document_position_params);
+                Response::new_ok(request.id, result)
+            }
+            References::METHOD => {
+                let params: ReferenceParams = serde_json::from_value(request.params)?;
+                let result = self.references(&params);
+                Response::new_ok(request.id, result)
+            }
+            HoverRequest::METHOD => {
+                let params: HoverParams = serde_json::from_value(request.params)?;
+                let result = self.hover(&params.text_document_position_params);
+                Response::new_ok(request.id, result)
+            }
+            SemanticTokensFullRequest::METHOD => {
+                let params: SemanticTokensParams = serde_json::from_value(request.params)?;
+                let result =
+                    self.analysis(&params.text_document.uri)
+                        .map(|(text, it)| SemanticTokens {
+                            result_id: None,
+                            data: highlight::semantic_tokens(text, &it.index),
+                        });
+                Response::new_ok(request.id, result)
+            }
+            DocumentSymbolRequest::METHOD => {
+                let params: DocumentSymbolParams = serde_json::from_value(request.params)?;
+                let result = self.analysis(&params.text_document.uri).map(|(text, it)| {
+                    let symbols = outline::document_symbols(text, it.items(), &it.index);
+                    DocumentSymbolResponse::Nested(symbols)
+                });
+                Response::new_ok(request.id, result)
+            }
+            Completion::METHOD => {
+                let params: CompletionParams = serde_json::from_value(request.params)?;
+                let position = params.text_document_position;
+                let result = self
+                    .analysis(&position.text_document.uri)
+                    .map(|(text, it)| {
+                        let offset = convert::offset(text, position.position);
+                        let items = completion::completions(
+                            text,
+                            offset,
+                            it.items(),
+                            &it.index,
+                            &it.typeck,
+                        );
+                        CompletionResponse::Array(items)
+                    });
+                Response::new_ok(request.id, result)
+            }
+            SignatureHelpRequest::METHOD => {
+                let params: SignatureHelpParams = serde_json::from_value(request.params)?;
+                let position = params.text_document_position_params;
+                let result = self
+                    .analysis(&position.text_document.uri)
+                    .and_then(|(text, it)| {
+                        let offset = convert::offset(text, position.position);
+                        completion::signature_help(text, offset, &it.index)
+                    });
+                Response::new_ok(request.id, result)
+            }
+            PrepareRenameRequest::METHOD => {
+                let params: TextDocumentPositionParams = serde_json::from_value(request.params)?;
+                match self.prepare_rename(&params) {
+                    Ok(result) => Response::new_ok(request.id, result),
+                    Err(message) => {
+                        Response::new_err(request.id, ErrorCode::RequestFailed as i32, message)
+                    }
+                }
+            }
+            Rename::METHOD => {
+                let params: RenameParams = serde_json::from_value(request.params)?;
+                match self.rename(&params) {
+                    Ok(result) => Response::new_ok(request.id, result),
+                    Err(message) => {
+                        Response::new_err(request.id, ErrorCode::RequestFailed as i32, message)
+                    }
+                }
+            }
+            FoldingRangeRequest::METHOD => {
+                let params: FoldingRangeParams = serde_json::from_value(request.params)?;
+                let result = self
+                    .analysis(&params.text_document.uri)
+                    .map(|(text, it)| outline::folding_ranges(text, it.items()));
+                Response::new_ok(request.id, result)
+            }
+            Formatting::METHOD => {
+                let params: DocumentFormattingParams = serde_json::from_value(request.params)?;
+                let result = self.format(&params.text_document.uri);
+                Response::new_ok(request.id, result)
+            }
+            _ => {
+                let message = format!("Unknown request `{}`", request.method);
+                Response::new_err(request.id, ErrorCode::MethodNotFound as i32, message)
+            }
+        })
+    }
+
+    /// The text of a document and its analysis, if it parsed
+    fn analysis(&self, uri: &Url) -> Option<(&str, &Analysis)> {
+        let document = self.documents.get(uri)?;
+        let analysis = document.analysis.as_ref().ok()?;
+        Some((&document.text, analysis))
+    }
+
+    /// The analysis of a document and the offset of a position in it
+    fn at_position(&self, params: &TextDocumentPositionParams) -> Option<(&Analysis, usize)> {
+        let document = self.documents.get(&params.text_document.uri)?;
+        let analysis = document.analysis.as_ref().ok()?;
+        // The document is the first file of its source map
+        let offset = convert::offset(&document.text, params.position);
+        Some((analysis, offset))
+    }
+
+    fn definition(&self, params: &TextDocumentPositionParams) -> Option<GotoDefinitionResponse> {
+        let (analysis, offset) = self.at_position(params)?;
+        let (def, _) = analysis.index.def_at(offset)?;
+        let decl = analysis.index.decls.get(&def)?;
+        let location = self.location(&params.text_document.uri, analysis, decl.name_span)?;
+        Some(GotoDefinitionResponse::Scalar(location))
+    }
+
+    fn references(&self, params: &ReferenceParams) -> Option<Vec<Location>> {
+        let position = &params.text_document_position;
+        let (analysis, offset) = self.at_position(position)?;
+        let index = &analysis.index;
+        let (def, _) = index.def_at(offset)?;
+        let decl = index
+            .decls
+            .get(&def)
+            .filter(|_| params.context.include_declaration)
+            .map(|it| it.name_span);
+        let spans = decl.into_iter().chain(index.refs_to(def).map(|it| it.span));
+        let uri = &position.text_document.uri;
+        Some(
+            spans
+                .filter_map(|span| self.location(uri, analysis, span))
+                .collect(),
+        )
+    }
+
+    /// The signature and doc comment of the declaration that the name under
+    /// the cursor refers to, or the type of the expression under the cursor
+    fn hover(&self, params: &TextDocumentPositionParams) -> Option<Hover> {
+        let (analysis, offset) = self.at_position(params)?;
+        let index = &analysis.index;
+        let (value, span) = match index.def_at(offset) {
+            Some((def, span)) => {
+                let decl = index.decls.get(&def)?;
+                let mut value = match &decl.signature {
+                    Some(signature) => signature.clone(),
+                    None => format!("{}: {}", decl.name, analysis.typeck.local_types.get(&def)?),
+                };
+                match analysis.typeck.const_values.get(&def) {
+                    Some(ConstValue::Int(int)) => value += &format!(" = {}", int),
+                    Some(ConstValue::Bool(bool)) => value += &format!(" = {}", bool),
+                    None => {}
+                }
+                value = format!("```hades\n{}\n```", value);
+                if let Some(doc) = index.doc_comment(decl) {
+                    value = format!("{}\n\n{}", value, doc);
+                }
+                (value, span)
+            }
+            None => {
+                let (id, span) = index.expr_at(offset)?;
+                let ty = analysis.typeck.expr_types.get(&id)?;
+                (format!("```hades\n{}\n```", ty), span)
+            }
+        };
+        let text = &self.documents[&params.text_document.uri].text;
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(convert::range(text, span.start, span.end)),
+        })
+    }
+
+    fn prepare_rename(
+        &self,
+        params: &TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>, String> {
+        let Some((analysis, offset)) = self.at_position(params) else {
+            return Ok(None);
+        };
+        let span = rename::prepare_rename(analysis, offset)?;
+        let text = &self.documents[&params.text_document.uri].text;
+        let range = convert::range(text, span.start, span.end);
+        Ok(Some(PrepareRenameResponse::Range(range)))
+    }
+
+    /// The edits renaming a declaration in the document and in the other files
+    /// of its crate
+    fn rename(&self, params: &RenameParams) -> Result<Option<WorkspaceEdit>, String> {
+        let position = &params.text_document_position;
+        let Some((analysis, offset)) = self.at_position(position) else {
+            return Ok(None);
+        };
+        let uri = &position.text_document.uri;
+        let edits = rename::rename(analysis, offset, &params.new_name)?;
+        let mut changes = HashMap::<Url, Vec<TextEdit>>::new();
+        for (span, new_text) in edits {
+            let Some(location) = self.location(uri, analysis, span) else {
+                continue;
+            };
+            changes.entry(location.uri).or_default().push(TextEdit {
+                range: location.range,
+                new_text,
+            });
+        }
+        Ok(Some(WorkspaceEdit::new(changes)))
+    }
+
+    /// An edit replacing the whole document with its formatted text, or no
+    /// edits if it's formatted already. Documents with syntax errors aren't
+    /// formatted.
+    fn format(&self, uri: &Url) -> Option<Vec<TextEdit>> {
+        let text = &self.documents.get(uri)?.text;
+        let formatted = formatter::format(text, path_of(uri)).ok()?;
+        if formatted == *text {
+            return Some(vec![]);
+        }
+        Some(vec![TextEdit {
+            range: convert::range(text, 0, text.chars().count()),
+            new_text: formatted,
+        }])
+    }
+
+    /// The location of a span in the document with the given URI or in another
+    /// file of its crate
+    fn location(&self, uri: &Url, analysis: &Analysis, span: Span) -> Option<Location> {
+        let (file, start) = analysis.source_map.lookup(span.start);
+        let uri = if file.start == 0 {
+            uri.clone()
+        } else {
+            Url::from_file_path(&file.path).ok()?
+        };
+        let range = convert::range(&file.text, start, span.end - file.start);
+        Some(Location { uri, range })
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> Result<()> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let Some(params) = notification_params::<DidOpenTextDocumentParams>(notification)
+                else {
+                    return Ok(());
+                };
+                let document = params.text_document;
+                let path = path_of(&document.uri);
+                let parsed = ParsedFile::parse(&document.text, path.clone());
+                let mut edited = self.edited();
+                edited.insert(path.clone(), document.text.clone());
+                let root = loader::crate_root(&path, &edited);
+                let analysis = analyze(&root, &document.text, &parsed, edited);
+                self.documents.insert(
+                    document.uri.clone(),
+                    Document {
+                        text: document.text,
+                        version: document.version,
+                        root: root.clone(),
+                        parsed,
+                        analysis,
+                    },
+                );
+                self.reanalyze_crate(&root, &document.uri)?;
+                self.publish_diagnostics(document.uri)
+            }
+            DidChangeTextDocument::METHOD => {
+                let Some(params) = notification_params::<DidChangeTextDocumentParams>(notification)
+                else {
+                    return Ok(());
+                };
+                let uri = params.text_document.uri;
+                let Some(document) = self.documents.get_mut(&uri) else {
+                    return Ok(());
+                };
+                let path = path_of(&uri);
+                for change in params.content_changes {
+                    match apply_change(&mut document.text, change) {
+                        Some(edit) => document.parsed.reparse(&document.text, edit),
+                        None => document.parsed = ParsedFile::parse(&document.text, path.clone()),
+                    }
+                }
+                document.version = params.text_document.version;
+                let edited = self.edited();
+                let document = self.documents.get_mut(&uri).unwrap();
+                let root = document.root.clone();
+                document.analysis = analyze(&root, &document.text, &document.parsed, edited);
+                self.reanalyze_crate(&root, &uri)?;
+                self.publish_diagnostics(uri)
+            }
+            DidCloseTextDocument::METHOD => {
+                let Some(params) = notification_params::<DidCloseTextDocumentParams>(notification)
+                else {
+                    return Ok(());
+                };
+                let uri = params.text_document.uri;
+                if let Some(document) = self.documents.remove(&uri) {
+                    // The file on disk replaces the text of the document
+                    self.reanalyze_crate(&document.root, &uri)?;
+                }
+                // Clears the diagnostics shown for the document
+                self.notify::<PublishDiagnostics>(PublishDiagnosticsParams {
+                    uri,
+                    diagnostics: vec![],
+                    version: None,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The texts of the open documents by their paths
+    fn edited(&self) -> HashMap<PathBuf, String> {
+        self.documents
+            .iter()
+            .map(|(uri, it)| (path_of(uri), it.text.clone()))
+            .collect()
+    }
+
+    /// Analyzes the other open documents of a crate again after one of its
+    /// documents changed, which can change what's found in them
+    fn reanalyze_crate(&mut self, root: &Path, changed: &Url) -> Result<()> {
+        let uris = self
+            .documents
+            .iter()
+            .filter(|(uri, it)| it.root == root && *uri != changed)
+            .map(|(uri, _)| uri.clone())
+            .collect::<Vec<_>>();
+        for uri in uris {
+            let edited = self.edited();
+            let document = self.documents.get_mut(&uri).unwrap();
+            document.analysis = analyze(root, &document.text, &document.parsed, edited);
+            self.publish_diagnostics(uri)?;
+        }
+        Ok(())
+    }
+
+    fn publish_diagnostics(&self, uri: Url) -> Result<()> {
+        let document = &self.documents[&uri];
+        let diagnostics = match &document.analysis {
+            Ok(analysis) => analysis
+                .diagnostics
+                .iter()
+                .filter_map(|diagnostic| {
+                    // Diagnostics in the files of modules are shown when they're open
+                    let (file, start) = analysis.source_map.lookup(diagnostic.span.start);
+                    if file.start != 0 {
+                        return None;
+                    }
+                    let range = convert::range(&document.text, start, diagnostic.span.end);
+                    Some(lsp_diagnostic(range, diagnostic))
+                })
+                .collect(),
+            // Panics have no span, so they're shown at the start
+            Err(message) => vec![lsp_types::Diagnostic {
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("hades".to_string()),
+                message: message.clone(),
+                ..lsp_types::Diagnostic::default()
+            }],
+        };
+        let version = Some(document.version);
+        self.notify::<PublishDiagnostics>(PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version,
+        })
+    }
+
+    fn notify<N: lsp_types::notification::Notification>(&self, params: N::Params) -> Result<()> {
+        let notification = Notification::new(N::METHOD.to_string(), params);
+        self.send(notification.into())
+    }
+
+    fn send(&self, message: Message) -> Result<()> {
+        self.connection.sender.send(message)?;
+        Ok(())
+    }
+}
+
+/// The params of a notification. Invalid ones are logged, and the notification
+/// is ignored since there's nobody to answer.
+fn notification_params<P: DeserializeOwned>(notification: Notification) -> Option<P> {
+    serde_json::from_value(notification.params)
+        .map_err(|err| eprintln!("Ignoring `{}`: {}", notification.method, err))
+        .ok()
+}
+
+/// Applies an edit to a document, returning the span of the text it replaced;
+/// Edits without a range replace the whole text
+fn apply_change(text: &mut String, change: TextDocumentContentChangeEvent) -> Option<Span> {
+    let Some(range) = change.range else {
+        *text = change.text;
+        return None;
+    };
+    let (start, end) = (
+        convert::offset(text, range.start),
+        convert::offset(text, range.end),
+    );
+    let (start_byte, end_byte) = (
+        convert::byte_index(text, start),
+        convert::byte_index(text, end),
+    );
+    text.replace_range(start_byte..end_byte, &change.text);
+    Some(Span { start, end })
+}
+
+/// Documents that aren't files, like unsaved ones, are checked as if they were
+/// at their URI's path
+fn path_of(uri: &Url) -> PathBuf {
+    uri.to_file_path()
+        .unwrap_or_else(|_| PathBuf::from(uri.path()))
+}
+
+/// Resolves and type checks the crate of a parsed document from its root,
+/// loading the files of its modules from `edited` or from disk. Syntax errors
+/// are among the diagnostics, along with those of monomorphizing a crate
+/// without errors, and the message of a panic in one of the passes is
+/// returned instead.
+fn analyze(
+    root: &Path,
+    text: &str,
+    parsed: &ParsedFile,
+    edited: HashMap<PathBuf, String>,
+) -> Result<Analysis, String> {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let (deps, libs) = crate_libraries(root);
+        let result = loader::load_parsed(root, text.to_string(), parsed, edited, deps, &libs);
+        let mut resolve_result = resolve(&result.source_file);
+        let mut typeck_result = typeck(&result.source_file, &resolve_result);
+        let index = Index::new(&result.source_file, &result.source_map, &resolve_result);
+        let mut diagnostics = result
+            .diagnostics
+            .into_iter()
+            .chain(std::mem::take(&mut resolve_result.diagnostics))
+            .chain(std::mem::take(&mut typeck_result.diagnostics))
+            .collect::<Vec<_>>();
+        // Monomorphization expects a crate without errors
+        if !diagnostics.iter().any(|it| it.is_error()) {
+            let mono = mono::collect(&result.source_file, &resolve_result, &typeck_result);
+            diagnostics.extend(mono.diagnostics);
+        }
+        Analysis {
+            source_file: result.source_file,
+            source_map: result.source_map,
+            diagnostics,
+            resolve: resolve_result,
+            typeck: typeck_result,
+            index,
+        }
+    }))
+    .map_err(|payload| {
+        payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|it| it.to_string()))
+            .unwrap_or_else(|| "Internal compiler error".to_string())
+    })
+}
+
+/// The names of the libraries that the crate with its root at `root` depends
+/// on, and the libraries it can use, from the manifest of its package like
+/// [build::build_package] loads them. Crates outside of packages, or in ones
+/// whose manifests can't be loaded, use no libraries.
+fn crate_libraries(root: &Path) -> (Vec<Ident>, Vec<Library>) {
+    let Some(dir) = root.ancestors().find(|it| it.join(MANIFEST_NAME).exists()) else {
+        return (vec![], vec![]);
+    };
+    let Ok(packages) = manifest::load_packages(dir) else {
+        return (vec![], vec![]);
+    };
+    let package = packages.last().expect("The root package is loaded");
+    let mut libraries = build::libraries(&packages);
+    let mut deps = package.deps();
+    // The library of the package is a crate of its own to the files in it,
+    // and a dependency of its binaries
+    if let Some(lib) = libraries.pop_if(|it| it.name == *package.name()) {
+        let entry = lib.entry.canonicalize();
+        if root
+            .canonicalize()
+            .is_ok_and(|root| entry.is_ok_and(|it| it == root))
+        {
+            return (lib.deps, libraries);
+        }
+        deps.push(lib.name.clone());
+        libraries.push(lib);
+    }
+    (deps, libraries)
+}
+
+/// The items among `items` and the items of their modules that start within
+/// the first `len` chars, which are those of the first file
+fn document_items(items: &[Item], len: usize) -> Option<&[Item]> {
+    if items.first().is_some_and(|it| it.span().start <= len) {
+        return Some(items);
+    }
+    items.iter().find_map(|item| match &item.kind {
+        ItemKind::Mod(m) => document_items(&m.items, len),
+        _ => None,
+    })
+}
+
+fn lsp_diagnostic(range: Range, diagnostic: &Diagnostic) -> lsp_types::Diagnostic {
+    let severity = match diagnostic.severity {
+        Severity::Error => DiagnosticSeverity::ERROR,
+        Severity::Warning => DiagnosticSeverity::WARNING,
+    };
+    lsp_types::Diagnostic {
+        range,
+        severity: Some(severity),
+        code: diagnostic
+            .lint
+            .map(|it| NumberOrString::String(it.to_string())),
+        source: Some("hades".to_string()),
+        message: diagnostic.message.clone(),
+        ..lsp_types::Diagnostic::default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{thread::JoinHandle, time::Duration};
+
+    use lsp_server::RequestId;
+    use lsp_types::{
+        notification::{Exit, Initialized},
+        request::{Initialize, Shutdown},
+        InitializeParams, InitializedParams, Position, ReferenceContext, TextDocumentIdentifier,
+        TextDocumentItem, VersionedTextDocumentIdentifier,
+    };
+    use serde_json::Value;
+
+    use super::*;
+
+    /// A client talking to a server running on another thread
+    struct TestClient {
+        connection: Connection,
+        server: JoinHandle<Result<()>>,
+        next_id: i32,
+    }
+
+    impl TestClient {
+        fn start() -> Self {
+            let (client, server) = Connection::memory();
+            let server = std::thread::spawn(move || run(server));
+            let mut client = TestClient {
+                connection: client,
+                server,
+                next_id: 0,
+            };
+            let result = client.request::<Initialize>(InitializeParams::default());
+            assert_eq!(result["capabilities"]["textDocumentSync"], 2);
+            client.notify::<Initialized>(InitializedParams {});
+            client
+        }
+
+        fn request<R: lsp_types::request::Request>(&mut self, params: R::Params) -> Value {
+            self.next_id += 1;
+            let id = RequestId::from(self.next_id);
+            let request = Request::new(id.clone(), R::METHOD.to_string(), params);
+            self.connection.sender.send(request.into()).unwrap();
+            match self.recv() {
+                Message::Response(response) => {
+                    assert_eq!(response.id, id);
+                    response.result.unwrap_or_else(|| {
+                        serde_json::to_value(response.error.unwrap().code).unwrap()
+                    })
+                }
+                message => panic!("Expected a response, got {:?}", message),
+            }
+        }
+
+        fn notify<N: lsp_types::notification::Notification>(&self, params: N::Params) {
+            let notification = Notification::new(N::METHOD.to_string(), params);
+            self.connection.sender.send(notification.into()).unwrap();
+        }
+
+        fn recv(&self) -> Message {
+            self.connection
+                .receiver
+                .recv_timeout(Duration::from_secs(10))
+                .expect("The server should respond")
+        }
+
+        /// The next diagnostics published by the server
+        fn diagnostics(&self) -> PublishDiagnosticsParams {
+            match self.recv() {
+                Message::Notification(it) if it.method == PublishDiagnostics::METHOD => {
+                    serde_json::from_value(it.params).unwrap()
+                }
+                message => panic!("Expected diagnostics, got {:?}", message),
+            }
+        }
+
+        fn open(&self, uri: &Url, text: &str) {
+            self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem {
+                    uri: uri.clone(),
+                    language_id: "hades".to_string(),
+                    version: 1,
+                    text: text.to_string(),
+                },
+            });
+        }
+
+        fn change(&self, uri: &Url, version: i32, changes: Vec<TextDocumentContentChangeEvent>) {
+            self.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier {
+                    uri: uri.clone(),
+                    version,
+                },
+                content_changes: changes,
+            });
+        }
+
+        fn shutdown(mut self) {
+            assert_eq!(self.request::<Shutdown>(()), Value::Null);
+            self.notify::<Exit>(());
+            self.server.join().unwrap().unwrap();
+        }
+    }
+
+    fn at(uri: &Url, line: u32, character: u32) -> TextDocumentPositionParams {
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            position: Position::new(line, character),
+        }
+    }
+
+    fn hover(client: &mut TestClient, uri: &Url, line: u32, character: u32) -> Value {
+        client.request::<HoverRequest>(HoverParams {
+            text_document_position_params: at(uri, line, character),
+            work_done_progress_params: Default::default(),
+        })["contents"]["value"]
+            .clone()
+    }
+
+    fn uri(path: &str) -> Url {
+        Url::from_file_path(std::env::temp_dir().join(path)).unwrap()
+    }
+
+    fn messages(params: &PublishDiagnosticsParams) -> Vec<&str> {
+        params
+            .diagnostics
+            .iter()
+            .map(|it| it.message.as_str())
+            .collect()
+    }
+
+    fn edit(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range {
+                start: Position::new(start.0, start.1),
+                end: Position::new(end.0, end.1),
+            }),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn publishes_diagnostics_when_documents_open_and_change() {
+        let client = TestClient::start();
+        let uri = uri("lsp-diagnostics.hds");
+        client.open(&uri, "fn main() {\n    x;\n}\n");
+        let params = client.diagnostics();
+        assert_eq!(params.uri, uri);
+        assert_eq!(params.version, Some(1));
+        assert_eq!(messages(&params), vec!["Unbound variable `x`"]);
+        let diagnostic = &params.diagnostics[0];
+        assert_eq!(
+            diagnostic.range,
+            Range::new(Position::new(1, 4), Position::new(1, 5))
+        );
+        assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::ERROR));
+
+        // Edits are applied in order
+        client.change(
+            &uri,
+            2,
+            vec![
+                edit((1, 4), (1, 5), "let y = 1"),
+                edit((1, 13), (1, 14), "; y;"),
+            ],
+        );
+        let params = client.diagnostics();
+        assert_eq!(params.version, Some(2));
+        assert_eq!(messages(&params), Vec::<&str>::new());
+        client.shutdown();
+    }
+
+    #[test]
+    fn publishes_the_errors_of_monomorphizing() {
+        let client = TestClient::start();
+        let uri = uri("lsp-mono.hds");
+        client.open(
+            &uri,
+            "fn nest<T>(x: T) { nest((x,)) }\nfn main() { nest(1) }",
+        );
+        let params = client.diagnostics();
+        assert_eq!(params.diagnostics.len(), 1);
+        assert!(
+            params.diagnostics[0]
+                .message
+                .starts_with("Reached the type depth limit"),
+            "{:?}",
+            params
+        );
+        // Fixing the body clears the error
+        client.change(&uri, 2, vec![edit((0, 24), (0, 28), "x")]);
+        assert_eq!(messages(&client.diagnostics()), Vec::<&str>::new());
+        client.shutdown();
+    }
+
+    #[test]
+    fn reparses_edited_documents_like_opened_ones() {
+        let client = TestClient::start();
+        let (uri, reopened) = (uri("lsp-reparse.hds"), uri("lsp-reparse-reopened.hds"));
+        let text =
+            "fn one() -> i32 { 1 }\n\nfn two() -> i32 { one() + 1 }\n\nfn main() { two(); }\n";
+        client.open(&uri, text);
+        assert_eq!(messages(&client.diagnostics()), Vec::<&str>::new());
+        // Unclosing a block makes the items after it part of it, until it's closed again
+        client.change(&uri, 2, vec![edit((0, 20), (0, 21), "")]);
+        let edited = client.diagnostics();
+        client.change(
+            &uri,
+            3,
+            vec![edit((2, 18), (2, 23), "on()"), edit((0, 20), (0, 20), "}")],
+        );
+        let fixed = client.diagnostics();
+        assert_eq!(messages(&fixed), vec!["Unbound variable `on`"]);
+
+        client.open(&reopened, &text.replacen("1 }", "1 ", 1));
+        let params = client.diagnostics();
+        assert_eq!(params.diagnostics, edited.diagnostics);
+        assert!(!params.diagnostics.is_empty());
+        client.open(&reopened, &text.replacen("one() + 1", "on() + 1", 1));
+        assert_eq!(client.diagnostics().diagnostics, fixed.diagnostics);
+        client.shutdown();
+    }
+
+    #[test]
+    fn reports_syntax_errors_and_recovers() {
+        let mut client = TestClient::start();
+        let uri = uri("lsp-syntax.hds");
+        client.open(&uri, "fn main( {}");
+        let params = client.diagnostics();
+        assert_eq!(params.diagnostics.len(), 1);
+        assert!(
+            params.diagnostics[0].message.starts_with("Parse error"),
+            "{:?}",
+            params
+        );
+        assert_eq!(
+            params.diagnostics[0].range,
+            Range::new(Position::new(0, 9), Position::new(0, 10))
+        );
+        let full_text = TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "fn main() {}".to_string(),
+        };
+        client.change(&uri, 2, vec![full_text]);
+        assert_eq!(messages(&client.diagnostics()), Vec::<&str>::new());
+
+        // Closing a document clears its diagnostics
+        client.notify::<DidCloseTextDocument>(DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+        });
+        let params = client.diagnostics();
+        assert_eq!((params.uri, params.version), (uri, None));
+        // Requests the server doesn't support are answered with an error
+        let code = client.request::<lsp_types::request::WorkspaceSymbolRequest>(
+            lsp_types::WorkspaceSymbolParams::default(),
+        );
+        assert_eq!(code, ErrorCode::MethodNotFound as i32);
+        client.shutdown();
+    }
+
+    #[test]
+    fn answers_invalid_params_with_an_error() {
+        let client = TestClient::start();
+        // Invalid notifications are ignored, since there's nobody to answer
+        let open = Notification::new(DidOpenTextDocument::METHOD.to_string(), "fn main() {}");
+        client.connection.sender.send(open.into()).unwrap();
+
+        let hover = Request::new(RequestId::from(100), HoverRequest::METHOD.to_string(), 1);
+        client.connection.sender.send(hover.into()).unwrap();
+        match client.recv() {
+            Message::Response(response) => {
+                assert_eq!(response.id, RequestId::from(100));
+                assert_eq!(
+                    response.error.unwrap().code,
+                    ErrorCode::InvalidParams as i32
+                );
+            }
+            message => panic!("Expected a response, got {:?}", message),
+        }
+        // The server keeps going afterwards
+        client.shutdown();
+    }
+
+    #[test]
+    fn loads_modules_of_documents_from_disk() {
+        let dir = std::env::temp_dir().join(format!("hades-lsp-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("util.hds"), "pub fn f() { y; }").unwrap();
+        let client = TestClient::start();
+        let uri = Url::from_file_path(dir.join("main.hds")).unwrap();
+        client.open(&uri, "mod util;\nfn main() { util::f(); util::g(); }");
+        // Errors in other files are published when those files are opened
+        let params = client.diagnostics();
+        assert_eq!(messages(&params), vec!["Unresolved path `util::g`"]);
+        client.shutdown();
+    }
+
+    #[test]
+    fn analyzes_module_files_from_the_root_of_their_crate() {
+        let dir = std::env::temp_dir().join(format!("hades-lsp-root-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let main =
+            "mod shapes;\nmod io {\n    pub fn print_digit(d: i32) {}\n}\nfn main() { x; }\n";
+        std::fs::write(dir.join("main.hds"), main).unwrap();
+        let mut client = TestClient::start();
+        let shapes = Url::from_file_path(dir.join("shapes.hds")).unwrap();
+        client.open(
+            &shapes,
+            "use crate::io::print_digit;\npub fn f() {\n    print_digit(1);\n    y;\n}\n",
+        );
+        // Only the diagnostics of the document are published
+        let params = client.diagnostics();
+        assert_eq!(params.uri, shapes);
+        assert_eq!(messages(&params), vec!["Unbound variable `y`"]);
+        let params = GotoDefinitionParams {
+            text_document_position_params: at(&shapes, 2, 6),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let location: Location =
+            serde_json::from_value(client.request::<GotoDefinition>(params)).unwrap();
+        assert_eq!(
+            location.uri,
+            Url::from_file_path(dir.join("main.hds")).unwrap()
+        );
+        assert_eq!(
+            location.range,
+            Range::new(Position::new(2, 11), Position::new(2, 22))
+        );
+        // The outline has the items of the module
+        let result = client.request::<DocumentSymbolRequest>(DocumentSymbolParams {
+            text_document: TextDocumentIdentifier {
+                uri: shapes.clone(),
+            },
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        let symbols: Vec<lsp_types::DocumentSymbol> = serde_json::from_value(result).unwrap();
+        let names = symbols
+            .iter()
+            .map(|it| it.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["f"]);
+
+        // Opening another file of the crate analyzes the open ones again
+        let main_uri = Url::from_file_path(dir.join("main.hds")).unwrap();
+        client.open(&main_uri, main);
+        let params = client.diagnostics();
+        assert_eq!(params.uri, shapes);
+        assert_eq!(messages(&params), vec!["Unbound variable `y`"]);
+        let params = client.diagnostics();
+        assert_eq!(params.uri, main_uri);
+        assert_eq!(messages(&params), vec!["Unbound variable `x`"]);
+        client.shutdown();
+    }
+
+    const POINTS: &str = "\
+/// A point on the plane
+struct Point { x: i32, y: i32 }
+
+/// Adds two points
+fn add(a: Point, b: Point) -> Point {
+    let p = Point { x: a.x + b.x, y: a.y + b.y };
+    p
+}
+
+const ORIGIN_X: i32 = 2 * 0;
+
+fn main() {
+    add(Point { x: ORIGIN_X, y: 0 }, Point { x: 1, y: 2 });
+}
+";
+
+    #[test]
+    fn finds_definitions_and_references() {
+        let mut client = TestClient::start();
+        let uri = uri("lsp-definitions.hds");
+        client.open(&uri, POINTS);
+        client.diagnostics();
+        let definition = |client: &mut TestClient, line, character| {
+            let params = GotoDefinitionParams {
+                text_document_position_params: at(&uri, line, character),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            };
+            let result = client.request::<GotoDefinition>(params);
+            serde_json::from_value::<Option<Location>>(result)
+                .unwrap()
+                .map(|it| (it.uri, it.range))
+        };
+        let range =
+            |line, start, end| Range::new(Position::new(line, start), Position::new(line, end));
+        // `add` in `main`
+        assert_eq!(
+            definition(&mut client, 12, 5),
+            Some((uri.clone(), range(4, 3, 6)))
+        );
+        // `p` at the end of `add`
+        assert_eq!(
+            definition(&mut client, 6, 4),
+            Some((uri.clone(), range(5, 8, 9)))
+        );
+        // `b` in a field access
+        assert_eq!(
+            definition(&mut client, 5, 29),
+            Some((uri.clone(), range(4, 17, 18)))
+        );
+        assert_eq!(definition(&mut client, 11, 0), None);
+
+        let references = |client: &mut TestClient, line, character, include_declaration| {
+            let params = ReferenceParams {
+                text_document_position: at(&uri, line, character),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: ReferenceContext {
+                    include_declaration,
+                },
+            };
+            let result = client.request::<References>(params);
+            serde_json::from_value::<Vec<Location>>(result)
+                .unwrap()
+                .into_iter()
+                .map(|it| it.range)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            references(&mut client, 1, 8, false),
+            vec![
+                range(4, 10, 15),
+                range(4, 20, 25),
+                range(4, 30, 35),
+                range(5, 12, 17),
+                range(12, 8, 13),
+                range(12, 37, 42),
+            ]
+        );
+        assert_eq!(
+            references(&mut client, 12, 19, true),
+            vec![range(9, 6, 14), range(12, 19, 27)]
+        );
+        client.shutdown();
+    }
+
+    #[test]
+    fn finds_definitions_in_module_files() {
+        let dir = std::env::temp_dir().join(format!("hades-lsp-def-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("util.hds"), "\n\npub fn f() {}").unwrap();
+        let mut client = TestClient::start();
+        let uri = Url::from_file_path(dir.join("main.hds")).unwrap();
+        client.open(&uri, "mod util;\nfn main() { util::f(); }");
+        client.diagnostics();
+        let params = GotoDefinitionParams {
+            text_document_position_params: at(&uri, 1, 18),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let location: Location =
+            serde_json::from_value(client.request::<GotoDefinition>(params)).unwrap();
+        let util = Url::from_file_path(dir.join("util.hds").canonicalize().unwrap()).unwrap();
+        assert_eq!(location.uri, util);
+        assert_eq!(
+            location.range,
+            Range::new(Position::new(2, 7), Position::new(2, 8))
+        );
+        client.shutdown();
+    }
+
+    #[test]
+    fn uses_the_libraries_of_the_package() {
+        let mut client = TestClient::start();
+        let path = Path::new("test/packages/app/src/main.hds")
+            .canonicalize()
+            .unwrap();
+        let uri = Url::from_file_path(&path).unwrap();
+        client.open(&uri, &std::fs::read_to_string(&path).unwrap());
+        assert_eq!(messages(&client.diagnostics()), Vec::<&str>::new());
+        // `print_digit` in `main`
+        let params = GotoDefinitionParams {
+            text_document_position_params: at(&uri, 4, 6),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let location: Location =
+            serde_json::from_value(client.request::<GotoDefinition>(params)).unwrap();
+        let lib = Path::new("test/packages/digits/src/lib.hds")
+            .canonicalize()
+            .unwrap();
+        assert_eq!(location.uri, Url::from_file_path(lib).unwrap());
+        assert_eq!(
+            location.range,
+            Range::new(Position::new(2, 7), Position::new(2, 18))
+        );
+        client.shutdown();
+    }
+
+    #[test]
+    fn renames_across_the_files_of_modules() {
+        let dir = std::env::temp_dir().join(format!("hades-lsp-rename-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("util.hds"), "pub fn f() {}\npub fn g() {}\n").unwrap();
+        let mut client = TestClient::start();
+        let uri = Url::from_file_path(dir.join("main.hds")).unwrap();
+        client.open(
+            &uri,
+            "mod util;\nuse util::f;\nfn main() { f(); util::f(); }",
+        );
+        client.diagnostics();
+
+        let range = client.request::<PrepareRenameRequest>(at(&uri, 2, 23));
+        assert_eq!(
+            serde_json::from_value::<Range>(range).unwrap(),
+            Range::new(Position::new(2, 23), Position::new(2, 24))
+        );
+        let mut rename = |new_name: &str| {
+            client.request::<Rename>(RenameParams {
+                text_document_position: at(&uri, 2, 12),
+                new_name: new_name.to_string(),
+                work_done_progress_params: Default::default(),
+            })
+        };
+        let edit: WorkspaceEdit = serde_json::from_value(rename("h")).unwrap();
+        let mut changes = edit.changes.unwrap();
+        let ranges = |edits: Vec<TextEdit>| {
+            edits
+                .into_iter()
+                .map(|it| (it.range.start.line, it.range.start.character, it.new_text))
+                .collect::<Vec<_>>()
+        };
+        let util = Url::from_file_path(dir.join("util.hds").canonicalize().unwrap()).unwrap();
+        assert_eq!(ranges(changes.remove(&util).unwrap()), [(0, 7, "h".into())]);
+        assert_eq!(
+            ranges(changes.remove(&uri).unwrap()),
+            [
+                (1, 10, "h".into()),
+                (2, 12, "h".into()),
+                (2, 23, "h".into())
+            ]
+        );
+        assert!(changes.is_empty());
+        // `g` is declared alongside `f` in the module
+        assert_eq!(rename("g"), ErrorCode::RequestFailed as i32);
+        let main = client.request::<PrepareRenameRequest>(at(&uri, 2, 4));
+        assert_eq!(main, ErrorCode::RequestFailed as i32);
+        client.shutdown();
+    }
+
+    #[test]
+    fn renames_from_the_files_of_modules() {
+        let dir =
+            std::env::temp_dir().join(format!("hades-lsp-rename-module-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let main =
+            "mod shapes;\nfn twice(x: i32) -> i32 { x + x }\nfn main() { shapes::area(2); }\n";
+        std::fs::write(dir.join("main.hds"), main).unwrap();
+        let mut client = TestClient::start();
+        let uri = Url::from_file_path(dir.join("shapes.hds")).unwrap();
+        client.open(
+            &uri,
+            "pub fn area(side: i32) -> i32 {\n    crate::twice(side)\n}\n",
+        );
+        assert_eq!(messages(&client.diagnostics()), Vec::<&str>::new());
+        let mut rename = |line, character, new_name: &str| {
+            client.request::<Rename>(RenameParams {
+                text_document_position: at(&uri, line, character),
+                new_name: new_name.to_string(),
+                work_done_progress_params: Default::default(),
+            })
+        };
+        let ranges = |edit: Value| {
+            let changes = serde_json::from_value::<WorkspaceEdit>(edit)
+                .unwrap()
+                .changes;
+            let mut ranges = changes
+                .unwrap()
+                .into_iter()
+                .flat_map(|(uri, edits)| {
+                    let name = uri
+                        .path_segments()
+                        .unwrap()
+                        .next_back()
+                        .unwrap()
+                        .to_string();
+                    edits.into_iter().map(move |it| {
+                        (name.clone(), it.range.start.line, it.range.start.character)
+                    })
+                })
+                .collect::<Vec<_>>();
+            ranges.sort();
+            ranges
+        };
+        // A function of the module used in the crate root
+        assert_eq!(
+            ranges(rename(0, 7, "square")),
+            [("main.hds".into(), 2, 20), ("shapes.hds".into(), 0, 7)]
+        );
+        // A function of the crate root used in the module
+        assert_eq!(
+            ranges(rename(1, 12, "double")),
+            [("main.hds".into(), 1, 3), ("shapes.hds".into(), 1, 11)]
+        );
+        // `main` is declared alongside `twice` in the crate root
+        assert_eq!(rename(1, 12, "main"), ErrorCode::RequestFailed as i32);
+        client.shutdown();
+    }
+
+    #[test]
+    fn shows_signatures_types_and_doc_comments_on_hover() {
+        let mut client = TestClient::start();
+        let uri = uri("lsp-hover.hds");
+        client.open(&uri, POINTS);
+        client.diagnostics();
+        assert_eq!(
+            hover(&mut client, &uri, 12, 5),
+            "```hades\nfn add(a: Point, b: Point) -> Point\n```\n\nAdds two points"
+        );
+        assert_eq!(
+            hover(&mut client, &uri, 1, 8),
+            "```hades\nstruct Point { x: i32, y: i32 }\n```\n\nA point on the plane"
+        );
+        assert_eq!(hover(&mut client, &uri, 6, 4), "```hades\np: Point\n```");
+        assert_eq!(
+            hover(&mut client, &uri, 12, 20),
+            "```hades\nconst ORIGIN_X: i32 = 0\n```"
+        );
+        // The type of the expression `a.x + b.x`
+        assert_eq!(hover(&mut client, &uri, 5, 27), "```hades\ni32\n```");
+        assert_eq!(hover(&mut client, &uri, 11, 0), Value::Null);
+        client.shutdown();
+    }
+
+    #[test]
+    fn highlights_outlines_and_folds_documents() {
+        let mut client = TestClient::start();
+        let uri = uri("lsp-outline.hds");
+        let text = "\
+extern \"C\" {
+    fn puts(s: *u8) -> i32;
+}
+
+mod shapes {
+    pub enum Shape { Circle(i32), Square { side: i32 } }
+}
+
+impl shapes::Shape {
+    fn sides(self: *Self) -> i32 { 4 }
+}
+
+fn main() {
+    unsafe {
+        puts(null);
+    }
+}
+";
+        client.open(&uri, text);
+        client.diagnostics();
+        let document = || TextDocumentIdentifier { uri: uri.clone() };
+
+        let result = client.request::<SemanticTokensFullRequest>(SemanticTokensParams {
+            text_document: document(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        let tokens: SemanticTokens = serde_json::from_value(result).unwrap();
+        let legend = highlight::legend();
+        let (mut line, mut character) = (0, 0);
+        let tokens = tokens
+            .data
+            .iter()
+            .map(|token| {
+                if token.delta_line > 0 {
+                    (line, character) = (line + token.delta_line, token.delta_start);
+                } else {
+                    character += token.delta_start;
+                }
+                let start = convert::offset(text, Position::new(line, character));
+                let name = text.chars().skip(start).take(token.length as usize);
+                let ty = legend.token_types[token.token_type as usize].as_str();
+                let modifiers = (0..legend.token_modifiers.len())
+                    .filter(|it| token.token_modifiers_bitset & (1 << it) != 0)
+                    .map(|it| legend.token_modifiers[it].as_str())
+                    .collect::<Vec<_>>();
+                format!(
+                    "{} {} {}",
+                    name.collect::<String>(),
+                    ty,
+                    modifiers.join(",")
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens[..12],
+            [
+                "extern keyword ",
+                "\"C\" string ",
+                "fn keyword ",
+                "puts function declaration,foreign",
+                "s parameter declaration",
+                "u8 type ",
+                "i32 type ",
+                "mod keyword ",
+                "shapes namespace declaration",
+                "pub keyword ",
+                "enum keyword ",
+                "Shape enum declaration",
+            ]
+        );
+        let rest = tokens[12..].iter().map(String::as_str).collect::<Vec<_>>();
+        assert!(rest.contains(&"Shape enum "), "{:?}", rest);
+        assert!(rest.contains(&"Self keyword "), "{:?}", rest);
+        assert!(rest.contains(&"4 number "), "{:?}", rest);
+        assert!(rest.contains(&"puts function foreign"), "{:?}", rest);
+
+        let result = client.request::<DocumentSymbolRequest>(DocumentSymbolParams {
+            text_document: document(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        let symbols: Vec<lsp_types::DocumentSymbol> = serde_json::from_value(result).unwrap();
+        fn outline(symbols: &[lsp_types::DocumentSymbol], depth: usize, out: &mut Vec<String>) {
+            for symbol in symbols {
+                out.push(format!(
+                    "{}{} {:?}",
+                    "  ".repeat(depth),
+                    symbol.name,
+                    symbol.kind
+                ));
+                outline(
+                    symbol.children.as_deref().unwrap_or_default(),
+                    depth + 1,
+                    out,
+                );
+            }
+        }
+        let mut lines = vec![];
+        outline(&symbols, 0, &mut lines);
+        assert_eq!(
+            lines,
+            [
+                "puts Function",
+                "shapes Module",
+                "  Shape Enum",
+                "    Circle EnumMember",
+                "    Square EnumMember",
+                "      side Field",
+                "impl shapes::Shape Object",
+                "  sides Function",
+                "main Function",
+            ]
+        );
+        assert_eq!(symbols[0].detail.as_deref(), Some("fn puts(s: *u8) -> i32"));
+        assert_eq!(
+            symbols[1].selection_range,
+            Range::new(Position::new(4, 4), Position::new(4, 10))
+        );
+
+        let result = client.request::<FoldingRangeRequest>(FoldingRangeParams {
+            text_document: document(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        });
+        let ranges: Vec<lsp_types::FoldingRange> = serde_json::from_value(result).unwrap();
+        let lines = ranges
+            .iter()
+            .map(|it| (it.start_line, it.end_line))
+            .collect::<Vec<_>>();
+        assert_eq!(lines, [(0, 1), (4, 5), (8, 9), (12, 15), (13, 14)]);
+        client.shutdown();
+    }
+
+    #[test]
+    fn completes_names_and_helps_with_signatures_in_broken_documents() {
+        let mut client = TestClient::start();
+        let uri = uri("lsp-completion.hds");
+        let text = "\
+extern \"C\" {
+    fn puts(s: *u8) -> i32;
+}
+
+struct Counter { n: i32 }
+
+impl Counter {
+    fn bump(self: *Counter, by: i32) -> i32 { by }
+}
+
+/// Adds two numbers
+fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+fn main() {
+    let x = 1;
+    let y = add(x, add(1, ;
+    let x = true;
+    let c = Counter { n: 0 };
+    c.bump(
+    x
+}
+";
+        client.open(&uri, text);
+        assert_eq!(messages(&client.diagnostics()).len(), 2);
+        let mut complete = |line, character| {
+            let result = client.request::<Completion>(CompletionParams {
+                text_document_position: at(&uri, line, character),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: None,
+            });
+            let items: Vec<lsp_types::CompletionItem> = serde_json::from_value(result).unwrap();
+            items
+        };
+
+        // The later `x` shadows the earlier one, and `y` was in a broken statement
+        let items = complete(21, 5);
+        let labels = items.iter().map(|it| it.label.as_str()).collect::<Vec<_>>();
+        assert_eq!(labels[..6], ["c", "x", "puts", "Counter", "add", "main"]);
+        assert_eq!(items[1].detail.as_deref(), Some("bool"));
+        assert_eq!(items[2].detail.as_deref(), Some("fn puts(s: *u8) -> i32"));
+        assert!(labels.contains(&"let") && !labels.contains(&"struct"));
+        let labels = complete(12, 5)
+            .into_iter()
+            .map(|it| it.label)
+            .collect::<Vec<_>>();
+        assert_eq!(labels[..3], ["b", "a", "puts"]);
+        let labels = complete(14, 0)
+            .into_iter()
+            .map(|it| it.label)
+            .collect::<Vec<_>>();
+        assert_eq!(labels[..4], ["puts", "Counter", "add", "main"]);
+        assert!(labels.contains(&"struct".to_string()));
+        // Fields and methods aren't completed
+        assert!(complete(20, 6).is_empty());
+
+        let mut signature = |line, character| {
+            client.request::<SignatureHelpRequest>(SignatureHelpParams {
+                context: None,
+                text_document_position_params: at(&uri, line, character),
+                work_done_progress_params: Default::default(),
+            })
+        };
+        let help = signature(17, 18);
+        assert_eq!(
+            help["signatures"][0]["label"],
+            "fn add(a: i32, b: i32) -> i32"
+        );
+        assert_eq!(
+            help["signatures"][0]["parameters"][1]["label"],
+            serde_json::json!([15, 21])
+        );
+        assert_eq!(
+            help["signatures"][0]["documentation"]["value"],
+            "Adds two numbers"
+        );
+        assert_eq!(help["activeParameter"], 1);
+        assert_eq!(signature(17, 23)["activeParameter"], 0);
+        assert_eq!(signature(17, 26)["activeParameter"], 1);
+        // `self` is passed before the arguments of method calls
+        let help = signature(20, 11);
+        assert_eq!(
+            help["signatures"][0]["label"],
+            "fn bump(self: *Counter, by: i32) -> i32"
+        );
+        assert_eq!(help["activeParameter"], 1);
+        assert_eq!(signature(16, 8), Value::Null);
+        client.shutdown();
+    }
+
+    #[test]
+    fn formats_documents() {
+        let mut client = TestClient::start();
+        let uri = uri("lsp-format.hds");
+        client.open(&uri, "fn main() {\n  let x = 1; // one\n  x;\n}\n");
+        client.diagnostics();
+        let format = |client: &mut TestClient| {
+            client.request::<Formatting>(DocumentFormattingParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                options: Default::default(),
+                work_done_progress_params: Default::default(),
+            })
+        };
+        let edits: Vec<TextEdit> = serde_json::from_value(format(&mut client)).unwrap();
+        assert_eq!(edits.len(), 1);
+        assert_eq!(
+            edits[0].range,
+            Range::new(Position::new(0, 0), Position::new(4, 0))
+        );
+        assert_eq!(
+            edits[0].new_text,
+            "fn main() {\n    let x = 1; // one\n    x;\n}\n"
+        );
+
+        client.change(&uri, 2, vec![edit((0, 0), (4, 0), &edits[0].new_text)]);
+        client.diagnostics();
+        assert_eq!(format(&mut client), serde_json::json!([]));
+        // Documents with syntax errors are left alone
+        client.change(&uri, 3, vec![edit((0, 0), (0, 0), "fn (")]);
+        client.diagnostics();
+        assert_eq!(format(&mut client), Value::Null);
+        client.shutdown();
+    }
+}
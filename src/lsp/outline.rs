@@ -0,0 +1,209 @@
+//! The outline of a document, with its items as document symbols, and the
+//! ranges of lines that can be folded
+
+use std::cmp::Reverse;
+
+use libsyntax::{HasMeta, HasSpan, NodeId, Span};
+use lsp_types::{DocumentSymbol, FoldingRange, SymbolKind};
+
+use crate::ast::{
+    self,
+    visit::{self, Visitor},
+    ItemKind,
+};
+
+use super::{
+    convert,
+    index::{DeclKind, Index},
+};
+
+/// The symbols of the items declared in a document, which is the first file of
+/// the index
+pub fn document_symbols(text: &str, items: &[ast::Item], index: &Index) -> Vec<DocumentSymbol> {
+    let outline = Outline {
+        text,
+        len: text.chars().count(),
+        index,
+    };
+    outline.items(items)
+}
+
+struct Outline<'a> {
+    text: &'a str,
+    /// The length of the document, past which spans are in other files
+    len: usize,
+    index: &'a Index,
+}
+
+impl Outline<'_> {
+    fn items(&self, items: &[ast::Item]) -> Vec<DocumentSymbol> {
+        let mut symbols = vec![];
+        for item in items.iter().filter(|it| self.in_document(it.span())) {
+            match &item.kind {
+                // The functions of `extern` blocks are listed with the items around them
+                ItemKind::ForeignMod(foreign_mod) => {
+                    for item in &foreign_mod.items {
+                        symbols.extend(self.decl(item.id(), vec![]));
+                    }
+                }
+                ItemKind::Impl(imp) => {
+                    let name = match &imp.trait_ref {
+                        Some(trait_ref) => format!("impl {} for {}", trait_ref.path, imp.self_ty),
+                        None => format!("impl {}", imp.self_ty),
+                    };
+                    symbols.push(self.symbol(
+                        name,
+                        None,
+                        SymbolKind::OBJECT,
+                        item.span(),
+                        imp.self_ty.span(),
+                        self.items(&imp.items),
+                    ));
+                }
+                ItemKind::Use(_) => {}
+                kind => {
+                    let children = match kind {
+                        ItemKind::Struct(s) => self.fields(&s.data),
+                        ItemKind::Enum(e) => e
+                            .variants
+                            .iter()
+                            .filter_map(|it| self.decl(it.id(), self.fields(&it.data)))
+                            .collect(),
+                        ItemKind::Trait(t) => self.items(&t.items),
+                        ItemKind::Mod(m) => self.items(&m.items),
+                        _ => vec![],
+                    };
+                    symbols.extend(self.decl(item.id(), children));
+                }
+            }
+        }
+        symbols
+    }
+
+    fn fields(&self, data: &ast::VariantData) -> Vec<DocumentSymbol> {
+        match data {
+            ast::VariantData::Struct(fields) => fields
+                .iter()
+                .filter_map(|it| self.decl(it.id(), vec![]))
+                .collect(),
+            ast::VariantData::Tuple(_) | ast::VariantData::Unit => vec![],
+        }
+    }
+
+    /// The symbol of a declaration in the index
+    fn decl(&self, id: NodeId, children: Vec<DocumentSymbol>) -> Option<DocumentSymbol> {
+        let decl = self.index.decls.get(&id)?;
+        let kind = match decl.kind {
+            DeclKind::Fn | DeclKind::ForeignFn => SymbolKind::FUNCTION,
+            DeclKind::Struct | DeclKind::Newtype => SymbolKind::STRUCT,
+            DeclKind::Enum => SymbolKind::ENUM,
+            DeclKind::Variant => SymbolKind::ENUM_MEMBER,
+            DeclKind::Field => SymbolKind::FIELD,
+            DeclKind::Trait => SymbolKind::INTERFACE,
+            DeclKind::Mod => SymbolKind::MODULE,
+            DeclKind::Const => SymbolKind::CONSTANT,
+            DeclKind::Static => SymbolKind::VARIABLE,
+            DeclKind::TyAlias | DeclKind::TyParam => SymbolKind::TYPE_PARAMETER,
+            DeclKind::Param | DeclKind::Local => SymbolKind::VARIABLE,
+        };
+        Some(self.symbol(
+            decl.name.clone(),
+            decl.signature.clone(),
+            kind,
+            &decl.span,
+            &decl.name_span,
+            children,
+        ))
+    }
+
+    fn symbol(
+        &self,
+        name: String,
+        detail: Option<String>,
+        kind: SymbolKind,
+        span: &Span,
+        selection: &Span,
+        children: Vec<DocumentSymbol>,
+    ) -> DocumentSymbol {
+        #[allow(deprecated)]
+        DocumentSymbol {
+            name,
+            detail,
+            kind,
+            tags: None,
+            deprecated: None,
+            range: convert::range(self.text, span.start, span.end),
+            selection_range: convert::range(self.text, selection.start, selection.end),
+            children: Some(children).filter(|it| !it.is_empty()),
+        }
+    }
+
+    /// Whether a span is in the document rather than in the file of a module
+    /// or library
+    fn in_document(&self, span: &Span) -> bool {
+        span.start < self.len
+    }
+}
+
+/// The ranges of blocks and of the bodies of items that span several lines.
+/// A range ends on the line before the closing brace, which stays visible.
+pub fn folding_ranges(text: &str, items: &[ast::Item]) -> Vec<FoldingRange> {
+    let mut collector = FoldingRanges {
+        len: text.chars().count(),
+        spans: vec![],
+    };
+    for item in items {
+        collector.visit_item(item);
+    }
+    let mut ranges = collector
+        .spans
+        .into_iter()
+        .filter_map(|span| {
+            let start_line = convert::position(text, span.start).line;
+            let end_line = convert::position(text, span.end).line.checked_sub(1)?;
+            (end_line > start_line).then_some(FoldingRange {
+                start_line,
+                end_line,
+                ..FoldingRange::default()
+            })
+        })
+        .collect::<Vec<_>>();
+    // An item and its body may start on the same line
+    ranges.sort_by_key(|it| (it.start_line, Reverse(it.end_line)));
+    ranges.dedup_by_key(|it| it.start_line);
+    ranges
+}
+
+struct FoldingRanges {
+    /// The length of the document, past which spans are in other files
+    len: usize,
+    spans: Vec<Span>,
+}
+
+impl FoldingRanges {
+    fn add(&mut self, span: &Span) {
+        if span.end <= self.len {
+            self.spans.push(*span);
+        }
+    }
+}
+
+impl Visitor for FoldingRanges {
+    fn visit_item(&mut self, item: &ast::Item) {
+        match &item.kind {
+            ItemKind::Struct(_)
+            | ItemKind::Enum(_)
+            | ItemKind::Trait(_)
+            | ItemKind::Impl(_)
+            | ItemKind::ForeignMod(_) => self.add(item.span()),
+            ItemKind::Mod(m) if m.inline => self.add(item.span()),
+            _ => {}
+        }
+        visit::walk_item(self, item);
+    }
+
+    fn visit_block(&mut self, block: &ast::Block) {
+        self.add(block.span());
+        visit::walk_block(self, block);
+    }
+}
@@ -0,0 +1,381 @@
+//! Renaming functions, foreign functions, parameters and local variables
+//! everywhere they're used in the files of a crate. A rename is checked
+//! by resolving the renamed files again: it's refused if any name would refer
+//! to something else, or if the new name is already declared alongside it.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use libsyntax::{NodeId, Span};
+
+use crate::{
+    analysis::resolve::{resolve, Res, ResolveResult},
+    lexer::{Lexer, TokenKind},
+    loader,
+};
+
+use super::{
+    index::{Decl, DeclKind},
+    Analysis,
+};
+
+/// The name under the cursor, if it can be renamed
+pub fn prepare_rename(analysis: &Analysis, offset: usize) -> Result<Span, String> {
+    let (span, id) = target(analysis, offset)?;
+    let decl = &analysis.index.decls[&id];
+    // A path like `util::f` is renamed by its last segment
+    Ok(Span {
+        start: span.end - decl.name.chars().count(),
+        end: span.end,
+    })
+}
+
+/// The edits renaming what the name at `offset` refers to, as spans and the
+/// text replacing them
+pub fn rename(
+    analysis: &Analysis,
+    offset: usize,
+    new_name: &str,
+) -> Result<Vec<(Span, String)>, String> {
+    let (_, id) = target(analysis, offset)?;
+    let decl = &analysis.index.decls[&id];
+    if !is_ident(new_name) {
+        return Err(format!("`{}` isn't a valid name", new_name));
+    }
+    if new_name == decl.name {
+        return Ok(vec![]);
+    }
+    if decl.kind == DeclKind::Fn && new_name == "main" && is_root_fn(analysis, id) {
+        return Err("`main` is the entry point of the program".to_string());
+    }
+    if decl.kind == DeclKind::Param && param_names(analysis, decl).contains(new_name) {
+        return Err(format!("A parameter is already named `{}`", new_name));
+    }
+
+    let name_len = decl.name.chars().count();
+    let mut edits = vec![(decl.name_span, new_name.to_string())];
+    // Uses through an import under another name keep that name
+    let chars = analysis.index.chars();
+    for it in analysis.index.refs_to(id) {
+        let span = Span {
+            start: it.span.end - name_len,
+            end: it.span.end,
+        };
+        if chars[span.start..span.end]
+            .iter()
+            .copied()
+            .eq(decl.name.chars())
+        {
+            edits.push((span, new_name.to_string()));
+        }
+    }
+    edits.sort_by_key(|(span, _)| span.start);
+    check(analysis, id, &decl.name, new_name, &edits)?;
+
+    // Foreign functions are linked by their name
+    let has_symbol = analysis.typeck.attrs.symbols.contains_key(&id);
+    if decl.kind == DeclKind::ForeignFn && !has_symbol {
+        let start = decl.span.start;
+        let attr = format!(
+            "#[link_name = \"{}\"]{}",
+            decl.name,
+            separator(chars, start)
+        );
+        edits.push((Span { start, end: start }, attr));
+        edits.sort_by_key(|(span, _)| span.start);
+    }
+    Ok(edits)
+}
+
+/// The name at `offset` and what it declares or refers to
+fn target(analysis: &Analysis, offset: usize) -> Result<(Span, NodeId), String> {
+    let index = &analysis.index;
+    let (id, span) = index
+        .def_at(offset)
+        .ok_or_else(|| "There's no name to rename here".to_string())?;
+    let decl = index
+        .decls
+        .get(&id)
+        .ok_or("There's no name to rename here")?;
+    match decl.kind {
+        DeclKind::Fn => {
+            let typeck = &analysis.typeck;
+            if typeck.fn_impls.contains_key(&id) || typeck.fn_traits.contains_key(&id) {
+                return Err("Methods can't be renamed".to_string());
+            }
+            if decl.name == "main" && is_root_fn(analysis, id) {
+                return Err("`main` is the entry point of the program".to_string());
+            }
+        }
+        DeclKind::Param if decl.name == "self" => {
+            return Err("`self` can't be renamed".to_string());
+        }
+        DeclKind::ForeignFn | DeclKind::Param | DeclKind::Local => {}
+        _ => {
+            return Err(
+                "Only functions, parameters and local variables can be renamed".to_string(),
+            );
+        }
+    }
+    Ok((span, id))
+}
+
+fn is_root_fn(analysis: &Analysis, id: NodeId) -> bool {
+    analysis.resolve.def_paths.get(&id).map(String::as_str) == Some("main")
+}
+
+/// The names of the parameters of the function declaring a parameter
+fn param_names<'a>(analysis: &'a Analysis, param: &Decl) -> HashSet<&'a str> {
+    let f = analysis
+        .index
+        .decls
+        .values()
+        .filter(|it| matches!(it.kind, DeclKind::Fn | DeclKind::ForeignFn))
+        .filter(|it| it.span.start <= param.span.start && param.span.end <= it.span.end)
+        .min_by_key(|it| it.span.end - it.span.start);
+    f.into_iter()
+        .flat_map(|f| &f.params)
+        .filter_map(|it| it.split(':').next())
+        .collect()
+}
+
+/// Whether a name can be declared, which keywords can't
+fn is_ident(name: &str) -> bool {
+    let mut lexer = Lexer::new(name, PathBuf::new());
+    let token = lexer.next_token();
+    token.kind == TokenKind::IDENT
+        && token.text == name
+        && name != "Self"
+        && lexer.next_token().kind == TokenKind::EOF
+}
+
+/// What goes between an attribute and the item starting at `offset`: a line
+/// break and the indentation of the item if it starts its line
+fn separator(chars: &[char], offset: usize) -> String {
+    let mut line_start = offset;
+    while line_start > 0 && chars[line_start - 1] != '\n' {
+        line_start -= 1;
+    }
+    let indent = &chars[line_start..offset];
+    if indent.iter().all(|c| c.is_whitespace()) {
+        format!("\n{}", indent.iter().collect::<String>())
+    } else {
+        " ".to_string()
+    }
+}
+
+/// Resolves the files with the edits applied, and compares what their names
+/// refer to with what they refer to now
+fn check(
+    analysis: &Analysis,
+    id: NodeId,
+    old_name: &str,
+    new_name: &str,
+    edits: &[(Span, String)],
+) -> Result<(), String> {
+    let mut files = analysis
+        .source_map
+        .files()
+        .iter()
+        .map(|file| {
+            let mut text = String::new();
+            let mut offset = file.start;
+            let chars = file.text.chars().collect::<Vec<_>>();
+            let end = file.start + chars.len();
+            for (span, new_text) in edits {
+                if span.start < file.start || span.start > end {
+                    continue;
+                }
+                text.extend(&chars[offset - file.start..span.start - file.start]);
+                text.push_str(new_text);
+                offset = span.end;
+            }
+            text.extend(&chars[offset - file.start..]);
+            (file.path.clone(), text)
+        })
+        .collect::<HashMap<_, _>>();
+    let path = &analysis.source_file.path;
+    let root = files.remove(path).unwrap_or_default();
+    let result = loader::load_edited(path, root, files);
+    let renamed = resolve(&result.source_file);
+
+    // The edits only change names, so the nodes have the same ids
+    let old = &analysis.resolve;
+    let mut ids = resolved(old).chain(resolved(&renamed)).collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+    for it in ids {
+        let (before, after) = (res(old, it), res(&renamed, it));
+        if before == after {
+            continue;
+        }
+        return Err(if after == Some(id) {
+            format!(
+                "Renaming `{}` to `{}` would shadow another `{}` where it's used",
+                old_name, new_name, new_name
+            )
+        } else {
+            format!(
+                "Renaming `{}` to `{}` would make its uses refer to another `{}`",
+                old_name, new_name, new_name
+            )
+        });
+    }
+    let messages = analysis
+        .diagnostics
+        .iter()
+        .map(|it| &it.message)
+        .collect::<HashSet<_>>();
+    match renamed
+        .diagnostics
+        .iter()
+        .find(|it| !messages.contains(&it.message))
+    {
+        Some(diagnostic) => Err(format!(
+            "Renaming `{}` to `{}` would be an error: {}",
+            old_name, new_name, diagnostic.message
+        )),
+        None => Ok(()),
+    }
+}
+
+fn resolved(result: &ResolveResult) -> impl Iterator<Item = NodeId> + '_ {
+    result.res.keys().chain(result.imports.keys()).copied()
+}
+
+/// What a name or the path of an import refers to
+fn res(result: &ResolveResult, id: NodeId) -> Option<NodeId> {
+    let res = result
+        .res
+        .get(&id)
+        .or_else(|| result.imports.get(&id))
+        .copied();
+    match res? {
+        Res::Def(_, def) | Res::Local(def) => Some(def),
+        Res::PrimTy(_) | Res::Err => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lsp::analyze, parser::ParsedFile};
+
+    /// Renames the name at the first occurrence of `at` in a document, and
+    /// returns the document with the edits applied
+    fn rename_at(text: &str, at: &str, new_name: &str) -> Result<String, String> {
+        let path = std::env::temp_dir().join("hades-rename.hds");
+        let parsed = ParsedFile::parse(text, path.clone());
+        let edited = HashMap::from([(path.clone(), text.to_string())]);
+        let analysis = analyze(&path, text, &parsed, edited).unwrap();
+        let offset = text.find(at).unwrap();
+        let offset = text[..offset].chars().count();
+        let edits = rename(&analysis, offset, new_name)?;
+        let mut chars = text.chars().collect::<Vec<_>>();
+        for (span, new_text) in edits.iter().rev() {
+            chars.splice(span.start..span.end, new_text.chars());
+        }
+        Ok(chars.into_iter().collect())
+    }
+
+    #[test]
+    fn renames_locals_params_and_functions() {
+        let text = "\
+fn main() {
+    let x = 1;
+    let y = x + 1;
+    let x = y;
+    x;
+}
+";
+        assert_eq!(
+            rename_at(text, "x = 1", "z").unwrap(),
+            text.replacen("x = 1", "z = 1", 1).replace("x + 1", "z + 1")
+        );
+        let text = "fn double(n: i32) -> i32 { n + n }";
+        assert_eq!(
+            rename_at(text, "n + n", "it").unwrap(),
+            "fn double(it: i32) -> i32 { it + it }"
+        );
+        // Imports are renamed too, but not the names they import under
+        let text = "\
+mod util {
+    pub fn f() -> i32 { 1 }
+}
+use util::f;
+use util::f as g;
+fn main() { f(); g(); util::f(); }
+";
+        assert_eq!(
+            rename_at(text, "f()", "h").unwrap(),
+            text.replace("f(", "h(").replace("::f", "::h")
+        );
+    }
+
+    #[test]
+    fn keeps_the_symbols_of_foreign_functions() {
+        let text = "\
+extern \"C\" {
+    fn puts(s: *u8) -> i32;
+}
+fn main() { unsafe { puts(null); } }
+";
+        assert_eq!(
+            rename_at(text, "puts(null)", "print").unwrap(),
+            "\
+extern \"C\" {
+    #[link_name = \"puts\"]
+    fn print(s: *u8) -> i32;
+}
+fn main() { unsafe { print(null); } }
+"
+        );
+    }
+
+    #[test]
+    fn refuses_renames_that_change_what_names_refer_to() {
+        let text = "\
+fn add(a: i32, b: i32) -> i32 {
+    let c = 1;
+    a + b + c
+}
+struct Point { x: i32 }
+fn main() {}
+";
+        let cases = [
+            (
+                "c = 1",
+                "a",
+                "Renaming `c` to `a` would shadow another `a` where it's used",
+            ),
+            (
+                "a: i32",
+                "c",
+                "Renaming `a` to `c` would make its uses refer to another `c`",
+            ),
+            ("a: i32", "b", "A parameter is already named `b`"),
+            (
+                "add",
+                "main",
+                "Renaming `add` to `main` would be an error: `main` is defined multiple times",
+            ),
+            ("c = 1", "let", "`let` isn't a valid name"),
+            ("main", "start", "`main` is the entry point of the program"),
+            (
+                "Point",
+                "P",
+                "Only functions, parameters and local variables can be renamed",
+            ),
+        ];
+        for (at, new_name, message) in cases {
+            assert_eq!(
+                rename_at(text, at, new_name),
+                Err(message.to_string()),
+                "{}",
+                at
+            );
+        }
+    }
+}
@@ -1,33 +1,72 @@
 mod analysis;
 mod ast;
+mod build;
+mod cache;
 mod cli;
+mod cst;
+mod db;
+mod diagnostics;
+mod formatter;
 mod lexer;
+mod loader;
 mod lower;
+mod lsp;
+mod manifest;
+mod mono;
 mod parser;
-use anyhow::Result;
+mod source_map;
+#[cfg(test)]
+mod testing;
+use anyhow::{bail, Result};
 use ron::{self, ser::PrettyConfig};
-use std::io::Read;
 
-use crate::{cli::CliArgs, parser::Parser};
+use crate::{
+    cli::{CliArgs, Command},
+    db::Database,
+};
 
 fn main() -> Result<()> {
     let args = CliArgs::parse();
-    let mut file = std::fs::File::open(&args.input)?;
-    let mut buffer = vec![];
-    file.read_to_end(&mut buffer)?;
-    let text = String::from_utf8(buffer)?;
-
-    let parser = Parser::new(&text, args.input);
-
-    let source_file = parser.parse_source_file();
+    match args.command {
+        Some(Command::Lsp) => return lsp::run_stdio(),
+        Some(Command::Fmt { paths, check }) => {
+            if !formatter::format_paths(&paths, check)? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+    let Some(input) = &args.input else {
+        return build::build_package(&std::env::current_dir()?, !args.no_cache);
+    };
+    let db = Database::new(input.clone());
+    let tree = db.crate_tree();
+    let loader::LoadResult {
+        source_file,
+        source_map,
+        diagnostics,
+    } = match &*tree {
+        Ok(tree) => tree,
+        Err(err) => bail!("{}", err),
+    };
+    eprint!("{}", diagnostics::render(source_map, diagnostics));
+    if !diagnostics.is_empty() {
+        std::process::exit(1);
+    }
     eprintln!(
         "{}",
-        ron::ser::to_string_pretty(&source_file, PrettyConfig::new()).unwrap(),
+        ron::ser::to_string_pretty(source_file, PrettyConfig::new()).unwrap(),
     );
 
-    let _resolve_result = analysis::resolve::resolve(&source_file);
-
-    lower::lower_source_file(source_file);
-
-    panic!();
+    let diagnostics = db.diagnostics();
+    eprint!("{}", diagnostics::render(source_map, &diagnostics));
+    let Some(out) = &*db.compile() else {
+        std::process::exit(1);
+    };
+    match &args.output {
+        Some(path) => std::fs::write(path, &out.code)?,
+        None => print!("{}", out.code),
+    }
+    Ok(())
 }
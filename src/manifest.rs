@@ -0,0 +1,302 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+
+use crate::ast::Ident;
+
+pub const MANIFEST_NAME: &str = "hades.toml";
+
+/// The contents of a `hades.toml`:
+///
+/// ```toml
+/// [package]
+/// name = "app"
+///
+/// [lib]
+/// entry = "src/lib.hds"
+///
+/// [[bin]]
+/// name = "app"
+/// entry = "src/main.hds"
+///
+/// [c]
+/// sources = ["native/clock.c"]
+/// headers = ["native/clock.h"]
+/// libs = ["m"]
+///
+/// [dependencies]
+/// util = { path = "../util" }
+/// ```
+///
+/// A package without `[lib]` and `[[bin]]` sections is a binary named after the
+/// package with its entry at `src/main.hds`. Paths are relative to the directory
+/// containing the manifest.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub package: PackageInfo,
+    pub lib: Option<LibTarget>,
+    #[serde(default)]
+    pub bin: Vec<BinTarget>,
+    #[serde(default)]
+    pub c: CSources,
+    #[serde(default)]
+    pub dependencies: BTreeMap<Ident, Dependency>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackageInfo {
+    pub name: Ident,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LibTarget {
+    pub entry: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BinTarget {
+    pub name: String,
+    pub entry: PathBuf,
+}
+
+/// C code compiled and linked into every binary that depends on the package
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CSources {
+    #[serde(default)]
+    pub sources: Vec<PathBuf>,
+    /// Included by the generated C, so that foreign functions can use macros
+    /// and types they declare
+    #[serde(default)]
+    pub headers: Vec<PathBuf>,
+    /// Libraries passed to the linker as `-l<name>`
+    #[serde(default)]
+    pub libs: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dependency {
+    pub path: PathBuf,
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Result<Manifest> {
+        let mut manifest: Manifest = toml::from_str(text)?;
+        if manifest.lib.is_none() && manifest.bin.is_empty() {
+            manifest.bin.push(BinTarget {
+                name: manifest.package.name.clone(),
+                entry: PathBuf::from("src/main.hds"),
+            });
+        }
+        if !is_ident(&manifest.package.name) {
+            bail!(
+                "Package name `{}` must be a valid identifier",
+                manifest.package.name
+            );
+        }
+        Ok(manifest)
+    }
+
+    pub fn load(dir: &Path) -> Result<Manifest> {
+        let path = dir.join(MANIFEST_NAME);
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        Manifest::parse(&text).with_context(|| format!("Invalid manifest {}", path.display()))
+    }
+}
+
+/// Packages are referred to by name in Hades code, e.g. `util::f()`
+fn is_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// A package along with the directory of its manifest
+#[derive(Debug)]
+pub struct Package {
+    pub dir: PathBuf,
+    pub manifest: Manifest,
+}
+impl Package {
+    pub fn name(&self) -> &Ident {
+        &self.manifest.package.name
+    }
+
+    /// Names of the packages this package depends on
+    pub fn deps(&self) -> Vec<Ident> {
+        self.manifest.dependencies.keys().cloned().collect()
+    }
+}
+
+/// Loads the package in `dir` and the packages it depends on, directly or
+/// through other packages. Dependencies come before the packages that depend
+/// on them, so the package in `dir` is last.
+pub fn load_packages(dir: &Path) -> Result<Vec<Package>> {
+    let mut graph = PackageGraph {
+        packages: vec![],
+        stack: vec![],
+    };
+    graph.visit(dir, None)?;
+    Ok(graph.packages)
+}
+
+struct PackageGraph {
+    packages: Vec<Package>,
+    /// Canonical directories and names of the packages being loaded
+    stack: Vec<(PathBuf, Ident)>,
+}
+impl PackageGraph {
+    /// `name` is the name the dependent package refers to the package by
+    fn visit(&mut self, dir: &Path, name: Option<&Ident>) -> Result<()> {
+        let dir = dir
+            .canonicalize()
+            .with_context(|| format!("Package directory {} not found", dir.display()))?;
+        if let Some(index) = self.stack.iter().position(|(it, _)| *it == dir) {
+            let cycle = self.stack[index..]
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .chain([self.stack[index].1.as_str()])
+                .collect::<Vec<_>>();
+            bail!("Dependency cycle: {}", cycle.join(" -> "));
+        }
+        if let Some(package) = self.packages.iter().find(|it| it.dir == dir) {
+            return check_dep_name(package, name);
+        }
+        let manifest = Manifest::load(&dir)?;
+        let package = Package { dir, manifest };
+        check_dep_name(&package, name)?;
+        self.stack
+            .push((package.dir.clone(), package.name().clone()));
+        for (dep_name, dep) in package.manifest.dependencies.iter() {
+            self.visit(&package.dir.join(&dep.path), Some(dep_name))
+                .with_context(|| format!("In dependency `{}` of `{}`", dep_name, package.name()))?;
+        }
+        self.stack.pop();
+        if let Some(other) = self.packages.iter().find(|it| it.name() == package.name()) {
+            bail!(
+                "Two packages are named `{}`: {} and {}",
+                package.name(),
+                other.dir.display(),
+                package.dir.display()
+            );
+        }
+        self.packages.push(package);
+        Ok(())
+    }
+}
+
+/// Dependencies are referred to by their package name, and must be libraries
+fn check_dep_name(package: &Package, name: Option<&Ident>) -> Result<()> {
+    let Some(name) = name else {
+        return Ok(());
+    };
+    if package.name() != name {
+        return Err(anyhow!(
+            "Dependency `{}` refers to the package `{}`",
+            name,
+            package.name()
+        ));
+    }
+    if package.manifest.lib.is_none() {
+        bail!("Dependency `{}` is not a library", name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing::write_files;
+
+    #[test]
+    fn parses_manifests() {
+        let manifest = Manifest::parse(
+            r#"
+            [package]
+            name = "app"
+
+            [lib]
+            entry = "src/lib.hds"
+
+            [[bin]]
+            name = "tool"
+            entry = "src/tool.hds"
+
+            [c]
+            sources = ["native/clock.c"]
+            libs = ["m"]
+
+            [dependencies]
+            util = { path = "../util" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(manifest.package.name, "app");
+        assert_eq!(manifest.lib.unwrap().entry, PathBuf::from("src/lib.hds"));
+        assert_eq!(manifest.bin[0].name, "tool");
+        assert_eq!(manifest.c.libs, vec!["m"]);
+        assert!(manifest.c.headers.is_empty());
+        assert_eq!(manifest.dependencies["util"].path, PathBuf::from("../util"));
+
+        let manifest = Manifest::parse("[package]\nname = \"app\"").unwrap();
+        assert_eq!(manifest.bin[0].entry, PathBuf::from("src/main.hds"));
+        assert!(Manifest::parse("[package]\nname = \"my-app\"").is_err());
+        assert!(Manifest::parse("[package]\nname = \"app\"\nversion = 1").is_err());
+    }
+
+    #[test]
+    fn orders_packages_after_their_dependencies() {
+        let dir = write_files(
+            "graph",
+            &[
+                (
+                    "app/hades.toml",
+                    "[package]\nname = \"app\"\n[dependencies]\nutil = { path = \"../util\" }\nbase = { path = \"../base\" }",
+                ),
+                (
+                    "util/hades.toml",
+                    "[package]\nname = \"util\"\n[lib]\nentry = \"lib.hds\"\n[dependencies]\nbase = { path = \"../base\" }",
+                ),
+                ("base/hades.toml", "[package]\nname = \"base\"\n[lib]\nentry = \"lib.hds\""),
+            ],
+        );
+        let packages = load_packages(&dir.join("app")).unwrap();
+        let names = packages
+            .iter()
+            .map(|it| it.name().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["base", "util", "app"]);
+    }
+
+    #[test]
+    fn reports_dependency_cycles() {
+        let dir = write_files(
+            "cycle",
+            &[
+                (
+                    "a/hades.toml",
+                    "[package]\nname = \"a\"\n[lib]\nentry = \"lib.hds\"\n[dependencies]\nb = { path = \"../b\" }",
+                ),
+                (
+                    "b/hades.toml",
+                    "[package]\nname = \"b\"\n[lib]\nentry = \"lib.hds\"\n[dependencies]\na = { path = \"../a\" }",
+                ),
+            ],
+        );
+        let error = load_packages(&dir.join("a")).unwrap_err();
+        assert_eq!(
+            error.root_cause().to_string(),
+            "Dependency cycle: a -> b -> a"
+        );
+    }
+}
@@ -0,0 +1,432 @@
+//! Monomorphization: generic functions and types are lowered to C once for each
+//! distinct list of type arguments that they're used with. Starting from the
+//! non generic functions, this walks function bodies to find the instances of
+//! generic functions that they call, and the concrete types that they use.
+//! Calls to trait functions are resolved to the functions of the matching impl,
+//! and casts to `dyn` pointers add every function of the impl for a vtable.
+use std::collections::{HashMap, HashSet};
+
+use libsyntax::{HasMeta, HasSpan, NodeId, Span};
+
+use crate::{
+    analysis::{
+        resolve::{DefKind, Res, ResolveResult},
+        ty::{Type, MAX_TYPE_DEPTH, MAX_TYPE_SIZE},
+        typeck::TypeckResult,
+    },
+    ast::{self, visit::Visitor, ExprKind, SourceFile},
+    diagnostics::Diagnostic,
+};
+
+#[derive(Default, PartialEq)]
+pub struct MonoResult {
+    /// The functions to emit; Non generic functions in source order, followed by
+    /// instances of generic functions in the order they were found.
+    pub instances: Vec<Instance>,
+    /// Every type used by the instances, with type parameters substituted
+    pub tys: Vec<Type>,
+    /// The vtables to emit, as the trait and the type that implements it
+    pub vtables: Vec<(NodeId, Type)>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The function that each diagnostic is in, by the id of its item
+    pub diagnostic_fns: Vec<NodeId>,
+}
+
+/// A function along with the type arguments for its type parameters
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Instance {
+    pub def: NodeId,
+    pub args: Vec<Type>,
+}
+
+pub fn collect(
+    source_file: &SourceFile,
+    resolve: &ResolveResult,
+    typeck: &TypeckResult,
+) -> MonoResult {
+    let fns = source_file
+        .fns()
+        .map(|(item, f)| FnContext {
+            item,
+            f,
+            resolve,
+            typeck,
+        })
+        .collect::<Vec<_>>();
+    collect_fns(&fns)
+}
+
+/// A function along with the results of checking the crate it's in. Each
+/// function can come from its own copy of the crate, as long as they all agree
+/// on the node ids of items.
+#[derive(Clone, Copy)]
+pub struct FnContext<'a> {
+    pub item: &'a ast::Item,
+    pub f: &'a ast::Fn,
+    pub resolve: &'a ResolveResult,
+    pub typeck: &'a TypeckResult,
+}
+
+/// Like [collect], for the functions of a crate in source order
+pub fn collect_fns(fns: &[FnContext]) -> MonoResult {
+    let by_id = fns
+        .iter()
+        .map(|it| (it.item.id(), it))
+        .collect::<HashMap<_, _>>();
+    let Some(first) = fns.first() else {
+        return MonoResult::default();
+    };
+    let mut collector = Collector {
+        resolve: first.resolve,
+        typeck: first.typeck,
+        seen_instances: HashSet::new(),
+        instances: vec![],
+        seen_tys: HashSet::new(),
+        tys: vec![],
+        vtables: vec![],
+        args: vec![],
+        span: Span { start: 0, end: 0 },
+        item: first.item.id(),
+        diagnostics: vec![],
+        diagnostic_fns: vec![],
+    };
+    for FnContext { item, typeck, .. } in fns {
+        if typeck.generics_of(item.id()).is_empty() {
+            collector.item = item.id();
+            collector.add_instance(item.id(), vec![], item.span());
+        }
+    }
+    // Instances found while walking a body are appended to the list
+    let mut next = 0;
+    while let Some(instance) = collector.instances.get(next).cloned() {
+        next += 1;
+        let FnContext {
+            item,
+            f,
+            resolve,
+            typeck,
+        } = *by_id[&instance.def];
+        collector.resolve = resolve;
+        collector.typeck = typeck;
+        collector.args = instance.args;
+        collector.span = *item.span();
+        collector.item = item.id();
+        let sig = &typeck.fn_sigs[&item.id()];
+        for ty in sig.params.iter().chain([&sig.ret]) {
+            collector.add_ty(ty);
+        }
+        collector.visit_fn(f);
+    }
+    MonoResult {
+        instances: collector.instances,
+        tys: collector.tys,
+        vtables: collector.vtables,
+        diagnostics: collector.diagnostics,
+        diagnostic_fns: collector.diagnostic_fns,
+    }
+}
+
+struct Collector<'a> {
+    /// The results of checking the function being walked
+    resolve: &'a ResolveResult,
+    typeck: &'a TypeckResult,
+    seen_instances: HashSet<Instance>,
+    instances: Vec<Instance>,
+    seen_tys: HashSet<Type>,
+    tys: Vec<Type>,
+    vtables: Vec<(NodeId, Type)>,
+    /// Type arguments of the instance being walked
+    args: Vec<Type>,
+    /// Where errors about the types being collected are reported
+    span: Span,
+    /// The function being walked
+    item: NodeId,
+    diagnostics: Vec<Diagnostic>,
+    diagnostic_fns: Vec<NodeId>,
+}
+impl<'a> Collector<'a> {
+    fn add_instance(&mut self, def: NodeId, args: Vec<Type>, span: &Span) {
+        if let Some(ty) = args.iter().find(|it| too_large(it)) {
+            self.error_type_limit(ty, span);
+            return;
+        }
+        let instance = Instance { def, args };
+        if self.seen_instances.insert(instance.clone()) {
+            self.instances.push(instance);
+        }
+    }
+
+    /// Adds the functions of the impl of a trait for a type, which are called
+    /// through the vtable of a `dyn` pointer.
+    fn add_vtable(&mut self, trait_id: NodeId, ty: &Type, span: &Span) {
+        let vtable = (trait_id, ty.clone());
+        if self.vtables.contains(&vtable) {
+            return;
+        }
+        self.vtables.push(vtable);
+        let typeck = self.typeck;
+        for (_, trait_fn) in typeck.traits[&trait_id].fns.iter() {
+            let (def, args) = typeck.resolve_trait_fn(*trait_fn, ty);
+            self.add_instance(def, args, span);
+        }
+    }
+
+    /// Records a type in the current instance, along with the types it's made
+    /// of and the fields of the generic structs and enums it refers to.
+    fn add_ty(&mut self, ty: &Type) {
+        let ty = ty.subst(&self.args);
+        if too_large(&ty) {
+            self.error_type_limit(&ty, &self.span.clone());
+            return;
+        }
+        if !self.seen_tys.insert(ty.clone()) {
+            return;
+        }
+        self.tys.push(ty.clone());
+        match &ty {
+            Type::Prim(_) | Type::Param(_) | Type::Dyn(_) | Type::Error => {}
+            Type::Tuple(items) => items.iter().for_each(|it| self.add_ty(it)),
+            Type::Adt(adt) => {
+                let field_tys = self.typeck.adts[&adt.def]
+                    .variants
+                    .iter()
+                    .flat_map(|it| it.fields.iter())
+                    .map(|it| it.ty.subst(&adt.args))
+                    .collect::<Vec<_>>();
+                field_tys.iter().for_each(|it| self.add_ty(it));
+            }
+            Type::Fn(params, ret) => {
+                params.iter().for_each(|it| self.add_ty(it));
+                self.add_ty(ret);
+            }
+            Type::Ptr(_, inner) | Type::Array(inner, _) | Type::Slice(inner) => self.add_ty(inner),
+            Type::Newtype(newtype) => self.add_ty(&self.typeck.newtypes[&newtype.def]),
+        }
+    }
+
+    /// Only the first error is reported, since the others are caused by it
+    fn error_type_limit(&mut self, ty: &Type, span: &Span) {
+        if !self.diagnostics.is_empty() {
+            return;
+        }
+        let (limit, max) = match ty.depth() > MAX_TYPE_DEPTH {
+            true => ("depth", MAX_TYPE_DEPTH),
+            false => ("size", MAX_TYPE_SIZE),
+        };
+        let message = format!(
+            "Reached the type {} limit of {} while instantiating generics; Check for \
+             generic functions or types that use themselves with a larger type",
+            limit, max
+        );
+        self.diagnostics.push(Diagnostic::new(*span, message));
+        self.diagnostic_fns.push(self.item);
+    }
+}
+/// Types past the limits can only come from polymorphic recursion
+fn too_large(ty: &Type) -> bool {
+    ty.depth() > MAX_TYPE_DEPTH || ty.size() > MAX_TYPE_SIZE
+}
+
+impl<'a> Visitor for Collector<'a> {
+    fn visit_param(&mut self, param: &ast::Param) {
+        let typeck = self.typeck;
+        self.add_ty(&typeck.local_types[&param.id()]);
+    }
+
+    fn visit_local(&mut self, local: &ast::Local) {
+        let typeck = self.typeck;
+        self.add_ty(&typeck.local_types[&local.id()]);
+        ast::visit::walk_local(self, local);
+    }
+
+    fn visit_pat(&mut self, pat: &ast::Pat) {
+        if let ast::PatKind::Binding(_) = pat.kind {
+            let typeck = self.typeck;
+            self.add_ty(&typeck.local_types[&pat.id()]);
+        }
+        ast::visit::walk_pat(self, pat);
+    }
+
+    fn visit_ty(&mut self, _: &ast::Ty) {
+        // Types are collected from the results of type checking instead
+    }
+
+    fn visit_expr(&mut self, expr: &ast::Expr) {
+        let typeck = self.typeck;
+        self.add_ty(typeck.expr_type(expr));
+        let def = match &expr.kind {
+            ExprKind::Var(_) | ExprKind::Path(_) => match self.resolve.get(expr.id()) {
+                Res::Def(DefKind::Fn | DefKind::TraitFn, def) => Some(def),
+                _ => None,
+            },
+            ExprKind::MethodCall(..) => Some(typeck.method_calls[&expr.id()].def),
+            _ => None,
+        };
+        if let Some(def) = def {
+            let args = self
+                .typeck
+                .generic_args
+                .get(&expr.id())
+                .map(|args| {
+                    args.iter()
+                        .map(|it| it.subst(&self.args))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            if !typeck.fn_traits.contains_key(&def) {
+                self.add_instance(def, args, expr.span());
+            } else if !matches!(args[0], Type::Dyn(_)) {
+                // Calls on `dyn` pointers go through the vtable instead
+                let (def, args) = typeck.resolve_trait_fn(def, &args[0]);
+                self.add_instance(def, args, expr.span());
+            }
+        }
+        if let ExprKind::Cast(inner, _) = &expr.kind {
+            let source = typeck.expr_type(inner).subst(&self.args);
+            if let (Type::Ptr(_, pointee), Type::Ptr(_, target)) = (&source, typeck.expr_type(expr))
+            {
+                if let (false, Type::Dyn(trait_ref)) = (source.is_dyn_ptr(), &**target) {
+                    self.add_vtable(trait_ref.def, pointee, expr.span());
+                }
+            }
+        }
+        if let ExprKind::Repeat(elem, _) = &expr.kind {
+            // The length is a constant, which isn't type checked as an expression
+            self.visit_expr(elem);
+            return;
+        }
+        ast::visit::walk_expr(self, expr);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use crate::{
+        analysis::{resolve::resolve, typeck::typeck},
+        parser::Parser,
+    };
+
+    use super::*;
+
+    fn collect_text(text: &str) -> (SourceFile, MonoResult) {
+        let source_file = Parser::new(text, PathBuf::from("test.hds")).parse_source_file();
+        let resolve_result = resolve(&source_file);
+        let typeck_result = typeck(&source_file, &resolve_result);
+        assert_eq!(resolve_result.diagnostics, vec![]);
+        assert_eq!(typeck_result.diagnostics, vec![]);
+        let mono = collect(&source_file, &resolve_result, &typeck_result);
+        (source_file, mono)
+    }
+
+    #[test]
+    fn collects_each_instance_once() {
+        let (source_file, mono) = collect_text(
+            "
+            fn id<T>(x: T) -> T { x }
+            fn twice<T>(x: T) -> (T, T) { (id(x), id(x)) }
+            fn main() {
+                twice(1);
+                twice(true);
+                id(2);
+            }
+            ",
+        );
+        let instances = mono
+            .instances
+            .iter()
+            .map(|it| {
+                let name = &source_file
+                    .items
+                    .iter()
+                    .find(|item| item.id() == it.def)
+                    .unwrap()
+                    .name;
+                let args = it.args.iter().map(|it| it.to_string()).collect::<Vec<_>>();
+                format!("{}<{}>", name, args.join(", "))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            instances,
+            vec!["main<>", "twice<i32>", "twice<bool>", "id<i32>", "id<bool>"]
+        );
+        assert!(mono.tys.contains(&Type::Tuple(vec![
+            Type::Prim(
+                crate::analysis::ty::PrimTy::Bool
+            );
+            2
+        ])));
+        assert!(!mono
+            .tys
+            .iter()
+            .any(|it| it.any(&|it| matches!(it, Type::Param(_)))));
+    }
+
+    #[test]
+    fn resolves_trait_calls_to_impls() {
+        let (source_file, mono) = collect_text(
+            "
+            trait Show { fn show(self: *Self) -> i32; }
+            struct Pair<T> { a: T, b: T }
+            impl<T: Show> Show for Pair<T> {
+                fn show(self: *Self) -> i32 { unsafe { Show::show(&(*self).a) } }
+            }
+            impl Show for i32 { fn show(self: *i32) -> i32 { 0 } }
+            impl Show for bool { fn show(self: *bool) -> i32 { 1 } }
+            fn main() {
+                let p = Pair { a: 1, b: 2 };
+                Show::show(&p);
+                let b = true;
+                let d = &b as *dyn Show;
+            }
+            ",
+        );
+        let fns = source_file
+            .fns()
+            .map(|(item, _)| item.id())
+            .collect::<Vec<_>>();
+        let instances = mono
+            .instances
+            .iter()
+            .map(|it| {
+                let index = fns.iter().position(|id| *id == it.def).unwrap();
+                let args = it.args.iter().map(|it| it.to_string()).collect::<Vec<_>>();
+                format!("{}<{}>", index, args.join(", "))
+            })
+            .collect::<Vec<_>>();
+        // Non generic impl functions are instantiated even if they're unused
+        assert_eq!(instances, vec!["1<>", "2<>", "3<>", "0<i32>"]);
+        assert_eq!(mono.vtables.len(), 1);
+        assert_eq!(
+            mono.vtables[0].1,
+            Type::Prim(crate::analysis::ty::PrimTy::Bool)
+        );
+    }
+
+    #[test]
+    fn reports_polymorphic_recursion() {
+        let (_, mono) = collect_text(
+            "
+            fn nest<T>(x: T) { nest((x,)) }
+            fn main() { nest(1) }
+            ",
+        );
+        assert_eq!(mono.diagnostics.len(), 1);
+        assert!(mono.diagnostics[0]
+            .message
+            .starts_with("Reached the type depth limit"));
+
+        // Doubling the type reaches the size limit long before the depth limit
+        let (_, mono) = collect_text(
+            "
+            fn rec<T>(x: T, n: i32) -> i32 { match n { 0 => 0, _ => rec((x, x), n - 1) } }
+            fn main() -> i32 { rec(1, 3) }
+            ",
+        );
+        assert_eq!(mono.diagnostics.len(), 1);
+        assert!(mono.diagnostics[0]
+            .message
+            .starts_with("Reached the type size limit"));
+    }
+}
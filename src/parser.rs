@@ -1,100 +1,560 @@
 use std::array;
+use std::cell::Cell;
+use std::ops::Range;
 use std::{path::PathBuf, rc::Rc};
 
 use crate::ast::{
-    Block, Expr, ExprKind, Fn, ForeignItem, ForeignItemKind, ForeignMod, Ident, Item, ItemKind,
-    Lit, LitKind, Param, SourceFile, Stmt, StmtKind, Ty, TyKind, Var, Visibility,
+    Arm, AttrArgs, Attribute, BinOp, Block, Const, Enum, Expr, ExprField, ExprKind, FieldDef, Fn,
+    ForeignItem, ForeignItemKind, ForeignMod, GenericParam, Ident, Impl, Item, ItemKind, Lit,
+    LitKind, Local, Mod, Mutability, Newtype, Param, Pat, PatField, PatKind, Path, SourceFile,
+    Static, Stmt, StmtKind, Struct, StructExpr, Trait, TraitRef, Ty, TyAlias, TyKind, UnOp, Use,
+    UseKind, Var, Variant, VariantData, Visibility,
 };
+use crate::diagnostics::Diagnostic;
 use crate::lexer::{Lexer, Token, TokenKind};
 
+/// Parses a file, recovering from syntax errors by skipping to the next item
+/// or statement. The constructs with errors are left out of the AST.
 pub struct Parser<'text> {
     path: Rc<PathBuf>,
     tokens: TokenBuffer<'text>,
+    next_node_id: u32,
+    /// Set while parsing the scrutinee of a `match`, where `x {` starts the match arms
+    /// instead of a struct expression.
+    no_struct_literal: bool,
+    /// The number of `{` consumed that haven't been closed yet
+    depth: usize,
+    diagnostics: Vec<Diagnostic>,
 }
 
-use libsyntax::{HasSpan, Meta, Span};
+/// A syntax error, returned up to the item or statement being parsed
+struct ParseError(Diagnostic);
+
+type PResult<T> = Result<T, ParseError>;
+
+use libsyntax::{HasSpan, Meta, NodeId, Span, WalkMeta};
 use t::*;
 use TokenKind as t;
 impl<'text> Parser<'text> {
     pub fn new(text: &'text str, path: PathBuf) -> Self {
-        let lexer = Lexer::new(text, path.clone());
+        Parser::for_module(text, path, 0, 0)
+    }
+
+    /// A parser for a module file whose spans start at `offset` and whose node
+    /// ids start at `next_node_id`, so that they're unique across files
+    pub fn for_module(text: &'text str, path: PathBuf, offset: usize, next_node_id: u32) -> Self {
+        let lexer = Lexer::new(text, path.clone()).starting_at(offset);
         let tokens = TokenBuffer::new(lexer);
         Parser {
             path: Rc::new(path),
             tokens,
+            next_node_id,
+            no_struct_literal: false,
+            depth: 0,
+            diagnostics: vec![],
+        }
+    }
+
+    /// The syntax errors found so far
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    /// Parses a file without loading the files of its modules
+    pub fn parse_source_file(&mut self) -> SourceFile {
+        SourceFile {
+            path: PathBuf::clone(&self.path),
+            items: self.parse_items(),
+            deps: vec![],
         }
     }
 
-    pub fn parse_source_file(mut self) -> SourceFile {
+    pub fn parse_items(&mut self) -> Vec<Item> {
         let mut items = vec![];
         while self.current_kind() != TokenKind::EOF {
-            items.push(self.parse_item());
+            items.extend(self.parse_item_or_recover());
         }
-        SourceFile {
-            path: PathBuf::clone(&self.path),
-            items,
+        items
+    }
+
+    /// Parses an item, or skips to the next one after a syntax error
+    fn parse_item_or_recover(&mut self) -> Option<Item> {
+        let (start, depth) = (self.current_span().start, self.depth);
+        let item = self.recover(Self::parse_item);
+        if item.is_none() {
+            if self.current_span().start == start {
+                self.advance();
+            }
+            // Stop at the `}` closing the list of items, if any
+            while !self.eof() {
+                let kind = self.current_kind();
+                if self.depth == depth && (kind == RBRACE || kind == POUND || starts_item(kind)) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+        item
+    }
+
+    /// Runs `parse`, recording the syntax error it stops at if any
+    fn recover<T>(&mut self, parse: impl FnOnce(&mut Self) -> PResult<T>) -> Option<T> {
+        match parse(self) {
+            Ok(it) => Some(it),
+            Err(error) => {
+                self.report(error.0);
+                None
+            }
         }
     }
 
-    fn parse_item(&mut self) -> Item {
+    fn report(&mut self, diagnostic: Diagnostic) {
+        // An error at the token where the previous one stopped repeats it
+        if self.diagnostics.last().map(|it| it.span.start) != Some(diagnostic.span.start) {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    /// A syntax error at the current token, which stops parsing the current
+    /// item or statement
+    fn error(&self, message: &str) -> ParseError {
+        let token = self.tokens.current();
+        let found = match token.kind {
+            EOF => "end of file".to_string(),
+            ERROR if token.text.starts_with('"') => "an unterminated string".to_string(),
+            _ => format!("`{}`", token.text),
+        };
+        let message = format!("Parse error: {}; Found {}", message, found);
+        ParseError(Diagnostic::new(token.span, message))
+    }
+
+    /// The id the next node will get; Files parsed after this one start from it
+    pub fn next_node_id(&self) -> u32 {
+        self.next_node_id
+    }
+
+    fn parse_item(&mut self) -> PResult<Item> {
         use t::*;
+        let attrs = self.parse_attrs()?;
         let (vis, vis_token) = self.parse_visibility();
         match self.current_kind() {
             FN => {
-                let (func, name) = self.parse_fn();
+                let (func, name) = self.parse_fn()?;
                 let start = vis_token
                     .map(|it| *it.span())
                     .unwrap_or_else(|| *func.span());
-                Item {
-                    meta: Meta {
-                        span: Span::between(&start, &func),
-                    },
+                Ok(Item {
+                    meta: self.mk_meta(Span::between(&start, &func)),
+                    attrs,
                     name,
                     vis,
                     kind: ItemKind::Fn(Box::new(func)),
-                }
+                })
             }
             EXTERN => {
-                let (start, foreign_mod, rbrace) = self.parse_foreign_mod();
-                Item {
-                    meta: Meta {
-                        span: Span::between(&start, &rbrace),
-                    },
+                let (start, foreign_mod, rbrace) = self.parse_foreign_mod()?;
+                Ok(Item {
+                    meta: self.mk_meta(Span::between(&start, &rbrace)),
+                    attrs,
                     name: "extern".to_string(),
                     vis,
                     kind: ItemKind::ForeignMod(foreign_mod),
+                })
+            }
+            STRUCT => {
+                let start = self.advance();
+                let start = vis_token.map(|it| *it.span()).unwrap_or(start.span);
+                let name = self.expect(IDENT, "struct [name]")?.text;
+                let generics = self.parse_generics()?;
+                let (data, end) = match self.parse_variant_data()? {
+                    (data @ VariantData::Struct(_), Some(rbrace)) => (data, rbrace),
+                    (data, _) => (
+                        data,
+                        self.expect(SEMI, "Expected a semicolon after a tuple or unit struct")?,
+                    ),
+                };
+                Ok(Item {
+                    meta: self.mk_meta(Span::between(&start, &end)),
+                    attrs,
+                    name,
+                    vis,
+                    kind: ItemKind::Struct(Struct { generics, data }),
+                })
+            }
+            ENUM => {
+                let start = self.advance();
+                let start = vis_token.map(|it| *it.span()).unwrap_or(start.span);
+                let name = self.expect(IDENT, "enum [name]")?.text;
+                let generics = self.parse_generics()?;
+                let (e, end) = self.parse_enum_variants(generics)?;
+                Ok(Item {
+                    meta: self.mk_meta(Span::between(&start, &end)),
+                    attrs,
+                    name,
+                    vis,
+                    kind: ItemKind::Enum(e),
+                })
+            }
+            TRAIT => {
+                let start = self.advance();
+                let start = vis_token.map(|it| *it.span()).unwrap_or(start.span);
+                let name = self.expect(IDENT, "trait [name]")?.text;
+                let (items, end) = self.parse_fn_items("trait")?;
+                Ok(Item {
+                    meta: self.mk_meta(Span::between(&start, &end)),
+                    attrs,
+                    name,
+                    vis,
+                    kind: ItemKind::Trait(Trait { items }),
+                })
+            }
+            IMPL => {
+                let start = self.advance();
+                let start = vis_token.map(|it| *it.span()).unwrap_or(start.span);
+                let generics = self.parse_generics()?;
+                // The trait is only known to be a trait once `for` is reached
+                let ty = self.parse_ty()?;
+                let (trait_ref, self_ty) = if self.at(FOR) {
+                    self.advance();
+                    (Some(self.ty_to_trait_ref(ty)?), self.parse_ty()?)
+                } else {
+                    (None, ty)
+                };
+                let (items, end) = self.parse_fn_items("impl")?;
+                Ok(Item {
+                    meta: self.mk_meta(Span::between(&start, &end)),
+                    attrs,
+                    name: "impl".to_string(),
+                    vis,
+                    kind: ItemKind::Impl(Impl {
+                        generics,
+                        trait_ref,
+                        self_ty,
+                        items,
+                    }),
+                })
+            }
+            MOD => {
+                let start = self.advance();
+                let start = vis_token.map(|it| *it.span()).unwrap_or(start.span);
+                let name = self.expect(IDENT, "mod [name]")?.text;
+                let (m, end) = if self.at(SEMI) {
+                    let semi = self.advance();
+                    let m = Mod {
+                        items: vec![],
+                        inline: false,
+                        crate_deps: None,
+                    };
+                    (m, semi)
+                } else {
+                    self.expect(LBRACE, "Expected `;` or the body of the module")?;
+                    let mut items = vec![];
+                    while !self.at(RBRACE) && !self.eof() {
+                        items.extend(self.parse_item_or_recover());
+                    }
+                    let rbrace = self.expect(RBRACE, "Unexpected eof when parsing module")?;
+                    let m = Mod {
+                        items,
+                        inline: true,
+                        crate_deps: None,
+                    };
+                    (m, rbrace)
+                };
+                Ok(Item {
+                    meta: self.mk_meta(Span::between(&start, &end)),
+                    attrs,
+                    name,
+                    vis,
+                    kind: ItemKind::Mod(m),
+                })
+            }
+            USE => {
+                let start = self.advance();
+                let start = vis_token.map(|it| *it.span()).unwrap_or(start.span);
+                let (u, end) = self.parse_use()?;
+                Ok(Item {
+                    meta: self.mk_meta(Span::between(&start, &end)),
+                    attrs,
+                    name: "use".to_string(),
+                    vis,
+                    kind: ItemKind::Use(u),
+                })
+            }
+            CONST | STATIC => {
+                let start = self.advance();
+                let is_const = start.kind == CONST;
+                let start = vis_token.map(|it| *it.span()).unwrap_or(start.span);
+                let mutability = if !is_const && self.at(MUT) {
+                    self.advance();
+                    Mutability::Mut
+                } else {
+                    Mutability::Not
+                };
+                let name = self.expect(IDENT, "Expected a name")?.text;
+                self.expect(COLON, "Expected `:` and the type of the item")?;
+                let ty = self.parse_ty()?;
+                self.expect(EQ, "Expected `=` and the value of the item")?;
+                let expr = self.parse_expr()?;
+                let end = self.expect(SEMI, "Expected a semicolon after the value")?;
+                let kind = if is_const {
+                    ItemKind::Const(Const { ty, expr })
+                } else {
+                    ItemKind::Static(Static {
+                        mutability,
+                        ty,
+                        expr,
+                    })
+                };
+                Ok(Item {
+                    meta: self.mk_meta(Span::between(&start, &end)),
+                    attrs,
+                    name,
+                    vis,
+                    kind,
+                })
+            }
+            TYPE | NEWTYPE => {
+                let start = self.advance();
+                let is_alias = start.kind == TYPE;
+                let start = vis_token.map(|it| *it.span()).unwrap_or(start.span);
+                let name = self.expect(IDENT, "Expected a type name")?.text;
+                let generics = if is_alias {
+                    self.parse_generics()?
+                } else {
+                    vec![]
+                };
+                self.expect(EQ, "Expected `=` and the type it stands for")?;
+                let ty = self.parse_ty()?;
+                let end = self.expect(SEMI, "Expected a semicolon after the type")?;
+                let kind = if is_alias {
+                    ItemKind::TyAlias(TyAlias { generics, ty })
+                } else {
+                    ItemKind::Newtype(Newtype { ty })
+                };
+                Ok(Item {
+                    meta: self.mk_meta(Span::between(&start, &end)),
+                    attrs,
+                    name,
+                    vis,
+                    kind,
+                })
+            }
+            _ => Err(self.error("Expected an item")),
+        }
+    }
+
+    /// Parses the functions in the body of a trait or impl, returning the closing brace
+    fn parse_fn_items(&mut self, owner: &str) -> PResult<(Vec<Item>, Token)> {
+        self.expect(LBRACE, &format!("Expected the body of the {}", owner))?;
+        let mut items = vec![];
+        while !self.at(RBRACE) && !self.eof() {
+            let Some(item) = self.parse_item_or_recover() else {
+                continue;
+            };
+            if matches!(item.kind, ItemKind::Fn(_)) {
+                items.push(item);
+            } else {
+                let message = format!("Parse error: Only functions are allowed in a {}", owner);
+                self.report(Diagnostic::new(*item.span(), message));
+            }
+        }
+        let rbrace = self.expect(RBRACE, &format!("Unexpected eof when parsing {}", owner))?;
+        Ok((items, rbrace))
+    }
+
+    /// Parses the path of a `use` item up to and including the `;`
+    fn parse_use(&mut self) -> PResult<(Use, Token)> {
+        let first = self.expect(IDENT, "Expected a path after `use`")?;
+        let mut segments = vec![first.text];
+        let mut kind = UseKind::Single(None);
+        while self.at(COLONCOLON) {
+            self.advance();
+            if self.at(STAR) {
+                self.advance();
+                kind = UseKind::Glob;
+                break;
+            }
+            let segment = self.expect(IDENT, "Expected a name or `*` after `::`")?;
+            segments.push(segment.text);
+        }
+        if matches!(kind, UseKind::Single(_)) && self.at(AS) {
+            self.advance();
+            kind = UseKind::Single(Some(self.expect(IDENT, "Expected a name after `as`")?.text));
+        }
+        let semi = self.expect(SEMI, "Expected a semicolon after `use`")?;
+        Ok((
+            Use {
+                path: Path { segments },
+                kind,
+            },
+            semi,
+        ))
+    }
+
+    fn parse_trait_ref(&mut self) -> PResult<TraitRef> {
+        let (path, start, end) = self.parse_path()?;
+        Ok(TraitRef {
+            meta: self.mk_meta(Span::between(&start, &end)),
+            path,
+        })
+    }
+
+    /// Reinterprets the type before the `for` of an impl as the trait it implements
+    fn ty_to_trait_ref(&mut self, ty: Ty) -> PResult<TraitRef> {
+        let path = match ty.kind {
+            TyKind::Var(name, args) if args.is_empty() => Path {
+                segments: vec![name],
+            },
+            TyKind::Path(path, args) if args.is_empty() => path,
+            _ => return Err(self.error("Expected a trait name before `for`")),
+        };
+        Ok(TraitRef {
+            meta: ty.meta,
+            path,
+        })
+    }
+
+    /// Parses the fields of a struct or enum variant, returning the closing delimiter
+    /// of the field list if there is one.
+    fn parse_variant_data(&mut self) -> PResult<(VariantData, Option<Token>)> {
+        match self.current_kind() {
+            LBRACE => {
+                self.advance();
+                let mut fields = vec![];
+                while !self.at(RBRACE) && !self.eof() {
+                    let (vis, vis_token) = self.parse_visibility();
+                    let name = self.expect(IDENT, "Expected field name")?;
+                    self.expect(COLON, "Expected field type separator")?;
+                    let ty = self.parse_ty()?;
+                    let start = vis_token.map(|it| *it.span()).unwrap_or(name.span);
+                    fields.push(FieldDef {
+                        meta: self.mk_meta(Span::between(&start, &ty)),
+                        vis,
+                        name: name.text,
+                        ty,
+                    });
+                    if !self.at(RBRACE) {
+                        self.expect(COMMA, "Expected a comma between struct fields")?;
+                    }
                 }
+                let rbrace = self.expect(RBRACE, "Unexpected eof when parsing struct fields")?;
+                Ok((VariantData::Struct(fields), Some(rbrace)))
+            }
+            LPAREN => {
+                self.advance();
+                let mut fields = vec![];
+                while !self.at(RPAREN) && !self.eof() {
+                    let (vis, vis_token) = self.parse_visibility();
+                    let ty = self.parse_ty()?;
+                    let start = vis_token.map(|it| *it.span()).unwrap_or(*ty.span());
+                    fields.push(FieldDef {
+                        meta: self.mk_meta(Span::between(&start, &ty)),
+                        vis,
+                        name: fields.len().to_string(),
+                        ty,
+                    });
+                    if !self.at(RPAREN) {
+                        self.expect(COMMA, "Expected a comma between tuple struct fields")?;
+                    }
+                }
+                let rparen = self.expect(RPAREN, "Unexpected eof when parsing tuple fields")?;
+                Ok((VariantData::Tuple(fields), Some(rparen)))
+            }
+            _ => Ok((VariantData::Unit, None)),
+        }
+    }
+
+    fn parse_enum_variants(&mut self, generics: Vec<GenericParam>) -> PResult<(Enum, Token)> {
+        self.expect(LBRACE, "Expected enum variants")?;
+        let mut variants = vec![];
+        while !self.at(RBRACE) && !self.eof() {
+            let name = self.expect(IDENT, "Expected variant name")?;
+            let (data, end) = self.parse_variant_data()?;
+            let end = end.map(|it| it.span).unwrap_or(name.span);
+            variants.push(Variant {
+                meta: self.mk_meta(Span::between(&name, &end)),
+                name: name.text,
+                data,
+            });
+            if !self.at(RBRACE) {
+                self.expect(COMMA, "Expected a comma between enum variants")?;
             }
-            _ => todo!(
-                "Unexpected token while parsing item: {:?}",
-                self.current_kind()
-            ),
         }
+        let rbrace = self.expect(RBRACE, "Unexpected eof when parsing enum variants")?;
+        Ok((Enum { generics, variants }, rbrace))
     }
 
-    fn parse_foreign_mod(&mut self) -> (Token, ForeignMod, Token) {
-        let start = self.expect(EXTERN, "Parsing foreign mod");
-        self.expect(LBRACE, "Parsing foreign mod");
+    fn parse_foreign_mod(&mut self) -> PResult<(Token, ForeignMod, Token)> {
+        let start = self.expect(EXTERN, "Parsing foreign mod")?;
+        let abi = if self.at(STRING) {
+            Some(unquote(&self.advance().text))
+        } else {
+            None
+        };
+        self.expect(LBRACE, "Parsing foreign mod")?;
         let mut items = vec![];
         while !self.at(RBRACE) && !self.at(EOF) {
-            items.push(self.parse_foreign_item());
+            items.push(self.parse_foreign_item()?);
         }
-        let rbrace = self.expect(RBRACE, "Unexpected eof when parsing foreign mod");
-        (start, ForeignMod { items }, rbrace)
+        let rbrace = self.expect(RBRACE, "Unexpected eof when parsing foreign mod")?;
+        Ok((start, ForeignMod { abi, items }, rbrace))
     }
 
-    fn parse_foreign_item(&mut self) -> ForeignItem {
+    fn parse_foreign_item(&mut self) -> PResult<ForeignItem> {
+        let attrs = self.parse_attrs()?;
         let (visibility, vis_token) = self.parse_visibility();
-        let (f, name) = self.parse_fn();
+        let (f, name) = self.parse_fn()?;
         let start = vis_token.map(|it| *it.span()).unwrap_or_else(|| *f.span());
-        ForeignItem {
-            meta: Meta {
-                span: Span::between(&start, &f),
-            },
+        Ok(ForeignItem {
+            meta: self.mk_meta(Span::between(&start, &f)),
+            attrs,
             name,
             vis: visibility,
             kind: ForeignItemKind::Fn(f),
+        })
+    }
+
+    /// Parses the `#[...]` attributes before an item, param or statement
+    fn parse_attrs(&mut self) -> PResult<Vec<Attribute>> {
+        let mut attrs = vec![];
+        while self.at(POUND) {
+            self.advance();
+            self.expect(LBRACKET, "Expected `[` after `#`")?;
+            attrs.push(self.parse_attr()?);
+            self.expect(RBRACKET, "Expected `]` after an attribute")?;
         }
+        Ok(attrs)
+    }
+
+    /// Parses `name`, `name = "value"` or `name(args, ...)`
+    fn parse_attr(&mut self) -> PResult<Attribute> {
+        let name = self.expect(IDENT, "Expected an attribute name")?;
+        let (args, end) = match self.current_kind() {
+            EQ => {
+                self.advance();
+                let value = self.expect(STRING, "Expected a string after `=` in an attribute")?;
+                (AttrArgs::Eq(unquote(&value.text)), value.span)
+            }
+            LPAREN => {
+                self.advance();
+                let mut args = vec![];
+                while !self.at(RPAREN) && !self.eof() {
+                    args.push(self.parse_attr()?);
+                    if !self.at(RPAREN) {
+                        self.expect(COMMA, "Expected a comma between attribute arguments")?;
+                    }
+                }
+                let rparen =
+                    self.expect(RPAREN, "Unexpected eof when parsing attribute arguments")?;
+                (AttrArgs::List(args), rparen.span)
+            }
+            _ => (AttrArgs::Empty, name.span),
+        };
+        Ok(Attribute {
+            meta: self.mk_meta(Span::between(&name, &end)),
+            name: name.text,
+            args,
+        })
     }
 
     fn at(&self, kind: TokenKind) -> bool {
@@ -111,20 +571,21 @@ impl<'text> Parser<'text> {
         }
     }
 
-    fn parse_fn(&mut self) -> (Fn, Ident) {
-        let start = self.expect(TokenKind::FN, "Trying to parse function");
-        let name = self.expect(TokenKind::IDENT, "fn [name]").text;
-        let (params, rparen) = self.parse_params();
+    fn parse_fn(&mut self) -> PResult<(Fn, Ident)> {
+        let start = self.expect(TokenKind::FN, "Trying to parse function")?;
+        let name = self.expect(TokenKind::IDENT, "fn [name]")?.text;
+        let generics = self.parse_generics()?;
+        let (params, variadic, rparen) = self.parse_params()?;
         let return_ty = if self.current_kind() == TokenKind::ARROW {
             self.advance();
-            Some(self.parse_ty())
+            Some(self.parse_ty()?)
         } else {
             None
         };
         let body = if self.at(LBRACE) {
-            Some(self.parse_block_expr())
+            Some(self.parse_block_expr()?)
         } else {
-            self.expect(SEMI, "Expected a semicolon after a function without a body");
+            self.expect(SEMI, "Expected a semicolon after a function without a body")?;
             None
         };
         let end = body
@@ -132,217 +593,1740 @@ impl<'text> Parser<'text> {
             .map(|it| *it.span())
             .or(return_ty.as_ref().map(|it| *it.span()))
             .unwrap_or(*rparen.span());
-        (
+        Ok((
             Fn {
-                meta: Meta {
-                    span: Span::between(&start, &end),
-                },
-                body: body.map(|it| Box::new(it)),
+                meta: self.mk_meta(Span::between(&start, &end)),
+                body: body.map(Box::new),
+                generics,
                 params,
+                variadic,
                 return_ty,
             },
             name,
-        )
+        ))
+    }
+
+    /// `<T, U: Show>` after the name of a function, struct or enum, or after `impl`
+    fn parse_generics(&mut self) -> PResult<Vec<GenericParam>> {
+        let mut generics = vec![];
+        if !self.at(LT) {
+            return Ok(generics);
+        }
+        self.advance();
+        while !self.at(GT) && !self.eof() {
+            let name = self.expect(IDENT, "Expected a type parameter name")?;
+            let mut bounds = vec![];
+            if self.at(COLON) {
+                self.advance();
+                bounds.push(self.parse_trait_ref()?);
+                while self.at(PLUS) {
+                    self.advance();
+                    bounds.push(self.parse_trait_ref()?);
+                }
+            }
+            let end = bounds.last().map(|it| *it.span()).unwrap_or(name.span);
+            generics.push(GenericParam {
+                meta: self.mk_meta(Span::between(&name, &end)),
+                name: name.text,
+                bounds,
+            });
+            if !self.at(GT) {
+                self.expect(COMMA, "Expected a comma or `>`")?;
+            }
+        }
+        self.expect(GT, "Unexpected EOF while looking for `>`")?;
+        Ok(generics)
     }
 
     /// Returns the closing parenthesis token along with the parameters
-    fn parse_params(&mut self) -> (Vec<Param>, Token) {
-        self.expect(LPAREN, "Expected parameter list start");
+    /// Parses the parameter list of a function, and whether it ends with `...`
+    fn parse_params(&mut self) -> PResult<(Vec<Param>, bool, Token)> {
+        self.expect(LPAREN, "Expected parameter list start")?;
 
         let mut params = vec![];
+        let mut variadic = false;
 
         while !self.at(RPAREN) && !self.eof() {
-            let name = self.expect(IDENT, "Expected parameter name");
-            self.expect(COLON, "Expected parameter type separator");
-            let ty = self.parse_ty();
+            if self.at(DOTDOTDOT) {
+                self.advance();
+                variadic = true;
+                break;
+            }
+            let attrs = self.parse_attrs()?;
+            let name = self.expect(IDENT, "Expected parameter name")?;
+            self.expect(COLON, "Expected parameter type separator")?;
+            let ty = self.parse_ty()?;
             params.push(Param {
-                meta: Meta {
-                    span: Span::between(&name, &ty),
-                },
+                meta: self.mk_meta(Span::between(&name, &ty)),
+                attrs,
                 name: name.text,
                 ty,
             });
+            if !self.at(RPAREN) {
+                self.expect(COMMA, "Expected a comma between parameters")?;
+            }
         }
 
-        let rparen = self.expect(TokenKind::RPAREN, "Expected parameter list end");
+        let rparen = self.expect(TokenKind::RPAREN, "Expected parameter list end")?;
+
+        Ok((params, variadic, rparen))
+    }
 
-        (params, rparen)
+    fn parse_block(&mut self) -> PResult<Block> {
+        let start = self.expect(TokenKind::LBRACE, "Trying to parse block")?;
+        Ok(self.parse_block_rest(start, false))
     }
 
-    fn parse_block(&mut self) -> Block {
-        let start = self.expect(TokenKind::LBRACE, "Trying to parse block");
+    /// Parses the statements and closing brace of a block whose `{` is `start`
+    fn parse_block_rest(&mut self, start: Token, is_unsafe: bool) -> Block {
         let mut stmts = vec![];
-        while self.current_kind() != TokenKind::RBRACE && !self.eof() {
-            stmts.push(self.parse_stmt());
+        // Items can't be in blocks, so one means that the block isn't closed
+        while !self.at(RBRACE) && !self.eof() && !starts_item(self.current_kind()) {
+            let (start, depth) = (self.current_span().start, self.depth);
+            let stmt = self.recover(|this| this.with_struct_literals(true, Self::parse_stmt));
+            match stmt {
+                Some(stmt) => stmts.push(stmt),
+                None => self.skip_stmt(start, depth),
+            }
         }
 
-        let end = self.expect(
-            TokenKind::RBRACE,
-            "Unexpected end of file while looking for block terminator",
-        );
+        let end = if self.at(RBRACE) {
+            self.advance().span
+        } else {
+            // The block extends to the item or end of file that was reached
+            let span = self.current_span();
+            let message = "Parse error: Expected `}` at the end of the block";
+            self.report(Diagnostic::new(span, message));
+            Span {
+                start: span.start,
+                end: span.start,
+            }
+        };
         Block {
-            meta: Meta {
-                span: Span::between(&start, &end),
-            },
+            meta: self.mk_meta(Span::between(&start, &end)),
             stmts,
+            is_unsafe,
         }
     }
 
-    fn parse_block_expr(&mut self) -> Expr {
-        let block = self.parse_block();
-        Expr {
-            meta: Meta {
-                span: *block.span(),
-            },
+    /// Skips the rest of a statement with a syntax error, up to its `;`, the `}`
+    /// of a block-like statement, or the `}` closing the enclosing block. `start`
+    /// and `depth` are where the statement started.
+    fn skip_stmt(&mut self, start: usize, depth: usize) {
+        if self.current_span().start == start {
+            self.advance();
+        }
+        while !self.eof() && !starts_item(self.current_kind()) {
+            if self.depth == depth && self.at(RBRACE) {
+                break;
+            }
+            let token = self.advance();
+            if self.depth == depth && matches!(token.kind, SEMI | RBRACE) {
+                break;
+            }
+        }
+    }
+
+    fn parse_block_expr(&mut self) -> PResult<Expr> {
+        let block = self.parse_block()?;
+        Ok(Expr {
+            meta: self.mk_meta(*block.span()),
             kind: ExprKind::Block(block),
+        })
+    }
+
+    fn parse_unsafe_block_expr(&mut self) -> PResult<Expr> {
+        let start = self.expect(UNSAFE, "Trying to parse unsafe block")?;
+        self.expect(LBRACE, "Expected a block after `unsafe`")?;
+        let block = self.parse_block_rest(start, true);
+        Ok(Expr {
+            meta: self.mk_meta(*block.span()),
+            kind: ExprKind::Block(block),
+        })
+    }
+
+    fn parse_stmt(&mut self) -> PResult<Stmt> {
+        let attrs = self.parse_attrs()?;
+        match self.current_kind() {
+            // Like Rust, a block-like expression in statement position ends the
+            // statement, so `{ ... } *p = 1` is not parsed as a multiplication.
+            LBRACE | UNSAFE | MATCH => {
+                let expr = match self.current_kind() {
+                    LBRACE => self.parse_block_expr()?,
+                    UNSAFE => self.parse_unsafe_block_expr()?,
+                    _ => self.parse_match_expr()?,
+                };
+                Ok(Stmt {
+                    meta: self.mk_meta(*expr.span()),
+                    attrs,
+                    kind: StmtKind::Expr(Box::new(expr)),
+                })
+            }
+            SEMI => {
+                let tok = self.advance();
+                Ok(Stmt {
+                    meta: self.mk_meta(*tok.span()),
+                    attrs,
+                    kind: StmtKind::Semi,
+                })
+            }
+            LET => {
+                let local = self.parse_local()?;
+                Ok(Stmt {
+                    meta: self.mk_meta(*local.span()),
+                    attrs,
+                    kind: StmtKind::Let(Box::new(local)),
+                })
+            }
+            _ => {
+                let expr = self.parse_expr()?;
+                Ok(Stmt {
+                    meta: self.mk_meta(*expr.span()),
+                    attrs,
+                    kind: StmtKind::Expr(Box::new(expr)),
+                })
+            }
         }
     }
 
-    fn parse_stmt(&mut self) -> Stmt {
-        if self.current_kind() == TokenKind::SEMI {
-            let tok = self.advance();
-            return Stmt {
-                meta: Meta { span: *tok.span() },
-                kind: StmtKind::Semi,
+    fn parse_local(&mut self) -> PResult<Local> {
+        let start = self.expect(LET, "Trying to parse let statement")?;
+        let pat = self.parse_pat()?;
+        let ty = if self.at(COLON) {
+            self.advance();
+            Some(self.parse_ty()?)
+        } else {
+            None
+        };
+        let init = if self.at(EQ) {
+            self.advance();
+            Some(Box::new(self.parse_expr()?))
+        } else {
+            None
+        };
+        let end = self.expect(SEMI, "Expected a semicolon after a let statement")?;
+        Ok(Local {
+            meta: self.mk_meta(Span::between(&start, &end)),
+            pat,
+            ty,
+            init,
+        })
+    }
+
+    fn parse_expr(&mut self) -> PResult<Expr> {
+        let lhs = self.parse_binary_expr(0)?;
+        if self.at(EQ) {
+            self.advance();
+            // Assignment is right associative; a = b = c is a = (b = c)
+            let rhs = self.parse_expr()?;
+            return Ok(Expr {
+                meta: self.mk_meta(Span::between(&lhs, &rhs)),
+                kind: ExprKind::Assign(Box::new(lhs), Box::new(rhs)),
+            });
+        }
+        Ok(lhs)
+    }
+
+    /// Precedence climbing over binary operators and `as` casts;
+    /// Only operators binding at least as tightly as `min_prec` are consumed.
+    fn parse_binary_expr(&mut self, min_prec: u8) -> PResult<Expr> {
+        let mut lhs = self.parse_unary_expr()?;
+        loop {
+            let kind = self.current_kind();
+            if kind == AS {
+                if AS_PREC < min_prec {
+                    break;
+                }
+                self.advance();
+                let ty = self.parse_ty()?;
+                lhs = Expr {
+                    meta: self.mk_meta(Span::between(&lhs, &ty)),
+                    kind: ExprKind::Cast(Box::new(lhs), ty),
+                };
+                continue;
+            }
+            let Some((op, prec)) = binary_op(kind) else {
+                break;
+            };
+            if prec < min_prec {
+                break;
+            }
+            self.advance();
+            let rhs = self.parse_binary_expr(prec + 1)?;
+            if op.is_comparison()
+                && binary_op(self.current_kind()).is_some_and(|it| it.0.is_comparison())
+            {
+                return Err(self.error("Comparison operators cannot be chained; Use parentheses"));
+            }
+            lhs = Expr {
+                meta: self.mk_meta(Span::between(&lhs, &rhs)),
+                kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
             };
         }
-        let expr = self.parse_expr();
-        Stmt {
-            meta: Meta { span: *expr.span() },
-            kind: StmtKind::Expr(Box::new(expr)),
+        Ok(lhs)
+    }
+
+    fn parse_unary_expr(&mut self) -> PResult<Expr> {
+        let kind = match self.current_kind() {
+            MINUS => Some(UnOp::Neg),
+            BANG => Some(UnOp::Not),
+            STAR => Some(UnOp::Deref),
+            AMP => None,
+            _ => return self.parse_postfix_expr(),
+        };
+        let start = self.advance();
+        let expr_kind = match kind {
+            Some(op) => {
+                let operand = self.parse_unary_expr()?;
+                ExprKind::Unary(op, Box::new(operand))
+            }
+            None => {
+                let mutability = self.parse_mutability();
+                let operand = self.parse_unary_expr()?;
+                ExprKind::AddrOf(mutability, Box::new(operand))
+            }
+        };
+        let end = match &expr_kind {
+            ExprKind::Unary(_, operand) | ExprKind::AddrOf(_, operand) => *operand.span(),
+            _ => unreachable!(),
+        };
+        Ok(Expr {
+            meta: self.mk_meta(Span::between(&start, &end)),
+            kind: expr_kind,
+        })
+    }
+
+    fn parse_mutability(&mut self) -> Mutability {
+        if self.at(MUT) {
+            self.advance();
+            Mutability::Mut
+        } else {
+            Mutability::Not
         }
     }
 
-    fn parse_expr(&mut self) -> Expr {
+    fn with_struct_literals<T>(&mut self, allowed: bool, f: impl FnOnce(&mut Self) -> T) -> T {
+        let old = std::mem::replace(&mut self.no_struct_literal, !allowed);
+        let result = f(self);
+        self.no_struct_literal = old;
+        result
+    }
+
+    fn parse_postfix_expr(&mut self) -> PResult<Expr> {
         let head = match self.current_kind() {
             TokenKind::IDENT => {
-                let token = self.advance();
-                Expr {
-                    meta: Meta {
-                        span: *token.span(),
-                    },
-                    kind: ExprKind::Var(Var { name: token.text }),
+                let (path, start, end) = self.parse_path()?;
+                if self.at(LBRACE) && !self.no_struct_literal {
+                    self.parse_struct_expr(path, start)?
+                } else if path.segments.len() == 1 {
+                    Expr {
+                        meta: self.mk_meta(Span::between(&start, &end)),
+                        kind: ExprKind::Var(Var {
+                            name: path.segments.into_iter().next().unwrap(),
+                        }),
+                    }
+                } else {
+                    Expr {
+                        meta: self.mk_meta(Span::between(&start, &end)),
+                        kind: ExprKind::Path(path),
+                    }
                 }
             }
+            TokenKind::MATCH => self.parse_match_expr()?,
             TokenKind::LPAREN => {
-                let start = self.advance();
-                let end = self.expect(TokenKind::RPAREN, "Trying to parse unit expression");
+                let (start, mut exprs, has_comma, end) = self
+                    .with_struct_literals(true, |this| {
+                        this.parse_parenthesized(|this| this.parse_expr())
+                    })?;
+                let kind = match exprs.len() {
+                    0 => ExprKind::Unit,
+                    1 if !has_comma => ExprKind::Paren(Box::new(exprs.pop().unwrap())),
+                    _ => ExprKind::Tup(exprs),
+                };
                 Expr {
-                    meta: Meta {
-                        span: Span::between(&start, &end),
-                    },
-                    kind: ExprKind::Unit,
+                    meta: self.mk_meta(Span::between(&start, &end)),
+                    kind,
                 }
             }
-            TokenKind::UNSAFE => {
-                let start = self.advance();
-                let block = self.parse_block();
+            TokenKind::LBRACE => self.parse_block_expr()?,
+            TokenKind::UNSAFE => self.parse_unsafe_block_expr()?,
+            TokenKind::LBRACKET => {
+                self.with_struct_literals(true, |this| this.parse_array_expr())?
+            }
+            INT | TRUE | FALSE | NULL => {
+                let (lit, span) = self.parse_lit()?;
                 Expr {
-                    meta: Meta {
-                        span: Span::between(&start, &block),
-                    },
-                    kind: ExprKind::Block(block),
+                    meta: self.mk_meta(span),
+                    kind: ExprKind::Lit(lit),
                 }
             }
-            INT => {
-                let token = self.advance();
+            _ => return Err(self.error("Expected an expression")),
+        };
+        self.parse_expr_tail(head)
+    }
+
+    /// Parses `(a, b, ...)` with an optional trailing comma. Also returns whether
+    /// the list contains a comma, to tell `(a)` apart from the tuple `(a,)`.
+    fn parse_parenthesized<T>(
+        &mut self,
+        mut parse_elem: impl FnMut(&mut Self) -> PResult<T>,
+    ) -> PResult<(Token, Vec<T>, bool, Token)> {
+        let start = self.expect(LPAREN, "Expected `(`")?;
+        let mut elems = vec![];
+        let mut has_comma = false;
+        while !self.at(RPAREN) && !self.eof() {
+            elems.push(parse_elem(self)?);
+            if !self.at(RPAREN) {
+                self.expect(COMMA, "Expected a comma or `)`")?;
+                has_comma = true;
+            }
+        }
+        let end = self.expect(RPAREN, "Unexpected EOF while looking for `)`")?;
+        Ok((start, elems, has_comma, end))
+    }
+
+    /// `[a, b, c]` or `[value; len]`
+    fn parse_array_expr(&mut self) -> PResult<Expr> {
+        let start = self.expect(LBRACKET, "Trying to parse array expression")?;
+        let mut elems = vec![];
+        if !self.at(RBRACKET) {
+            let first = self.parse_expr()?;
+            if self.at(SEMI) {
+                self.advance();
+                let len = self.parse_expr()?;
+                let end = self.expect(RBRACKET, "Expected `]` after array length")?;
+                return Ok(Expr {
+                    meta: self.mk_meta(Span::between(&start, &end)),
+                    kind: ExprKind::Repeat(Box::new(first), Box::new(len)),
+                });
+            }
+            elems.push(first);
+            while self.at(COMMA) {
+                self.advance();
+                if self.at(RBRACKET) {
+                    break;
+                }
+                elems.push(self.parse_expr()?);
+            }
+        }
+        let end = self.expect(RBRACKET, "Expected `]` after array elements")?;
+        Ok(Expr {
+            meta: self.mk_meta(Span::between(&start, &end)),
+            kind: ExprKind::Array(elems),
+        })
+    }
+
+    fn parse_lit(&mut self) -> PResult<(Lit, Span)> {
+        let kind = match self.current_kind() {
+            INT => LitKind::Integer,
+            TRUE | FALSE => LitKind::Bool,
+            NULL => LitKind::Null,
+            _ => return Err(self.error("Expected a literal")),
+        };
+        let token = self.advance();
+        Ok((
+            Lit {
+                kind,
+                text: token.text,
+            },
+            token.span,
+        ))
+    }
+
+    /// Returns the path along with its first and last tokens
+    fn parse_path(&mut self) -> PResult<(Path, Token, Token)> {
+        let start = self.expect(IDENT, "Expected a name")?;
+        let mut segments = vec![start.text.clone()];
+        let mut end = None;
+        while self.at(COLONCOLON) {
+            self.advance();
+            let segment = self.expect(IDENT, "Expected a name after `::`")?;
+            segments.push(segment.text.clone());
+            end = Some(segment);
+        }
+        let end = end.unwrap_or_else(|| Token {
+            kind: start.kind,
+            span: start.span,
+            text: start.text.clone(),
+        });
+        Ok((Path { segments }, start, end))
+    }
+
+    fn parse_struct_expr(&mut self, path: Path, start: Token) -> PResult<Expr> {
+        self.expect(LBRACE, "Trying to parse struct expression")?;
+        let mut fields = vec![];
+        while !self.at(RBRACE) && !self.eof() {
+            let field_name = self.expect(IDENT, "Expected a field name")?;
+            let expr = if self.at(COLON) {
+                self.advance();
+                self.with_struct_literals(true, |this| this.parse_expr())?
+            } else {
+                // Shorthand: `Point { x, y }` is the same as `Point { x: x, y: y }`
                 Expr {
-                    meta: Meta {
-                        span: *token.span(),
-                    },
-                    kind: ExprKind::Lit(Lit {
-                        kind: LitKind::Integer,
-                        text: token.text,
+                    meta: self.mk_meta(field_name.span),
+                    kind: ExprKind::Var(Var {
+                        name: field_name.text.clone(),
                     }),
                 }
+            };
+            fields.push(ExprField {
+                meta: self.mk_meta(Span::between(&field_name, &expr)),
+                name: field_name.text,
+                expr,
+            });
+            if !self.at(RBRACE) {
+                self.expect(COMMA, "Expected a comma between struct expression fields")?;
             }
-            k => todo!("Unexpected token while parsing expression: {:?}", k),
-        };
-        self.parse_expr_tail(head)
+        }
+        let end = self.expect(RBRACE, "Unexpected EOF while parsing struct expression")?;
+        Ok(Expr {
+            meta: self.mk_meta(Span::between(&start, &end)),
+            kind: ExprKind::Struct(StructExpr { path, fields }),
+        })
     }
 
-    fn parse_expr_tail(&mut self, head: Expr) -> Expr {
+    fn parse_match_expr(&mut self) -> PResult<Expr> {
+        let start = self.expect(MATCH, "Trying to parse match expression")?;
+        let scrutinee = self.with_struct_literals(false, |this| this.parse_expr())?;
+        self.expect(LBRACE, "Expected match arms")?;
+        let mut arms = vec![];
+        while !self.at(RBRACE) && !self.eof() {
+            let pat = self.parse_pat()?;
+            let guard = if self.at(IF) {
+                self.advance();
+                Some(Box::new(self.parse_expr()?))
+            } else {
+                None
+            };
+            self.expect(FATARROW, "Expected `=>` after match arm pattern")?;
+            let body = self.with_struct_literals(true, |this| this.parse_expr())?;
+            let is_block = matches!(body.kind, ExprKind::Block(_) | ExprKind::Match(..));
+            arms.push(Arm {
+                meta: self.mk_meta(Span::between(&pat, &body)),
+                pat,
+                guard,
+                body: Box::new(body),
+            });
+            // Like Rust, the comma is optional after arms with block bodies
+            if self.at(COMMA) || !(is_block || self.at(RBRACE)) {
+                self.expect(COMMA, "Expected a comma between match arms")?;
+            }
+        }
+        let end = self.expect(RBRACE, "Unexpected EOF while parsing match arms")?;
+        Ok(Expr {
+            meta: self.mk_meta(Span::between(&start, &end)),
+            kind: ExprKind::Match(Box::new(scrutinee), arms),
+        })
+    }
+
+    fn parse_pat(&mut self) -> PResult<Pat> {
         match self.current_kind() {
+            INT | TRUE | FALSE => {
+                let (lit, span) = self.parse_lit()?;
+                Ok(Pat {
+                    meta: self.mk_meta(span),
+                    kind: PatKind::Lit(lit),
+                })
+            }
             LPAREN => {
+                let (start, mut pats, has_comma, end) =
+                    self.parse_parenthesized(Self::parse_pat)?;
+                if pats.len() == 1 && !has_comma {
+                    return Ok(pats.pop().unwrap());
+                }
+                Ok(Pat {
+                    meta: self.mk_meta(Span::between(&start, &end)),
+                    kind: PatKind::Tuple(pats),
+                })
+            }
+            IDENT => {
+                let (path, start, end) = self.parse_path()?;
+                match self.current_kind() {
+                    LPAREN => {
+                        self.advance();
+                        let mut pats = vec![];
+                        while !self.at(RPAREN) && !self.eof() {
+                            pats.push(self.parse_pat()?);
+                            if !self.at(RPAREN) {
+                                self.expect(COMMA, "Expected a comma between patterns")?;
+                            }
+                        }
+                        let end = self.expect(RPAREN, "Unexpected EOF while parsing pattern")?;
+                        Ok(Pat {
+                            meta: self.mk_meta(Span::between(&start, &end)),
+                            kind: PatKind::TupleStruct(path, pats),
+                        })
+                    }
+                    LBRACE => self.parse_struct_pat(path, start),
+                    _ if path.segments.len() == 1 => {
+                        let kind = if start.text == "_" {
+                            PatKind::Wild
+                        } else {
+                            PatKind::Binding(start.text.clone())
+                        };
+                        Ok(Pat {
+                            meta: self.mk_meta(start.span),
+                            kind,
+                        })
+                    }
+                    _ => Ok(Pat {
+                        meta: self.mk_meta(Span::between(&start, &end)),
+                        kind: PatKind::Path(path),
+                    }),
+                }
+            }
+            _ => Err(self.error("Expected a pattern")),
+        }
+    }
+
+    fn parse_struct_pat(&mut self, path: Path, start: Token) -> PResult<Pat> {
+        self.expect(LBRACE, "Trying to parse struct pattern")?;
+        let mut fields = vec![];
+        let mut has_rest = false;
+        while !self.at(RBRACE) && !self.eof() {
+            if self.at(DOTDOT) {
                 self.advance();
-                let mut exprs = vec![];
-                while self.current_kind() != RPAREN && self.current_kind() != EOF {
-                    exprs.push(self.parse_expr());
+                has_rest = true;
+                break;
+            }
+            let name = self.expect(IDENT, "Expected a field name")?;
+            let pat = if self.at(COLON) {
+                self.advance();
+                self.parse_pat()?
+            } else {
+                // Shorthand: `Point { x }` binds the field `x` to a local named `x`
+                Pat {
+                    meta: self.mk_meta(name.span),
+                    kind: PatKind::Binding(name.text.clone()),
                 }
-                let end = self.expect(
-                    RPAREN,
-                    "Unexpected EOF while trying to parse call arguments",
-                );
-                Expr {
-                    meta: Meta {
-                        span: Span::between(&head, &end),
-                    },
+            };
+            fields.push(PatField {
+                meta: self.mk_meta(Span::between(&name, &pat)),
+                name: name.text,
+                pat,
+            });
+            if !self.at(RBRACE) {
+                self.expect(COMMA, "Expected a comma between field patterns")?;
+            }
+        }
+        let end = self.expect(RBRACE, "Unexpected EOF while parsing struct pattern")?;
+        Ok(Pat {
+            meta: self.mk_meta(Span::between(&start, &end)),
+            kind: PatKind::Struct(path, fields, has_rest),
+        })
+    }
+
+    fn parse_expr_tail(&mut self, head: Expr) -> PResult<Expr> {
+        match self.current_kind() {
+            LPAREN => {
+                let (exprs, end) = self.parse_call_args()?;
+                let expr = Expr {
+                    meta: self.mk_meta(Span::between(&head, &end)),
                     kind: ExprKind::Call(Box::new(head), exprs),
+                };
+                self.parse_expr_tail(expr)
+            }
+            DOT => {
+                self.advance();
+                let field = match self.current_kind() {
+                    IDENT | INT => self.advance(),
+                    _ => self.expect(IDENT, "Expected a field name after `.`")?,
+                };
+                if field.kind == IDENT && self.at(LPAREN) {
+                    let (exprs, end) = self.parse_call_args()?;
+                    let expr = Expr {
+                        meta: self.mk_meta(Span::between(&head, &end)),
+                        kind: ExprKind::MethodCall(Box::new(head), field.text, exprs),
+                    };
+                    return self.parse_expr_tail(expr);
                 }
+                let expr = Expr {
+                    meta: self.mk_meta(Span::between(&head, &field)),
+                    kind: ExprKind::Field(Box::new(head), field.text),
+                };
+                self.parse_expr_tail(expr)
+            }
+            LBRACKET => {
+                self.advance();
+                let index = self.with_struct_literals(true, |this| this.parse_expr())?;
+                let end = self.expect(RBRACKET, "Expected `]` after index")?;
+                let expr = Expr {
+                    meta: self.mk_meta(Span::between(&head, &end)),
+                    kind: ExprKind::Index(Box::new(head), Box::new(index)),
+                };
+                self.parse_expr_tail(expr)
             }
-            _ => head,
+            _ => Ok(head),
         }
     }
 
-    fn parse_ty(&mut self) -> Ty {
+    /// Parses `(a, b)` after a callee, returning the closing paren
+    fn parse_call_args(&mut self) -> PResult<(Vec<Expr>, Token)> {
+        self.expect(LPAREN, "Expected `(` before call arguments")?;
+        let mut exprs = vec![];
+        while self.current_kind() != RPAREN && self.current_kind() != EOF {
+            exprs.push(self.with_struct_literals(true, |this| this.parse_expr())?);
+            if !self.at(RPAREN) {
+                self.expect(COMMA, "Expected a comma between call arguments")?;
+            }
+        }
+        let end = self.expect(
+            RPAREN,
+            "Unexpected EOF while trying to parse call arguments",
+        )?;
+        Ok((exprs, end))
+    }
+
+    fn parse_ty(&mut self) -> PResult<Ty> {
         match self.current_kind() {
             LPAREN => {
-                let start = self.expect(TokenKind::LPAREN, "Trying to parse unit type");
-
-                let end = self.expect(TokenKind::RPAREN, "Trying to parse end of unit type");
-                Ty {
-                    meta: Meta {
-                        span: Span::between(&start, &end),
-                    },
-                    kind: TyKind::Tup(vec![]),
+                let (start, mut tys, has_comma, end) = self.parse_parenthesized(Self::parse_ty)?;
+                if tys.len() == 1 && !has_comma {
+                    return Ok(tys.pop().unwrap());
                 }
+                Ok(Ty {
+                    meta: self.mk_meta(Span::between(&start, &end)),
+                    kind: TyKind::Tup(tys),
+                })
             }
             IDENT => {
-                let token = self.advance();
-                Ty {
-                    meta: Meta {
-                        span: *token.span(),
-                    },
-                    kind: TyKind::Var(token.text),
+                let (path, token, end) = self.parse_path()?;
+                let mut args = vec![];
+                let mut end = end.span;
+                if self.at(LT) {
+                    self.advance();
+                    while !self.at(GT) && !self.eof() {
+                        args.push(self.parse_ty()?);
+                        if !self.at(GT) {
+                            self.expect(COMMA, "Expected a comma or `>`")?;
+                        }
+                    }
+                    end = self
+                        .expect(GT, "Unexpected EOF while looking for `>`")?
+                        .span;
                 }
+                let kind = match <[_; 1]>::try_from(path.segments) {
+                    Ok([name]) => TyKind::Var(name, args),
+                    Err(segments) => TyKind::Path(Path { segments }, args),
+                };
+                Ok(Ty {
+                    meta: self.mk_meta(Span::between(&token, &end)),
+                    kind,
+                })
+            }
+            STAR => {
+                let start = self.advance();
+                let mutability = self.parse_mutability();
+                let inner = self.parse_ty()?;
+                Ok(Ty {
+                    meta: self.mk_meta(Span::between(&start, &inner)),
+                    kind: TyKind::Ptr(mutability, Box::new(inner)),
+                })
             }
-            _ => todo!("Parsing type"),
+            LBRACKET => {
+                let start = self.advance();
+                let elem = Box::new(self.parse_ty()?);
+                let kind = if self.at(SEMI) {
+                    self.advance();
+                    TyKind::Array(elem, Box::new(self.parse_expr()?))
+                } else {
+                    TyKind::Slice(elem)
+                };
+                let end = self.expect(RBRACKET, "Expected `]` after array or slice type")?;
+                Ok(Ty {
+                    meta: self.mk_meta(Span::between(&start, &end)),
+                    kind,
+                })
+            }
+            DYN => {
+                let start = self.advance();
+                let trait_ref = self.parse_trait_ref()?;
+                Ok(Ty {
+                    meta: self.mk_meta(Span::between(&start, &trait_ref)),
+                    kind: TyKind::Dyn(trait_ref),
+                })
+            }
+            _ => Err(self.error("Expected a type")),
         }
     }
 
+    fn mk_meta(&mut self, span: Span) -> Meta {
+        let id = NodeId(self.next_node_id);
+        self.next_node_id += 1;
+        Meta { span, id }
+    }
+
     fn eof(&self) -> bool {
         self.current_kind() == TokenKind::EOF
     }
 
-    fn expect(&mut self, kind: TokenKind, message: &str) -> Token {
-        let token = self.advance();
-        if token.kind != kind {
-            panic!(
-                "Parse error: {}\nExpected: {:?}; Found: '{}' ({:?});",
-                message, kind, token.text, token.kind
-            )
+    fn expect(&mut self, kind: TokenKind, message: &str) -> PResult<Token> {
+        if !self.at(kind) {
+            return Err(self.error(message));
         }
-        token
+        Ok(self.advance())
     }
 
     fn current_kind(&self) -> TokenKind {
         self.tokens.current_kind()
     }
 
+    fn current_span(&self) -> Span {
+        self.tokens.current().span
+    }
+
     fn advance(&mut self) -> Token {
+        match self.current_kind() {
+            LBRACE => self.depth += 1,
+            RBRACE => self.depth = self.depth.saturating_sub(1),
+            _ => {}
+        }
         self.tokens.advance()
     }
 }
 
+/// A file parsed one item at a time, which [ParsedFile::reparse] updates after
+/// an edit by parsing only the items around the edit again
+#[derive(PartialEq)]
+pub struct ParsedFile {
+    pub path: PathBuf,
+    pub items: Vec<Item>,
+    chunks: Vec<Chunk>,
+    /// The length of the text in chars
+    len: usize,
+}
+
+/// The tokens that parsing an item, or skipping tokens that don't start one,
+/// went through
+#[derive(PartialEq)]
+struct Chunk {
+    /// From the start of the first token consumed to the end of the last one
+    span: Span,
+    /// The end of the furthest token looked at
+    lookahead: usize,
+    node_ids: Range<u32>,
+    /// The number of `{` left open after the chunk
+    depth: usize,
+    has_item: bool,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl ParsedFile {
+    pub fn parse(text: &str, path: PathBuf) -> Self {
+        let mut file = ParsedFile {
+            path,
+            items: vec![],
+            chunks: vec![],
+            len: 0,
+        };
+        file.reparse(text, Span { start: 0, end: 0 });
+        file
+    }
+
+    /// Updates the file after the chars in `edit` were replaced to give `text`.
+    /// Items that end before the edit are kept, and parsing stops once it gets
+    /// to an item after the edit in the same state it was parsed in before,
+    /// with the rest of the items moved by the change in length.
+    pub fn reparse(&mut self, text: &str, edit: Span) {
+        let delta = text.chars().count() as isize - self.len as isize;
+        self.len = text.chars().count();
+        // Edits right after a token can extend it
+        let kept = self
+            .chunks
+            .iter()
+            .take_while(|it| it.lookahead < edit.start)
+            .count();
+        let kept_items = self.chunks[..kept].iter().filter(|it| it.has_item).count();
+        let mut old_chunks = self.chunks.split_off(kept).into_iter().peekable();
+        let mut old_items = self.items.split_off(kept_items).into_iter();
+
+        let last = self.chunks.last();
+        let (start, mut depth) = last.map_or((0, 0), |it| (it.span.end, it.depth));
+        let next_node_id = last.map_or(0, |it| it.node_ids.end);
+        let byte_start = text.char_indices().nth(start).map_or(text.len(), |it| it.0);
+        let mut parser =
+            Parser::for_module(&text[byte_start..], self.path.clone(), start, next_node_id);
+        parser.depth = depth;
+        while !parser.eof() {
+            let position = parser.current_span().start as isize;
+            // Chunks after the edit are lexed the same way, so they're parsed the
+            // same way if they start at the same token with the same `{` open
+            while let Some(old) = old_chunks.next_if(|it| {
+                it.span.start < edit.end || (it.span.start as isize + delta) < position
+            }) {
+                if old.has_item {
+                    old_items.next();
+                }
+                depth = old.depth;
+            }
+            if let Some(old) = old_chunks.peek() {
+                if old.span.start as isize + delta == position && depth == parser.depth {
+                    let id_delta = parser.next_node_id as i64 - old.node_ids.start as i64;
+                    self.move_chunks(old_chunks, old_items, delta, id_delta);
+                    return;
+                }
+            }
+            self.chunks.push(parser.parse_chunk(&mut self.items));
+        }
+    }
+
+    /// Adds the chunks and items of the old text after an edit, moved by the
+    /// change in length and in the number of nodes
+    fn move_chunks(
+        &mut self,
+        chunks: impl Iterator<Item = Chunk>,
+        items: impl Iterator<Item = Item>,
+        delta: isize,
+        id_delta: i64,
+    ) {
+        let move_span = |span: &mut Span| {
+            span.start = span.start.checked_add_signed(delta).unwrap();
+            span.end = span.end.checked_add_signed(delta).unwrap();
+        };
+        let move_id = |id: u32| (id as i64 + id_delta) as u32;
+        for mut chunk in chunks {
+            move_span(&mut chunk.span);
+            chunk.lookahead = chunk.lookahead.checked_add_signed(delta).unwrap();
+            chunk.node_ids = move_id(chunk.node_ids.start)..move_id(chunk.node_ids.end);
+            for diagnostic in &mut chunk.diagnostics {
+                move_span(&mut diagnostic.span);
+            }
+            self.chunks.push(chunk);
+        }
+        for mut item in items {
+            item.walk_meta(&mut |meta| {
+                move_span(&mut meta.span);
+                meta.id = NodeId(move_id(meta.id.0));
+            });
+            self.items.push(item);
+        }
+    }
+
+    /// The syntax errors in the file, as [Parser::take_diagnostics] would give them
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics: Vec<Diagnostic> = vec![];
+        for diagnostic in self.chunks.iter().flat_map(|it| &it.diagnostics) {
+            if diagnostics.last().map(|it| it.span.start) != Some(diagnostic.span.start) {
+                diagnostics.push(diagnostic.clone());
+            }
+        }
+        diagnostics
+    }
+
+    /// The id the next node will get; Files parsed after this one start from it
+    pub fn next_node_id(&self) -> u32 {
+        self.chunks.last().map_or(0, |it| it.node_ids.end)
+    }
+
+    /// The items and syntax errors as [Parser::for_module] would give them for
+    /// the file at offset `start` with ids from `first_node_id`
+    pub fn moved_to(&self, start: usize, first_node_id: u32) -> (Vec<Item>, Vec<Diagnostic>) {
+        let mut items = self.items.clone();
+        for item in items.iter_mut() {
+            item.walk_meta(&mut |meta| {
+                meta.span.start += start;
+                meta.span.end += start;
+                meta.id.0 += first_node_id;
+            });
+        }
+        let mut diagnostics = self.diagnostics();
+        for diagnostic in diagnostics.iter_mut() {
+            diagnostic.span.start += start;
+            diagnostic.span.end += start;
+        }
+        (items, diagnostics)
+    }
+}
+
+impl Parser<'_> {
+    /// Parses an item like [Parser::parse_items] does, recording what it went
+    /// through. Errors are only compared with the chunk's own, and
+    /// [ParsedFile::diagnostics] compares the rest.
+    fn parse_chunk(&mut self, items: &mut Vec<Item>) -> Chunk {
+        let (start, first_node_id) = (self.current_span().start, self.next_node_id);
+        self.tokens.lookahead.set(0);
+        let item = self.parse_item_or_recover();
+        let has_item = item.is_some();
+        items.extend(item);
+        Chunk {
+            span: Span {
+                start,
+                end: self.tokens.end,
+            },
+            lookahead: self.tokens.lookahead.get(),
+            node_ids: first_node_id..self.next_node_id,
+            depth: self.depth,
+            has_item,
+            diagnostics: self.take_diagnostics(),
+        }
+    }
+}
+
+/// The contents of a string literal token, with escapes replaced
+fn unquote(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text[1..text.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('0') => result.push('\0'),
+            Some(c) => result.push(c),
+            None => {}
+        }
+    }
+    result
+}
+
+/// Whether a token starts an item, which is where parsing resumes after an error
+fn starts_item(kind: TokenKind) -> bool {
+    matches!(
+        kind,
+        FN | PUB
+            | EXTERN
+            | STRUCT
+            | ENUM
+            | TRAIT
+            | IMPL
+            | MOD
+            | USE
+            | CONST
+            | STATIC
+            | TYPE
+            | NEWTYPE
+    )
+}
+
+const AS_PREC: u8 = 6;
+
+/// The operator for a binary operator token along with its precedence
+fn binary_op(kind: TokenKind) -> Option<(BinOp, u8)> {
+    Some(match kind {
+        OROR => (BinOp::Or, 1),
+        ANDAND => (BinOp::And, 2),
+        EQEQ => (BinOp::Eq, 3),
+        BANGEQ => (BinOp::Ne, 3),
+        LT => (BinOp::Lt, 3),
+        LE => (BinOp::Le, 3),
+        GT => (BinOp::Gt, 3),
+        GE => (BinOp::Ge, 3),
+        PLUS => (BinOp::Add, 4),
+        MINUS => (BinOp::Sub, 4),
+        STAR => (BinOp::Mul, 5),
+        SLASH => (BinOp::Div, 5),
+        PERCENT => (BinOp::Rem, 5),
+        _ => return None,
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::cst::test::Random;
 
     #[test]
     fn test_parse_empty_function() {
         let path = PathBuf::from("test.hds");
-        let parser = Parser::new("fn main() -> () {}", path);
+        let mut parser = Parser::new("fn main() -> () {}", path);
+        parser.parse_source_file();
+    }
+
+    #[test]
+    fn test_parse_structs() {
+        let path = PathBuf::from("test.hds");
+        let mut parser = Parser::new(
+            "struct Point { x: i32, pub y: i32, } struct Pair(i32, Point); struct Unit;",
+            path,
+        );
+        let items = parser.parse_source_file().items;
+        let fields = |item: &Item| match &item.kind {
+            ItemKind::Struct(s) => s
+                .data
+                .fields()
+                .iter()
+                .map(|f| f.name.clone())
+                .collect::<Vec<_>>(),
+            _ => panic!("Expected a struct"),
+        };
+        assert_eq!(fields(&items[0]), vec!["x", "y"]);
+        assert_eq!(fields(&items[1]), vec!["0", "1"]);
+        assert!(matches!(
+            &items[2].kind,
+            ItemKind::Struct(Struct {
+                data: VariantData::Unit,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_parse_struct_expr_and_field_assignment() {
+        let path = PathBuf::from("test.hds");
+        let mut parser = Parser::new("fn f() { let p = P { x: 1, y }; p.x = p.y; }", path);
+        let items = parser.parse_source_file().items;
+        let ItemKind::Fn(f) = &items[0].kind else {
+            panic!("Expected a function")
+        };
+        let Some(ExprKind::Block(block)) = f.body.as_ref().map(|it| &it.kind) else {
+            panic!("Expected a block")
+        };
+        let StmtKind::Let(local) = &block.stmts[0].kind else {
+            panic!("Expected a let statement")
+        };
+        assert!(matches!(
+            local.init.as_ref().map(|it| &it.kind),
+            Some(ExprKind::Struct(s)) if s.fields.len() == 2
+        ));
+        let StmtKind::Expr(expr) = &block.stmts[1].kind else {
+            panic!("Expected an expression statement")
+        };
+        let ExprKind::Assign(lhs, _) = &expr.kind else {
+            panic!("Expected an assignment")
+        };
+        assert!(matches!(&lhs.kind, ExprKind::Field(_, name) if name == "x"));
+    }
+
+    #[test]
+    fn test_parse_enum_and_match() {
+        let path = PathBuf::from("test.hds");
+        let mut parser = Parser::new(
+            "
+            enum Shape { Circle(i32), Rect { w: i32, h: i32 }, Empty }
+            fn area(s: Shape) -> i32 {
+                match s {
+                    Shape::Circle(r) if big => r,
+                    Shape::Rect { w, .. } => { w }
+                    _ => 0,
+                }
+            }
+            ",
+            path,
+        );
+        let items = parser.parse_source_file().items;
+        let ItemKind::Enum(e) = &items[0].kind else {
+            panic!("Expected an enum")
+        };
+        assert_eq!(e.variants.len(), 3);
+        assert!(matches!(e.variants[1].data, VariantData::Struct(_)));
+        let ItemKind::Fn(f) = &items[1].kind else {
+            panic!("Expected a function")
+        };
+        let Some(ExprKind::Block(block)) = f.body.as_ref().map(|it| &it.kind) else {
+            panic!("Expected a block")
+        };
+        let Some(ExprKind::Match(scrutinee, arms)) = block.tail_expr().map(|it| &it.kind) else {
+            panic!("Expected a match expression")
+        };
+        assert!(matches!(scrutinee.kind, ExprKind::Var(_)));
+        assert_eq!(arms.len(), 3);
+        assert!(arms[0].guard.is_some());
+        assert!(matches!(&arms[0].pat.kind, PatKind::TupleStruct(path, pats)
+            if path.to_string() == "Shape::Circle" && pats.len() == 1));
+        assert!(matches!(
+            &arms[1].pat.kind,
+            PatKind::Struct(_, fields, true) if fields.len() == 1
+        ));
+        assert!(matches!(arms[2].pat.kind, PatKind::Wild));
+    }
+
+    #[test]
+    fn test_parse_pointers_and_operators() {
+        let path = PathBuf::from("test.hds");
+        let mut parser = Parser::new(
+            "
+            fn f(p: *mut *i32) {
+                unsafe { *(p + 1) = &x; }
+                a + b * c == d as u8 - 1 && !e
+            }
+            ",
+            path,
+        );
+        let items = parser.parse_source_file().items;
+        let ItemKind::Fn(f) = &items[0].kind else {
+            panic!("Expected a function")
+        };
+        assert!(matches!(
+            &f.params[0].ty.kind,
+            TyKind::Ptr(Mutability::Mut, inner)
+                if matches!(inner.kind, TyKind::Ptr(Mutability::Not, _))
+        ));
+        let Some(ExprKind::Block(block)) = f.body.as_ref().map(|it| &it.kind) else {
+            panic!("Expected a block")
+        };
+        let StmtKind::Expr(unsafe_block) = &block.stmts[0].kind else {
+            panic!("Expected an expression statement")
+        };
+        let ExprKind::Block(unsafe_block) = &unsafe_block.kind else {
+            panic!("Expected an unsafe block")
+        };
+        assert!(unsafe_block.is_unsafe);
+        let StmtKind::Expr(assign) = &unsafe_block.stmts[0].kind else {
+            panic!("Expected an expression statement")
+        };
+        let ExprKind::Assign(lhs, rhs) = &assign.kind else {
+            panic!("Expected an assignment")
+        };
+        assert!(matches!(&lhs.kind, ExprKind::Unary(UnOp::Deref, inner)
+            if matches!(inner.kind, ExprKind::Paren(_))));
+        assert!(matches!(rhs.kind, ExprKind::AddrOf(Mutability::Not, _)));
+
+        // (a + (b * c)) == ((d as u8) - 1)) && (!e)
+        let Some(ExprKind::Binary(BinOp::And, lhs, rhs)) = block.tail_expr().map(|it| &it.kind)
+        else {
+            panic!("Expected `&&`")
+        };
+        assert!(matches!(rhs.kind, ExprKind::Unary(UnOp::Not, _)));
+        let ExprKind::Binary(BinOp::Eq, lhs, rhs) = &lhs.kind else {
+            panic!("Expected `==`")
+        };
+        assert!(matches!(&lhs.kind, ExprKind::Binary(BinOp::Add, _, mul)
+            if matches!(mul.kind, ExprKind::Binary(BinOp::Mul, ..))));
+        assert!(matches!(&rhs.kind, ExprKind::Binary(BinOp::Sub, cast, _)
+            if matches!(cast.kind, ExprKind::Cast(..))));
+    }
+
+    #[test]
+    fn test_parse_tuples() {
+        let path = PathBuf::from("test.hds");
+        let mut parser = Parser::new(
+            "fn f(p: (i32, (bool,)), q: (u8)) -> () { let (a, (b,), _) = (1, (true,), (2)); p.1.0 }",
+            path,
+        );
+        let items = parser.parse_source_file().items;
+        let ItemKind::Fn(f) = &items[0].kind else {
+            panic!("Expected a function")
+        };
+        assert!(matches!(&f.params[0].ty.kind, TyKind::Tup(tys)
+            if matches!(&tys[1].kind, TyKind::Tup(inner) if inner.len() == 1)));
+        assert!(matches!(f.params[1].ty.kind, TyKind::Var(_, _)));
+        assert!(matches!(&f.return_ty.as_ref().unwrap().kind, TyKind::Tup(tys) if tys.is_empty()));
+        let Some(ExprKind::Block(block)) = f.body.as_ref().map(|it| &it.kind) else {
+            panic!("Expected a block")
+        };
+        let StmtKind::Let(local) = &block.stmts[0].kind else {
+            panic!("Expected a let statement")
+        };
+        assert!(matches!(&local.pat.kind, PatKind::Tuple(pats)
+            if pats.len() == 3 && matches!(&pats[1].kind, PatKind::Tuple(inner) if inner.len() == 1)));
+        assert!(matches!(
+            local.init.as_ref().map(|it| &it.kind),
+            Some(ExprKind::Tup(exprs)) if matches!(exprs[2].kind, ExprKind::Paren(_))
+        ));
+        assert!(matches!(block.tail_expr().map(|it| &it.kind),
+            Some(ExprKind::Field(base, index)) if index == "0"
+                && matches!(&base.kind, ExprKind::Field(_, index) if index == "1")));
+    }
+
+    #[test]
+    fn test_parse_generics() {
+        let path = PathBuf::from("test.hds");
+        let mut parser = Parser::new(
+            "struct Pair<A, B> { first: A, second: B }
+            enum Option<T> { Some(T), None }
+            fn id<T>(p: Pair<T, Option<i32>>) -> T { p.first }",
+            path,
+        );
+        let items = parser.parse_source_file().items;
+        let ItemKind::Struct(s) = &items[0].kind else {
+            panic!("Expected a struct")
+        };
+        let names = s
+            .generics
+            .iter()
+            .map(|it| it.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["A", "B"]);
+        assert!(matches!(&items[1].kind, ItemKind::Enum(e) if e.generics.len() == 1));
+        let ItemKind::Fn(f) = &items[2].kind else {
+            panic!("Expected a function")
+        };
+        assert_eq!(f.generics[0].name, "T");
+        assert!(matches!(&f.params[0].ty.kind, TyKind::Var(name, args)
+            if name == "Pair" && args.len() == 2
+                && matches!(&args[1].kind, TyKind::Var(_, inner) if inner.len() == 1)));
+        assert!(matches!(&f.return_ty.as_ref().unwrap().kind,
+            TyKind::Var(name, args) if name == "T" && args.is_empty()));
+    }
+
+    #[test]
+    fn test_parse_traits() {
+        let path = PathBuf::from("test.hds");
+        let mut parser = Parser::new(
+            "trait Show { fn show(self: *Self) -> i32; }
+            impl<T: Show + Eq> Show for Pair<T> { fn show(self: *Self) -> i32 { 0 } }
+            fn f(s: *dyn Show) {}",
+            path,
+        );
+        let items = parser.parse_source_file().items;
+        let ItemKind::Trait(t) = &items[0].kind else {
+            panic!("Expected a trait")
+        };
+        assert_eq!(items[0].name, "Show");
+        assert!(matches!(&t.items[0].kind, ItemKind::Fn(f) if f.body.is_none()));
+        let ItemKind::Impl(imp) = &items[1].kind else {
+            panic!("Expected an impl")
+        };
+        let bounds = imp.generics[0]
+            .bounds
+            .iter()
+            .map(|it| it.path.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(bounds, vec!["Show", "Eq"]);
+        assert_eq!(imp.trait_ref.as_ref().unwrap().path.to_string(), "Show");
+        assert!(matches!(&imp.self_ty.kind, TyKind::Var(name, _) if name == "Pair"));
+        assert_eq!(imp.items.len(), 1);
+        let ItemKind::Fn(f) = &items[2].kind else {
+            panic!("Expected a function")
+        };
+        assert!(matches!(&f.params[0].ty.kind, TyKind::Ptr(_, inner)
+            if matches!(&inner.kind, TyKind::Dyn(t) if t.path.segments == ["Show"])));
+    }
+
+    #[test]
+    fn test_parse_modules_and_imports() {
+        let path = PathBuf::from("test.hds");
+        let mut parser = Parser::new(
+            "
+            mod a;
+            pub mod b { pub use super::a::f; }
+            use a::*;
+            use a::b as c;
+            fn f(p: a::Pair<i32>) {}
+            impl a::Show for i32 {}
+            ",
+            path,
+        );
+        let items = parser.parse_source_file().items;
+        assert!(matches!(&items[0].kind, ItemKind::Mod(m) if !m.inline && m.items.is_empty()));
+        let ItemKind::Mod(m) = &items[1].kind else {
+            panic!("Expected a module")
+        };
+        assert!(m.inline && matches!(m.items[0].vis, Visibility::Public));
+        let ItemKind::Use(u) = &m.items[0].kind else {
+            panic!("Expected an import")
+        };
+        assert_eq!(u.path.to_string(), "super::a::f");
+        assert!(matches!(&items[2].kind, ItemKind::Use(u) if matches!(u.kind, UseKind::Glob)));
+        let ItemKind::Use(u) = &items[3].kind else {
+            panic!("Expected an import")
+        };
+        assert_eq!(u.binding().map(|it| it.as_str()), Some("c"));
+        let ItemKind::Fn(f) = &items[4].kind else {
+            panic!("Expected a function")
+        };
+        assert!(matches!(&f.params[0].ty.kind,
+            TyKind::Path(path, args) if path.to_string() == "a::Pair" && args.len() == 1));
+        let ItemKind::Impl(imp) = &items[5].kind else {
+            panic!("Expected an impl")
+        };
+        assert_eq!(imp.trait_ref.as_ref().unwrap().path.to_string(), "a::Show");
+    }
+
+    #[test]
+    fn test_parse_attributes_and_abis() {
+        let mut parser = Parser::new(
+            r#"
+            #[link(name = "m")]
+            extern "C" {
+                #[link_name = "sqrt\"x"]
+                fn square_root(x: i32) -> i32;
+            }
+            "#,
+            PathBuf::from("test.hds"),
+        );
+        let items = parser.parse_source_file().items;
+        let [link] = &items[0].attrs[..] else {
+            panic!("Expected an attribute")
+        };
+        assert_eq!(link.name, "link");
+        let AttrArgs::List(args) = &link.args else {
+            panic!("Expected attribute arguments")
+        };
+        assert_eq!(args[0].name, "name");
+        assert!(matches!(&args[0].args, AttrArgs::Eq(lib) if lib == "m"));
+        let ItemKind::ForeignMod(foreign_mod) = &items[0].kind else {
+            panic!("Expected a foreign mod")
+        };
+        assert_eq!(foreign_mod.abi.as_deref(), Some("C"));
+        let attr = &foreign_mod.items[0].attrs[0];
+        assert!(matches!(&attr.args, AttrArgs::Eq(name) if name == "sqrt\"x"));
+    }
+
+    #[test]
+    fn test_parse_variadic_fns() {
+        let mut parser = Parser::new(
+            "extern { fn printf(fmt: *u8, ...) -> i32; }",
+            PathBuf::from("test.hds"),
+        );
+        let items = parser.parse_source_file().items;
+        let ItemKind::ForeignMod(foreign_mod) = &items[0].kind else {
+            panic!("Expected a foreign mod")
+        };
+        let ForeignItemKind::Fn(f) = &foreign_mod.items[0].kind;
+        assert_eq!(f.params.len(), 1);
+        assert!(f.variadic);
+    }
+
+    #[test]
+    fn test_parse_param_and_statement_attributes() {
+        let mut parser = Parser::new(
+            "fn f(#[allow(deprecated)] x: i32) { #[allow(deprecated)] let y = x; }",
+            PathBuf::from("test.hds"),
+        );
+        let items = parser.parse_source_file().items;
+        let ItemKind::Fn(f) = &items[0].kind else {
+            panic!("Expected a function")
+        };
+        assert_eq!(f.params[0].attrs[0].name, "allow");
+        let ExprKind::Block(block) = &f.body.as_ref().unwrap().kind else {
+            panic!("Expected a block")
+        };
+        assert_eq!(block.stmts[0].attrs[0].name, "allow");
+    }
+
+    #[test]
+    fn test_parse_consts_and_statics() {
+        let mut parser = Parser::new(
+            "pub const N: i32 = 4 * 1024; static mut COUNTER: u64 = 0; static FLAG: bool = true;",
+            PathBuf::from("test.hds"),
+        );
+        let items = parser.parse_source_file().items;
+        let ItemKind::Const(c) = &items[0].kind else {
+            panic!("Expected a const")
+        };
+        assert_eq!(items[0].name, "N");
+        assert!(matches!(items[0].vis, Visibility::Public));
+        assert!(matches!(c.expr.kind, ExprKind::Binary(BinOp::Mul, ..)));
+        assert!(matches!(&items[1].kind, ItemKind::Static(s) if s.mutability == Mutability::Mut));
+        assert_eq!(items[1].name, "COUNTER");
+        assert!(matches!(&items[2].kind, ItemKind::Static(s) if s.mutability == Mutability::Not));
+    }
+
+    #[test]
+    fn test_parse_type_aliases_and_newtypes() {
+        let mut parser = Parser::new(
+            "type Fd = i32; pub type Pair<T> = (T, T); newtype Handle = *mut u8;",
+            PathBuf::from("test.hds"),
+        );
+        let items = parser.parse_source_file().items;
+        assert!(matches!(&items[0].kind, ItemKind::TyAlias(a) if a.generics.is_empty()));
+        let ItemKind::TyAlias(pair) = &items[1].kind else {
+            panic!("Expected a type alias")
+        };
+        assert_eq!(pair.generics[0].name, "T");
+        assert_eq!(pair.ty.to_string(), "(T, T)");
+        let ItemKind::Newtype(handle) = &items[2].kind else {
+            panic!("Expected a newtype")
+        };
+        assert_eq!(items[2].name, "Handle");
+        assert_eq!(handle.ty.to_string(), "*mut u8");
+    }
+
+    #[test]
+    fn test_parse_methods() {
+        let path = PathBuf::from("test.hds");
+        let mut parser = Parser::new(
+            "impl Point { fn new() -> Point { Point::origin() } }
+            fn f(p: Point) -> i32 { p.x + p.len(1).y }",
+            path,
+        );
+        let items = parser.parse_source_file().items;
+        let ItemKind::Impl(imp) = &items[0].kind else {
+            panic!("Expected an impl")
+        };
+        assert!(imp.trait_ref.is_none());
+        assert!(matches!(&imp.self_ty.kind, TyKind::Var(name, _) if name == "Point"));
+        let ItemKind::Fn(f) = &items[1].kind else {
+            panic!("Expected a function")
+        };
+        let ExprKind::Block(body) = &f.body.as_ref().unwrap().kind else {
+            panic!("Expected a block")
+        };
+        let StmtKind::Expr(expr) = &body.stmts[0].kind else {
+            panic!("Expected an expression")
+        };
+        let ExprKind::Binary(_, lhs, rhs) = &expr.kind else {
+            panic!("Expected a binary expression")
+        };
+        assert!(matches!(&lhs.kind, ExprKind::Field(_, name) if name == "x"));
+        let ExprKind::Field(call, field) = &rhs.kind else {
+            panic!("Expected a field access")
+        };
+        assert_eq!(field, "y");
+        assert!(
+            matches!(&call.kind, ExprKind::MethodCall(receiver, name, args)
+            if name == "len" && args.len() == 1 && matches!(receiver.kind, ExprKind::Var(_)))
+        );
+    }
+
+    #[test]
+    fn test_parse_arrays() {
+        let path = PathBuf::from("test.hds");
+        let mut parser = Parser::new(
+            "fn f(a: [i32; 3], s: [u8]) { let b = [0; 16]; a[1] = [1, 2, 3,][s[0]]; }",
+            path,
+        );
+        let items = parser.parse_source_file().items;
+        let ItemKind::Fn(f) = &items[0].kind else {
+            panic!("Expected a function")
+        };
+        assert!(matches!(f.params[0].ty.kind, TyKind::Array(..)));
+        assert!(matches!(f.params[1].ty.kind, TyKind::Slice(_)));
+        let Some(ExprKind::Block(block)) = f.body.as_ref().map(|it| &it.kind) else {
+            panic!("Expected a block")
+        };
+        let StmtKind::Let(local) = &block.stmts[0].kind else {
+            panic!("Expected a let statement")
+        };
+        assert!(matches!(
+            local.init.as_ref().map(|it| &it.kind),
+            Some(ExprKind::Repeat(..))
+        ));
+        let StmtKind::Expr(assign) = &block.stmts[1].kind else {
+            panic!("Expected an expression statement")
+        };
+        let ExprKind::Assign(lhs, rhs) = &assign.kind else {
+            panic!("Expected an assignment")
+        };
+        assert!(matches!(lhs.kind, ExprKind::Index(..)));
+        assert!(matches!(&rhs.kind, ExprKind::Index(array, _)
+            if matches!(&array.kind, ExprKind::Array(elems) if elems.len() == 3)));
+    }
+
+    #[test]
+    fn test_recovers_from_syntax_errors() {
+        let path = PathBuf::from("test.hds");
+        let text = "\
+fn f(a: i32) -> i32 {
+    let x = a + ;
+    match a { 1 => 2 3 => 4 }
+    a @ 1;
+    a
+}
+struct P { x: }
+impl P { struct Q; fn g() {} }
+fn h() {
+    let y = f(1
+fn main() {}
+";
+        let mut parser = Parser::new(text, path);
+        let items = parser.parse_source_file().items;
+        let errors = parser
+            .take_diagnostics()
+            .into_iter()
+            .map(|it| {
+                let found = text.chars().skip(it.span.start).take(1).collect::<String>();
+                (it.message, found)
+            })
+            .collect::<Vec<_>>();
+        let expected = [
+            ("Parse error: Expected an expression; Found `;`", ";"),
+            (
+                "Parse error: Expected a comma between match arms; Found `3`",
+                "3",
+            ),
+            ("Parse error: Expected an expression; Found `@`", "@"),
+            ("Parse error: Expected a type; Found `}`", "}"),
+            ("Parse error: Only functions are allowed in a impl", "s"),
+            // The missing `}` is at the same token, so it isn't reported again
+            (
+                "Parse error: Expected a comma between call arguments; Found `fn`",
+                "f",
+            ),
+        ];
+        assert_eq!(
+            errors,
+            expected.map(|(message, found)| (message.to_string(), found.to_string()))
+        );
+        // Statements and items with errors are left out
+        let names = items.iter().map(|it| it.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["f", "impl", "h", "main"]);
+        let ItemKind::Fn(f) = &items[0].kind else {
+            panic!("Expected a function")
+        };
+        let Some(ExprKind::Block(block)) = f.body.as_ref().map(|it| &it.kind) else {
+            panic!("Expected a block")
+        };
+        // `a` before the `@`, and the `a` at the end
+        assert_eq!(block.stmts.len(), 2);
+        let ItemKind::Impl(imp) = &items[1].kind else {
+            panic!("Expected an impl")
+        };
+        assert_eq!(imp.items.len(), 1);
+    }
+
+    #[test]
+    fn test_reports_unexpected_characters() {
+        let mut parser = Parser::new("fn f() { a | b; }\n#[x = \"y]", PathBuf::from("test.hds"));
         parser.parse_source_file();
+        let messages = parser
+            .take_diagnostics()
+            .into_iter()
+            .map(|it| it.message)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "Parse error: Expected an expression; Found `|`",
+                "Parse error: Expected a string after `=` in an attribute; Found an unterminated string"
+            ]
+        );
+    }
+
+    fn assert_reparsed(file: &ParsedFile, text: &str) {
+        let mut parser = Parser::new(text, PathBuf::from("test.hds"));
+        let items = parser.parse_items();
+        assert_eq!(
+            serde_json::to_value(&file.items).unwrap(),
+            serde_json::to_value(&items).unwrap(),
+            "{:?}",
+            text
+        );
+        assert_eq!(file.diagnostics(), parser.take_diagnostics(), "{:?}", text);
+        assert_eq!(file.next_node_id(), parser.next_node_id());
+    }
+
+    /// Replaces the chars in `span` with `insert`
+    fn edit(text: &str, span: Span, insert: &str) -> String {
+        let chars = text.chars().collect::<Vec<_>>();
+        let (before, after) = (&chars[..span.start], &chars[span.end..]);
+        before
+            .iter()
+            .chain(insert.chars().collect::<Vec<_>>().iter())
+            .chain(after)
+            .collect()
+    }
+
+    #[test]
+    fn reparses_only_the_items_an_edit_touches() {
+        let text = "fn a() { 1 }\nfn b() { 2 }\n// c\nfn c() { 3 }\n";
+        let mut file = ParsedFile::parse(text, PathBuf::from("test.hds"));
+        // Items that are reused keep these names
+        for item in &mut file.items {
+            item.name.push_str("_old");
+        }
+        let span = Span { start: 22, end: 23 };
+        let text = edit(text, span, "x + 20");
+        file.reparse(&text, span);
+        let names = file
+            .items
+            .iter()
+            .map(|it| it.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a_old", "b", "c_old"]);
+        for item in &mut file.items {
+            item.name = item.name.trim_end_matches("_old").to_string();
+        }
+        assert_reparsed(&file, &text);
+        assert_eq!(file.diagnostics(), vec![]);
+    }
+
+    #[test]
+    fn reparses_items_that_an_edit_joins_or_splits() {
+        let mut text = "fn a() { 1 }\nfn b() { 2 }\nfn c() { 3 }\n".to_string();
+        let mut file = ParsedFile::parse(&text, PathBuf::from("test.hds"));
+        // Opening a block takes in the items after it, until it's closed again
+        for (span, insert) in [
+            (Span { start: 9, end: 9 }, "{ "),
+            (Span { start: 34, end: 34 }, "let x = "),
+            (Span { start: 9, end: 11 }, ""),
+            (Span { start: 14, end: 16 }, "fx"),
+            (Span { start: 0, end: 0 }, "\0"),
+            (Span { start: 0, end: 1 }, ""),
+        ] {
+            text = edit(&text, span, insert);
+            file.reparse(&text, span);
+            assert_reparsed(&file, &text);
+        }
+        // The error in `struct S` is at the token after it, which the edit extends
+        let text = "struct S\nfn a() {}";
+        let mut file = ParsedFile::parse(text, PathBuf::from("test.hds"));
+        let span = Span { start: 11, end: 11 };
+        let text = edit(text, span, "x");
+        file.reparse(&text, span);
+        assert_reparsed(&file, &text);
+    }
+
+    #[test]
+    fn reparses_random_edits_like_parsing_from_scratch() {
+        let pieces = [
+            "{",
+            "}",
+            "(",
+            ")",
+            "[",
+            "]",
+            ";",
+            ",",
+            ":",
+            "::",
+            "=",
+            "=>",
+            ".",
+            "&",
+            "*",
+            "<",
+            ">",
+            "#",
+            "#[test]",
+            "fn",
+            "fn f() {",
+            "struct S",
+            "enum E {",
+            "impl",
+            "mod m {",
+            "pub",
+            "let x = ",
+            "match x {",
+            "x",
+            "1",
+            " + 2",
+            "\"",
+            "\\",
+            "//",
+            "\n",
+            " ",
+            "\t",
+            "\0",
+            "é",
+        ];
+        let mut random = Random(0x5851f42d4c957f2d);
+        let mut files = vec![];
+        for entry in std::fs::read_dir("test").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|it| it == "hds") {
+                files.push(path);
+            }
+        }
+        files.sort();
+        for path in files {
+            let mut text = std::fs::read_to_string(&path).unwrap();
+            let mut file = ParsedFile::parse(&text, path.clone());
+            assert_reparsed(&file, &text);
+            for _ in 0..30 {
+                let len = text.chars().count();
+                let start = random.next(len + 1);
+                let end = start + random.next(10).min(len - start);
+                // Either random tokens, or text copied from elsewhere in the file
+                let insert = if random.next(2) == 0 {
+                    (0..random.next(4))
+                        .map(|_| pieces[random.next(pieces.len())])
+                        .collect::<String>()
+                } else {
+                    let from = random.next(len + 1);
+                    let to = from + random.next(40).min(len - from);
+                    text.chars().skip(from).take(to - from).collect()
+                };
+                let span = Span { start, end };
+                text = edit(&text, span, &insert);
+                file.reparse(&text, span);
+                assert_reparsed(&file, &text);
+            }
+        }
     }
 }
 
@@ -353,6 +2337,11 @@ struct TokenBuffer<'text> {
     start: usize,
     tokens: [Token; MAX_LOOKAHEAD],
     lexer: Lexer<'text>,
+    /// The end of the furthest token peeked at, since an edit before it can
+    /// change what was parsed
+    lookahead: Cell<usize>,
+    /// The end of the last token consumed
+    end: usize,
 }
 impl<'text> TokenBuffer<'text> {
     pub fn new(mut lexer: Lexer<'text>) -> Self {
@@ -361,12 +2350,16 @@ impl<'text> TokenBuffer<'text> {
             lexer,
             tokens,
             start: 0,
+            lookahead: Cell::new(0),
+            end: 0,
         }
     }
 
     pub fn peek(&self, offset: usize) -> &Token {
         assert!(offset < MAX_LOOKAHEAD);
-        &self.tokens[(self.start + offset) % MAX_LOOKAHEAD]
+        let token = &self.tokens[(self.start + offset) % MAX_LOOKAHEAD];
+        self.lookahead.set(self.lookahead.get().max(token.span.end));
+        token
     }
 
     pub fn current(&self) -> &Token {
@@ -387,6 +2380,7 @@ impl<'text> TokenBuffer<'text> {
         //     ^
         let old = std::mem::replace(&mut self.tokens[self.start], t);
         self.start = (self.start + 1) % MAX_LOOKAHEAD;
+        self.end = old.span.end;
         old
     }
 }
@@ -0,0 +1,67 @@
+use std::path::PathBuf;
+
+/// The files of a compilation. Spans are offsets into the concatenation of all
+/// files, so that a span identifies the file it's in.
+#[derive(Debug, Default, PartialEq)]
+pub struct SourceMap {
+    files: Vec<SourceFileInfo>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SourceFileInfo {
+    pub path: PathBuf,
+    pub text: String,
+    /// Offset of the first character of the file
+    pub start: usize,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap::default()
+    }
+
+    /// Adds a file, returning the offset that spans in it start from
+    pub fn add_file(&mut self, path: PathBuf, text: String) -> usize {
+        let start = self.next_start();
+        self.files.push(SourceFileInfo { path, text, start });
+        start
+    }
+
+    /// One past the end of the last file, leaving room for its EOF token
+    pub fn next_start(&self) -> usize {
+        self.files
+            .last()
+            .map_or(0, |it| it.start + it.text.chars().count() + 1)
+    }
+
+    pub fn files(&self) -> &[SourceFileInfo] {
+        &self.files
+    }
+
+    /// The file containing `offset` along with the offset relative to its start
+    pub fn lookup(&self, offset: usize) -> (&SourceFileInfo, usize) {
+        let file = self
+            .files
+            .iter()
+            .rev()
+            .find(|it| it.start <= offset)
+            .expect("Spans always point into a file");
+        (file, offset - file.start)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn looks_up_files_by_offset() {
+        let mut source_map = SourceMap::new();
+        assert_eq!(source_map.add_file("a.hds".into(), "fn a() {}".into()), 0);
+        assert_eq!(source_map.add_file("b.hds".into(), "fn b() {}".into()), 10);
+        let (file, offset) = source_map.lookup(3);
+        assert_eq!((file.path.to_str(), offset), (Some("a.hds"), 3));
+        let (file, offset) = source_map.lookup(13);
+        assert_eq!((file.path.to_str(), offset), (Some("b.hds"), 3));
+    }
+}
@@ -0,0 +1,14 @@
+//! Helpers shared by the tests of several modules
+use std::path::PathBuf;
+
+/// Writes `files` to a fresh directory named after the test, returning its path
+pub fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hades-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    for (name, text) in files {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, text).unwrap();
+    }
+    dir
+}
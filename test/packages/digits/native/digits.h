@@ -0,0 +1,2 @@
+int put_digit(int d);
+int put_newline(void);
@@ -1 +1,22 @@
 "use strict";
+Object.defineProperty(exports, "__esModule", { value: true });
+exports.deactivate = exports.activate = void 0;
+var vscode_1 = require("vscode");
+var vscode_languageclient_1 = require("vscode-languageclient");
+var client;
+function activate(context) {
+    var command = vscode_1.workspace
+        .getConfiguration("hades")
+        .get("serverPath", "hades-lang");
+    var serverOptions = { command: command, args: ["lsp"] };
+    var clientOptions = {
+        documentSelector: [{ scheme: "file", language: "hades" }],
+    };
+    client = new vscode_languageclient_1.LanguageClient("hades", "Hades", serverOptions, clientOptions);
+    context.subscriptions.push(client.start());
+}
+exports.activate = activate;
+function deactivate() {
+    return client === null || client === void 0 ? void 0 : client.stop();
+}
+exports.deactivate = deactivate;
@@ -0,0 +1,24 @@
+import { ExtensionContext, workspace } from "vscode";
+import {
+    LanguageClient,
+    LanguageClientOptions,
+    ServerOptions,
+} from "vscode-languageclient";
+
+let client: LanguageClient | undefined;
+
+export function activate(context: ExtensionContext) {
+    const command = workspace
+        .getConfiguration("hades")
+        .get<string>("serverPath", "hades-lang");
+    const serverOptions: ServerOptions = { command, args: ["lsp"] };
+    const clientOptions: LanguageClientOptions = {
+        documentSelector: [{ scheme: "file", language: "hades" }],
+    };
+    client = new LanguageClient("hades", "Hades", serverOptions, clientOptions);
+    context.subscriptions.push(client.start());
+}
+
+export function deactivate(): Thenable<void> | undefined {
+    return client?.stop();
+}